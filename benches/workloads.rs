@@ -0,0 +1,62 @@
+//! Runs each workload against each built-in backend with criterion. Run with
+//! `cargo bench --features bench`, optionally with a filter like
+//! `large_stream/std`.
+
+use std::path::Path;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use floppy_disk::prelude::*;
+use floppy_disk::workload::Workload;
+use tokio::runtime::Runtime;
+
+fn bench<'a, D: FloppyDisk<'a>>(
+    c: &mut Criterion,
+    runtime: &Runtime,
+    backend: &str,
+    disk: &'a D,
+    root: &Path,
+) {
+    for workload in Workload::all() {
+        let mut group = c.benchmark_group(workload.name());
+        // Each iteration is a whole workload, so a few samples go a long way.
+        group.sample_size(10);
+        let root = root.join(workload.name());
+        group.bench_function(backend, |b| {
+            b.to_async(runtime).iter_custom(|iters| {
+                let workload = workload.clone();
+                let root = root.clone();
+                async move {
+                    let mut elapsed = Duration::ZERO;
+                    for _ in 0..iters {
+                        elapsed += workload.run(disk, &root).await.unwrap().elapsed;
+                    }
+                    elapsed
+                }
+            })
+        });
+        group.finish();
+    }
+}
+
+fn workloads(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mem = MemFloppyDisk::new();
+    bench(c, &runtime, "mem", &mem, Path::new("/bench"));
+
+    let temp = runtime.block_on(TempFloppyDisk::new()).unwrap();
+    bench(c, &runtime, "tokio", &*temp, Path::new("/bench"));
+
+    let std = StdFloppyDisk::new();
+    let std_root = std::env::temp_dir().join(format!("floppy-bench-{}", std::process::id()));
+    bench(c, &runtime, "std", &std, &std_root);
+    // Workloads only remove their own directories, not the one they share.
+    std::fs::remove_dir_all(&std_root).unwrap();
+}
+
+criterion_group!(benches, workloads);
+criterion_main!(benches);
@@ -0,0 +1,358 @@
+//! Logs of what a [`CacheStore`](crate::cache::CacheStore) was asked for,
+//! and recommendations for sizing it from them. See
+//! [`CacheOptions::with_access_log`](crate::cache::CacheOptions::with_access_log).
+//!
+//! Logs are compact enough to leave on in production:
+//!
+//! ```text
+//! magic "FLOPACCS", version: u32
+//! records: time since the previous record in microseconds: varint,
+//!          op and hit: u8, size: varint, path id: varint
+//! ```
+//!
+//! Each path is written out once, as a varint length and its bytes right
+//! after its id, the first time it's seen. Ids count up from 0 in that
+//! order. Varints are LEB128, and the first record's time is since the
+//! Unix epoch.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const LOG_MAGIC: &[u8; 8] = b"FLOPACCS";
+const LOG_VERSION: u32 = 1;
+const HIT: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessOp {
+    Get,
+    Put,
+    Remove,
+    /// The store evicted the artifact to stay under its size limit.
+    Evict,
+}
+
+impl AccessOp {
+    fn from_u8(op: u8) -> Result<Self> {
+        match op {
+            0 => Ok(Self::Get),
+            1 => Ok(Self::Put),
+            2 => Ok(Self::Remove),
+            3 => Ok(Self::Evict),
+            _ => Err(invalid_log(&format!("unknown op {op}"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessRecord {
+    /// When it happened, since the Unix epoch, to the microsecond.
+    pub at: Duration,
+    pub op: AccessOp,
+    /// The artifact's path in the store.
+    pub path: PathBuf,
+    /// Bytes read, written or evicted. Misses and removals are 0.
+    pub size: u64,
+    /// Whether a get found the artifact. Always false for other ops.
+    pub hit: bool,
+}
+
+#[derive(Debug)]
+pub struct AccessLog {
+    state: Mutex<LogState>,
+}
+
+#[derive(Debug)]
+struct LogState {
+    buf: Vec<u8>,
+    last: u64,
+    paths: HashMap<PathBuf, u64>,
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        let mut buf = LOG_MAGIC.to_vec();
+        buf.extend_from_slice(&LOG_VERSION.to_le_bytes());
+        Self {
+            state: Mutex::new(LogState {
+                buf,
+                last: 0,
+                paths: HashMap::new(),
+            }),
+        }
+    }
+}
+
+impl AccessLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Log `op` on `path`, as happening now.
+    pub(crate) fn record(&self, op: AccessOp, path: PathBuf, size: u64, hit: bool) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        // A clock that goes backwards is taken to have stood still.
+        let at = now.max(state.last);
+        push_varint(&mut state.buf, at - state.last);
+        state.last = at;
+        state.buf.push(op as u8 | if hit { HIT } else { 0 });
+        push_varint(&mut state.buf, size);
+
+        let next_id = state.paths.len() as u64;
+        match state.paths.get(&path) {
+            Some(id) => push_varint(&mut state.buf, *id),
+            None => {
+                push_varint(&mut state.buf, next_id);
+                let bytes = path.as_os_str().as_bytes();
+                push_varint(&mut state.buf, bytes.len() as u64);
+                state.buf.extend_from_slice(bytes);
+                state.paths.insert(path, next_id);
+            }
+        }
+    }
+
+    /// The log so far, to be saved somewhere and read back with
+    /// [`AccessLog::parse`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.state.lock().unwrap().buf.clone()
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Vec<AccessRecord>> {
+        let mut reader = LogReader { bytes, position: 0 };
+        if reader.take(8)? != LOG_MAGIC {
+            return Err(invalid_log("not an access log"));
+        }
+        let version = u32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+        if version != LOG_VERSION {
+            return Err(invalid_log(&format!(
+                "unsupported access log version {version}"
+            )));
+        }
+
+        let mut records = vec![];
+        let mut paths: Vec<PathBuf> = vec![];
+        let mut at = 0u64;
+        while reader.position < bytes.len() {
+            at = at
+                .checked_add(reader.varint()?)
+                .ok_or_else(|| invalid_log("time overflows"))?;
+            let op = reader.take(1)?[0];
+            let size = reader.varint()?;
+            let id = reader.varint()? as usize;
+            if id == paths.len() {
+                let len = reader.varint()? as usize;
+                paths.push(PathBuf::from(OsStr::from_bytes(reader.take(len)?)));
+            }
+            let path = paths
+                .get(id)
+                .ok_or_else(|| invalid_log(&format!("unknown path id {id}")))?
+                .clone();
+            records.push(AccessRecord {
+                at: Duration::from_micros(at),
+                op: AccessOp::from_u8(op & !HIT)?,
+                path,
+                size,
+                hit: op & HIT != 0,
+            });
+        }
+        Ok(records)
+    }
+}
+
+/// What a log says about how big a store should be, and how long its
+/// artifacts stay useful.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheRecommendation {
+    pub gets: u64,
+    pub hits: u64,
+    /// The size of every distinct artifact that was read or written.
+    pub working_set_bytes: u64,
+    /// The smallest [`CacheOptions::with_max_bytes`](crate::cache::CacheOptions::with_max_bytes)
+    /// under which an LRU store would have kept the targeted share of
+    /// artifacts until they were got again.
+    pub max_bytes: u64,
+    /// How long the targeted share of artifacts went unused before being got
+    /// again, so that expiring anything unused for longer loses few hits.
+    /// `None` if nothing was got twice.
+    pub ttl: Option<Duration>,
+}
+
+/// Replay `records` against an LRU store of unlimited size, and recommend
+/// settings that would have kept `target` of its hits, eg. `0.95`.
+pub fn recommend(records: &[AccessRecord], target: f64) -> CacheRecommendation {
+    // Artifacts in the store, least recently used first, with their sizes
+    // and when they were last used.
+    let mut stack: Vec<(&PathBuf, u64, Duration)> = vec![];
+    let mut sizes: HashMap<&PathBuf, u64> = HashMap::new();
+    let mut distances = vec![];
+    let mut intervals = vec![];
+    let (mut gets, mut hits) = (0, 0);
+
+    for record in records {
+        let position = stack.iter().position(|(path, ..)| *path == &record.path);
+        match record.op {
+            AccessOp::Get => {
+                gets += 1;
+                if record.hit {
+                    hits += 1;
+                    sizes.insert(&record.path, record.size);
+                }
+                let Some(position) = position else {
+                    continue;
+                };
+                // Everything used since, and this artifact itself, had to
+                // fit for it to still be there.
+                distances.push(stack[position..].iter().map(|(_, size, _)| size).sum());
+                let (path, size, used) = stack.remove(position);
+                intervals.push(record.at.saturating_sub(used));
+                stack.push((path, size, record.at));
+            }
+            AccessOp::Put => {
+                if let Some(position) = position {
+                    stack.remove(position);
+                }
+                stack.push((&record.path, record.size, record.at));
+                sizes.insert(&record.path, record.size);
+            }
+            AccessOp::Remove => {
+                if let Some(position) = position {
+                    stack.remove(position);
+                }
+            }
+            // The store's own evictions are what's being tuned, so they're
+            // left out.
+            AccessOp::Evict => {}
+        }
+    }
+
+    CacheRecommendation {
+        gets,
+        hits,
+        working_set_bytes: sizes.values().sum(),
+        max_bytes: percentile(&mut distances, target).unwrap_or(0),
+        ttl: percentile(&mut intervals, target),
+    }
+}
+
+fn percentile<T: Ord + Copy>(values: &mut [T], target: f64) -> Option<T> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort();
+    let index = (values.len() as f64 * target.clamp(0.0, 1.0)).ceil() as usize;
+    Some(values[index.clamp(1, values.len()) - 1])
+}
+
+fn push_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+struct LogReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> LogReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position.saturating_add(len))
+            .ok_or_else(|| invalid_log("truncated access log"))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid_log("varint too long"))
+    }
+}
+
+fn invalid_log(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::cache::{CacheOptions, CacheStore};
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_access_log() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        let log = Arc::new(AccessLog::new());
+        let options = CacheOptions::new()
+            .with_max_bytes(8)
+            .with_access_log(log.clone());
+        let store = CacheStore::open(&fs, "/cache", options).await?;
+        store.get("a").await?;
+        store
+            .get_or_insert_with("a", || async { Ok(b"aaaa".to_vec()) })
+            .await?;
+        store.put("b", "bbbb").await?;
+        store.get("a").await?;
+        store.put("c", "cccc").await?;
+        store.get("b").await?;
+        store.remove("a").await?;
+
+        let records = AccessLog::parse(&log.to_bytes())?;
+        let ops: Vec<_> = records
+            .iter()
+            .map(|record| (record.op, record.size, record.hit))
+            .collect();
+        assert_eq!(
+            vec![
+                (AccessOp::Get, 0, false),
+                (AccessOp::Get, 0, false),
+                (AccessOp::Put, 4, false),
+                (AccessOp::Put, 4, false),
+                (AccessOp::Get, 4, true),
+                (AccessOp::Put, 4, false),
+                (AccessOp::Evict, 4, false),
+                (AccessOp::Get, 0, false),
+                (AccessOp::Remove, 0, false),
+            ],
+            ops
+        );
+        assert_eq!(records[0].path, records[4].path);
+        assert_eq!(records[3].path, records[6].path);
+        assert!(records.windows(2).all(|pair| pair[0].at <= pair[1].at));
+
+        // b was evicted for want of 4 more bytes, and c was never got.
+        let recommendation = recommend(&records, 1.0);
+        assert_eq!(4, recommendation.gets);
+        assert_eq!(1, recommendation.hits);
+        assert_eq!(12, recommendation.working_set_bytes);
+        assert_eq!(12, recommendation.max_bytes);
+        assert!(recommendation.ttl.is_some());
+
+        assert!(AccessLog::parse(b"not a log").is_err());
+        let bytes = log.to_bytes();
+        assert!(AccessLog::parse(&bytes[..bytes.len() - 1]).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+//! Tar and zip archives of the files on a disk.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Component, Path, PathBuf};
+
+use crate::{FloppyDirEntry, FloppyDisk, FloppyMetadata, FloppyReadDir};
+
+#[cfg(feature = "tar")]
+mod tar;
+#[cfg(feature = "zip")]
+mod zip;
+
+#[cfg(feature = "tar")]
+pub use self::tar::{export_tar, import_tar};
+#[cfg(feature = "zip")]
+pub use self::zip::{export_zip, import_zip, ZipOptions};
+
+/// `path` as a relative path, as long as it can't escape the directory it's
+/// relative to.
+fn contained_path(path: &Path) -> Result<PathBuf> {
+    let mut contained = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => contained.push(part),
+            Component::CurDir => {}
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("archive entry {} escapes the root", path.display()),
+                ))
+            }
+        }
+    }
+    Ok(contained)
+}
+
+/// Refuse to extract through a symlink from an earlier entry, which could
+/// point anywhere.
+async fn reject_symlinks<'a, D: FloppyDisk<'a>>(
+    disk: &D,
+    root: &Path,
+    relative: &Path,
+) -> Result<()> {
+    let mut path = root.to_path_buf();
+    for part in relative.iter() {
+        path.push(part);
+        match disk.symlink_metadata(&path).await {
+            Ok(metadata) if metadata.is_symlink() => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "archive entry {} goes through a symlink",
+                        relative.display()
+                    ),
+                ))
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// The entries in `dir`, in reverse order so they pop off a stack sorted.
+async fn sorted_children<'a, D: FloppyDisk<'a>>(disk: &D, dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    let mut read_dir = disk.read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        paths.push(entry.path());
+    }
+    paths.sort_by(|a, b| b.cmp(a));
+    Ok(paths)
+}
@@ -0,0 +1,336 @@
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use futures::StreamExt;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio_tar::{Archive, Builder, EntryType, Header};
+use tracing::debug;
+
+use super::{contained_path, reject_symlinks, sorted_children};
+use crate::{
+    FloppyDisk, FloppyDiskUnixExt, FloppyFile, FloppyMetadata, FloppyOpenOptions,
+    FloppyUnixMetadata, FloppyUnixPermissions,
+};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Write everything under `root` on `disk` to `writer` as a tar archive.
+/// Entry paths are relative to `root`, and keep their permissions, owner,
+/// modification time, and symlink targets.
+///
+/// Entries are written in sorted order, parents before children.
+pub async fn export_tar<'a, D, W>(
+    disk: &'a D,
+    root: impl AsRef<Path> + Send,
+    writer: W,
+) -> Result<()>
+where
+    D: FloppyDisk<'a>,
+    D::Metadata: FloppyUnixMetadata,
+    D::Permissions: FloppyUnixPermissions,
+    W: AsyncWrite + Unpin + Send,
+{
+    let root = root.as_ref();
+    let mut builder = Builder::new_non_terminated(writer);
+    let mut stack = sorted_children(disk, root).await?;
+    while let Some(path) = stack.pop() {
+        let name = path.strip_prefix(root).unwrap_or(&path);
+        let metadata = disk.symlink_metadata(&path).await?;
+        let mut header = Header::new_gnu();
+        header.set_mode(metadata.permissions().mode() & 0o7777);
+        header.set_uid(metadata.uid()? as u64);
+        header.set_gid(metadata.gid()? as u64);
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_secs())
+            .unwrap_or(0);
+        header.set_mtime(mtime);
+
+        debug!("archiving {}", path.display());
+        if metadata.is_symlink() {
+            header.set_entry_type(EntryType::Symlink);
+            header.set_size(0);
+            header.set_link_name(disk.read_link(&path).await?)?;
+            builder.append_data(&mut header, name, &[][..]).await?;
+        } else if metadata.is_dir() {
+            header.set_entry_type(EntryType::Directory);
+            header.set_size(0);
+            builder.append_data(&mut header, name, &[][..]).await?;
+            stack.extend(sorted_children(disk, &path).await?);
+        } else {
+            header.set_entry_type(EntryType::Regular);
+            header.set_size(metadata.len());
+            let file = D::OpenOptions::new().read(true).open(disk, &path).await?;
+            builder.append_data(&mut header, name, file).await?;
+        }
+    }
+
+    let mut writer = builder.into_inner().await?;
+    writer.flush().await
+}
+
+/// Extract the tar archive in `reader` into `root` on `disk`. Plain, gzipped,
+/// and zstd-compressed archives are all accepted.
+///
+/// Modes and owners are applied from the archive. Changing owners needs
+/// privileges most processes don't have, so a permission error from that is
+/// ignored, the same as `tar` does for unprivileged users. Entries whose path
+/// or hard link target would end up outside of `root` are rejected.
+pub async fn import_tar<'a, D, R>(
+    disk: &'a D,
+    root: impl AsRef<Path> + Send,
+    reader: R,
+) -> Result<()>
+where
+    D: FloppyDisk<'a> + FloppyDiskUnixExt,
+    D::Permissions: FloppyUnixPermissions,
+    R: AsyncRead + Unpin + Send,
+{
+    let root = root.as_ref();
+    let mut reader = BufReader::new(reader);
+    let magic = reader.fill_buf().await?;
+    let reader: Box<dyn AsyncRead + Unpin + Send + '_> = if magic.starts_with(GZIP_MAGIC) {
+        let mut decoder = GzipDecoder::new(reader);
+        decoder.multiple_members(true);
+        Box::new(decoder)
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Box::new(ZstdDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+
+    disk.create_dir_all(root).await?;
+    let mut archive = Archive::new(reader);
+    let mut entries = archive.entries()?;
+    // Directory modes are applied last, so a read-only directory doesn't stop
+    // its own contents from being extracted.
+    let mut dir_modes = vec![];
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+        let header = entry.header().clone();
+        let relative = contained_path(&entry.path()?)?;
+        reject_symlinks(disk, root, &relative).await?;
+        let path = root.join(relative);
+        debug!("extracting {}", path.display());
+
+        if let Some(parent) = path.parent() {
+            disk.create_dir_all(parent).await?;
+        }
+        match header.entry_type() {
+            EntryType::Directory => {
+                disk.create_dir_all(&path).await?;
+                dir_modes.push((path.clone(), header.mode()?));
+            }
+            EntryType::Regular | EntryType::Continuous => {
+                let mut file = D::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(disk, &path)
+                    .await?;
+                tokio::io::copy(&mut entry, &mut file).await?;
+                file.close().await?;
+                disk.set_permissions(&path, FloppyUnixPermissions::from_mode(header.mode()?))
+                    .await?;
+            }
+            EntryType::Symlink => {
+                let target = link_name(&entry)?;
+                disk.symlink(target, path.clone()).await?;
+                // Symlinks don't have modes of their own.
+                continue;
+            }
+            EntryType::Link => {
+                let target = root.join(contained_path(&link_name(&entry)?)?);
+                match disk.hard_link(target.clone(), path.clone()).await {
+                    // The best a disk without hard links can do is a copy,
+                    // which is what `tar` does on filesystems without them.
+                    Err(e) if e.kind() == ErrorKind::Unsupported => {
+                        debug!("copying {} for a hard link: {e}", target.display());
+                        disk.copy(target, path.clone()).await?;
+                    }
+                    result => result?,
+                }
+                continue;
+            }
+            entry_type => {
+                debug!("skipping {} of type {entry_type:?}", path.display());
+                continue;
+            }
+        }
+
+        match disk
+            .chown(path.clone(), header.uid()? as u32, header.gid()? as u32)
+            .await
+        {
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                debug!("couldn't chown {}: {e}", path.display());
+            }
+            result => result?,
+        }
+    }
+
+    // Children before parents.
+    for (path, mode) in dir_modes.into_iter().rev() {
+        disk.set_permissions(&path, FloppyUnixPermissions::from_mode(mode))
+            .await?;
+    }
+    Ok(())
+}
+
+fn link_name<R: AsyncRead + Unpin>(entry: &tokio_tar::Entry<R>) -> Result<PathBuf> {
+    entry
+        .link_name()?
+        .map(|link_name| link_name.into_owned())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "link entry without a target"))
+}
+
+#[cfg(test)]
+mod tests {
+    use async_compression::tokio::write::GzipEncoder;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_export_tar() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root/a/b").await?;
+        fs.write("/root/a/b/c.txt", "asdf").await?;
+        fs.set_permissions("/root/a/b/c.txt", FloppyUnixPermissions::from_mode(0o600))
+            .await?;
+        fs.chown("/root/a/b/c.txt", 1000, 1001).await?;
+        fs.write("/root/d.txt", "jkl").await?;
+        fs.symlink("a/b/c.txt", "/root/e").await?;
+
+        let mut tar = vec![];
+        export_tar(&fs, "/root", &mut tar).await?;
+
+        let mut archive = Archive::new(&tar[..]);
+        let mut entries = archive.entries()?;
+        let mut seen = vec![];
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry?;
+            let path = entry.path()?.display().to_string();
+            let header = entry.header().clone();
+            match path.as_str() {
+                "a/b/c.txt" => {
+                    let mut contents = String::new();
+                    entry.read_to_string(&mut contents).await?;
+                    assert_eq!("asdf", contents);
+                    assert_eq!(0o600, header.mode()?);
+                    assert_eq!((1000, 1001), (header.uid()?, header.gid()?));
+                }
+                "e" => {
+                    assert_eq!(EntryType::Symlink, header.entry_type());
+                    assert_eq!("a/b/c.txt", header.link_name()?.unwrap().to_str().unwrap());
+                }
+                _ => {}
+            }
+            seen.push(path);
+        }
+        assert_eq!(vec!["a", "a/b", "a/b/c.txt", "d.txt", "e"], seen);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_tar() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root/a/b").await?;
+        fs.write("/root/a/b/c.txt", "asdf").await?;
+        fs.set_permissions("/root/a/b/c.txt", FloppyUnixPermissions::from_mode(0o600))
+            .await?;
+        fs.chown("/root/a/b/c.txt", 1000, 1001).await?;
+        fs.symlink("a/b/c.txt", "/root/e").await?;
+
+        let mut tar = GzipEncoder::new(vec![]);
+        export_tar(&fs, "/root", &mut tar).await?;
+        tar.shutdown().await?;
+        let tar = tar.into_inner();
+        assert!(tar.starts_with(GZIP_MAGIC));
+
+        let imported = MemFloppyDisk::new();
+        import_tar(&imported, "/out", &tar[..]).await?;
+        assert_eq!("asdf", imported.read_to_string("/out/a/b/c.txt").await?);
+        assert_eq!("asdf", imported.read_to_string("/out/e").await?);
+        let metadata = imported.metadata("/out/a/b/c.txt").await?;
+        assert_eq!(0o600, metadata.permissions().mode() & 0o7777);
+        assert_eq!((1000, 1001), (metadata.uid()?, metadata.gid()?));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_tar_hard_link() -> Result<()> {
+        let mut builder = Builder::new_non_terminated(vec![]);
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        builder
+            .append_data(&mut header, "a.txt", &b"asdf"[..])
+            .await?;
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Link);
+        header.set_size(0);
+        header.set_link_name("a.txt")?;
+        builder.append_data(&mut header, "b.txt", &[][..]).await?;
+        let tar = builder.into_inner().await?;
+
+        let fs = MemFloppyDisk::new();
+        import_tar(&fs, "/out", &tar[..]).await?;
+        assert_eq!("asdf", fs.read_to_string("/out/b.txt").await?);
+        fs.write("/out/a.txt", "jkl").await?;
+        assert_eq!("jkl", fs.read_to_string("/out/b.txt").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_tar_traversal() -> Result<()> {
+        let mut header = Header::new_gnu();
+        let name = b"../evil.txt";
+        header.as_old_mut().name[..name.len()].copy_from_slice(name);
+        header.set_size(4);
+        header.set_entry_type(EntryType::Regular);
+        header.set_cksum();
+        let mut builder = Builder::new_non_terminated(vec![]);
+        builder.append(&header, &b"evil"[..]).await?;
+        let tar = builder.into_inner().await?;
+
+        let fs = MemFloppyDisk::new();
+        let err = import_tar(&fs, "/out", &tar[..]).await.unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+        assert!(!fs.try_exists("/evil.txt").await?);
+
+        let mut builder = Builder::new_non_terminated(vec![]);
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        header.set_link_name("/etc")?;
+        builder.append_data(&mut header, "etc", &[][..]).await?;
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(4);
+        builder
+            .append_data(&mut header, "etc/passwd", &b"evil"[..])
+            .await?;
+        let tar = builder.into_inner().await?;
+
+        fs.create_dir("/etc").await?;
+        let err = import_tar(&fs, "/out", &tar[..]).await.unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+        assert!(!fs.try_exists("/etc/passwd").await?);
+
+        Ok(())
+    }
+}
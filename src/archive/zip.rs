@@ -0,0 +1,245 @@
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use async_zip::base::read::seek::ZipFileReader;
+use async_zip::base::write::ZipFileWriter;
+use async_zip::error::ZipError;
+use async_zip::{Compression, ZipEntryBuilder};
+use futures::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::io::{AsyncBufRead, AsyncReadExt, AsyncSeek, AsyncWrite, AsyncWriteExt};
+use tracing::debug;
+
+use super::{contained_path, reject_symlinks, sorted_children};
+use crate::{FloppyDisk, FloppyFile, FloppyMetadata, FloppyOpenOptions, FloppyUnixPermissions};
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFREG: u32 = 0o100000;
+
+/// How [`export_zip`] and [`import_zip`] treat the parts of a disk that plain
+/// zip files can't describe.
+#[derive(Debug, Clone, Default)]
+pub struct ZipOptions {
+    unix_modes: bool,
+}
+
+impl ZipOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep unix modes and symlinks in the entries' external attributes, the
+    /// way Info-ZIP does. Without this, symlinks are skipped on export and
+    /// modes are ignored on import.
+    pub fn with_unix_modes(mut self, unix_modes: bool) -> Self {
+        self.unix_modes = unix_modes;
+        self
+    }
+}
+
+/// Write everything under `root` on `disk` to `writer` as a zip archive.
+/// Files are deflated as they're read, so nothing is buffered whole.
+pub async fn export_zip<'a, D, W>(
+    disk: &'a D,
+    root: impl AsRef<Path> + Send,
+    writer: W,
+    options: &ZipOptions,
+) -> Result<()>
+where
+    D: FloppyDisk<'a>,
+    D::Permissions: FloppyUnixPermissions,
+    W: AsyncWrite + Unpin + Send,
+{
+    let root = root.as_ref();
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    let mut stack = sorted_children(disk, root).await?;
+    let mut buf = vec![0; 64 * 1024];
+    while let Some(path) = stack.pop() {
+        let name = path
+            .strip_prefix(root)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .into_owned();
+        let metadata = disk.symlink_metadata(&path).await?;
+        let mode = metadata.permissions().mode() & 0o7777;
+
+        debug!("zipping {}", path.display());
+        if metadata.is_symlink() {
+            if !options.unix_modes {
+                debug!("skipping symlink {}", path.display());
+                continue;
+            }
+            let target = disk.read_link(&path).await?;
+            let entry = ZipEntryBuilder::new(name.into(), Compression::Stored)
+                .unix_permissions((S_IFLNK | 0o777) as u16);
+            zip.write_entry_whole(entry, target.to_string_lossy().as_bytes())
+                .await
+                .map_err(zip_error)?;
+        } else if metadata.is_dir() {
+            let mut entry = ZipEntryBuilder::new(format!("{name}/").into(), Compression::Stored);
+            if options.unix_modes {
+                entry = entry.unix_permissions((S_IFDIR | mode) as u16);
+            }
+            zip.write_entry_whole(entry, &[]).await.map_err(zip_error)?;
+            stack.extend(sorted_children(disk, &path).await?);
+        } else {
+            let mut entry = ZipEntryBuilder::new(name.into(), Compression::Deflate);
+            if options.unix_modes {
+                entry = entry.unix_permissions((S_IFREG | mode) as u16);
+            }
+            let mut file = D::OpenOptions::new().read(true).open(disk, &path).await?;
+            let mut writer = zip.write_entry_stream(entry).await.map_err(zip_error)?;
+            loop {
+                let n = file.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                writer.write_all(&buf[..n]).await?;
+            }
+            writer.close().await.map_err(zip_error)?;
+        }
+    }
+
+    let mut writer = zip.close().await.map_err(zip_error)?.into_inner();
+    writer.flush().await
+}
+
+/// Extract the zip archive in `reader` into `root` on `disk`. Entries whose
+/// path would end up outside of `root` are rejected.
+///
+/// Unix modes are only kept in a zip's central directory, at the end of the
+/// archive, so `reader` has to be seekable. Each entry is still streamed.
+pub async fn import_zip<'a, D, R>(
+    disk: &'a D,
+    root: impl AsRef<Path> + Send,
+    reader: R,
+    options: &ZipOptions,
+) -> Result<()>
+where
+    D: FloppyDisk<'a>,
+    D::Permissions: FloppyUnixPermissions,
+    R: AsyncBufRead + AsyncSeek + Unpin + Send,
+{
+    let root = root.as_ref();
+    disk.create_dir_all(root).await?;
+    let mut zip = ZipFileReader::with_tokio(reader).await.map_err(zip_error)?;
+    // Directory modes are applied last, so a read-only directory doesn't stop
+    // its own contents from being extracted.
+    let mut dir_modes = vec![];
+    let mut buf = vec![0; 64 * 1024];
+    for index in 0..zip.file().entries().len() {
+        let entry = &zip.file().entries()[index];
+        let name = entry.filename().as_str().map_err(zip_error)?.to_string();
+        let is_dir = entry.dir().map_err(zip_error)?;
+        let mode = entry
+            .unix_permissions()
+            .filter(|_| options.unix_modes)
+            .map(u32::from);
+
+        let relative = contained_path(Path::new(&name))?;
+        reject_symlinks(disk, root, &relative).await?;
+        let path = root.join(relative);
+        debug!("extracting {}", path.display());
+        if let Some(parent) = path.parent() {
+            disk.create_dir_all(parent).await?;
+        }
+
+        if is_dir || mode.is_some_and(|mode| mode & S_IFMT == S_IFDIR) {
+            disk.create_dir_all(&path).await?;
+            if let Some(mode) = mode {
+                dir_modes.push((path, mode & 0o7777));
+            }
+        } else if mode.is_some_and(|mode| mode & S_IFMT == S_IFLNK) {
+            let mut target = String::new();
+            zip.reader_with_entry(index)
+                .await
+                .map_err(zip_error)?
+                .read_to_string_checked(&mut target)
+                .await
+                .map_err(zip_error)?;
+            disk.symlink(Path::new(&target), &path).await?;
+        } else {
+            let mut file = D::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(disk, &path)
+                .await?;
+            let mut reader = zip.reader_without_entry(index).await.map_err(zip_error)?;
+            loop {
+                let n = reader.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                file.write_all(&buf[..n]).await?;
+            }
+            file.close().await?;
+            if let Some(mode) = mode {
+                disk.set_permissions(&path, FloppyUnixPermissions::from_mode(mode & 0o7777))
+                    .await?;
+            }
+        }
+    }
+
+    // Children before parents.
+    for (path, mode) in dir_modes.into_iter().rev() {
+        disk.set_permissions(&path, FloppyUnixPermissions::from_mode(mode))
+            .await?;
+    }
+    Ok(())
+}
+
+fn zip_error(e: ZipError) -> Error {
+    match e {
+        ZipError::UpstreamReadError(e) => e,
+        e => Error::new(ErrorKind::InvalidData, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_zip_round_trip() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root/a/b").await?;
+        fs.write("/root/a/b/c.txt", "asdf".repeat(100_000)).await?;
+        fs.set_permissions("/root/a/b/c.txt", FloppyUnixPermissions::from_mode(0o600))
+            .await?;
+        fs.write("/root/d.txt", "jkl").await?;
+        fs.symlink("a/b/c.txt", "/root/e").await?;
+
+        let options = ZipOptions::new().with_unix_modes(true);
+        let mut zip = vec![];
+        export_zip(&fs, "/root", &mut zip, &options).await?;
+        assert!(zip.len() < 100_000);
+
+        let imported = MemFloppyDisk::new();
+        import_zip(&imported, "/out", Cursor::new(&zip), &options).await?;
+        assert_eq!(
+            "asdf".repeat(100_000),
+            imported.read_to_string("/out/a/b/c.txt").await?
+        );
+        assert_eq!("jkl", imported.read_to_string("/out/d.txt").await?);
+        assert_eq!(
+            "a/b/c.txt",
+            imported.read_link("/out/e").await?.to_str().unwrap()
+        );
+        let metadata = imported.metadata("/out/a/b/c.txt").await?;
+        assert_eq!(0o600, metadata.permissions().mode() & 0o7777);
+
+        let mut zip = vec![];
+        export_zip(&fs, "/root", &mut zip, &ZipOptions::new()).await?;
+        let imported = MemFloppyDisk::new();
+        import_zip(&imported, "/out", Cursor::new(&zip), &ZipOptions::new()).await?;
+        assert!(imported.try_exists("/out/d.txt").await?);
+        assert!(!imported.try_exists("/out/e").await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,683 @@
+//! A store for build artifacts and the like, on any disk. Artifacts are
+//! looked up by key, usually a hash of whatever they were built from.
+//!
+//! Under the store's root, each artifact is kept at `objects/ab/abcd...`,
+//! named by the sha256 of its key, with the time it was last used in a
+//! `.used` file next to it, since not every backend keeps access times.
+//! Artifacts are written to `tmp/` first and renamed into place, so readers
+//! never see partial ones. Writers of the same key take turns, using
+//! lockfiles in `locks/` that name the writer holding them and when it last
+//! refreshed them. Holders refresh their locks while they work, so only the
+//! locks of writers that died go stale.
+//!
+//! A store can be told to carry on without its disk when the disk fails,
+//! building artifacts as if none were cached; see
+//! [`CacheOptions::with_failure_policy`]. Either way, the store reports when
+//! its disk starts and stops failing.
+
+use std::future::Future;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::future::Either;
+use tokio::sync::broadcast;
+use tracing::debug;
+
+use crate::access_log::{AccessLog, AccessOp};
+use crate::hash::HashAlgorithm;
+use crate::walk::FloppyWalkDir;
+use crate::{FloppyDisk, FloppyFile, FloppyMetadata, FloppyOpenOptions};
+
+const USED_SUFFIX: &str = ".used";
+
+#[derive(Debug, Clone)]
+pub struct CacheOptions {
+    max_bytes: Option<u64>,
+    lock_timeout: Duration,
+    access_log: Option<Arc<AccessLog>>,
+    failure_policy: FailurePolicy,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: None,
+            lock_timeout: Duration::from_secs(60),
+            access_log: None,
+            failure_policy: FailurePolicy::Fail,
+        }
+    }
+}
+
+impl CacheOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Evict the least recently used artifacts whenever the store grows past
+    /// `max_bytes`.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// How old another writer's lock has to be before it's assumed to have
+    /// died, and the lock is taken from it.
+    pub fn with_lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.lock_timeout = lock_timeout;
+        self
+    }
+
+    /// Log every get, put, removal and eviction to `access_log`, for tuning
+    /// the store with [`access_log::recommend`](crate::access_log::recommend).
+    pub fn with_access_log(mut self, access_log: Arc<AccessLog>) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /// What to do when the store's disk fails.
+    pub fn with_failure_policy(mut self, failure_policy: FailurePolicy) -> Self {
+        self.failure_policy = failure_policy;
+        self
+    }
+}
+
+/// What a [`CacheStore`] does when its disk fails during a get, put or
+/// [`CacheStore::get_or_insert_with`]. Removing and evicting always fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Return the disk's error.
+    Fail,
+    /// Carry on as if the store were empty: gets miss, puts are dropped, and
+    /// artifacts are built but not kept.
+    Bypass,
+}
+
+/// Sent when a [`CacheStore`]'s disk starts or stops failing. See
+/// [`CacheStore::subscribe`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheHealth {
+    /// An operation on the disk failed after the last one succeeded.
+    Degraded { error: String },
+    /// An operation on the disk succeeded after the last one failed.
+    Recovered,
+}
+
+#[derive(Debug)]
+pub struct CacheStore<'a, D: FloppyDisk<'a>> {
+    disk: &'a D,
+    root: PathBuf,
+    options: CacheOptions,
+    degraded: AtomicBool,
+    health: broadcast::Sender<CacheHealth>,
+}
+
+impl<'a, D: FloppyDisk<'a>> CacheStore<'a, D> {
+    /// Open the store at `root`, creating it if it doesn't exist.
+    pub async fn open(
+        disk: &'a D,
+        root: impl AsRef<Path> + Send,
+        options: CacheOptions,
+    ) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        for dir in ["objects", "locks", "tmp"] {
+            disk.create_dir_all(root.join(dir)).await?;
+        }
+        Ok(Self {
+            disk,
+            root,
+            options,
+            degraded: AtomicBool::new(false),
+            health: broadcast::channel(16).0,
+        })
+    }
+
+    /// Whether the last operation on the disk failed.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    /// Be told when the disk starts or stops failing.
+    pub fn subscribe(&self) -> broadcast::Receiver<CacheHealth> {
+        self.health.subscribe()
+    }
+
+    pub async fn get(&self, key: impl AsRef<[u8]>) -> Result<Option<Vec<u8>>> {
+        let path = self.object_path(&key_hash(key.as_ref()));
+        let read = self.read(&path).await;
+        let data = self.bypass(read, None)?;
+        let size = data.as_ref().map_or(0, |data| data.len() as u64);
+        self.log(AccessOp::Get, path, size, data.is_some());
+        Ok(data)
+    }
+
+    async fn read(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+        match self.disk.read(path).await {
+            Ok(data) => {
+                self.touch(path).await?;
+                Ok(Some(data))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn put(&self, key: impl AsRef<[u8]>, data: impl AsRef<[u8]>) -> Result<()> {
+        let put = self
+            .put_locked(&key_hash(key.as_ref()), data.as_ref())
+            .await;
+        self.bypass(put, ())
+    }
+
+    async fn put_locked(&self, hash: &str, data: &[u8]) -> Result<()> {
+        let lock = self.lock(hash).await?;
+        let result = self.holding(&lock, self.publish(hash, data)).await;
+        self.unlock(lock).await?;
+        result?;
+        self.evict().await?;
+        Ok(())
+    }
+
+    /// The artifact for `key`, built with `build` if there isn't one yet.
+    /// Only one writer builds a given key at a time; the others wait, and
+    /// then use what it built.
+    pub async fn get_or_insert_with<F, Fut>(
+        &self,
+        key: impl AsRef<[u8]>,
+        build: F,
+    ) -> Result<Vec<u8>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<u8>>>,
+    {
+        let key = key.as_ref();
+        if let Some(data) = self.get(key).await? {
+            return Ok(data);
+        }
+        let hash = key_hash(key);
+        let locked = self.lock(&hash).await.map(Some);
+        let Some(lock) = self.bypass(locked, None)? else {
+            return build().await;
+        };
+        let result = self.holding(&lock, self.build_locked(&hash, build)).await;
+        let unlocked = self.unlock(lock).await;
+        self.bypass(unlocked, ())?;
+        let data = result?;
+        let evicted = self.evict().await;
+        self.bypass(evicted, 0)?;
+        Ok(data)
+    }
+
+    async fn build_locked<F, Fut>(&self, hash: &str, build: F) -> Result<Vec<u8>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<u8>>>,
+    {
+        // Whoever had the lock before may have built it already.
+        let read = self.read(&self.object_path(hash)).await;
+        if let Some(data) = self.bypass(read, None)? {
+            return Ok(data);
+        }
+        let data = build().await?;
+        let published = self.publish(hash, &data).await;
+        self.bypass(published, ())?;
+        Ok(data)
+    }
+
+    pub async fn remove(&self, key: impl AsRef<[u8]>) -> Result<()> {
+        let path = self.object_path(&key_hash(key.as_ref()));
+        for path in [used_path(&path), path.clone()] {
+            match self.disk.remove_file(path).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        self.log(AccessOp::Remove, path, 0, false);
+        Ok(())
+    }
+
+    /// Remove the least recently used artifacts until the store fits in
+    /// [`CacheOptions::with_max_bytes`]. Returns the number of bytes freed.
+    pub async fn evict(&self) -> Result<u64> {
+        let Some(max_bytes) = self.options.max_bytes else {
+            return Ok(0);
+        };
+        let mut objects = vec![];
+        let mut total = 0;
+        let mut walk = FloppyWalkDir::new(self.disk, self.root.join("objects"));
+        while let Some(entry) = walk.next_entry().await? {
+            let path = entry.path();
+            if !entry.metadata().is_file() || path.to_string_lossy().ends_with(USED_SUFFIX) {
+                continue;
+            }
+            let len = entry.metadata().len();
+            total += len;
+            objects.push((self.last_used(path).await?, len, entry.into_path()));
+        }
+
+        objects.sort();
+        let mut freed = 0;
+        for (_, len, path) in objects {
+            if total - freed <= max_bytes {
+                break;
+            }
+            debug!("evicting {}", path.display());
+            self.disk.remove_file(&path).await?;
+            match self.disk.remove_file(used_path(&path)).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            freed += len;
+            self.log(AccessOp::Evict, path, len, false);
+        }
+        Ok(freed)
+    }
+
+    async fn publish(&self, hash: &str, data: &[u8]) -> Result<()> {
+        let tmp = self
+            .root
+            .join("tmp")
+            .join(format!("{hash}.{}", rand::random::<u64>()));
+        self.disk.write(&tmp, data).await?;
+        let path = self.object_path(hash);
+        self.disk.create_dir_all(path.parent().unwrap()).await?;
+        if let Err(e) = self.disk.rename(&tmp, &path).await {
+            let _ = self.disk.remove_file(&tmp).await;
+            return Err(e);
+        }
+        self.touch(&path).await?;
+        self.log(AccessOp::Put, path, data.len() as u64, false);
+        Ok(())
+    }
+
+    /// Note how an operation on the disk went, and with
+    /// [`FailurePolicy::Bypass`], swap its error for `fallback`.
+    fn bypass<T>(&self, result: Result<T>, fallback: T) -> Result<T> {
+        match result {
+            Ok(value) => {
+                if self.degraded.swap(false, Ordering::Relaxed) {
+                    debug!("cache store at {} recovered", self.root.display());
+                    let _ = self.health.send(CacheHealth::Recovered);
+                }
+                Ok(value)
+            }
+            Err(e) => {
+                if !self.degraded.swap(true, Ordering::Relaxed) {
+                    debug!("cache store at {} degraded: {e}", self.root.display());
+                    let _ = self.health.send(CacheHealth::Degraded {
+                        error: e.to_string(),
+                    });
+                }
+                match self.options.failure_policy {
+                    FailurePolicy::Fail => Err(e),
+                    FailurePolicy::Bypass => Ok(fallback),
+                }
+            }
+        }
+    }
+
+    fn log(&self, op: AccessOp, path: PathBuf, size: u64, hit: bool) {
+        if let Some(access_log) = &self.options.access_log {
+            access_log.record(op, path, size, hit);
+        }
+    }
+
+    /// Take the lock for `hash`, waiting for whoever has it, unless their
+    /// lock has gone stale.
+    async fn lock(&self, hash: &str) -> Result<Lock> {
+        let lock = Lock {
+            path: self.root.join("locks").join(hash),
+            token: format!("{}.{:016x}", std::process::id(), rand::random::<u64>()),
+        };
+        loop {
+            let created = D::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.disk, &lock.path)
+                .await;
+            match created {
+                Ok(file) => {
+                    if let Err(e) = lock.write(file).await {
+                        let _ = self.disk.remove_file(&lock.path).await;
+                        return Err(e);
+                    }
+                    return Ok(lock);
+                }
+                Err(e) if e.kind() != ErrorKind::AlreadyExists => return Err(e),
+                Err(_) => match self.stale_contents(&lock.path).await? {
+                    Some(stale) => self.break_lock(&lock.path, &stale).await?,
+                    None => tokio::time::sleep(Duration::from_millis(10)).await,
+                },
+            }
+        }
+    }
+
+    /// What the lockfile at `path` holds, if it hasn't been refreshed within
+    /// the lock timeout. One that's gone already isn't stale, just released.
+    async fn stale_contents(&self, path: &Path) -> Result<Option<String>> {
+        let (modified, contents) = match self.disk.metadata(path).await {
+            Ok(metadata) => (metadata.modified()?, self.disk.read_to_string(path).await),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let contents = match contents {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        Ok((age >= self.options.lock_timeout).then_some(contents))
+    }
+
+    /// Break the lock at `path`, if it still holds the `stale` contents.
+    ///
+    /// Two waiters can both find the same lock stale. The lock is moved
+    /// aside to a name of this waiter's own before it's looked at again, so
+    /// only one of them can break it. If the other has already broken it
+    /// and taken a fresh lock, that lock is what was moved, and it's put
+    /// back.
+    async fn break_lock(&self, path: &Path, stale: &str) -> Result<()> {
+        let mut tombstone = path.as_os_str().to_owned();
+        tombstone.push(format!(".{:016x}.stale", rand::random::<u64>()));
+        let tombstone = PathBuf::from(tombstone);
+        match self.disk.rename(path, tombstone.as_path()).await {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            result => result?,
+        }
+        if self.disk.read_to_string(&tombstone).await? == stale {
+            debug!("broke stale lock {}", path.display());
+            self.disk.remove_file(&tombstone).await
+        } else {
+            debug!("lock {} was taken while breaking it", path.display());
+            self.disk.rename(tombstone.as_path(), path).await
+        }
+    }
+
+    /// Run `work` while holding `lock`, refreshing the lock often enough
+    /// that it doesn't go stale however long `work` takes.
+    async fn holding<T>(&self, lock: &Lock, work: impl Future<Output = T>) -> T {
+        let work = std::pin::pin!(work);
+        let refreshing = std::pin::pin!(async {
+            loop {
+                let interval = self.options.lock_timeout / 3;
+                tokio::time::sleep(interval.max(Duration::from_millis(1))).await;
+                match self.refresh(lock).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        debug!("lock {} was taken while held", lock.path.display());
+                        return;
+                    }
+                    Err(e) => debug!("failed to refresh lock {}: {e}", lock.path.display()),
+                }
+            }
+        });
+        match futures::future::select(work, refreshing).await {
+            Either::Left((value, _)) => value,
+            Either::Right(((), work)) => work.await,
+        }
+    }
+
+    /// Rewrite `lock` with the current time, unless someone else has it.
+    async fn refresh(&self, lock: &Lock) -> Result<bool> {
+        match self.disk.read_to_string(&lock.path).await {
+            Ok(contents) if contents.lines().next() == Some(&lock.token[..]) => {
+                let file = D::OpenOptions::new()
+                    .write(true)
+                    .truncate(true)
+                    .open(self.disk, &lock.path)
+                    .await?;
+                lock.write(file).await?;
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Give `lock` back, unless it went stale and someone else took it.
+    async fn unlock(&self, lock: Lock) -> Result<()> {
+        match self.disk.read_to_string(&lock.path).await {
+            Ok(contents) if contents.lines().next() == Some(&lock.token[..]) => {
+                self.disk.remove_file(&lock.path).await
+            }
+            Ok(_) => {
+                debug!("lock {} was taken while held", lock.path.display());
+                Ok(())
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn touch(&self, path: &Path) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        self.disk
+            .write(used_path(path), now.as_nanos().to_string())
+            .await
+    }
+
+    async fn last_used(&self, path: &Path) -> Result<u128> {
+        match self.disk.read_to_string(used_path(path)).await {
+            // Anything unreadable is treated as long unused.
+            Ok(used) => Ok(used.trim().parse().unwrap_or(0)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.root.join("objects").join(&hash[..2]).join(hash)
+    }
+}
+
+/// A lock on a key, held by whoever wrote `token` into the lockfile at
+/// `path`, followed by when they last refreshed it.
+#[derive(Debug)]
+struct Lock {
+    path: PathBuf,
+    token: String,
+}
+
+impl Lock {
+    async fn write<'a, F: FloppyFile<'a, D>, D: FloppyDisk<'a>>(&self, mut file: F) -> Result<()> {
+        let taken = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let contents = format!("{}\n{}\n", self.token, taken.as_nanos());
+        tokio::io::AsyncWriteExt::write_all(&mut file, contents.as_bytes()).await?;
+        file.close().await
+    }
+}
+
+fn key_hash(key: &[u8]) -> String {
+    let mut hasher = HashAlgorithm::Sha256.hasher();
+    hasher.update(key);
+    hasher.finish()
+}
+
+fn used_path(path: &Path) -> PathBuf {
+    let mut used = path.as_os_str().to_owned();
+    used.push(USED_SUFFIX);
+    PathBuf::from(used)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+    use crate::FloppyReadDir;
+
+    #[tokio::test]
+    async fn test_cache_store() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        let options = CacheOptions::new().with_max_bytes(8);
+        let store = CacheStore::open(&fs, "/cache", options).await?;
+        assert_eq!(None, store.get("a").await?);
+
+        let built = Arc::new(AtomicUsize::new(0));
+        for _ in 0..2 {
+            let built = built.clone();
+            let data = store
+                .get_or_insert_with("a", || async move {
+                    built.fetch_add(1, Ordering::SeqCst);
+                    Ok(b"aaaa".to_vec())
+                })
+                .await?;
+            assert_eq!(b"aaaa", &data[..]);
+        }
+        assert_eq!(1, built.load(Ordering::SeqCst));
+
+        // Using `a` makes `b` the least recently used, so it goes first.
+        store.put("b", "bbbb").await?;
+        store.get("a").await?;
+        store.put("c", "cccc").await?;
+        assert_eq!(None, store.get("b").await?);
+        assert_eq!(Some(b"aaaa".to_vec()), store.get("a").await?);
+        assert_eq!(Some(b"cccc".to_vec()), store.get("c").await?);
+
+        store.remove("a").await?;
+        assert_eq!(None, store.get("a").await?);
+        let mut tmp = fs.read_dir("/cache/tmp").await?;
+        assert!(tmp.next_entry().await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failure_policy() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        let options = CacheOptions::new().with_failure_policy(FailurePolicy::Bypass);
+        let bypassing = CacheStore::open(&fs, "/cache", options).await?;
+        let failing = CacheStore::open(&fs, "/cache", CacheOptions::new()).await?;
+        let mut health = bypassing.subscribe();
+        bypassing.put("a", "aaaa").await?;
+
+        // Without anywhere to take locks, nothing can be written.
+        fs.remove_dir("/cache/locks").await?;
+        fs.write("/cache/locks", "").await?;
+        bypassing.put("b", "bbbb").await?;
+        let data = bypassing
+            .get_or_insert_with("b", || async { Ok(b"bbbb".to_vec()) })
+            .await?;
+        assert_eq!(b"bbbb", &data[..]);
+        assert!(bypassing.is_degraded());
+        // Reading succeeds on the way, and taking the lock fails again.
+        let events: Vec<_> = std::iter::from_fn(|| health.try_recv().ok()).collect();
+        assert!(matches!(
+            &events[..],
+            [
+                CacheHealth::Degraded { .. },
+                CacheHealth::Recovered,
+                CacheHealth::Degraded { .. },
+            ]
+        ));
+        assert!(failing.put("b", "bbbb").await.is_err());
+        assert!(failing.is_degraded());
+
+        assert_eq!(Some(b"aaaa".to_vec()), bypassing.get("a").await?);
+        assert_eq!(Ok(CacheHealth::Recovered), health.try_recv());
+        assert_eq!(None, bypassing.get("b").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cache_store_stale_lock() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        let options = CacheOptions::new().with_lock_timeout(Duration::from_millis(20));
+        let store = CacheStore::open(&fs, "/cache", options).await?;
+        fs.write(format!("/cache/locks/{}", key_hash(b"a")), "")
+            .await?;
+        store.put("a", "aaaa").await?;
+        assert_eq!(Some(b"aaaa".to_vec()), store.get("a").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cache_store_lock_owner() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        let options = CacheOptions::new().with_lock_timeout(Duration::from_secs(3600));
+        let store = CacheStore::open(&fs, "/cache", options).await?;
+
+        // However long a waiter has waited, a fresh lock isn't broken.
+        let lock = store.lock("h").await?;
+        let waited = tokio::time::timeout(Duration::from_millis(50), store.lock("h")).await;
+        assert!(waited.is_err());
+        let contents = fs.read_to_string("/cache/locks/h").await?;
+        assert_eq!(Some(&lock.token[..]), contents.lines().next());
+
+        // A lock that was broken and taken by someone else is left to them.
+        fs.write("/cache/locks/h", "someone-else\n0\n").await?;
+        store.unlock(lock).await?;
+        assert!(fs.try_exists("/cache/locks/h").await?);
+        fs.remove_file("/cache/locks/h").await?;
+
+        let lock = store.lock("h").await?;
+        store.unlock(lock).await?;
+        assert!(!fs.try_exists("/cache/locks/h").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cache_store_break_lock_race() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        let store = CacheStore::open(&fs, "/cache", CacheOptions::new()).await?;
+
+        // Another waiter already broke the stale lock and took a fresh one,
+        // which this waiter mustn't break in turn.
+        fs.write("/cache/locks/h", "fresh\n1\n").await?;
+        store
+            .break_lock(Path::new("/cache/locks/h"), "stale\n0\n")
+            .await?;
+        assert_eq!("fresh\n1\n", fs.read_to_string("/cache/locks/h").await?);
+        let mut locks = fs.read_dir("/cache/locks").await?;
+        assert!(locks.next_entry().await?.is_some());
+        assert!(locks.next_entry().await?.is_none());
+
+        store
+            .break_lock(Path::new("/cache/locks/h"), "fresh\n1\n")
+            .await?;
+        assert!(!fs.try_exists("/cache/locks/h").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cache_store_long_build() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        let options = CacheOptions::new().with_lock_timeout(Duration::from_millis(60));
+        let store = CacheStore::open(&fs, "/cache", options).await?;
+        let builds = std::sync::atomic::AtomicUsize::new(0);
+        let build = || async {
+            builds.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Ok(b"built".to_vec())
+        };
+
+        // The first build outlasts the lock timeout, but its lock is kept
+        // fresh, so the second waiter waits for it instead of building too.
+        let second = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            store.get_or_insert_with("k", build).await
+        };
+        let (first, second) = tokio::join!(store.get_or_insert_with("k", build), second);
+        assert_eq!(b"built".to_vec(), first?);
+        assert_eq!(b"built".to_vec(), second?);
+        assert_eq!(1, builds.load(Ordering::SeqCst));
+
+        Ok(())
+    }
+}
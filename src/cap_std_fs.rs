@@ -0,0 +1,677 @@
+use std::ffi::OsString;
+use std::io::IoSlice;
+use std::path::Component;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use cap_std::fs::{
+    Dir, DirBuilder, DirBuilderExt, DirEntry, FileType, Metadata, MetadataExt, OpenOptions,
+    Permissions, PermissionsExt, ReadDir,
+};
+use tokio::fs::File;
+use tokio::io::ReadBuf;
+use tracing::debug;
+
+use crate::*;
+
+/// A [`FloppyDisk`] rooted at a directory handle, built on `cap_std`.
+///
+/// Unlike a scoped [`TokioFloppyDisk`](crate::tokio_fs::TokioFloppyDisk), the
+/// scope is enforced by the OS: every path is resolved relative to the
+/// directory with `openat`-style calls, so `..` and symlinks can't be used to
+/// escape it. Paths are presented as if the directory were `/`.
+#[derive(Debug)]
+pub struct CapStdFloppyDisk {
+    dir: Arc<Dir>,
+}
+
+impl CapStdFloppyDisk {
+    pub fn new(dir: Dir) -> Self {
+        Self { dir: Arc::new(dir) }
+    }
+
+    /// Open `path` on the host filesystem and use it as the root of the disk.
+    pub async fn open_ambient<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let dir =
+            blocking(move || Dir::open_ambient_dir(path, cap_std::ambient_authority())).await?;
+        Ok(Self::new(dir))
+    }
+
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Dir) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let dir = self.dir.clone();
+        blocking(move || f(&dir)).await
+    }
+}
+
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await?
+}
+
+/// Turn a path on the disk into one relative to the root directory. `Dir`
+/// rejects absolute paths, so the leading `/` is dropped.
+fn relative(path: &Path) -> PathBuf {
+    let path: PathBuf = path
+        .components()
+        .filter(|component| !matches!(component, Component::RootDir | Component::Prefix(_)))
+        .collect();
+    if path.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        path
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyDisk<'a> for CapStdFloppyDisk {
+    type DirBuilder = CapStdDirBuilder;
+    type DirEntry = CapStdDirEntry;
+    type File = CapStdFile;
+    type FileType = CapStdFileType;
+    type Metadata = CapStdMetadata;
+    type OpenOptions = CapStdOpenOptions;
+    type Permissions = CapStdPermissions;
+    type ReadDir = CapStdReadDir;
+
+    async fn canonicalize<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        let path = relative(path.as_ref());
+        debug!("canonicalise {}", path.display());
+        self.run(move |dir| dir.canonicalize(path))
+            .await
+            .map(|path| Path::new("/").join(path))
+    }
+
+    async fn copy<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<u64> {
+        check_not_same_file(self, from.as_ref(), to.as_ref()).await?;
+        let (from, to) = (relative(from.as_ref()), relative(to.as_ref()));
+        debug!("copy {} -> {}", from.display(), to.display());
+        self.run(move |dir| dir.copy(from, dir, to)).await
+    }
+
+    async fn create_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = relative(path.as_ref());
+        debug!("create_dir {}", path.display());
+        self.run(move |dir| dir.create_dir(path)).await
+    }
+
+    async fn create_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = relative(path.as_ref());
+        debug!("create_dir_all {}", path.display());
+        self.run(move |dir| dir.create_dir_all(path)).await
+    }
+
+    async fn hard_link<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        let (src, dst) = (relative(src.as_ref()), relative(dst.as_ref()));
+        debug!("hard_link {} -> {}", src.display(), dst.display());
+        self.run(move |dir| dir.hard_link(src, dir, dst)).await
+    }
+
+    async fn metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        let path = relative(path.as_ref());
+        debug!("metadata {}", path.display());
+        self.run(move |dir| dir.metadata(path).map(CapStdMetadata))
+            .await
+    }
+
+    async fn read<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>> {
+        let path = relative(path.as_ref());
+        debug!("read {}", path.display());
+        self.run(move |dir| dir.read(path)).await
+    }
+
+    async fn read_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::ReadDir> {
+        let base = path.as_ref().to_path_buf();
+        let path = relative(&base);
+        debug!("read_dir {}", path.display());
+        let read_dir = self.run(move |dir| dir.read_dir(path)).await?;
+        Ok(CapStdReadDir {
+            read_dir: Some(read_dir),
+            base,
+        })
+    }
+
+    async fn read_link<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        let path = relative(path.as_ref());
+        debug!("read_link {}", path.display());
+        self.run(move |dir| dir.read_link(path)).await
+    }
+
+    async fn read_to_string<P: AsRef<Path> + Send>(&self, path: P) -> Result<String> {
+        let path = relative(path.as_ref());
+        debug!("read_to_string {}", path.display());
+        self.run(move |dir| dir.read_to_string(path)).await
+    }
+
+    async fn remove_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = relative(path.as_ref());
+        debug!("remove_dir {}", path.display());
+        self.run(move |dir| dir.remove_dir(path)).await
+    }
+
+    async fn remove_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = relative(path.as_ref());
+        debug!("remove_dir_all {}", path.display());
+        self.run(move |dir| dir.remove_dir_all(path)).await
+    }
+
+    async fn remove_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = relative(path.as_ref());
+        debug!("remove_file {}", path.display());
+        self.run(move |dir| dir.remove_file(path)).await
+    }
+
+    async fn rename<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<()> {
+        check_not_into_itself(self, from.as_ref(), to.as_ref()).await?;
+        let (from, to) = (relative(from.as_ref()), relative(to.as_ref()));
+        debug!("rename {} -> {}", from.display(), to.display());
+        self.run(move |dir| dir.rename(from, dir, to)).await
+    }
+
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        perm: Self::Permissions,
+    ) -> Result<()> {
+        let path = relative(path.as_ref());
+        debug!("set_permissions {}", path.display());
+        self.run(move |dir| dir.set_permissions(path, perm.0)).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        let (src, dst) = (src.as_ref().to_path_buf(), relative(dst.as_ref()));
+        debug!("symlink {} -> {}", src.display(), dst.display());
+        self.run(move |dir| dir.symlink(src, dst)).await
+    }
+
+    async fn symlink_metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        let path = relative(path.as_ref());
+        debug!("symlink_metadata {}", path.display());
+        self.run(move |dir| dir.symlink_metadata(path).map(CapStdMetadata))
+            .await
+    }
+
+    async fn try_exists<P: AsRef<Path> + Send>(&self, path: P) -> Result<bool> {
+        let path = relative(path.as_ref());
+        debug!("try_exists {}", path.display());
+        self.run(move |dir| dir.try_exists(path)).await
+    }
+
+    async fn write<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        let contents = contents.as_ref().to_vec();
+        let path = relative(path.as_ref());
+        debug!("write {}", path.display());
+        self.run(move |dir| dir.write(path, contents)).await
+    }
+
+    fn new_dir_builder(&'a self) -> Self::DirBuilder {
+        CapStdDirBuilder {
+            dir: self.dir.clone(),
+            recursive: false,
+            mode: 0o777,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CapStdMetadata(#[doc(hidden)] Metadata);
+
+#[async_trait::async_trait]
+impl<'a> FloppyMetadata<'a, CapStdFloppyDisk> for CapStdMetadata {
+    fn file_type(&self) -> <CapStdFloppyDisk as FloppyDisk<'a>>::FileType {
+        CapStdFileType(self.0.file_type())
+    }
+
+    fn is_dir(&self) -> bool {
+        self.0.is_dir()
+    }
+
+    fn is_file(&self) -> bool {
+        self.0.is_file()
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.0.is_symlink()
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len()
+    }
+
+    fn permissions(&self) -> <CapStdFloppyDisk as FloppyDisk<'a>>::Permissions {
+        CapStdPermissions(self.0.permissions())
+    }
+
+    fn modified(&self) -> Result<SystemTime> {
+        self.0.modified().map(|time| time.into_std())
+    }
+
+    fn accessed(&self) -> Result<SystemTime> {
+        self.0.accessed().map(|time| time.into_std())
+    }
+
+    fn created(&self) -> Result<SystemTime> {
+        self.0.created().map(|time| time.into_std())
+    }
+}
+
+#[cfg(unix)]
+impl FloppyUnixMetadata for CapStdMetadata {
+    fn uid(&self) -> Result<u32> {
+        Ok(self.0.uid())
+    }
+
+    fn gid(&self) -> Result<u32> {
+        Ok(self.0.gid())
+    }
+
+    fn dev(&self) -> Result<u64> {
+        Ok(self.0.dev())
+    }
+
+    fn ino(&self) -> Result<u64> {
+        Ok(self.0.ino())
+    }
+
+    fn blocks(&self) -> Result<u64> {
+        Ok(self.0.blocks())
+    }
+}
+
+#[derive(Debug)]
+pub struct CapStdReadDir {
+    read_dir: Option<ReadDir>,
+    base: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyReadDir<'a, CapStdFloppyDisk> for CapStdReadDir {
+    async fn next_entry(
+        &mut self,
+    ) -> Result<Option<<CapStdFloppyDisk as FloppyDisk<'a>>::DirEntry>> {
+        let mut read_dir = match self.read_dir.take() {
+            Some(read_dir) => read_dir,
+            None => return Ok(None),
+        };
+        let (read_dir, entry) = tokio::task::spawn_blocking(move || {
+            let entry = read_dir.next();
+            (read_dir, entry)
+        })
+        .await?;
+        self.read_dir = Some(read_dir);
+        entry.transpose().map(|entry| {
+            entry.map(|entry| CapStdDirEntry {
+                path: self.base.join(entry.file_name()),
+                entry: Arc::new(entry),
+            })
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct CapStdPermissions(#[doc(hidden)] Permissions);
+
+impl FloppyPermissions for CapStdPermissions {
+    fn readonly(&self) -> bool {
+        self.0.readonly()
+    }
+
+    fn set_readonly(&mut self, readonly: bool) {
+        self.0.set_readonly(readonly)
+    }
+}
+
+#[cfg(unix)]
+impl FloppyUnixPermissions for CapStdPermissions {
+    fn mode(&self) -> u32 {
+        self.0.mode()
+    }
+
+    fn set_mode(&mut self, mode: u32) {
+        self.0.set_mode(mode)
+    }
+
+    fn from_mode(mode: u32) -> Self {
+        Self(Permissions::from_mode(mode))
+    }
+}
+
+#[derive(Debug)]
+pub struct CapStdDirBuilder {
+    dir: Arc<Dir>,
+    recursive: bool,
+    mode: u32,
+}
+
+#[async_trait::async_trait]
+impl FloppyDirBuilder for CapStdDirBuilder {
+    fn recursive(&mut self, recursive: bool) -> &mut Self {
+        self.recursive = recursive;
+        self
+    }
+
+    async fn create<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = relative(path.as_ref());
+        let dir = self.dir.clone();
+        let (recursive, mode) = (self.recursive, self.mode);
+        blocking(move || {
+            let mut builder = DirBuilder::new();
+            builder.recursive(recursive).mode(mode);
+            dir.create_dir_with(path, &builder)
+        })
+        .await
+    }
+
+    fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+}
+
+#[derive(Debug)]
+pub struct CapStdDirEntry {
+    entry: Arc<DirEntry>,
+    path: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyDirEntry<'a, CapStdFloppyDisk> for CapStdDirEntry {
+    fn file_name(&self) -> OsString {
+        self.entry.file_name()
+    }
+
+    async fn file_type(&self) -> Result<<CapStdFloppyDisk as FloppyDisk<'a>>::FileType> {
+        let entry = self.entry.clone();
+        blocking(move || entry.file_type().map(CapStdFileType)).await
+    }
+
+    async fn metadata(&self) -> Result<CapStdMetadata> {
+        let entry = self.entry.clone();
+        blocking(move || entry.metadata().map(CapStdMetadata)).await
+    }
+
+    fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    #[cfg(unix)]
+    fn ino(&self) -> u64 {
+        use std::os::unix::fs::DirEntryExt;
+        self.entry.ino()
+    }
+}
+
+#[derive(Debug)]
+pub struct CapStdFileType(#[doc(hidden)] FileType);
+
+impl FloppyFileType for CapStdFileType {
+    fn is_dir(&self) -> bool {
+        self.0.is_dir()
+    }
+
+    fn is_file(&self) -> bool {
+        self.0.is_file()
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.0.is_symlink()
+    }
+}
+
+#[derive(Debug)]
+pub struct CapStdOpenOptions {
+    options: OpenOptions,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyOpenOptions<'a, CapStdFloppyDisk> for CapStdOpenOptions {
+    fn new() -> Self {
+        Self {
+            options: OpenOptions::new(),
+        }
+    }
+
+    fn read(mut self, read: bool) -> Self {
+        self.options.read(read);
+        self
+    }
+
+    fn write(mut self, write: bool) -> Self {
+        self.options.write(write);
+        self
+    }
+
+    fn append(mut self, append: bool) -> Self {
+        self.options.append(append);
+        self
+    }
+
+    fn truncate(mut self, truncate: bool) -> Self {
+        self.options.truncate(truncate);
+        self
+    }
+
+    fn create(mut self, create: bool) -> Self {
+        self.options.create(create);
+        self
+    }
+
+    fn create_new(mut self, create_new: bool) -> Self {
+        self.options.create_new(create_new);
+        self
+    }
+
+    async fn open<P: AsRef<Path> + Send>(
+        &self,
+        disk: &'a CapStdFloppyDisk,
+        path: P,
+    ) -> Result<<CapStdFloppyDisk as FloppyDisk<'a>>::File> {
+        let path = relative(path.as_ref());
+        let options = self.options.clone();
+        debug!("opening {}", path.display());
+        let file = disk.run(move |dir| dir.open_with(path, &options)).await?;
+        Ok(CapStdFile {
+            file: File::from_std(file.into_std()),
+        })
+    }
+}
+
+/// Files are opened relative to the disk's directory, then handed to tokio
+/// for reads and writes. As with [`TokioFile`](crate::tokio_fs::TokioFile),
+/// dropping the file without [closing](FloppyFile::close) it loses errors
+/// from the last write.
+#[derive(Debug)]
+pub struct CapStdFile {
+    file: File,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyFile<'a, CapStdFloppyDisk> for CapStdFile {
+    async fn sync_all(&mut self) -> Result<()> {
+        self.file.sync_all().await
+    }
+
+    async fn sync_data(&mut self) -> Result<()> {
+        self.file.sync_data().await
+    }
+
+    async fn set_len(&mut self, size: u64) -> Result<()> {
+        self.file.set_len(size).await
+    }
+
+    async fn metadata(&self) -> Result<<CapStdFloppyDisk as FloppyDisk<'a>>::Metadata> {
+        self.file
+            .metadata()
+            .await
+            .map(|metadata| CapStdMetadata(Metadata::from_just_metadata(metadata)))
+    }
+
+    async fn try_clone(&'a self) -> Result<Box<Self>> {
+        Ok(Box::new(CapStdFile {
+            file: self.file.try_clone().await?,
+        }))
+    }
+
+    async fn set_permissions(
+        &self,
+        perm: <CapStdFloppyDisk as FloppyDisk>::Permissions,
+    ) -> Result<()> {
+        use std::os::unix::prelude::PermissionsExt;
+        self.file
+            .set_permissions(std::fs::Permissions::from_mode(perm.mode()))
+            .await
+    }
+
+    async fn permissions(&self) -> Result<<CapStdFloppyDisk as FloppyDisk<'a>>::Permissions> {
+        self.file
+            .metadata()
+            .await
+            .map(|metadata| CapStdPermissions(Permissions::from_std(metadata.permissions())))
+    }
+
+    async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        crate::tokio_fs::read_at(&self.file, buf, offset).await
+    }
+
+    async fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        crate::tokio_fs::write_at(&self.file, buf, offset).await
+    }
+
+    async fn allocate(&self, offset: u64, len: u64) -> Result<()> {
+        crate::tokio_fs::allocate(&self.file, offset, len).await
+    }
+
+    async fn seek_data(&self, offset: u64) -> Result<u64> {
+        crate::tokio_fs::seek_extent(&self.file, offset, true).await
+    }
+
+    async fn seek_hole(&self, offset: u64) -> Result<u64> {
+        crate::tokio_fs::seek_extent(&self.file, offset, false).await
+    }
+}
+
+impl AsyncRead for CapStdFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for CapStdFile {
+    fn start_seek(self: Pin<&mut Self>, position: std::io::SeekFrom) -> Result<()> {
+        Pin::new(&mut self.get_mut().file).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        Pin::new(&mut self.get_mut().file).poll_complete(cx)
+    }
+}
+
+impl AsyncWrite for CapStdFile {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.get_mut().file).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize>> {
+        Pin::new(&mut self.get_mut().file).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.file.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{FloppyPolicy, PolicyDisk};
+
+    async fn temp_disk() -> Result<(PathBuf, CapStdFloppyDisk)> {
+        let root = PathBuf::from(format!("/tmp/floppy-cap-std-{}", rand::random::<u64>()));
+        tokio::fs::create_dir_all(root.join("jail")).await?;
+        let fs = CapStdFloppyDisk::open_ambient(root.join("jail")).await?;
+        Ok((root, fs))
+    }
+
+    #[tokio::test]
+    async fn test_cap_std_floppy_disk() -> Result<()> {
+        let (root, fs) = temp_disk().await?;
+        fs.create_dir_all("/a/b").await?;
+        fs.write("/a/b/c.txt", "asdf").await?;
+        assert_eq!("asdf", fs.read_to_string("/a/b/c.txt").await?);
+        assert_eq!(
+            PathBuf::from("/a/b/c.txt"),
+            fs.canonicalize("/a/../a/b/c.txt").await?
+        );
+
+        let mut entries = fs.read_dir("/a").await?;
+        let entry = entries.next_entry().await?.unwrap();
+        assert_eq!(PathBuf::from("/a/b"), entry.path());
+        assert!(entry.file_type().await?.is_dir());
+        assert!(entries.next_entry().await?.is_none());
+
+        tokio::fs::remove_dir_all(root).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_content_policy_links() -> Result<()> {
+        let (root, fs) = temp_disk().await?;
+        let fs = PolicyDisk::new(fs, FloppyPolicy::new().deny_extension("/denied", "exe"));
+        fs.create_dir("/denied").await?;
+        fs.write("/ok.bin", "asdf").await?;
+
+        assert!(fs.hard_link("/ok.bin", "/denied/x.exe").await.is_err());
+        assert!(fs.symlink("/ok.bin", "/denied/y.exe").await.is_err());
+        assert!(!fs.try_exists("/denied/x.exe").await?);
+
+        tokio::fs::remove_dir_all(root).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_escapes_are_rejected() -> Result<()> {
+        let (root, fs) = temp_disk().await?;
+        tokio::fs::write(root.join("secret.txt"), "hunter2").await?;
+
+        assert!(fs.read_to_string("/../secret.txt").await.is_err());
+        fs.symlink("../secret.txt", "/link").await?;
+        assert!(fs.read_to_string("/link").await.is_err());
+        tokio::fs::symlink(root.join("secret.txt"), root.join("jail/abs-link")).await?;
+        assert!(fs.read_to_string("/abs-link").await.is_err());
+
+        tokio::fs::remove_dir_all(root).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,908 @@
+//! Least-privilege access to a disk within one process. A [`CapabilityDisk`]
+//! only allows the operations its [`Capability`] grants, and can hand out
+//! narrower views of itself to the parts of a program that need less.
+//!
+//! ```rust
+//! # use floppy_disk::prelude::*;
+//! # use floppy_disk::capability::{Access, Capability, CapabilityDisk};
+//! let disk = CapabilityDisk::new(
+//!     MemFloppyDisk::new(),
+//!     Capability::new()
+//!         .allow("/data", Access::READ)
+//!         .allow("/tmp", Access::READ | Access::WRITE),
+//! );
+//!
+//! // A plugin that only gets to read its own scratch space.
+//! let plugin = disk.attenuate(&Capability::new().allow("/tmp", Access::READ));
+//! assert!(plugin.capability().permits("/tmp/scratch", Access::READ));
+//! assert!(!plugin.capability().permits("/tmp/scratch", Access::WRITE));
+//! assert!(!plugin.capability().permits("/data/x", Access::READ));
+//! ```
+//!
+//! Paths are checked both as given and with any symlinks in them resolved,
+//! so a symlink can't lead anywhere its holder couldn't go directly. New
+//! symlinks must point somewhere that allows at least the reading and
+//! writing their own path does, and new hard links need write access to
+//! the file they link to. Renames need read and delete access to what's
+//! moved, and delete access to anything they'd replace.
+//!
+//! Hard links made by anything else can't be told apart from the files
+//! they link to, so a grant shouldn't cover a directory that others can
+//! link into.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::ops::BitOr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::policy::normalise;
+use crate::resolve_symlinks;
+use crate::*;
+
+/// Kinds of operation a [`Capability`] can grant, combined with `|`.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Access(u8);
+
+impl Access {
+    pub const NONE: Access = Access(0);
+    /// Reading files, listing directories, and looking at metadata.
+    pub const READ: Access = Access(1);
+    /// Creating and changing files, directories, symlinks and permissions.
+    pub const WRITE: Access = Access(2);
+    /// Removing anything, including by renaming it away.
+    pub const DELETE: Access = Access(4);
+    pub const ALL: Access = Access(7);
+
+    pub fn contains(self, other: Access) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    fn intersection(self, other: Access) -> Access {
+        Access(self.0 & other.0)
+    }
+}
+
+impl BitOr for Access {
+    type Output = Access;
+
+    fn bitor(self, other: Access) -> Access {
+        Access(self.0 | other.0)
+    }
+}
+
+impl fmt::Debug for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = [
+            (Access::READ, "READ"),
+            (Access::WRITE, "WRITE"),
+            (Access::DELETE, "DELETE"),
+        ]
+        .into_iter()
+        .filter(|(access, _)| self.contains(*access))
+        .map(|(_, name)| name)
+        .collect();
+        if names.is_empty() {
+            write!(f, "NONE")
+        } else {
+            write!(f, "{}", names.join(" | "))
+        }
+    }
+}
+
+/// A set of grants, each allowing some [`Access`] to everything under a
+/// path. Anything not granted is denied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capability {
+    grants: Vec<(PathBuf, Access)>,
+}
+
+impl Capability {
+    /// A capability that allows nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow `access` to `prefix` and everything under it.
+    pub fn allow(mut self, prefix: impl AsRef<Path>, access: Access) -> Self {
+        self.grants.push((normalise(prefix.as_ref()), access));
+        self
+    }
+
+    /// Whether `access` to `path` is allowed.
+    pub fn permits(&self, path: impl AsRef<Path>, access: Access) -> bool {
+        self.granted(path.as_ref()).contains(access)
+    }
+
+    fn granted(&self, path: &Path) -> Access {
+        let path = normalise(path);
+        self.grants
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix))
+            .fold(Access::NONE, |granted, (_, access)| granted | *access)
+    }
+
+    /// What both `self` and `other` allow, and nothing more. Holders of a
+    /// capability can only ever attenuate it, never widen it.
+    pub fn attenuate(&self, other: &Capability) -> Capability {
+        let mut grants = vec![];
+        for (a, a_access) in &self.grants {
+            for (b, b_access) in &other.grants {
+                // Grants only overlap where one's prefix is under the
+                // other's, and then only under the deeper one.
+                let prefix = if a.starts_with(b) {
+                    a
+                } else if b.starts_with(a) {
+                    b
+                } else {
+                    continue;
+                };
+                let access = a_access.intersection(*b_access);
+                if access != Access::NONE {
+                    grants.push((prefix.clone(), access));
+                }
+            }
+        }
+        Capability { grants }
+    }
+
+    fn check(&self, path: &Path, access: Access) -> Result<()> {
+        if self.permits(path, access) {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{access:?} access to {} is not permitted", path.display()),
+            ))
+        }
+    }
+}
+
+/// A disk that only allows what its [`Capability`] grants, and fails
+/// everything else with [`ErrorKind::PermissionDenied`].
+#[derive(Debug)]
+pub struct CapabilityDisk<D> {
+    disk: Arc<D>,
+    capability: Capability,
+}
+
+impl<D> CapabilityDisk<D> {
+    pub fn new(disk: D, capability: Capability) -> Self {
+        Self {
+            disk: Arc::new(disk),
+            capability,
+        }
+    }
+
+    /// A view of the same disk that allows only what both this disk's
+    /// capability and `capability` allow.
+    pub fn attenuate(&self, capability: &Capability) -> Self {
+        Self {
+            disk: self.disk.clone(),
+            capability: self.capability.attenuate(capability),
+        }
+    }
+
+    pub fn capability(&self) -> &Capability {
+        &self.capability
+    }
+}
+
+impl<'a, D: FloppyDisk<'a> + Sync> CapabilityDisk<D> {
+    /// Check `access` to `path` as given, and again with its symlinks
+    /// resolved. The last component is only resolved if `follow` is set,
+    /// for operations that act on what a symlink points to.
+    async fn check(&self, path: &Path, access: Access, follow: bool) -> Result<()> {
+        self.capability.check(path, access)?;
+        let resolved = resolve_symlinks(&*self.disk, path, follow).await?;
+        self.capability.check(&resolved, access)
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyDisk<'a> for CapabilityDisk<D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirBuilder: Sync,
+    D::DirEntry: Sync,
+    D::File: Sync,
+    D::OpenOptions: Sync,
+    D::Permissions: 'static,
+{
+    type DirBuilder = CapabilityDirBuilder<'a, D>;
+    type DirEntry = CapabilityDirEntry<'a, D>;
+    type File = CapabilityFile<'a, D>;
+    type FileType = D::FileType;
+    type Metadata = CapabilityMetadata<'a, D>;
+    type OpenOptions = CapabilityOpenOptions<'a, D>;
+    type Permissions = D::Permissions;
+    type ReadDir = CapabilityReadDir<'a, D>;
+
+    async fn canonicalize<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        self.check(path.as_ref(), Access::READ, true).await?;
+        self.disk.canonicalize(path).await
+    }
+
+    async fn copy<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<u64> {
+        self.check(from.as_ref(), Access::READ, true).await?;
+        self.check(to.as_ref(), Access::WRITE, true).await?;
+        self.disk.copy(from, to).await
+    }
+
+    async fn create_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.check(path.as_ref(), Access::WRITE, false).await?;
+        self.disk.create_dir(path).await
+    }
+
+    async fn create_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.check(path.as_ref(), Access::WRITE, true).await?;
+        self.disk.create_dir_all(path).await
+    }
+
+    async fn hard_link<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        self.check(src.as_ref(), Access::READ | Access::WRITE, true)
+            .await?;
+        self.check(dst.as_ref(), Access::WRITE, false).await?;
+        self.disk.hard_link(src, dst).await
+    }
+
+    async fn metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        self.check(path.as_ref(), Access::READ, true).await?;
+        self.disk.metadata(path).await.map(CapabilityMetadata)
+    }
+
+    async fn read<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>> {
+        self.check(path.as_ref(), Access::READ, true).await?;
+        self.disk.read(path).await
+    }
+
+    async fn read_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::ReadDir> {
+        self.check(path.as_ref(), Access::READ, true).await?;
+        self.disk.read_dir(path).await.map(CapabilityReadDir)
+    }
+
+    async fn read_link<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        self.check(path.as_ref(), Access::READ, false).await?;
+        self.disk.read_link(path).await
+    }
+
+    async fn read_to_string<P: AsRef<Path> + Send>(&self, path: P) -> Result<String> {
+        self.check(path.as_ref(), Access::READ, true).await?;
+        self.disk.read_to_string(path).await
+    }
+
+    async fn remove_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.check(path.as_ref(), Access::DELETE, false).await?;
+        self.disk.remove_dir(path).await
+    }
+
+    async fn remove_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.check(path.as_ref(), Access::DELETE, false).await?;
+        self.disk.remove_dir_all(path).await
+    }
+
+    async fn remove_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.check(path.as_ref(), Access::DELETE, false).await?;
+        self.disk.remove_file(path).await
+    }
+
+    async fn rename<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<()> {
+        // Moving a file somewhere it can be read is reading it, and moving
+        // over a file removes it.
+        self.check(from.as_ref(), Access::READ | Access::DELETE, false)
+            .await?;
+        let mut access = Access::WRITE;
+        match self.disk.symlink_metadata(to.as_ref()).await {
+            Ok(_) => access = access | Access::DELETE,
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        self.check(to.as_ref(), access, false).await?;
+        self.disk.rename(from, to).await
+    }
+
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        perm: Self::Permissions,
+    ) -> Result<()> {
+        self.check(path.as_ref(), Access::WRITE, true).await?;
+        self.disk.set_permissions(path, perm).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        self.check(dst.as_ref(), Access::WRITE, false).await?;
+        // Whatever the link allows is allowed on its target too, through it.
+        let link = resolve_symlinks(&*self.disk, dst.as_ref(), false).await?;
+        let target = link.parent().unwrap_or(Path::new("/")).join(src.as_ref());
+        let target = resolve_symlinks(&*self.disk, &target, true).await?;
+        let access = self
+            .capability
+            .granted(&link)
+            .intersection(Access::READ | Access::WRITE);
+        self.capability.check(&target, access)?;
+        self.disk.symlink(src, dst).await
+    }
+
+    async fn symlink_metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        self.check(path.as_ref(), Access::READ, false).await?;
+        self.disk
+            .symlink_metadata(path)
+            .await
+            .map(CapabilityMetadata)
+    }
+
+    async fn try_exists<P: AsRef<Path> + Send>(&self, path: P) -> Result<bool> {
+        self.check(path.as_ref(), Access::READ, true).await?;
+        self.disk.try_exists(path).await
+    }
+
+    async fn write<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        self.check(path.as_ref(), Access::WRITE, true).await?;
+        self.disk.write(path, contents).await
+    }
+
+    fn new_dir_builder(&'a self) -> Self::DirBuilder {
+        CapabilityDirBuilder {
+            builder: self.disk.new_dir_builder(),
+            disk: self,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyDiskUnixExt for CapabilityDisk<D>
+where
+    D: FloppyDisk<'a> + FloppyDiskUnixExt + Send + Sync,
+{
+    async fn chown<P: Into<PathBuf> + Send>(&self, path: P, uid: u32, gid: u32) -> Result<()> {
+        let path = path.into();
+        self.check(&path, Access::WRITE, true).await?;
+        self.disk.chown(path, uid, gid).await
+    }
+}
+
+pub struct CapabilityDirBuilder<'a, D: FloppyDisk<'a>> {
+    builder: D::DirBuilder,
+    disk: &'a CapabilityDisk<D>,
+}
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for CapabilityDirBuilder<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CapabilityDirBuilder")
+            .field("builder", &self.builder)
+            .field("capability", &self.disk.capability)
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyDirBuilder for CapabilityDirBuilder<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirBuilder: Sync,
+{
+    fn recursive(&mut self, recursive: bool) -> &mut Self {
+        self.builder.recursive(recursive);
+        self
+    }
+
+    async fn create<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        // Nothing happens until this is awaited, after the check. Making it
+        // first means `self` isn't held across the check, which the compiler
+        // can't prove is `Send` for a disk that borrows.
+        let creating = self.builder.create(path.as_ref().to_path_buf());
+        self.disk.check(path.as_ref(), Access::WRITE, false).await?;
+        creating.await
+    }
+
+    #[cfg(unix)]
+    fn mode(&mut self, mode: u32) -> &mut Self {
+        self.builder.mode(mode);
+        self
+    }
+}
+
+pub struct CapabilityDirEntry<'a, D: FloppyDisk<'a>>(D::DirEntry);
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for CapabilityDirEntry<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CapabilityDirEntry").field(&self.0).finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyDirEntry<'a, CapabilityDisk<D>> for CapabilityDirEntry<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirBuilder: Sync,
+    D::DirEntry: Sync,
+    D::File: Sync,
+    D::OpenOptions: Sync,
+    D::Permissions: 'static,
+{
+    fn path(&self) -> PathBuf {
+        self.0.path()
+    }
+
+    fn file_name(&self) -> OsString {
+        self.0.file_name()
+    }
+
+    async fn metadata(&self) -> Result<CapabilityMetadata<'a, D>> {
+        self.0.metadata().await.map(CapabilityMetadata)
+    }
+
+    async fn file_type(&self) -> Result<D::FileType> {
+        self.0.file_type().await
+    }
+
+    #[cfg(unix)]
+    fn ino(&self) -> u64 {
+        self.0.ino()
+    }
+}
+
+pub struct CapabilityReadDir<'a, D: FloppyDisk<'a>>(D::ReadDir);
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for CapabilityReadDir<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CapabilityReadDir").field(&self.0).finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyReadDir<'a, CapabilityDisk<D>> for CapabilityReadDir<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirBuilder: Sync,
+    D::DirEntry: Sync,
+    D::File: Sync,
+    D::OpenOptions: Sync,
+    D::Permissions: 'static,
+{
+    async fn next_entry(&mut self) -> Result<Option<CapabilityDirEntry<'a, D>>> {
+        Ok(self.0.next_entry().await?.map(CapabilityDirEntry))
+    }
+}
+
+pub struct CapabilityMetadata<'a, D: FloppyDisk<'a>>(D::Metadata);
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for CapabilityMetadata<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CapabilityMetadata").field(&self.0).finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyMetadata<'a, CapabilityDisk<D>> for CapabilityMetadata<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirBuilder: Sync,
+    D::DirEntry: Sync,
+    D::File: Sync,
+    D::OpenOptions: Sync,
+    D::Permissions: 'static,
+{
+    fn file_type(&self) -> D::FileType {
+        self.0.file_type()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.0.is_dir()
+    }
+
+    fn is_file(&self) -> bool {
+        self.0.is_file()
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.0.is_symlink()
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len()
+    }
+
+    fn permissions(&self) -> D::Permissions {
+        self.0.permissions()
+    }
+
+    fn modified(&self) -> Result<SystemTime> {
+        self.0.modified()
+    }
+
+    fn accessed(&self) -> Result<SystemTime> {
+        self.0.accessed()
+    }
+
+    fn created(&self) -> Result<SystemTime> {
+        self.0.created()
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> FloppyUnixMetadata for CapabilityMetadata<'a, D>
+where
+    D::Metadata: FloppyUnixMetadata,
+{
+    fn uid(&self) -> Result<u32> {
+        self.0.uid()
+    }
+
+    fn gid(&self) -> Result<u32> {
+        self.0.gid()
+    }
+
+    fn dev(&self) -> Result<u64> {
+        self.0.dev()
+    }
+
+    fn ino(&self) -> Result<u64> {
+        self.0.ino()
+    }
+
+    fn blocks(&self) -> Result<u64> {
+        self.0.blocks()
+    }
+}
+
+/// Options for opening files on a [`CapabilityDisk`]. Opening for reading
+/// needs [`Access::READ`], and for writing, appending, truncating or creating
+/// needs [`Access::WRITE`].
+pub struct CapabilityOpenOptions<'a, D: FloppyDisk<'a>> {
+    options: D::OpenOptions,
+    read: bool,
+    write: bool,
+}
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for CapabilityOpenOptions<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CapabilityOpenOptions")
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyOpenOptions<'a, CapabilityDisk<D>> for CapabilityOpenOptions<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirBuilder: Sync,
+    D::DirEntry: Sync,
+    D::File: Sync,
+    D::OpenOptions: Sync,
+    D::Permissions: 'static,
+{
+    fn new() -> Self {
+        Self {
+            options: D::OpenOptions::new(),
+            read: false,
+            write: false,
+        }
+    }
+
+    fn read(mut self, read: bool) -> Self {
+        self.options = self.options.read(read);
+        self.read = read;
+        self
+    }
+
+    fn write(mut self, write: bool) -> Self {
+        self.options = self.options.write(write);
+        self.write |= write;
+        self
+    }
+
+    fn append(mut self, append: bool) -> Self {
+        self.options = self.options.append(append);
+        self.write |= append;
+        self
+    }
+
+    fn truncate(mut self, truncate: bool) -> Self {
+        self.options = self.options.truncate(truncate);
+        self.write |= truncate;
+        self
+    }
+
+    fn create(mut self, create: bool) -> Self {
+        self.options = self.options.create(create);
+        self.write |= create;
+        self
+    }
+
+    fn create_new(mut self, create_new: bool) -> Self {
+        self.options = self.options.create_new(create_new);
+        self.write |= create_new;
+        self
+    }
+
+    async fn open<P: AsRef<Path> + Send>(
+        &self,
+        disk: &'a CapabilityDisk<D>,
+        path: P,
+    ) -> Result<CapabilityFile<'a, D>> {
+        // As with `CapabilityDirBuilder::create`, the file isn't opened until
+        // this is awaited, after the checks.
+        let (read, write) = (self.read, self.write);
+        let opening = self.options.open(&*disk.disk, path.as_ref().to_path_buf());
+        if read {
+            disk.check(path.as_ref(), Access::READ, true).await?;
+        }
+        if write {
+            disk.check(path.as_ref(), Access::WRITE, true).await?;
+        }
+        opening.await.map(CapabilityFile)
+    }
+}
+
+/// An open file on a [`CapabilityDisk`]. Access was checked when it was
+/// opened, so everything done through it is allowed.
+pub struct CapabilityFile<'a, D: FloppyDisk<'a>>(D::File);
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for CapabilityFile<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CapabilityFile").field(&self.0).finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyFile<'a, CapabilityDisk<D>> for CapabilityFile<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirBuilder: Sync,
+    D::DirEntry: Sync,
+    D::File: Sync,
+    D::OpenOptions: Sync,
+    D::Permissions: 'static,
+{
+    async fn sync_all(&mut self) -> Result<()> {
+        self.0.sync_all().await
+    }
+
+    async fn sync_data(&mut self) -> Result<()> {
+        self.0.sync_data().await
+    }
+
+    async fn set_len(&mut self, size: u64) -> Result<()> {
+        self.0.set_len(size).await
+    }
+
+    async fn metadata(&self) -> Result<CapabilityMetadata<'a, D>> {
+        self.0.metadata().await.map(CapabilityMetadata)
+    }
+
+    async fn try_clone(&'a self) -> Result<Box<CapabilityFile<'a, D>>> {
+        Ok(Box::new(CapabilityFile(*self.0.try_clone().await?)))
+    }
+
+    async fn set_permissions(&self, perm: D::Permissions) -> Result<()> {
+        self.0.set_permissions(perm).await
+    }
+
+    async fn permissions(&self) -> Result<D::Permissions> {
+        self.0.permissions().await
+    }
+
+    async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.0.read_at(buf, offset).await
+    }
+
+    async fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        self.0.write_at(buf, offset).await
+    }
+
+    async fn allocate(&self, offset: u64, len: u64) -> Result<()> {
+        self.0.allocate(offset, len).await
+    }
+
+    async fn seek_data(&self, offset: u64) -> Result<u64> {
+        self.0.seek_data(offset).await
+    }
+
+    async fn seek_hole(&self, offset: u64) -> Result<u64> {
+        self.0.seek_hole(offset).await
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> AsyncRead for CapabilityFile<'a, D> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> AsyncWrite for CapabilityFile<'a, D> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.0.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> AsyncSeek for CapabilityFile<'a, D> {
+    fn start_seek(self: Pin<&mut Self>, position: std::io::SeekFrom) -> Result<()> {
+        Pin::new(&mut self.get_mut().0).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        Pin::new(&mut self.get_mut().0).poll_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_capability_disk() -> Result<()> {
+        let mem = MemFloppyDisk::new();
+        mem.create_dir_all("/data").await?;
+        mem.create_dir_all("/tmp").await?;
+        mem.write("/data/a.txt", "asdf").await?;
+        let disk = CapabilityDisk::new(
+            mem,
+            Capability::new()
+                .allow("/data", Access::READ)
+                .allow("/tmp", Access::ALL),
+        );
+
+        assert_eq!("asdf", disk.read_to_string("/data/a.txt").await?);
+        let denied = disk.write("/data/b.txt", "jkl").await.unwrap_err();
+        assert_eq!(ErrorKind::PermissionDenied, denied.kind());
+        assert!(disk.remove_file("/data/a.txt").await.is_err());
+        assert!(disk.read("/tmp/../etc/passwd").await.is_err());
+        assert!(disk.rename("/data/a.txt", "/tmp/a.txt").await.is_err());
+        assert!(disk.copy("/data/a.txt", "/tmp/a.txt").await.is_ok());
+
+        let mut file = CapabilityOpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&disk, "/tmp/b.txt")
+            .await?;
+        file.write_all(b"jkl").await?;
+        file.close().await?;
+        let opened = CapabilityOpenOptions::new()
+            .write(true)
+            .open(&disk, "/data/a.txt")
+            .await;
+        assert!(opened.is_err());
+        let mut file = CapabilityOpenOptions::new()
+            .read(true)
+            .open(&disk, "/data/a.txt")
+            .await?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await?;
+        assert_eq!("asdf", contents);
+
+        // Attenuating can only take access away.
+        let narrow = disk.attenuate(
+            &Capability::new()
+                .allow("/", Access::READ | Access::WRITE)
+                .allow("/tmp/sub", Access::DELETE),
+        );
+        assert!(narrow.read("/data/a.txt").await.is_ok());
+        assert!(narrow.write("/data/c.txt", "").await.is_err());
+        assert!(narrow.write("/tmp/c.txt", "").await.is_ok());
+        assert!(narrow.remove_file("/tmp/c.txt").await.is_err());
+        assert!(narrow.capability().permits("/tmp/sub/x", Access::DELETE));
+        assert!(!narrow.capability().permits("/etc", Access::READ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_capability_links() -> Result<()> {
+        let mem = MemFloppyDisk::new();
+        mem.create_dir_all("/data").await?;
+        mem.create_dir_all("/tmp").await?;
+        mem.create_dir_all("/etc").await?;
+        mem.write("/data/f", "asdf").await?;
+        mem.write("/etc/passwd", "root").await?;
+        mem.symlink("/data", "/tmp/data").await?;
+        mem.symlink("../etc/passwd", "/tmp/passwd").await?;
+        let disk = CapabilityDisk::new(
+            mem,
+            Capability::new()
+                .allow("/data", Access::READ)
+                .allow("/tmp", Access::ALL),
+        );
+
+        // Hard links need write access to what they link to, or a read-only
+        // file could be written through one.
+        let denied = disk.hard_link("/data/f", "/tmp/h").await.unwrap_err();
+        assert_eq!(ErrorKind::PermissionDenied, denied.kind());
+        disk.write("/tmp/h", "PWNED").await?;
+        assert_eq!("asdf", disk.read_to_string("/data/f").await?);
+
+        // New symlinks can't reach further than their own paths can.
+        for target in ["/data/f", "../data/f", "/etc/passwd", "data/f"] {
+            let denied = disk.symlink(target, "/tmp/s").await.unwrap_err();
+            assert_eq!(ErrorKind::PermissionDenied, denied.kind());
+        }
+        disk.symlink("h", "/tmp/s").await?;
+        assert_eq!("PWNED", disk.read_to_string("/tmp/s").await?);
+
+        // Symlinks that were already there are followed when checking.
+        assert_eq!("asdf", disk.read_to_string("/tmp/data/f").await?);
+        assert!(disk.write("/tmp/data/f", "PWNED").await.is_err());
+        assert!(disk.write("/tmp/data/g", "PWNED").await.is_err());
+        assert!(disk.read("/tmp/passwd").await.is_err());
+        assert!(disk.new_dir_builder().create("/tmp/data/d").await.is_err());
+        let opened = CapabilityOpenOptions::new()
+            .write(true)
+            .open(&disk, "/tmp/data/f")
+            .await;
+        assert!(opened.is_err());
+        // The links themselves are still the disk's to remove.
+        disk.remove_file("/tmp/passwd").await?;
+        assert_eq!("asdf", disk.read_to_string("/data/f").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_capability_rename() -> Result<()> {
+        let mem = MemFloppyDisk::new();
+        mem.create_dir_all("/secret").await?;
+        mem.create_dir_all("/tmp").await?;
+        mem.create_dir_all("/keep").await?;
+        mem.write("/secret/key", "hunter2").await?;
+        mem.write("/keep/a", "asdf").await?;
+        let disk = CapabilityDisk::new(
+            mem,
+            Capability::new()
+                .allow("/secret", Access::DELETE)
+                .allow("/tmp", Access::ALL)
+                .allow("/keep", Access::READ | Access::WRITE),
+        );
+
+        // Deleting a file doesn't grant reading it somewhere else.
+        let denied = disk.rename("/secret/key", "/tmp/key").await.unwrap_err();
+        assert_eq!(ErrorKind::PermissionDenied, denied.kind());
+        assert!(disk.read("/tmp/key").await.is_err());
+
+        // Nor does writing a file grant removing the one that's there.
+        disk.write("/tmp/b", "jkl").await?;
+        let denied = disk.rename("/tmp/b", "/keep/a").await.unwrap_err();
+        assert_eq!(ErrorKind::PermissionDenied, denied.kind());
+        assert_eq!("asdf", disk.read_to_string("/keep/a").await?);
+        disk.rename("/tmp/b", "/keep/b").await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1091 @@
+//! An in-memory disk that stores file bodies by their blake3 hash, so that
+//! identical files, as are common in package trees, are only stored once.
+//!
+//! ```rust
+//! # use floppy_disk::prelude::*;
+//! # use floppy_disk::cas::CasFloppyDisk;
+//! # async fn example() -> std::io::Result<()> {
+//! let disk = CasFloppyDisk::new();
+//! disk.create_dir_all("/a/node_modules/left-pad").await?;
+//! disk.create_dir_all("/b/node_modules/left-pad").await?;
+//! disk.write("/a/node_modules/left-pad/index.js", "module.exports = ...").await?;
+//! disk.write("/b/node_modules/left-pad/index.js", "module.exports = ...").await?;
+//! assert_eq!(20, disk.savings().saved_bytes());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The tree itself, ie. directories, symlinks, permissions and owners, is
+//! kept in a [`MemFloppyDisk`], alongside an index from each file's path to
+//! the hash of its body. A file that's open is moved back into the tree
+//! until its last handle is closed, when its body is hashed and stored
+//! again.
+//!
+//! Hard links are copies that share a body, rather than one file: writing
+//! through one path doesn't change what the other reads.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind, IoSlice, Result};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tracing::debug;
+
+use crate::mem::{
+    MemDirBuilder, MemDirEntry, MemFile, MemFileType, MemFloppyDisk, MemMetadata, MemOpenOptions,
+    MemPermissions, MemReadDir,
+};
+use crate::policy::normalise;
+use crate::*;
+
+/// How much storing bodies by hash has saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CasSavings {
+    pub files: u64,
+    /// Distinct bodies stored.
+    pub blobs: u64,
+    /// The size of every file, counting each one separately.
+    pub logical_bytes: u64,
+    /// The size of every distinct body.
+    pub stored_bytes: u64,
+}
+
+impl CasSavings {
+    pub fn saved_bytes(&self) -> u64 {
+        self.logical_bytes - self.stored_bytes
+    }
+}
+
+/// See the [module docs](self).
+#[derive(Debug)]
+pub struct CasFloppyDisk {
+    cas: Arc<Cas>,
+}
+
+#[derive(Debug)]
+struct Cas {
+    tree: MemFloppyDisk,
+    state: Mutex<CasState>,
+    /// Held while moving bodies between the tree and the blob store, so that
+    /// eg. a read can't find a file's body in neither.
+    moving: tokio::sync::Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct CasState {
+    blobs: HashMap<blake3::Hash, Blob>,
+    /// Files whose bodies are in `blobs`. Their nodes in the tree are empty.
+    index: HashMap<PathBuf, blake3::Hash>,
+    /// Files whose bodies are in the tree because they're open.
+    open: HashMap<PathBuf, OpenBody>,
+}
+
+#[derive(Debug)]
+struct Blob {
+    data: Arc<[u8]>,
+    refs: usize,
+}
+
+#[derive(Debug)]
+struct OpenBody {
+    handles: usize,
+    path: Slot,
+}
+
+/// Where an open file is now, shared with its handles so that they can
+/// still find it after a rename. `None` once it's been removed.
+type Slot = Arc<Mutex<Option<PathBuf>>>;
+
+impl CasState {
+    fn store(&mut self, data: &[u8]) -> blake3::Hash {
+        let hash = blake3::hash(data);
+        self.blobs
+            .entry(hash)
+            .or_insert_with(|| Blob {
+                data: data.into(),
+                refs: 0,
+            })
+            .refs += 1;
+        hash
+    }
+
+    fn release(&mut self, hash: blake3::Hash) {
+        if let Some(blob) = self.blobs.get_mut(&hash) {
+            blob.refs -= 1;
+            if blob.refs == 0 {
+                self.blobs.remove(&hash);
+            }
+        }
+    }
+
+    fn set(&mut self, path: PathBuf, hash: Option<blake3::Hash>) {
+        let old = match hash {
+            Some(hash) => self.index.insert(path, hash),
+            None => self.index.remove(&path),
+        };
+        if let Some(old) = old {
+            self.release(old);
+        }
+    }
+
+    fn body(&self, path: &Path) -> Option<Arc<[u8]>> {
+        let hash = self.index.get(path)?;
+        self.blobs.get(hash).map(|blob| blob.data.clone())
+    }
+
+    /// Drop everything at or under `path`, which has been removed from the
+    /// tree.
+    fn forget_under(&mut self, path: &Path) {
+        let gone: Vec<_> = self
+            .index
+            .keys()
+            .filter(|key| key.starts_with(path))
+            .cloned()
+            .collect();
+        for key in gone {
+            self.set(key, None);
+        }
+        self.open.retain(|key, open| {
+            if key.starts_with(path) {
+                *open.path.lock().unwrap() = None;
+                return false;
+            }
+            true
+        });
+    }
+
+    /// Move everything at or under `from` to `to`, which it's been renamed
+    /// to in the tree.
+    fn rekey(&mut self, from: &Path, to: &Path) {
+        let moved = |key: &Path| to.join(key.strip_prefix(from).unwrap());
+        let keys: Vec<_> = self
+            .index
+            .keys()
+            .filter(|key| key.starts_with(from))
+            .cloned()
+            .collect();
+        for key in keys {
+            let hash = self.index.remove(&key).unwrap();
+            self.index.insert(moved(&key), hash);
+        }
+        let keys: Vec<_> = self
+            .open
+            .keys()
+            .filter(|key| key.starts_with(from))
+            .cloned()
+            .collect();
+        for key in keys {
+            let open = self.open.remove(&key).unwrap();
+            *open.path.lock().unwrap() = Some(moved(&key));
+            self.open.insert(moved(&key), open);
+        }
+    }
+}
+
+impl Cas {
+    /// The path that `path` is indexed by, with symlinks resolved. The last
+    /// component is only resolved if `follow` is set.
+    async fn key(&self, path: &Path, follow: bool) -> Result<PathBuf> {
+        let path = normalise(path);
+        if follow {
+            match self.tree.canonicalize(&path).await {
+                Ok(path) => return Ok(path),
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                Err(_) => {}
+            }
+        }
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => Ok(self.tree.canonicalize(parent).await?.join(name)),
+            _ => Ok(path),
+        }
+    }
+
+    /// Move the body of the file at `key` back into the tree, if it's not
+    /// there already, returning whether it was moved. Call with `moving`
+    /// held.
+    async fn materialise(&self, key: &Path) -> Result<bool> {
+        let Some(body) = self.state.lock().unwrap().body(key) else {
+            return Ok(false);
+        };
+        self.tree.write(key, &body).await?;
+        self.state.lock().unwrap().set(key.to_path_buf(), None);
+        Ok(true)
+    }
+
+    /// Move the body of the file at `key` from the tree into the blob store.
+    /// Call with `moving` held.
+    async fn store(&self, key: &Path) -> Result<()> {
+        let data = self.tree.read(key).await?;
+        self.tree.write(key, b"").await?;
+        let mut state = self.state.lock().unwrap();
+        let hash = state.store(&data);
+        state.set(key.to_path_buf(), Some(hash));
+        Ok(())
+    }
+
+    /// Store the body of a file whose last handle has been closed.
+    async fn commit(&self, slot: &Slot) -> Result<()> {
+        let _moving = self.moving.lock().await;
+        let Some(key) = slot.lock().unwrap().clone() else {
+            return Ok(());
+        };
+        {
+            let state = self.state.lock().unwrap();
+            match state.open.get(&key) {
+                // Opened again since.
+                Some(open) if open.handles > 0 || !Arc::ptr_eq(&open.path, slot) => return Ok(()),
+                Some(_) => {}
+                None => return Ok(()),
+            }
+        }
+        self.store(&key).await?;
+        self.state.lock().unwrap().open.remove(&key);
+        Ok(())
+    }
+
+    async fn metadata(&self, path: &Path, follow: bool) -> Result<CasMetadata> {
+        let _moving = self.moving.lock().await;
+        let key = self.key(path, follow).await?;
+        let metadata = if follow {
+            self.tree.metadata(&key).await?
+        } else {
+            self.tree.symlink_metadata(&key).await?
+        };
+        let len = self
+            .state
+            .lock()
+            .unwrap()
+            .body(&key)
+            .map(|body| body.len() as u64);
+        Ok(CasMetadata { metadata, len })
+    }
+}
+
+impl CasFloppyDisk {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            cas: Arc::new(Cas {
+                tree: MemFloppyDisk::new(),
+                state: Mutex::new(CasState::default()),
+                moving: tokio::sync::Mutex::new(()),
+            }),
+        }
+    }
+
+    /// What's stored now. Files that are open aren't counted until their
+    /// last handle is closed.
+    pub fn savings(&self) -> CasSavings {
+        let state = self.cas.state.lock().unwrap();
+        let len = |hash: &blake3::Hash| {
+            state
+                .blobs
+                .get(hash)
+                .map_or(0, |blob| blob.data.len() as u64)
+        };
+        CasSavings {
+            files: state.index.len() as u64,
+            blobs: state.blobs.len() as u64,
+            logical_bytes: state.index.values().map(len).sum(),
+            stored_bytes: state.blobs.keys().map(len).sum(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyDisk<'a> for CasFloppyDisk {
+    type DirBuilder = MemDirBuilder<'a>;
+    type DirEntry = CasDirEntry;
+    type File = CasFile;
+    type FileType = MemFileType;
+    type Metadata = CasMetadata;
+    type OpenOptions = CasOpenOptions;
+    type Permissions = MemPermissions;
+    type ReadDir = CasReadDir;
+
+    async fn canonicalize<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        self.cas.tree.canonicalize(path).await
+    }
+
+    async fn copy<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<u64> {
+        let data = self.read(from).await?;
+        self.write(to, &data).await?;
+        Ok(data.len() as u64)
+    }
+
+    async fn create_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.cas.tree.create_dir(path).await
+    }
+
+    async fn create_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.cas.tree.create_dir_all(path).await
+    }
+
+    /// Copies `src` to `dst`, sharing its body. See the [module docs](self).
+    async fn hard_link<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        if self.try_exists(dst.as_ref()).await? {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} already exists", dst.as_ref().display()),
+            ));
+        }
+        self.copy(src, dst).await.map(|_| ())
+    }
+
+    async fn metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        self.cas.metadata(path.as_ref(), true).await
+    }
+
+    async fn read<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>> {
+        let _moving = self.cas.moving.lock().await;
+        let key = self.cas.key(path.as_ref(), true).await?;
+        let body = self.cas.state.lock().unwrap().body(&key);
+        match body {
+            Some(body) => Ok(body.to_vec()),
+            None => self.cas.tree.read(&key).await,
+        }
+    }
+
+    async fn read_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::ReadDir> {
+        Ok(CasReadDir {
+            read_dir: self.cas.tree.read_dir(path).await?,
+            cas: self.cas.clone(),
+        })
+    }
+
+    async fn read_link<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        self.cas.tree.read_link(path).await
+    }
+
+    async fn read_to_string<P: AsRef<Path> + Send>(&self, path: P) -> Result<String> {
+        String::from_utf8(self.read(path).await?).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    async fn remove_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.cas.tree.remove_dir(path).await
+    }
+
+    async fn remove_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let _moving = self.cas.moving.lock().await;
+        let key = self.cas.key(path.as_ref(), false).await?;
+        self.cas.tree.remove_dir_all(&key).await?;
+        self.cas.state.lock().unwrap().forget_under(&key);
+        Ok(())
+    }
+
+    async fn remove_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let _moving = self.cas.moving.lock().await;
+        let key = self.cas.key(path.as_ref(), false).await?;
+        self.cas.tree.remove_file(&key).await?;
+        self.cas.state.lock().unwrap().forget_under(&key);
+        Ok(())
+    }
+
+    async fn rename<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<()> {
+        let _moving = self.cas.moving.lock().await;
+        let from = self.cas.key(from.as_ref(), false).await?;
+        let to = self.cas.key(to.as_ref(), false).await?;
+        self.cas.tree.rename(&from, &to).await?;
+        if from != to {
+            let mut state = self.cas.state.lock().unwrap();
+            state.forget_under(&to);
+            state.rekey(&from, &to);
+        }
+        Ok(())
+    }
+
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        perm: Self::Permissions,
+    ) -> Result<()> {
+        self.cas.tree.set_permissions(path, perm).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        self.cas.tree.symlink(src, dst).await
+    }
+
+    async fn symlink_metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        self.cas.metadata(path.as_ref(), false).await
+    }
+
+    async fn try_exists<P: AsRef<Path> + Send>(&self, path: P) -> Result<bool> {
+        self.cas.tree.try_exists(path).await
+    }
+
+    async fn write<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        let contents = contents.as_ref();
+        let _moving = self.cas.moving.lock().await;
+        let key = self.cas.key(path.as_ref(), true).await?;
+        // Open handles would otherwise keep reading the old body.
+        if self.cas.state.lock().unwrap().open.contains_key(&key) {
+            return self.cas.tree.write(&key, contents).await;
+        }
+        self.cas.tree.write(&key, b"").await?;
+        let mut state = self.cas.state.lock().unwrap();
+        let hash = state.store(contents);
+        state.set(key, Some(hash));
+        Ok(())
+    }
+
+    fn new_dir_builder(&'a self) -> Self::DirBuilder {
+        self.cas.tree.new_dir_builder()
+    }
+}
+
+#[async_trait::async_trait]
+impl FloppyDiskUnixExt for CasFloppyDisk {
+    async fn chown<P: Into<PathBuf> + Send>(&self, path: P, uid: u32, gid: u32) -> Result<()> {
+        self.cas.tree.chown(path, uid, gid).await
+    }
+}
+
+#[derive(Debug)]
+pub struct CasMetadata {
+    metadata: MemMetadata,
+    /// The length of the stored body, which the tree's empty node doesn't
+    /// know.
+    len: Option<u64>,
+}
+
+impl<'a> FloppyMetadata<'a, CasFloppyDisk> for CasMetadata {
+    fn file_type(&self) -> MemFileType {
+        FloppyMetadata::<MemFloppyDisk>::file_type(&self.metadata)
+    }
+
+    fn is_dir(&self) -> bool {
+        FloppyMetadata::<MemFloppyDisk>::is_dir(&self.metadata)
+    }
+
+    fn is_file(&self) -> bool {
+        FloppyMetadata::<MemFloppyDisk>::is_file(&self.metadata)
+    }
+
+    fn is_symlink(&self) -> bool {
+        FloppyMetadata::<MemFloppyDisk>::is_symlink(&self.metadata)
+    }
+
+    fn len(&self) -> u64 {
+        self.len
+            .unwrap_or_else(|| FloppyMetadata::<MemFloppyDisk>::len(&self.metadata))
+    }
+
+    fn permissions(&self) -> MemPermissions {
+        FloppyMetadata::<MemFloppyDisk>::permissions(&self.metadata)
+    }
+
+    fn modified(&self) -> Result<SystemTime> {
+        FloppyMetadata::<MemFloppyDisk>::modified(&self.metadata)
+    }
+
+    fn accessed(&self) -> Result<SystemTime> {
+        FloppyMetadata::<MemFloppyDisk>::accessed(&self.metadata)
+    }
+
+    fn created(&self) -> Result<SystemTime> {
+        FloppyMetadata::<MemFloppyDisk>::created(&self.metadata)
+    }
+}
+
+impl FloppyUnixMetadata for CasMetadata {
+    fn uid(&self) -> Result<u32> {
+        self.metadata.uid()
+    }
+
+    fn gid(&self) -> Result<u32> {
+        self.metadata.gid()
+    }
+
+    fn dev(&self) -> Result<u64> {
+        self.metadata.dev()
+    }
+
+    fn ino(&self) -> Result<u64> {
+        self.metadata.ino()
+    }
+
+    fn blocks(&self) -> Result<u64> {
+        self.metadata.blocks()
+    }
+}
+
+#[derive(Debug)]
+pub struct CasReadDir {
+    read_dir: MemReadDir,
+    cas: Arc<Cas>,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyReadDir<'a, CasFloppyDisk> for CasReadDir {
+    async fn next_entry(&mut self) -> Result<Option<CasDirEntry>> {
+        Ok(self.read_dir.next_entry().await?.map(|entry| CasDirEntry {
+            entry,
+            cas: self.cas.clone(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct CasDirEntry {
+    entry: MemDirEntry,
+    cas: Arc<Cas>,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyDirEntry<'a, CasFloppyDisk> for CasDirEntry {
+    fn path(&self) -> PathBuf {
+        FloppyDirEntry::<MemFloppyDisk>::path(&self.entry)
+    }
+
+    fn file_name(&self) -> OsString {
+        FloppyDirEntry::<MemFloppyDisk>::file_name(&self.entry)
+    }
+
+    async fn metadata(&self) -> Result<CasMetadata> {
+        self.cas.metadata(&self.path(), false).await
+    }
+
+    async fn file_type(&self) -> Result<MemFileType> {
+        FloppyDirEntry::<MemFloppyDisk>::file_type(&self.entry).await
+    }
+
+    #[cfg(unix)]
+    fn ino(&self) -> u64 {
+        FloppyDirEntry::<MemFloppyDisk>::ino(&self.entry)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CasOpenOptions {
+    options: MemOpenOptions,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyOpenOptions<'a, CasFloppyDisk> for CasOpenOptions {
+    fn new() -> Self {
+        Self {
+            options: MemOpenOptions::new(),
+        }
+    }
+
+    fn read(mut self, read: bool) -> Self {
+        self.options = self.options.read(read);
+        self
+    }
+
+    fn write(mut self, write: bool) -> Self {
+        self.options = self.options.write(write);
+        self
+    }
+
+    fn append(mut self, append: bool) -> Self {
+        self.options = self.options.append(append);
+        self
+    }
+
+    fn truncate(mut self, truncate: bool) -> Self {
+        self.options = self.options.truncate(truncate);
+        self
+    }
+
+    fn create(mut self, create: bool) -> Self {
+        self.options = self.options.create(create);
+        self
+    }
+
+    fn create_new(mut self, create_new: bool) -> Self {
+        self.options = self.options.create_new(create_new);
+        self
+    }
+
+    async fn open<P: AsRef<Path> + Send>(
+        &self,
+        disk: &'a CasFloppyDisk,
+        path: P,
+    ) -> Result<CasFile> {
+        let cas = &disk.cas;
+        let _moving = cas.moving.lock().await;
+        let key = cas.key(path.as_ref(), true).await?;
+        let materialised = cas.materialise(&key).await?;
+        let file = match self.options.open(&cas.tree, &key).await {
+            Ok(file) => file,
+            Err(e) => {
+                // Put the body back, eg. after a `create_new` of a file that
+                // exists.
+                if materialised {
+                    cas.store(&key).await?;
+                }
+                return Err(e);
+            }
+        };
+        let mut state = cas.state.lock().unwrap();
+        let open = state.open.entry(key.clone()).or_insert_with(|| OpenBody {
+            handles: 0,
+            path: Arc::new(Mutex::new(Some(key))),
+        });
+        open.handles += 1;
+        Ok(CasFile {
+            file,
+            cas: cas.clone(),
+            path: open.path.clone(),
+            released: false,
+        })
+    }
+}
+
+/// An open file, whose body is kept in the tree until its last handle is
+/// closed. [Closing](FloppyFile::close) it stores the body straight away;
+/// dropping it stores the body in the background.
+#[derive(Debug)]
+pub struct CasFile {
+    file: MemFile,
+    cas: Arc<Cas>,
+    path: Slot,
+    released: bool,
+}
+
+impl CasFile {
+    /// Give up this handle, returning whether it was the file's last.
+    fn release(&mut self) -> bool {
+        if std::mem::replace(&mut self.released, true) {
+            return false;
+        }
+        let Some(path) = self.path.lock().unwrap().clone() else {
+            return false;
+        };
+        match self.cas.state.lock().unwrap().open.get_mut(&path) {
+            Some(open) => {
+                open.handles -= 1;
+                open.handles == 0
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for CasFile {
+    fn drop(&mut self) {
+        if !self.release() {
+            return;
+        }
+        // Without a runtime the body just stays in the tree, which is only
+        // a missed chance to share it.
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let (cas, slot) = (self.cas.clone(), self.path.clone());
+            runtime.spawn(async move {
+                if let Err(e) = cas.commit(&slot).await {
+                    debug!("failed to store a closed file's body: {e}");
+                }
+            });
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyFile<'a, CasFloppyDisk> for CasFile {
+    async fn sync_all(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn sync_data(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn set_len(&mut self, size: u64) -> Result<()> {
+        self.file.set_len(size).await
+    }
+
+    async fn metadata(&self) -> Result<CasMetadata> {
+        Ok(CasMetadata {
+            metadata: self.file.metadata().await?,
+            len: None,
+        })
+    }
+
+    async fn try_clone(&'a self) -> Result<Box<CasFile>> {
+        let file = *self.file.try_clone().await?;
+        if let Some(path) = self.path.lock().unwrap().as_ref() {
+            if let Some(open) = self.cas.state.lock().unwrap().open.get_mut(path) {
+                open.handles += 1;
+            }
+        }
+        Ok(Box::new(CasFile {
+            file,
+            cas: self.cas.clone(),
+            path: self.path.clone(),
+            released: false,
+        }))
+    }
+
+    async fn set_permissions(&self, perm: MemPermissions) -> Result<()> {
+        self.file.set_permissions(perm).await
+    }
+
+    async fn permissions(&self) -> Result<MemPermissions> {
+        self.file.permissions().await
+    }
+
+    async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.file.read_at(buf, offset).await
+    }
+
+    async fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        self.file.write_at(buf, offset).await
+    }
+
+    async fn allocate(&self, offset: u64, len: u64) -> Result<()> {
+        self.file.allocate(offset, len).await
+    }
+
+    async fn close(mut self) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.file.flush().await?;
+        if self.release() {
+            self.cas.commit(&self.path).await?;
+        }
+        Ok(())
+    }
+}
+
+impl AsyncRead for CasFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for CasFile {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.get_mut().file).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize>> {
+        Pin::new(&mut self.get_mut().file).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.file.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_shutdown(cx)
+    }
+}
+
+impl AsyncSeek for CasFile {
+    fn start_seek(self: Pin<&mut Self>, position: std::io::SeekFrom) -> Result<()> {
+        Pin::new(&mut self.get_mut().file).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        Pin::new(&mut self.get_mut().file).poll_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cas_dedup() -> Result<()> {
+        let disk = CasFloppyDisk::new();
+        disk.create_dir_all("/pkg/lib").await?;
+        disk.write("/a", "same").await?;
+        disk.write("/pkg/lib/b", "same").await?;
+        disk.write("/pkg/c", "other").await?;
+        assert_eq!(
+            CasSavings {
+                files: 3,
+                blobs: 2,
+                logical_bytes: 13,
+                stored_bytes: 9,
+            },
+            disk.savings()
+        );
+        assert_eq!(4, disk.savings().saved_bytes());
+
+        assert_eq!("same", disk.read_to_string("/pkg/lib/b").await?);
+        assert_eq!(5, disk.metadata("/pkg/c").await?.len());
+        let mut read_dir = disk.read_dir("/pkg").await?;
+        let mut lens = vec![];
+        while let Some(entry) = read_dir.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() {
+                lens.push((entry.file_name(), metadata.len()));
+            }
+        }
+        assert_eq!(vec![(OsString::from("c"), 5)], lens);
+
+        disk.symlink("/pkg/lib/b", "/link").await?;
+        assert_eq!("same", disk.read_to_string("/link").await?);
+        assert!(disk.symlink_metadata("/link").await?.is_symlink());
+        disk.hard_link("/pkg/c", "/d").await?;
+        assert_eq!("other", disk.read_to_string("/d").await?);
+
+        disk.remove_file("/a").await?;
+        disk.write("/link", "changed").await?;
+        assert_eq!("changed", disk.read_to_string("/pkg/lib/b").await?);
+        disk.rename("/pkg", "/lib").await?;
+        assert_eq!("changed", disk.read_to_string("/lib/lib/b").await?);
+        assert!(disk.read("/pkg/c").await.is_err());
+        assert_eq!(
+            CasSavings {
+                files: 3,
+                blobs: 2,
+                logical_bytes: 17,
+                stored_bytes: 12,
+            },
+            disk.savings()
+        );
+
+        disk.remove_dir_all("/lib").await?;
+        disk.remove_file("/d").await?;
+        assert_eq!(0, disk.savings().stored_bytes);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cas_files() -> Result<()> {
+        let disk = CasFloppyDisk::new();
+        disk.write("/a", "asdf").await?;
+
+        let mut file = CasOpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&disk, "/b")
+            .await?;
+        file.write_all(b"as").await?;
+        file.write_all(b"df").await?;
+        assert_eq!("asdf", disk.read_to_string("/b").await?);
+        assert_eq!(1, disk.savings().files);
+        file.close().await?;
+        assert_eq!(2, disk.savings().files);
+        assert_eq!(1, disk.savings().blobs);
+
+        let mut file = CasOpenOptions::new().append(true).open(&disk, "/a").await?;
+        let mut reader = CasOpenOptions::new().read(true).open(&disk, "/a").await?;
+        file.write_all(b"!").await?;
+        drop(file);
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).await?;
+        assert_eq!("asdf!", contents);
+        drop(reader);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(2, disk.savings().blobs);
+        assert_eq!("asdf!", disk.read_to_string("/a").await?);
+        assert_eq!(5, disk.metadata("/a").await?.len());
+
+        // Renamed while open, and stored under its new name.
+        let mut file = CasOpenOptions::new().write(true).open(&disk, "/b").await?;
+        disk.rename("/b", "/c").await?;
+        file.write_all(b"qwer").await?;
+        file.close().await?;
+        assert_eq!("qwer", disk.read_to_string("/c").await?);
+        assert_eq!(2, disk.savings().files);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cas_rename_over_open() -> Result<()> {
+        let disk = CasFloppyDisk::new();
+        disk.write("/a", "asdf").await?;
+        disk.write("/b", "hjkl").await?;
+
+        // The open file is replaced, so closing it mustn't store its body
+        // over what was renamed there.
+        let mut file = CasOpenOptions::new().write(true).open(&disk, "/a").await?;
+        disk.rename("/b", "/a").await?;
+        file.write_all(b"qwer").await?;
+        file.close().await?;
+        assert_eq!("hjkl", disk.read_to_string("/a").await?);
+        assert!(!disk.try_exists("/b").await?);
+        assert_eq!(
+            CasSavings {
+                files: 1,
+                blobs: 1,
+                logical_bytes: 4,
+                stored_bytes: 4,
+            },
+            disk.savings()
+        );
+
+        // Renaming an open file away and another over its old name.
+        let mut file = CasOpenOptions::new().write(true).open(&disk, "/a").await?;
+        disk.write("/c", "zxcv").await?;
+        disk.rename("/a", "/d").await?;
+        disk.rename("/c", "/a").await?;
+        file.write_all(b"uiop").await?;
+        file.close().await?;
+        assert_eq!("uiop", disk.read_to_string("/d").await?);
+        assert_eq!("zxcv", disk.read_to_string("/a").await?);
+        assert_eq!(2, disk.savings().files);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cas_remove_open() -> Result<()> {
+        let disk = CasFloppyDisk::new();
+        disk.create_dir("/dir").await?;
+        disk.write("/dir/a", "asdf").await?;
+        disk.write("/b", "asdf").await?;
+
+        let mut file = CasOpenOptions::new()
+            .write(true)
+            .open(&disk, "/dir/a")
+            .await?;
+        disk.remove_dir_all("/dir").await?;
+        file.write_all(b"hjkl").await?;
+        file.close().await?;
+        assert!(!disk.try_exists("/dir").await?);
+        assert_eq!(
+            CasSavings {
+                files: 1,
+                blobs: 1,
+                logical_bytes: 4,
+                stored_bytes: 4,
+            },
+            disk.savings()
+        );
+
+        // Recreated under the same name while an old handle is still open.
+        let file = CasOpenOptions::new().read(true).open(&disk, "/b").await?;
+        disk.remove_file("/b").await?;
+        disk.write("/b", "qwer").await?;
+        drop(file);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!("qwer", disk.read_to_string("/b").await?);
+        assert_eq!(1, disk.savings().files);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cas_concurrent() -> Result<()> {
+        let disk = Arc::new(CasFloppyDisk::new());
+        disk.write("/a", "").await?;
+
+        // Handles to one file share its body, which is only stored once the
+        // last of them is closed.
+        let mut first = CasOpenOptions::new()
+            .append(true)
+            .open(&*disk, "/a")
+            .await?;
+        let mut second = first.try_clone().await?;
+        let mut third = CasOpenOptions::new()
+            .append(true)
+            .open(&*disk, "/a")
+            .await?;
+        first.write_all(b"as").await?;
+        second.write_all(b"df").await?;
+        first.close().await?;
+        second.close().await?;
+        assert_eq!(0, disk.savings().files);
+        third.write_all(b"!").await?;
+        third.close().await?;
+        assert_eq!("asdf!", disk.read_to_string("/a").await?);
+        assert_eq!(1, disk.savings().files);
+
+        let tasks: Vec<_> = (0..16)
+            .map(|i| {
+                let disk = disk.clone();
+                tokio::spawn(async move {
+                    let path = format!("/{i}");
+                    let mut file = CasOpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .open(&*disk, &path)
+                        .await?;
+                    file.write_all(b"same").await?;
+                    file.close().await?;
+                    disk.read_to_string(&path).await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!("same", task.await??);
+        }
+        assert_eq!(
+            CasSavings {
+                files: 17,
+                blobs: 2,
+                logical_bytes: 69,
+                stored_bytes: 9,
+            },
+            disk.savings()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cas_errors() -> Result<()> {
+        let disk = CasFloppyDisk::new();
+        disk.create_dir("/dir").await?;
+        disk.write("/dir/a", "asdf").await?;
+        let savings = disk.savings();
+
+        let missing = disk.read("/missing").await.unwrap_err();
+        assert_eq!(ErrorKind::NotFound, missing.kind());
+        assert!(disk.metadata("/missing").await.is_err());
+        assert!(disk.write("/missing/a", "asdf").await.is_err());
+        assert!(disk.copy("/missing", "/b").await.is_err());
+        assert!(disk.rename("/missing", "/b").await.is_err());
+        assert!(disk.remove_file("/missing").await.is_err());
+        assert!(disk.remove_dir("/dir").await.is_err());
+        let linked = disk.hard_link("/dir/a", "/dir/a").await.unwrap_err();
+        assert_eq!(ErrorKind::AlreadyExists, linked.kind());
+        let opened = CasOpenOptions::new()
+            .read(true)
+            .open(&disk, "/missing")
+            .await
+            .unwrap_err();
+        assert_eq!(ErrorKind::NotFound, opened.kind());
+        assert!(CasOpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&disk, "/dir/a")
+            .await
+            .is_err());
+
+        // Nothing failed halfway.
+        assert_eq!(savings, disk.savings());
+        assert_eq!("asdf", disk.read_to_string("/dir/a").await?);
+        assert!(!disk.try_exists("/b").await?);
+
+        disk.write("/bad", [0xff, 0xfe]).await?;
+        let invalid = disk.read_to_string("/bad").await.unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, invalid.kind());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! Sharing one read between everyone who asks for the same file at once.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{Error, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use futures::future::{BoxFuture, FutureExt, Shared};
+use tracing::debug;
+
+use crate::FloppyDisk;
+
+type SharedRead = Shared<BoxFuture<'static, std::result::Result<Arc<Vec<u8>>, Arc<Error>>>>;
+
+/// Reads whole files from a disk, so that concurrent reads of the same path
+/// are served by a single read of the disk. Nothing is cached: once a read
+/// finishes, the next one goes back to the disk.
+#[derive(Debug)]
+pub struct CoalescingReader<D> {
+    disk: Arc<D>,
+    in_flight: Mutex<HashMap<PathBuf, SharedRead>>,
+}
+
+impl<D> CoalescingReader<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    pub fn new(disk: Arc<D>) -> Self {
+        Self {
+            disk,
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn disk(&self) -> &Arc<D> {
+        &self.disk
+    }
+
+    /// Read the file at `path`, joining a read of it that's already running.
+    /// The read is registered when this is called, rather than when the
+    /// returned future is first polled.
+    pub fn read<P: AsRef<Path>>(&self, path: P) -> impl Future<Output = Result<Arc<Vec<u8>>>> + '_ {
+        let path = path.as_ref().to_path_buf();
+        let read = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            in_flight
+                .entry(path.clone())
+                .or_insert_with(|| {
+                    debug!("reading {}", path.display());
+                    let disk = self.disk.clone();
+                    let path = path.clone();
+                    async move { disk.read(path).await.map(Arc::new).map_err(Arc::new) }
+                        .boxed()
+                        .shared()
+                })
+                .clone()
+        };
+
+        async move {
+            let result = read.clone().await;
+            {
+                // A newer read may already have taken this one's place.
+                let mut in_flight = self.in_flight.lock().unwrap();
+                if in_flight
+                    .get(&path)
+                    .is_some_and(|current| current.ptr_eq(&read))
+                {
+                    in_flight.remove(&path);
+                }
+            }
+            result.map_err(|e| Error::new(e.kind(), e.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_coalesced_reads() -> Result<()> {
+        let disk = Arc::new(MemFloppyDisk::new());
+        disk.write("/a.txt", "asdf").await?;
+        let reader = CoalescingReader::new(disk.clone());
+
+        let (a, b) = (reader.read("/a.txt"), reader.read("/a.txt"));
+        let (a, b) = futures::try_join!(a, b)?;
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(b"asdf", &a[..]);
+        assert!(reader.in_flight.lock().unwrap().is_empty());
+
+        disk.write("/a.txt", "jkl").await?;
+        assert_eq!(b"jkl", &reader.read("/a.txt").await?[..]);
+        assert_eq!(
+            std::io::ErrorKind::NotFound,
+            reader.read("/b.txt").await.unwrap_err().kind()
+        );
+
+        Ok(())
+    }
+}
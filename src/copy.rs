@@ -0,0 +1,317 @@
+//! Copying trees between disks, which may be different backends.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use crate::{
+    FloppyDirEntry, FloppyDisk, FloppyFile, FloppyMetadata, FloppyOpenOptions, FloppyReadDir,
+    FloppyUnixPermissions,
+};
+
+/// How [`copy_all`] copies a tree.
+#[derive(Debug, Clone)]
+pub struct CopyOptions {
+    overwrite: bool,
+    permissions: bool,
+    follow_symlinks: bool,
+}
+
+impl CopyOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace files that already exist at the destination, rather than
+    /// failing. On by default.
+    pub fn with_overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Copy unix modes to the destination. On by default.
+    pub fn with_permissions(mut self, permissions: bool) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Copy what symlinks point to, rather than the symlinks themselves. A
+    /// symlink back to a directory that's already being copied is copied as
+    /// an empty directory, rather than looping forever.
+    pub fn with_follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            overwrite: true,
+            permissions: true,
+            follow_symlinks: false,
+        }
+    }
+}
+
+/// Copy `src_root` on `src` to `dst_root` on `dst`, along with everything
+/// under it. Returns the number of bytes of file contents copied.
+///
+/// Files are streamed, so they never have to fit in memory. Copying a tree
+/// onto itself or into itself on the same disk fails with `InvalidInput`.
+/// Only `src` and `dst` being the same disk value is detected: two disks
+/// over the same storage, eg. two `TokioFloppyDisk`s scoped to the same
+/// directory, look like different disks.
+///
+/// Whatever is in the way of a file or symlink at the destination is
+/// removed first when overwriting, so that symlinks and hard links there are
+/// replaced rather than written through.
+pub async fn copy_all<'a, 'b, S, T>(
+    src: &'a S,
+    src_root: impl AsRef<Path> + Send,
+    dst: &'b T,
+    dst_root: impl AsRef<Path> + Send,
+    options: &CopyOptions,
+) -> Result<u64>
+where
+    S: FloppyDisk<'a>,
+    T: FloppyDisk<'b>,
+    S::Permissions: FloppyUnixPermissions,
+    T::Permissions: FloppyUnixPermissions,
+{
+    let (src_root, dst_root) = (src_root.as_ref(), dst_root.as_ref());
+    if std::ptr::eq(src as *const S as *const (), dst as *const T as *const ()) {
+        let from = src.canonicalize(src_root).await?;
+        let to = canonicalize_destination(dst, dst_root).await?;
+        if to.starts_with(&from) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "can't copy {} into itself at {}",
+                    src_root.display(),
+                    dst_root.display()
+                ),
+            ));
+        }
+    }
+
+    let mut copied = 0;
+    // Directory modes are applied last, so a read-only directory can still be
+    // filled in.
+    let mut dir_modes = vec![];
+    // Each directory's canonical ancestors, to spot symlink loops when
+    // following symlinks.
+    let mut stack = vec![(src_root.to_path_buf(), dst_root.to_path_buf(), vec![])];
+    while let Some((from, to, mut ancestors)) = stack.pop() {
+        let metadata = if options.follow_symlinks {
+            src.metadata(&from).await?
+        } else {
+            src.symlink_metadata(&from).await?
+        };
+        let mode = metadata.permissions().mode();
+        debug!("copying {} to {}", from.display(), to.display());
+
+        if metadata.is_symlink() {
+            let target = src.read_link(&from).await?;
+            if options.overwrite {
+                remove_existing(dst, &to).await?;
+            }
+            dst.symlink(target, to).await?;
+        } else if metadata.is_dir() {
+            dst.create_dir_all(&to).await?;
+            if options.follow_symlinks {
+                let canonical = src.canonicalize(&from).await?;
+                if ancestors.contains(&canonical) {
+                    debug!("not copying symlink loop at {}", from.display());
+                    continue;
+                }
+                ancestors.push(canonical);
+            }
+            let mut read_dir = src.read_dir(&from).await?;
+            while let Some(entry) = read_dir.next_entry().await? {
+                stack.push((entry.path(), to.join(entry.file_name()), ancestors.clone()));
+            }
+            if options.permissions {
+                dir_modes.push((to, mode));
+            }
+        } else {
+            if options.overwrite {
+                remove_existing(dst, &to).await?;
+            }
+            let mut reader = S::OpenOptions::new().read(true).open(src, &from).await?;
+            let mut writer = T::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .create_new(!options.overwrite)
+                .truncate(true)
+                .open(dst, &to)
+                .await?;
+            copied += tokio::io::copy(&mut reader, &mut writer).await?;
+            writer.close().await?;
+            if options.permissions {
+                dst.set_permissions(&to, FloppyUnixPermissions::from_mode(mode))
+                    .await?;
+            }
+        }
+    }
+
+    for (path, mode) in dir_modes.into_iter().rev() {
+        dst.set_permissions(&path, FloppyUnixPermissions::from_mode(mode))
+            .await?;
+    }
+    Ok(copied)
+}
+
+/// Canonicalize `path`, which may not exist yet, by canonicalizing its
+/// nearest ancestor that does.
+async fn canonicalize_destination<'a, D: FloppyDisk<'a>>(disk: &D, path: &Path) -> Result<PathBuf> {
+    let mut missing = vec![];
+    let mut ancestor = path;
+    loop {
+        match disk.canonicalize(ancestor).await {
+            Ok(canonical) => {
+                return Ok(missing
+                    .into_iter()
+                    .rev()
+                    .fold(canonical, |path, name| path.join(name)))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => match ancestor.parent() {
+                Some(parent) => {
+                    missing.extend(ancestor.file_name());
+                    ancestor = parent;
+                }
+                None => return Err(e),
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Remove whatever non-directory is at `path`, so a file or symlink can
+/// replace it without writing through it.
+async fn remove_existing<'a, D: FloppyDisk<'a>>(disk: &D, path: &Path) -> Result<()> {
+    match disk.symlink_metadata(path).await {
+        Ok(metadata) if !metadata.is_dir() => disk.remove_file(path).await,
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+    use crate::temp::TempFloppyDisk;
+
+    #[tokio::test]
+    async fn test_copy_all_across_backends() -> Result<()> {
+        let src = MemFloppyDisk::new();
+        src.create_dir_all("/src/a/b").await?;
+        src.write("/src/a/b/c.txt", "asdf").await?;
+        src.set_permissions("/src/a/b/c.txt", FloppyUnixPermissions::from_mode(0o600))
+            .await?;
+        src.write("/src/d.txt", "jkl").await?;
+        src.symlink("a/b/c.txt", "/src/e").await?;
+
+        let dst = TempFloppyDisk::new().await?;
+        let options = CopyOptions::new().with_follow_symlinks(true);
+        let copied = copy_all(&src, "/src", &*dst, "/dst", &options).await?;
+        assert_eq!(11, copied);
+        assert_eq!("asdf", dst.read_to_string("/dst/a/b/c.txt").await?);
+        assert!(dst.symlink_metadata("/dst/e").await?.is_file());
+        let metadata = dst.metadata("/dst/a/b/c.txt").await?;
+        assert_eq!(0o600, metadata.permissions().mode() & 0o777);
+
+        let dst = MemFloppyDisk::new();
+        copy_all(&src, "/src", &dst, "/dst", &CopyOptions::new()).await?;
+        // Copying again replaces everything, symlinks included.
+        copy_all(&src, "/src", &dst, "/dst", &CopyOptions::new()).await?;
+        assert_eq!(
+            "a/b/c.txt",
+            dst.read_link("/dst/e").await?.to_str().unwrap()
+        );
+        let err = copy_all(
+            &src,
+            "/src/d.txt",
+            &dst,
+            "/dst/d.txt",
+            &CopyOptions::new().with_overwrite(false),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(ErrorKind::AlreadyExists, err.kind());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_all_replaces_symlinks() -> Result<()> {
+        let src = MemFloppyDisk::new();
+        src.create_dir("/src").await?;
+        src.write("/src/f", "new").await?;
+
+        let dst = MemFloppyDisk::new();
+        dst.create_dir("/dst").await?;
+        dst.write("/outside", "untouched").await?;
+        dst.symlink("/outside", "/dst/f").await?;
+        copy_all(&src, "/src", &dst, "/dst", &CopyOptions::new()).await?;
+        assert!(dst.symlink_metadata("/dst/f").await?.is_file());
+        assert_eq!("new", dst.read_to_string("/dst/f").await?);
+        assert_eq!("untouched", dst.read_to_string("/outside").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_all_symlink_loop() -> Result<()> {
+        let src = MemFloppyDisk::new();
+        src.create_dir_all("/src/a").await?;
+        src.write("/src/a/f", "asdf").await?;
+        src.symlink("/src", "/src/a/loop").await?;
+
+        let dst = MemFloppyDisk::new();
+        let options = CopyOptions::new().with_follow_symlinks(true);
+        copy_all(&src, "/src", &dst, "/dst", &options).await?;
+        assert_eq!("asdf", dst.read_to_string("/dst/a/f").await?);
+        assert!(dst.metadata("/dst/a/loop").await?.is_dir());
+        assert!(dst
+            .read_dir("/dst/a/loop")
+            .await?
+            .next_entry()
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_all_overlapping() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/d").await?;
+        fs.write("/d/f", "asdf").await?;
+        fs.symlink("/d", "/link").await?;
+
+        for (from, to) in [
+            ("/d/f", "/d/f"),
+            ("/d", "/d"),
+            ("/d", "/d/sub"),
+            ("/d", "/d/sub/deeper"),
+            ("/d", "/link/sub"),
+        ] {
+            let err = copy_all(&fs, from, &fs, to, &CopyOptions::new())
+                .await
+                .unwrap_err();
+            assert_eq!(ErrorKind::InvalidInput, err.kind());
+        }
+        assert_eq!("asdf", fs.read_to_string("/d/f").await?);
+        assert!(!fs.try_exists("/d/sub").await?);
+
+        // Siblings that share a prefix don't overlap.
+        copy_all(&fs, "/d", &fs, "/dd", &CopyOptions::new()).await?;
+        assert_eq!("asdf", fs.read_to_string("/dd/f").await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+//! Human-readable views of what's on a disk, for debugging and snapshot
+//! tests.
+
+use std::io::Result;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::diff::EntryKind;
+use crate::sorted::read_dir_sorted;
+use crate::{
+    FloppyDirEntry, FloppyDisk, FloppyMetadata, FloppyReadDir, FloppyUnixMetadata,
+    FloppyUnixPermissions,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    sizes: bool,
+    modes: bool,
+}
+
+impl RenderOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Show each file's size in bytes.
+    pub fn with_sizes(mut self, sizes: bool) -> Self {
+        self.sizes = sizes;
+        self
+    }
+
+    /// Show each entry's permission bits, in octal.
+    pub fn with_modes(mut self, modes: bool) -> Self {
+        self.modes = modes;
+        self
+    }
+}
+
+/// Everything under `root`, drawn like the `tree` command. Entries are
+/// sorted by name, so the output is the same on every backend:
+///
+/// ```text
+/// /root
+/// ├── a
+/// │   └── b.txt
+/// └── c -> a/b.txt
+/// ```
+pub async fn render_tree<'a, D>(
+    disk: &'a D,
+    root: impl AsRef<Path> + Send,
+    options: &RenderOptions,
+) -> Result<String>
+where
+    D: FloppyDisk<'a>,
+    D::Permissions: FloppyUnixPermissions,
+{
+    let root = root.as_ref();
+    let mut out = format!("{}\n", root.display());
+    // Directories being drawn, innermost last, with the prefix for their
+    // children's lines.
+    let mut stack = vec![(
+        read_dir_sorted(disk, root).await?,
+        root.to_path_buf(),
+        String::new(),
+    )];
+    while let Some((read_dir, dir, prefix)) = stack.last_mut() {
+        let Some(entry) = read_dir.next_entry().await? else {
+            stack.pop();
+            continue;
+        };
+        let path = dir.join(entry.file_name());
+        let metadata = disk.symlink_metadata(&path).await?;
+        // Sorted listings are read up front, so whether this is the last
+        // entry is known before drawing it.
+        let last = read_dir.is_empty();
+        let (branch, indent) = if last {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+
+        let mut line = format!("{prefix}{branch}");
+        if options.modes {
+            line.push_str(&format!(
+                "[{:04o}] ",
+                metadata.permissions().mode() & 0o7777
+            ));
+        }
+        if options.sizes && metadata.is_file() {
+            line.push_str(&format!("[{}] ", metadata.len()));
+        }
+        line.push_str(&entry.file_name().to_string_lossy());
+        if metadata.is_symlink() {
+            let target = disk.read_link(&path).await?;
+            line.push_str(&format!(" -> {}", target.display()));
+        }
+        out.push_str(&line);
+        out.push('\n');
+
+        if metadata.is_dir() {
+            let prefix = format!("{prefix}{indent}");
+            stack.push((read_dir_sorted(disk, &path).await?, path, prefix));
+        }
+    }
+    Ok(out)
+}
+
+/// The entries of `dir`, sorted by name, one per line like `ls -l`:
+///
+/// ```text
+/// drwxr-xr-x 1000 1000    0 2024-01-02 03:04 a
+/// -rw-r--r-- 1000 1000 1234 2024-01-02 03:04 b.txt
+/// lrwxrwxrwx 1000 1000    5 2024-01-02 03:04 c -> b.txt
+/// ```
+///
+/// Owners are numeric, and times are UTC.
+pub async fn list_long<'a, D>(disk: &'a D, dir: impl AsRef<Path> + Send) -> Result<String>
+where
+    D: FloppyDisk<'a>,
+    D::Metadata: FloppyUnixMetadata,
+    D::Permissions: FloppyUnixPermissions,
+{
+    let dir = dir.as_ref();
+    let mut rows = vec![];
+    let mut read_dir = read_dir_sorted(disk, dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let path = dir.join(entry.file_name());
+        let metadata = disk.symlink_metadata(&path).await?;
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        let kind = if metadata.is_symlink() {
+            let target = disk.read_link(&path).await?;
+            name.push_str(&format!(" -> {}", target.display()));
+            EntryKind::Symlink
+        } else if metadata.is_dir() {
+            EntryKind::Dir
+        } else {
+            EntryKind::File
+        };
+        rows.push([
+            mode_string(kind, metadata.permissions().mode()),
+            metadata.uid()?.to_string(),
+            metadata.gid()?.to_string(),
+            metadata.len().to_string(),
+            format_time(metadata.modified()?),
+            name,
+        ]);
+    }
+
+    // Everything but the name is padded to line up, with numbers to the
+    // right.
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for [mode, uid, gid, len, time, name] in rows {
+        out.push_str(&format!(
+            "{mode:<w0$} {uid:<w1$} {gid:<w2$} {len:>w3$} {time} {name}\n",
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        ));
+    }
+    Ok(out)
+}
+
+/// A mode like `ls -l` shows it, eg. `drwxr-xr-x`.
+pub fn mode_string(kind: EntryKind, mode: u32) -> String {
+    let mut out = String::with_capacity(10);
+    out.push(match kind {
+        EntryKind::Dir => 'd',
+        EntryKind::File => '-',
+        EntryKind::Symlink => 'l',
+    });
+    // setuid, setgid and sticky replace the x of the owner, group and other
+    // bits respectively, in lowercase if the x is also set.
+    for (shift, special, letter) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = (mode >> shift) & 0o7;
+        out.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        out.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        out.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => letter,
+            (false, true) => letter.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    out
+}
+
+/// `YYYY-MM-DD HH:MM`, in UTC.
+fn format_time(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Howard Hinnant's days-to-civil algorithm.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}",
+        secs / 3600,
+        secs % 3600 / 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_render_tree() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root/a/b").await?;
+        fs.write("/root/a/b/c.txt", "asdf").await?;
+        fs.write("/root/a/d.txt", "").await?;
+        fs.symlink("a/d.txt", "/root/e").await?;
+
+        assert_eq!(
+            "/root\n\
+             ├── a\n\
+             │   ├── b\n\
+             │   │   └── c.txt\n\
+             │   └── d.txt\n\
+             └── e -> a/d.txt\n",
+            render_tree(&fs, "/root", &RenderOptions::new()).await?
+        );
+
+        fs.set_permissions("/root/a/b/c.txt", FloppyUnixPermissions::from_mode(0o600))
+            .await?;
+        let rendered = render_tree(
+            &fs,
+            "/root/a/b",
+            &RenderOptions::new().with_sizes(true).with_modes(true),
+        )
+        .await?;
+        assert_eq!("/root/a/b\n└── [0600] [4] c.txt\n", rendered);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_long() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root/a").await?;
+        fs.write("/root/b.txt", "x".repeat(1234)).await?;
+        fs.symlink("b.txt", "/root/c").await?;
+        fs.set_permissions("/root/a", FloppyUnixPermissions::from_mode(0o755))
+            .await?;
+        fs.set_permissions("/root/b.txt", FloppyUnixPermissions::from_mode(0o644))
+            .await?;
+
+        let listing = list_long(&fs, "/root").await?;
+        let lines: Vec<Vec<&str>> = listing
+            .lines()
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(3, lines.len());
+        assert_eq!(["drwxr-xr-x", "1000", "1000"], lines[0][..3]);
+        assert_eq!("a", lines[0][6]);
+        assert_eq!(["-rw-r--r--", "1000", "1000", "1234"], lines[1][..4]);
+        assert_eq!(["c", "->", "b.txt"], lines[2][6..]);
+        // Sizes line up on the right.
+        let ends: Vec<_> = listing
+            .lines()
+            .map(|line| line.find(" 20").unwrap())
+            .collect();
+        assert!(ends.iter().all(|end| *end == ends[0]));
+
+        assert_eq!("-rwsr-S--T", mode_string(EntryKind::File, 0o7740));
+        assert_eq!("1970-01-01 00:00", format_time(UNIX_EPOCH));
+        assert_eq!(
+            "2024-02-29 13:05",
+            format_time(UNIX_EPOCH + std::time::Duration::from_secs(1709211900))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+//! Comparing the trees on two disks, eg. to check what a build step changed.
+
+use std::collections::BTreeMap;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{FloppyDirEntry, FloppyDisk, FloppyMetadata, FloppyReadDir, FloppyUnixPermissions};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// What an entry looked like on one side of a diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryInfo {
+    pub kind: EntryKind,
+    pub len: u64,
+    pub mode: u32,
+    /// Where the entry points, if it's a symlink.
+    pub target: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(EntryInfo),
+    Removed(EntryInfo),
+    Modified {
+        before: EntryInfo,
+        after: EntryInfo,
+        /// A line diff of the file's contents, if asked for and both sides
+        /// are UTF-8 text.
+        diff: Option<String>,
+    },
+}
+
+/// Every difference between two trees, by path relative to their roots.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeDiff {
+    pub changes: BTreeMap<PathBuf, Change>,
+}
+
+impl TreeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn added(&self) -> Vec<&Path> {
+        self.paths(|change| matches!(change, Change::Added(_)))
+    }
+
+    pub fn removed(&self) -> Vec<&Path> {
+        self.paths(|change| matches!(change, Change::Removed(_)))
+    }
+
+    pub fn modified(&self) -> Vec<&Path> {
+        self.paths(|change| matches!(change, Change::Modified { .. }))
+    }
+
+    fn paths(&self, filter: impl Fn(&Change) -> bool) -> Vec<&Path> {
+        self.changes
+            .iter()
+            .filter(|(_, change)| filter(change))
+            .map(|(path, _)| path.as_path())
+            .collect()
+    }
+}
+
+/// How [`tree_diff`] compares trees.
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    content_diffs: bool,
+}
+
+impl DiffOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Include line diffs of modified text files.
+    pub fn with_content_diffs(mut self, content_diffs: bool) -> Self {
+        self.content_diffs = content_diffs;
+        self
+    }
+}
+
+/// Compare `a_root` on `a` to `b_root` on `b`. Entries are modified if their
+/// kind, mode, symlink target, or contents differ; modification times are
+/// ignored, since they never match across disks.
+pub async fn tree_diff<'a, 'b, A, B>(
+    a: &'a A,
+    a_root: impl AsRef<Path> + Send,
+    b: &'b B,
+    b_root: impl AsRef<Path> + Send,
+    options: &DiffOptions,
+) -> Result<TreeDiff>
+where
+    A: FloppyDisk<'a>,
+    B: FloppyDisk<'b>,
+    A::Permissions: FloppyUnixPermissions,
+    B::Permissions: FloppyUnixPermissions,
+{
+    let (a_root, b_root) = (a_root.as_ref(), b_root.as_ref());
+    let mut before = entries(a, a_root).await?;
+    let after = entries(b, b_root).await?;
+    let mut diff = TreeDiff::default();
+    for (path, after) in after {
+        let Some(before) = before.remove(&path) else {
+            diff.changes.insert(path, Change::Added(after));
+            continue;
+        };
+        if before != after {
+            let diff_text = if before.kind == EntryKind::File && after.kind == EntryKind::File {
+                text_diff(a, &a_root.join(&path), b, &b_root.join(&path), options).await?
+            } else {
+                None
+            };
+            diff.changes.insert(
+                path,
+                Change::Modified {
+                    before,
+                    after,
+                    diff: diff_text,
+                },
+            );
+        } else if after.kind == EntryKind::File {
+            let (old, new) = (
+                a.read(a_root.join(&path)).await?,
+                b.read(b_root.join(&path)).await?,
+            );
+            if old != new {
+                let diff_text = options
+                    .content_diffs
+                    .then(|| line_diff(&old, &new))
+                    .flatten();
+                diff.changes.insert(
+                    path,
+                    Change::Modified {
+                        before,
+                        after,
+                        diff: diff_text,
+                    },
+                );
+            }
+        }
+    }
+    for (path, before) in before {
+        diff.changes.insert(path, Change::Removed(before));
+    }
+    Ok(diff)
+}
+
+async fn entries<'a, D>(disk: &D, root: &Path) -> Result<BTreeMap<PathBuf, EntryInfo>>
+where
+    D: FloppyDisk<'a>,
+    D::Permissions: FloppyUnixPermissions,
+{
+    let mut entries = BTreeMap::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut read_dir = disk.read_dir(&dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+            let metadata = disk.symlink_metadata(&path).await?;
+            let (kind, target) = if metadata.is_symlink() {
+                (EntryKind::Symlink, Some(disk.read_link(&path).await?))
+            } else if metadata.is_dir() {
+                dirs.push(path.clone());
+                (EntryKind::Dir, None)
+            } else {
+                (EntryKind::File, None)
+            };
+            let info = EntryInfo {
+                kind,
+                len: if kind == EntryKind::File {
+                    metadata.len()
+                } else {
+                    0
+                },
+                mode: metadata.permissions().mode() & 0o7777,
+                target,
+            };
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            entries.insert(relative, info);
+        }
+    }
+    Ok(entries)
+}
+
+async fn text_diff<'a, 'b, A: FloppyDisk<'a>, B: FloppyDisk<'b>>(
+    a: &A,
+    a_path: &Path,
+    b: &B,
+    b_path: &Path,
+    options: &DiffOptions,
+) -> Result<Option<String>> {
+    if !options.content_diffs {
+        return Ok(None);
+    }
+    Ok(line_diff(&a.read(a_path).await?, &b.read(b_path).await?))
+}
+
+/// Lines prefixed with `-`, `+`, or ` `, from the longest common subsequence
+/// of lines.
+fn line_diff(old: &[u8], new: &[u8]) -> Option<String> {
+    let old: Vec<&str> = std::str::from_utf8(old).ok()?.lines().collect();
+    let new: Vec<&str> = std::str::from_utf8(new).ok()?.lines().collect();
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            diff.push_str(&format!(" {}\n", old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push_str(&format!("-{}\n", old[i]));
+            i += 1;
+        } else {
+            diff.push_str(&format!("+{}\n", new[j]));
+            j += 1;
+        }
+    }
+    Some(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_tree_diff() -> Result<()> {
+        let a = MemFloppyDisk::new();
+        a.create_dir_all("/a/b").await?;
+        a.write("/a/b/c.txt", "one\ntwo\nthree\n").await?;
+        a.write("/a/same.txt", "same").await?;
+        a.write("/a/gone.txt", "gone").await?;
+        a.symlink("same.txt", "/a/link").await?;
+
+        let b = MemFloppyDisk::new();
+        b.create_dir_all("/b/b").await?;
+        b.write("/b/b/c.txt", "one\n2\nthree\n").await?;
+        b.write("/b/same.txt", "same").await?;
+        b.write("/b/new.txt", "new").await?;
+        b.symlink("b/c.txt", "/b/link").await?;
+
+        let options = DiffOptions::new().with_content_diffs(true);
+        let diff = tree_diff(&a, "/a", &b, "/b", &options).await?;
+        assert_eq!(vec![Path::new("new.txt")], diff.added());
+        assert_eq!(vec![Path::new("gone.txt")], diff.removed());
+        assert_eq!(
+            vec![Path::new("b/c.txt"), Path::new("link")],
+            diff.modified()
+        );
+        match &diff.changes[Path::new("b/c.txt")] {
+            Change::Modified { diff, .. } => {
+                assert_eq!(Some(" one\n-two\n+2\n three\n"), diff.as_deref())
+            }
+            change => panic!("unexpected change {change:?}"),
+        }
+
+        assert!(tree_diff(&a, "/a", &a, "/a", &options).await?.is_empty());
+
+        Ok(())
+    }
+}
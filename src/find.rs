@@ -0,0 +1,247 @@
+//! Finding entries on any disk by name, type, size, age, or mode, like
+//! `find`.
+
+use std::io::Result;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::time::SystemTime;
+
+use futures::Stream;
+
+use crate::diff::EntryKind;
+use crate::walk::{FloppyWalkDir, WalkEntry};
+use crate::{FloppyDisk, FloppyMetadata, FloppyUnixPermissions};
+
+/// Entries under a root that match every condition given. Everything under
+/// the root is walked, whether or not directories match.
+pub struct FloppyFind<'a, D: FloppyDisk<'a>> {
+    walk: FloppyWalkDir<'a, D>,
+    name: Option<String>,
+    kind: Option<EntryKind>,
+    size: (Bound<u64>, Bound<u64>),
+    newer_than: Option<SystemTime>,
+    mode: Option<u32>,
+}
+
+impl<'a, D> FloppyFind<'a, D>
+where
+    D: FloppyDisk<'a>,
+    D::Permissions: FloppyUnixPermissions,
+{
+    pub fn new(disk: &'a D, root: impl AsRef<Path>) -> Self {
+        Self {
+            walk: FloppyWalkDir::new(disk, root),
+            name: None,
+            kind: None,
+            size: (Bound::Unbounded, Bound::Unbounded),
+            newer_than: None,
+            mode: None,
+        }
+    }
+
+    /// Only entries whose file name matches `pattern`, where `*` matches any
+    /// run of characters and `?` matches any one.
+    pub fn with_name(mut self, pattern: impl Into<String>) -> Self {
+        self.name = Some(pattern.into());
+        self
+    }
+
+    pub fn with_kind(mut self, kind: EntryKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Only entries whose length is in `size`, eg. `1024..` for files of at
+    /// least 1KiB.
+    pub fn with_size(mut self, size: impl RangeBounds<u64>) -> Self {
+        self.size = (size.start_bound().cloned(), size.end_bound().cloned());
+        self
+    }
+
+    /// Only entries modified after `time`.
+    pub fn with_newer_than(mut self, time: SystemTime) -> Self {
+        self.newer_than = Some(time);
+        self
+    }
+
+    /// Only entries with every bit in `mask` set in their mode, like
+    /// `find -perm -mask`.
+    pub fn with_mode(mut self, mask: u32) -> Self {
+        self.mode = Some(mask);
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.walk = self.walk.with_max_depth(max_depth);
+        self
+    }
+
+    pub fn with_follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.walk = self.walk.with_follow_symlinks(follow_symlinks);
+        self
+    }
+
+    pub async fn next_entry(&mut self) -> Result<Option<WalkEntry<'a, D>>> {
+        while let Some(entry) = self.walk.next_entry().await? {
+            if self.matches(&entry)? {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = Result<WalkEntry<'a, D>>> + 'a {
+        futures::stream::try_unfold(self, |mut find| async move {
+            Ok(find.next_entry().await?.map(|entry| (entry, find)))
+        })
+    }
+
+    fn matches(&self, entry: &WalkEntry<'a, D>) -> Result<bool> {
+        let metadata = entry.metadata();
+        if let Some(pattern) = &self.name {
+            let name = entry.path().file_name().unwrap_or_default();
+            if !wildcard_match(pattern.as_bytes(), name.as_encoded_bytes()) {
+                return Ok(false);
+            }
+        }
+        if let Some(kind) = self.kind {
+            let actual = if metadata.is_symlink() {
+                EntryKind::Symlink
+            } else if metadata.is_dir() {
+                EntryKind::Dir
+            } else {
+                EntryKind::File
+            };
+            if actual != kind {
+                return Ok(false);
+            }
+        }
+        if !self.size.contains(&metadata.len()) {
+            return Ok(false);
+        }
+        if let Some(time) = self.newer_than {
+            if metadata.modified()? <= time {
+                return Ok(false);
+            }
+        }
+        if let Some(mask) = self.mode {
+            if metadata.permissions().mode() & mask != mask {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// `*` and `?` matching, over bytes so that names needn't be UTF-8.
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Where to resume if the most recent `*` has to match more.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+pub trait FloppyDiskFindExt<'a>: FloppyDisk<'a> + Sized
+where
+    Self::Permissions: FloppyUnixPermissions,
+{
+    /// Find entries under `root`. See [`FloppyFind`].
+    fn find(&'a self, root: impl AsRef<Path>) -> FloppyFind<'a, Self> {
+        FloppyFind::new(self, root)
+    }
+}
+
+impl<'a, D> FloppyDiskFindExt<'a> for D
+where
+    D: FloppyDisk<'a>,
+    D::Permissions: FloppyUnixPermissions,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    async fn found<'a>(find: FloppyFind<'a, MemFloppyDisk>) -> Result<Vec<String>> {
+        let mut paths: Vec<_> = find
+            .into_stream()
+            .map_ok(|entry| entry.path().display().to_string())
+            .try_collect()
+            .await?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    #[tokio::test]
+    async fn test_find() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root/lib").await?;
+        fs.write("/root/lib/libc.so", "x".repeat(2048)).await?;
+        fs.write("/root/lib/libm.so", "x").await?;
+        fs.write("/root/lib/notes.txt", "x").await?;
+        fs.set_permissions("/root/lib/libm.so", FloppyUnixPermissions::from_mode(0o755))
+            .await?;
+        fs.symlink("lib/libc.so", "/root/libc.so").await?;
+
+        assert_eq!(
+            vec!["/root/lib/libc.so", "/root/lib/libm.so", "/root/libc.so"],
+            found(fs.find("/root").with_name("lib?.so")).await?
+        );
+        assert_eq!(
+            vec!["/root/lib/libc.so", "/root/lib/libm.so"],
+            found(
+                fs.find("/root")
+                    .with_name("*.so")
+                    .with_kind(EntryKind::File)
+            )
+            .await?
+        );
+        assert_eq!(
+            vec!["/root/lib/libc.so"],
+            found(
+                fs.find("/root")
+                    .with_kind(EntryKind::File)
+                    .with_size(1024..)
+            )
+            .await?
+        );
+        assert_eq!(
+            vec!["/root/lib/libm.so"],
+            found(fs.find("/root").with_kind(EntryKind::File).with_mode(0o111)).await?
+        );
+        let future = SystemTime::now() + Duration::from_secs(60);
+        assert!(found(fs.find("/root").with_newer_than(future))
+            .await?
+            .is_empty());
+
+        assert!(wildcard_match(b"*.tar.*", b"a.tar.gz"));
+        assert!(!wildcard_match(b"*.tar.*", b"a.tar"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,555 @@
+//! Mounting a [`FloppyDisk`] with FUSE via `fuser`, so that it can be
+//! inspected with normal tools, eg. while a test is paused.
+//!
+//! ```no_run
+//! # use floppy_disk::prelude::*;
+//! # async fn example() -> std::io::Result<()> {
+//! let fs = MemFloppyDisk::new();
+//! let session = floppy_disk::fuse::mount(fs, "/mnt/floppy", &[])?;
+//! // `ls /mnt/floppy`; dropping the session unmounts it.
+//! drop(session);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::ffi::{OsStr, OsString};
+use std::io::{Error, ErrorKind, Result, SeekFrom};
+use std::os::raw::c_int;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fuser::{
+    BackgroundSession, FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyCreate,
+    ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyWrite, Request, TimeOrNow,
+};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::runtime::Handle;
+use tracing::debug;
+
+use crate::ids::{FileIds, ROOT_ID};
+use crate::{
+    FloppyDirEntry, FloppyDisk, FloppyFile, FloppyFileType, FloppyMetadata, FloppyOpenOptions,
+    FloppyReadDir, FloppyUnixMetadata, FloppyUnixPermissions,
+};
+
+/// How long the kernel may cache attributes and lookups. Kept short, since
+/// the disk can change underneath the mount.
+const TTL: Duration = Duration::from_secs(1);
+
+/// Mount `disk` at `mountpoint` on a background thread. The disk is unmounted
+/// when the returned session is dropped.
+///
+/// Must be called from within a tokio runtime, which the disk's operations
+/// are run on.
+pub fn mount<D, P>(disk: D, mountpoint: P, options: &[MountOption]) -> Result<BackgroundSession>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+    P: AsRef<Path>,
+{
+    let mut options = options.to_vec();
+    options.push(MountOption::FSName("floppy-disk".into()));
+    fuser::spawn_mount2(
+        FloppyFuse::new(disk, Handle::current()),
+        mountpoint,
+        &options,
+    )
+}
+
+/// A FUSE [`Filesystem`] backed by a [`FloppyDisk`].
+///
+/// Inode numbers are handed out as paths are looked up, the same way as for
+/// [`FloppyNfs`](crate::nfs::FloppyNfs) when that is enabled.
+#[derive(Debug)]
+pub struct FloppyFuse<D> {
+    disk: D,
+    ids: FileIds,
+    handle: Handle,
+}
+
+impl<D> FloppyFuse<D> {
+    /// Operations on `disk` are run on the runtime behind `handle`.
+    pub fn new(disk: D, handle: Handle) -> Self {
+        Self {
+            disk,
+            ids: FileIds::new(),
+            handle,
+        }
+    }
+
+    pub fn disk(&self) -> &D {
+        &self.disk
+    }
+
+    fn path(&self, ino: u64) -> Result<PathBuf> {
+        self.ids
+            .path(ino)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("unknown inode {ino}")))
+    }
+
+    fn child(&self, parent: u64, name: &OsStr) -> Result<PathBuf> {
+        Ok(self.path(parent)?.join(name))
+    }
+}
+
+fn errno(e: &Error) -> c_int {
+    if let Some(errno) = e.raw_os_error() {
+        return errno;
+    }
+    match e.kind() {
+        ErrorKind::NotFound => libc::ENOENT,
+        ErrorKind::PermissionDenied => libc::EACCES,
+        ErrorKind::AlreadyExists => libc::EEXIST,
+        ErrorKind::InvalidInput => libc::EINVAL,
+        ErrorKind::FileTooLarge => libc::EFBIG,
+        ErrorKind::Unsupported => libc::ENOTSUP,
+        ErrorKind::DirectoryNotEmpty => libc::ENOTEMPTY,
+        ErrorKind::NotADirectory => libc::ENOTDIR,
+        ErrorKind::IsADirectory => libc::EISDIR,
+        ErrorKind::ReadOnlyFilesystem => libc::EROFS,
+        _ => libc::EIO,
+    }
+}
+
+impl<D> FloppyFuse<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+{
+    async fn attr(&mut self, path: &Path) -> Result<FileAttr> {
+        let metadata = self.disk.symlink_metadata(path).await?;
+        let kind = if metadata.is_symlink() {
+            FileType::Symlink
+        } else if metadata.is_dir() {
+            FileType::Directory
+        } else {
+            FileType::RegularFile
+        };
+        let mtime = metadata.modified().unwrap_or(UNIX_EPOCH);
+        Ok(FileAttr {
+            ino: self.ids.id(path),
+            size: metadata.len(),
+            blocks: metadata.len().div_ceil(512),
+            atime: metadata.accessed().unwrap_or(mtime),
+            mtime,
+            ctime: mtime,
+            crtime: metadata.created().unwrap_or(mtime),
+            kind,
+            perm: (metadata.permissions().mode() & 0o7777) as u16,
+            nlink: 1,
+            uid: metadata.uid().unwrap_or(0),
+            gid: metadata.gid().unwrap_or(0),
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        })
+    }
+
+    async fn open_file<'a, F>(
+        &'a self,
+        path: &Path,
+        options: F,
+    ) -> Result<<D as FloppyDisk<'a>>::File>
+    where
+        F: FnOnce(<D as FloppyDisk<'a>>::OpenOptions) -> <D as FloppyDisk<'a>>::OpenOptions,
+    {
+        options(FloppyOpenOptions::new())
+            .open(&self.disk, path)
+            .await
+    }
+
+    async fn do_lookup(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr> {
+        let path = self.child(parent, name)?;
+        self.attr(&path).await
+    }
+
+    async fn do_getattr(&mut self, ino: u64) -> Result<FileAttr> {
+        let path = self.path(ino)?;
+        self.attr(&path).await
+    }
+
+    async fn do_setattr(
+        &mut self,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+    ) -> Result<FileAttr> {
+        let path = self.path(ino)?;
+        if uid.is_some() || gid.is_some() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "changing ownership is not supported",
+            ));
+        }
+        if let Some(mode) = mode {
+            self.disk
+                .set_permissions(&path, FloppyUnixPermissions::from_mode(mode))
+                .await?;
+        }
+        if let Some(size) = size {
+            let mut file = self.open_file(&path, |options| options.write(true)).await?;
+            file.set_len(size).await?;
+        }
+        // Disks can't have their timestamps set, so time changes are dropped.
+        self.attr(&path).await
+    }
+
+    async fn do_read(&mut self, ino: u64, offset: u64, size: u32) -> Result<Vec<u8>> {
+        let path = self.path(ino)?;
+        self.disk.read_range(&path, offset, size as u64).await
+    }
+
+    async fn do_write(&mut self, ino: u64, offset: u64, data: &[u8]) -> Result<u32> {
+        let path = self.path(ino)?;
+        let mut file = self.open_file(&path, |options| options.write(true)).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.write_all(data).await?;
+        file.flush().await?;
+        Ok(data.len() as u32)
+    }
+
+    /// Every entry in the directory, including `.` and `..`, in a stable
+    /// order so that offsets can be resumed from.
+    async fn do_readdir(&mut self, ino: u64) -> Result<Vec<(u64, FileType, OsString)>> {
+        let dir = self.path(ino)?;
+        let parent = dir
+            .parent()
+            .map(|parent| self.ids.id(parent))
+            .unwrap_or(ROOT_ID);
+        let mut read_dir = self.disk.read_dir(&dir).await?;
+        let mut children = vec![];
+        while let Some(entry) = read_dir.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            let kind = if file_type.is_symlink() {
+                FileType::Symlink
+            } else if file_type.is_dir() {
+                FileType::Directory
+            } else {
+                FileType::RegularFile
+            };
+            children.push((kind, entry.file_name()));
+        }
+        children.sort_by(|a, b| a.1.cmp(&b.1));
+
+        let mut entries = vec![
+            (ino, FileType::Directory, OsString::from(".")),
+            (parent, FileType::Directory, OsString::from("..")),
+        ];
+        for (kind, name) in children {
+            entries.push((self.ids.id(&dir.join(&name)), kind, name));
+        }
+        Ok(entries)
+    }
+
+    async fn do_mkdir(&mut self, parent: u64, name: &OsStr, mode: u32) -> Result<FileAttr> {
+        let path = self.child(parent, name)?;
+        self.disk.create_dir(&path).await?;
+        self.disk
+            .set_permissions(&path, FloppyUnixPermissions::from_mode(mode))
+            .await?;
+        self.attr(&path).await
+    }
+
+    async fn do_create(&mut self, parent: u64, name: &OsStr, mode: u32) -> Result<FileAttr> {
+        let path = self.child(parent, name)?;
+        let file = self
+            .open_file(&path, |options| {
+                options.write(true).create(true).truncate(true)
+            })
+            .await?;
+        drop(file);
+        self.disk
+            .set_permissions(&path, FloppyUnixPermissions::from_mode(mode))
+            .await?;
+        self.attr(&path).await
+    }
+
+    async fn do_unlink(&mut self, parent: u64, name: &OsStr) -> Result<()> {
+        let path = self.child(parent, name)?;
+        self.disk.remove_file(&path).await?;
+        self.ids.removed(&path);
+        Ok(())
+    }
+
+    async fn do_rmdir(&mut self, parent: u64, name: &OsStr) -> Result<()> {
+        let path = self.child(parent, name)?;
+        self.disk.remove_dir(&path).await?;
+        self.ids.removed(&path);
+        Ok(())
+    }
+
+    async fn do_rename(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        new_parent: u64,
+        new_name: &OsStr,
+    ) -> Result<()> {
+        let from = self.child(parent, name)?;
+        let to = self.child(new_parent, new_name)?;
+        self.disk.rename(&from, &to).await?;
+        self.ids.renamed(&from, &to);
+        Ok(())
+    }
+
+    async fn do_symlink(&mut self, parent: u64, name: &OsStr, target: &Path) -> Result<FileAttr> {
+        let path = self.child(parent, name)?;
+        self.disk.symlink(target, &path).await?;
+        self.attr(&path).await
+    }
+
+    async fn do_readlink(&mut self, ino: u64) -> Result<Vec<u8>> {
+        let path = self.path(ino)?;
+        let target = self.disk.read_link(&path).await?;
+        Ok(target.as_os_str().as_bytes().to_vec())
+    }
+}
+
+/// Run an operation on the disk's runtime from one of FUSE's threads.
+macro_rules! block_on {
+    ( $this:ident, $op:expr ) => {{
+        let handle = $this.handle.clone();
+        let result = handle.block_on($op);
+        if let Err(ref e) = result {
+            debug!("fuse operation failed: {e}");
+        }
+        result
+    }};
+}
+
+impl<D> Filesystem for FloppyFuse<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+{
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match block_on!(self, self.do_lookup(parent, name)) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match block_on!(self, self.do_getattr(ino)) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        match block_on!(self, self.do_setattr(ino, mode, uid, gid, size)) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        match block_on!(self, self.do_readlink(ino)) {
+            Ok(target) => reply.data(&target),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        reply: ReplyEntry,
+    ) {
+        match block_on!(self, self.do_mkdir(parent, name, mode & !umask)) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        match block_on!(self, self.do_unlink(parent, name)) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        match block_on!(self, self.do_rmdir(parent, name)) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn symlink(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        link_name: &OsStr,
+        target: &Path,
+        reply: ReplyEntry,
+    ) {
+        match block_on!(self, self.do_symlink(parent, link_name, target)) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        match block_on!(self, self.do_rename(parent, name, newparent, newname)) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        match block_on!(self, self.do_read(ino, offset as u64, size)) {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match block_on!(self, self.do_write(ino, offset as u64, data)) {
+            Ok(written) => reply.written(written),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        match block_on!(self, self.do_readdir(ino)) {
+            Ok(entries) => {
+                for (i, (ino, kind, name)) in entries.iter().enumerate().skip(offset as usize) {
+                    if reply.add(*ino, (i + 1) as i64, *kind, name) {
+                        break;
+                    }
+                }
+                reply.ok()
+            }
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        match block_on!(self, self.do_create(parent, name, mode & !umask)) {
+            Ok(attr) => reply.created(&TTL, &attr, 0, 0, 0),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_fuse_operations() -> Result<()> {
+        let mut fuse = FloppyFuse::new(MemFloppyDisk::new(), Handle::current());
+
+        let dir = fuse.do_mkdir(ROOT_ID, OsStr::new("a"), 0o755).await?;
+        assert_eq!(FileType::Directory, dir.kind);
+        assert_eq!(0o755, dir.perm);
+        let file = fuse.do_create(dir.ino, OsStr::new("b.txt"), 0o644).await?;
+        assert_eq!(4, fuse.do_write(file.ino, 0, b"asdf").await?);
+        fuse.do_write(file.ino, 2, b"jkl").await?;
+        assert_eq!(b"sjk".to_vec(), fuse.do_read(file.ino, 1, 3).await?);
+        assert_eq!(5, fuse.do_getattr(file.ino).await?.size);
+        assert_eq!(
+            file.ino,
+            fuse.do_lookup(dir.ino, OsStr::new("b.txt")).await?.ino
+        );
+
+        let attr = fuse
+            .do_setattr(file.ino, Some(0o600), None, None, Some(2))
+            .await?;
+        assert_eq!((0o600, 2), (attr.perm, attr.size));
+
+        fuse.do_symlink(dir.ino, OsStr::new("c"), Path::new("b.txt"))
+            .await?;
+        let names: Vec<OsString> = fuse
+            .do_readdir(dir.ino)
+            .await?
+            .into_iter()
+            .map(|(_, _, name)| name)
+            .collect();
+        assert_eq!(vec![".", "..", "b.txt", "c"], names);
+
+        fuse.do_rename(dir.ino, OsStr::new("b.txt"), ROOT_ID, OsStr::new("d.txt"))
+            .await?;
+        assert_eq!("as", fuse.disk().read_to_string("/d.txt").await?);
+        assert_eq!(PathBuf::from("/d.txt"), fuse.path(file.ino)?);
+
+        fuse.do_unlink(ROOT_ID, OsStr::new("d.txt")).await?;
+        assert!(fuse.do_getattr(file.ino).await.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+//! Walks that skip whatever `.gitignore` and `.ignore` files say to, read
+//! from the disk being walked.
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+use crate::walk::{FloppyWalkDir, WalkEntry};
+use crate::{FloppyDisk, FloppyMetadata};
+
+/// The files rules are read from, in increasing order of precedence.
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// Like [`FloppyWalkDir`], but skips ignored entries and `.git` directories.
+/// Rules in a directory's ignore files apply to everything under it, and
+/// rules further down take precedence, like in git.
+pub struct IgnoreWalk<'a, D: FloppyDisk<'a>> {
+    disk: &'a D,
+    root: PathBuf,
+    walk: FloppyWalkDir<'a, D>,
+    /// Rules from each directory walked so far that has ignore files.
+    rules: Arc<Mutex<HashMap<PathBuf, Gitignore>>>,
+    started: bool,
+}
+
+impl<'a, D: FloppyDisk<'a>> IgnoreWalk<'a, D> {
+    pub fn new(disk: &'a D, root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref().to_path_buf();
+        let rules: Arc<Mutex<HashMap<PathBuf, Gitignore>>> = Arc::default();
+        let walk = FloppyWalkDir::new(disk, &root).with_filter({
+            let (rules, root) = (rules.clone(), root.clone());
+            move |entry: &WalkEntry<'a, D>| !is_ignored(&rules.lock().unwrap(), &root, entry)
+        });
+        Self {
+            disk,
+            root,
+            walk,
+            rules,
+            started: false,
+        }
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.walk = self.walk.with_max_depth(max_depth);
+        self
+    }
+
+    pub fn with_follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.walk = self.walk.with_follow_symlinks(follow_symlinks);
+        self
+    }
+
+    pub async fn next_entry(&mut self) -> Result<Option<WalkEntry<'a, D>>> {
+        if !self.started {
+            self.started = true;
+            let root = self.root.clone();
+            self.load_rules(&root).await?;
+        }
+        let entry = self.walk.next_entry().await?;
+        // Directories are yielded before anything in them is, so their rules
+        // are in place before they're needed.
+        if let Some(entry) = &entry {
+            if entry.metadata().is_dir() {
+                self.load_rules(entry.path()).await?;
+            }
+        }
+        Ok(entry)
+    }
+
+    async fn load_rules(&self, dir: &Path) -> Result<()> {
+        let mut builder = GitignoreBuilder::new(dir);
+        let mut found = false;
+        for name in IGNORE_FILES {
+            let path = dir.join(name);
+            let contents = match self.disk.read_to_string(&path).await {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            found = true;
+            for line in contents.lines() {
+                builder
+                    .add_line(Some(path.clone()), line)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            }
+        }
+        if found {
+            let rules = builder
+                .build()
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            self.rules.lock().unwrap().insert(dir.to_path_buf(), rules);
+        }
+        Ok(())
+    }
+}
+
+fn is_ignored<'a, D: FloppyDisk<'a>>(
+    rules: &HashMap<PathBuf, Gitignore>,
+    root: &Path,
+    entry: &WalkEntry<'a, D>,
+) -> bool {
+    let is_dir = entry.metadata().is_dir();
+    if is_dir && entry.path().file_name().is_some_and(|name| name == ".git") {
+        return true;
+    }
+    // The nearest directory with an opinion wins.
+    for dir in entry.path().ancestors().skip(1) {
+        if let Some(rules) = rules.get(dir) {
+            match rules.matched(entry.path(), is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        if dir == root {
+            break;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_ignore_walk() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/project/.git").await?;
+        fs.create_dir_all("/project/target/debug").await?;
+        fs.create_dir_all("/project/src/generated").await?;
+        fs.write("/project/.gitignore", "target/\n*.log\n").await?;
+        fs.write("/project/.git/HEAD", "").await?;
+        fs.write("/project/target/debug/app", "").await?;
+        fs.write("/project/build.log", "").await?;
+        fs.write("/project/src/main.rs", "").await?;
+        fs.write("/project/src/.ignore", "generated/\n!keep.log\n")
+            .await?;
+        fs.write("/project/src/generated/out.rs", "").await?;
+        fs.write("/project/src/keep.log", "").await?;
+
+        let mut walk = IgnoreWalk::new(&fs, "/project");
+        let mut paths = vec![];
+        while let Some(entry) = walk.next_entry().await? {
+            paths.push(entry.into_path());
+        }
+        paths.sort();
+        assert_eq!(
+            vec![
+                PathBuf::from("/project/.gitignore"),
+                PathBuf::from("/project/src"),
+                PathBuf::from("/project/src/.ignore"),
+                PathBuf::from("/project/src/keep.log"),
+                PathBuf::from("/project/src/main.rs"),
+            ],
+            paths
+        );
+
+        Ok(())
+    }
+}
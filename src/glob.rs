@@ -0,0 +1,117 @@
+//! Finding paths on any disk by glob pattern.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Component, Path, PathBuf};
+
+use futures::Stream;
+use globset::{GlobBuilder, GlobMatcher};
+
+use crate::walk::FloppyWalkDir;
+use crate::FloppyDisk;
+
+pub trait FloppyDiskGlobExt<'a>: FloppyDisk<'a> + Sized {
+    /// Every path matching `pattern`, eg. `/usr/**/*.so`. `*` and `?` don't
+    /// match `/`, but `**` matches any number of directories. Only the part
+    /// of the tree that could match is walked: everything under the pattern's
+    /// longest literal prefix, and no deeper than the pattern goes unless it
+    /// has a `**`.
+    fn glob(&'a self, pattern: &str) -> Result<impl Stream<Item = Result<PathBuf>> + 'a> {
+        let matcher = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?
+            .compile_matcher();
+        let (root, rest) = split_literal_prefix(Path::new(pattern));
+        let mut walk = FloppyWalkDir::new(self, root);
+        if !rest.iter().any(|component| component == "**") {
+            walk = walk.with_max_depth(rest.len());
+        }
+        Ok(glob_stream(walk, matcher))
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> FloppyDiskGlobExt<'a> for D {}
+
+fn glob_stream<'a, D: FloppyDisk<'a>>(
+    walk: FloppyWalkDir<'a, D>,
+    matcher: GlobMatcher,
+) -> impl Stream<Item = Result<PathBuf>> + 'a {
+    futures::stream::try_unfold((walk, matcher), |(mut walk, matcher)| async move {
+        while let Some(entry) = walk.next_entry().await? {
+            // Relative patterns are walked from `.`, which they don't start
+            // with.
+            let path = entry.path().strip_prefix(".").unwrap_or(entry.path());
+            if matcher.is_match(path) {
+                let path = path.to_path_buf();
+                return Ok(Some((path, (walk, matcher))));
+            }
+        }
+        Ok(None)
+    })
+}
+
+/// Split `pattern` into the directory that every match is under, and the
+/// components after it.
+fn split_literal_prefix(pattern: &Path) -> (PathBuf, Vec<String>) {
+    let mut root = PathBuf::new();
+    let mut rest = vec![];
+    // The last component is never part of the root, since it's what's being
+    // matched.
+    let count = pattern.components().count();
+    for (i, component) in pattern.components().enumerate() {
+        let literal = match component {
+            Component::Normal(name) => {
+                let name = name.to_string_lossy();
+                !name.contains(['*', '?', '[', '{', '\\'])
+            }
+            _ => true,
+        };
+        if rest.is_empty() && literal && i + 1 < count {
+            root.push(component);
+        } else {
+            rest.push(component.as_os_str().to_string_lossy().into_owned());
+        }
+    }
+    if root.as_os_str().is_empty() {
+        root.push(".");
+    }
+    (root, rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_glob() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/usr/lib/x86_64").await?;
+        fs.write("/usr/lib/libc.so", "").await?;
+        fs.write("/usr/lib/x86_64/libm.so", "").await?;
+        fs.write("/usr/lib/x86_64/libm.a", "").await?;
+        fs.write("/usr/README", "").await?;
+
+        let mut paths: Vec<_> = fs.glob("/usr/**/*.so")?.try_collect().await?;
+        paths.sort();
+        assert_eq!(
+            vec![
+                PathBuf::from("/usr/lib/libc.so"),
+                PathBuf::from("/usr/lib/x86_64/libm.so"),
+            ],
+            paths
+        );
+
+        let paths: Vec<_> = fs.glob("/usr/*/*.so")?.try_collect().await?;
+        assert_eq!(vec![PathBuf::from("/usr/lib/libc.so")], paths);
+
+        assert_eq!(
+            ErrorKind::InvalidInput,
+            fs.glob("/usr/[").err().unwrap().kind()
+        );
+
+        Ok(())
+    }
+}
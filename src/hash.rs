@@ -0,0 +1,504 @@
+//! Hashing files and trees without reading them into memory whole.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    FloppyDirEntry, FloppyDisk, FloppyMetadata, FloppyOpenOptions, FloppyReadDir,
+    FloppyUnixMetadata, FloppyUnixPermissions,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub(crate) fn hasher(self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+pub(crate) enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    /// The digest, as lowercase hex.
+    pub(crate) fn finish(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+/// The hex digest of the file at `path`, streamed through the hasher.
+pub async fn file_digest<'a, D: FloppyDisk<'a>>(
+    disk: &'a D,
+    path: impl AsRef<Path> + Send,
+    algorithm: HashAlgorithm,
+) -> Result<String> {
+    let mut file = D::OpenOptions::new().read(true).open(disk, path).await?;
+    let mut hasher = algorithm.hasher();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+/// One hex digest for everything under `root`: the relative path and kind of
+/// every entry, and the digests of files and targets of symlinks. Two trees
+/// with the same layout and contents hash the same, whatever their modes,
+/// owners, or timestamps.
+pub async fn tree_digest<'a, D: FloppyDisk<'a>>(
+    disk: &'a D,
+    root: impl AsRef<Path> + Send,
+    algorithm: HashAlgorithm,
+) -> Result<String> {
+    hash_tree(disk, root.as_ref(), algorithm, None).await
+}
+
+/// Like [`tree_digest`], but files whose size and mtime haven't changed since
+/// they were last hashed into `cache` aren't read again. A file rewritten
+/// with the same size within the disk's mtime granularity is missed, so
+/// this is for spotting changes, not tampering.
+pub async fn tree_digest_cached<'a, D: FloppyDisk<'a>>(
+    disk: &'a D,
+    root: impl AsRef<Path> + Send,
+    algorithm: HashAlgorithm,
+    cache: &mut HashCache,
+) -> Result<String> {
+    hash_tree(disk, root.as_ref(), algorithm, Some(cache)).await
+}
+
+async fn hash_tree<'a, D: FloppyDisk<'a>>(
+    disk: &'a D,
+    root: &Path,
+    algorithm: HashAlgorithm,
+    mut cache: Option<&mut HashCache>,
+) -> Result<String> {
+    let mut entries = vec![];
+    let mut seen = HashSet::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let mut read_dir = disk.read_dir(root.join(&dir)).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let relative = dir.join(entry.file_name());
+            let path = root.join(&relative);
+            let metadata = disk.symlink_metadata(&path).await?;
+            let (kind, value) = if metadata.is_symlink() {
+                let target = disk.read_link(&path).await?;
+                ('l', target.into_os_string())
+            } else if metadata.is_dir() {
+                dirs.push(relative.clone());
+                ('d', OsString::new())
+            } else if let Some(cache) = cache.as_deref_mut() {
+                let key = CacheKey {
+                    len: metadata.len(),
+                    modified: metadata.modified()?,
+                    algorithm,
+                };
+                let digest = match cache.entries.get(&path) {
+                    Some((cached, digest)) if *cached == key => digest.clone(),
+                    _ => file_digest(disk, &path, algorithm).await?,
+                };
+                cache.entries.insert(path.clone(), (key, digest.clone()));
+                seen.insert(path);
+                ('f', digest.into())
+            } else {
+                ('f', file_digest(disk, &path, algorithm).await?.into())
+            };
+            entries.push((relative, kind, value));
+        }
+    }
+    entries.sort();
+    // Files that are gone don't need remembering.
+    if let Some(cache) = cache {
+        cache
+            .entries
+            .retain(|path, _| !path.starts_with(root) || seen.contains(path));
+    }
+
+    let mut hasher = algorithm.hasher();
+    for (path, kind, value) in entries {
+        // Neither paths nor symlink targets can contain NUL, so ending each
+        // with one keeps entries apart, whatever bytes they're made of.
+        hasher.update(format!("{kind} ").as_bytes());
+        hasher.update(path.as_os_str().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(value.as_bytes());
+        hasher.update(b"\0");
+    }
+    Ok(hasher.finish())
+}
+
+/// File digests from [`tree_digest_cached`], and the sizes and mtimes the
+/// files had when they were hashed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashCache {
+    entries: BTreeMap<PathBuf, (CacheKey, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CacheKey {
+    len: u64,
+    modified: SystemTime,
+    algorithm: HashAlgorithm,
+}
+
+impl HashCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a cache saved with [`HashCache::save`], or an empty one if
+    /// there's nothing at `path`.
+    pub async fn load<'a, D: FloppyDisk<'a>>(
+        disk: &'a D,
+        path: impl AsRef<Path> + Send,
+    ) -> Result<Self> {
+        let data = match disk.read(path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        // NUL-separated paths, lengths, mtimes in nanoseconds, algorithms,
+        // and digests.
+        let invalid = || Error::new(ErrorKind::InvalidData, "invalid hash cache");
+        let mut cache = Self::default();
+        let mut fields = data.split(|&b| b == 0);
+        while let (Some(path), Some(len), Some(modified), Some(algorithm), Some(digest)) = (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        ) {
+            let parse = |field: &[u8]| -> Result<String> {
+                String::from_utf8(field.to_vec()).map_err(|_| invalid())
+            };
+            let nanos: u64 = parse(modified)?.parse().map_err(|_| invalid())?;
+            let algorithm = match parse(algorithm)?.as_str() {
+                "sha256" => HashAlgorithm::Sha256,
+                "blake3" => HashAlgorithm::Blake3,
+                _ => return Err(invalid()),
+            };
+            let key = CacheKey {
+                len: parse(len)?.parse().map_err(|_| invalid())?,
+                modified: UNIX_EPOCH + Duration::from_nanos(nanos),
+                algorithm,
+            };
+            cache.entries.insert(
+                PathBuf::from(OsStr::from_bytes(path)),
+                (key, parse(digest)?),
+            );
+        }
+        Ok(cache)
+    }
+
+    pub async fn save<'a, D: FloppyDisk<'a>>(
+        &self,
+        disk: &'a D,
+        path: impl AsRef<Path> + Send,
+    ) -> Result<()> {
+        let mut data = vec![];
+        for (path, (key, digest)) in &self.entries {
+            let nanos = key
+                .modified
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+                .as_nanos();
+            data.extend_from_slice(path.as_os_str().as_bytes());
+            data.push(0);
+            for field in [
+                key.len.to_string(),
+                nanos.to_string(),
+                key.algorithm.name().to_string(),
+                digest.clone(),
+            ] {
+                data.extend_from_slice(field.as_bytes());
+                data.push(0);
+            }
+        }
+        disk.write(path, data).await
+    }
+}
+
+/// Hashes of every directory under a root, each covering everything below
+/// it. Comparing two trees' hashes finds which subtrees changed without
+/// looking inside the ones that didn't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleTree {
+    pub root: String,
+    /// Every directory's hash, by path relative to the root. The root itself
+    /// is the empty path.
+    pub dirs: BTreeMap<PathBuf, String>,
+}
+
+/// Build a [`MerkleTree`] of everything under `root`. A file's hash covers
+/// its contents, mode, and owner; a symlink's covers its target; and a
+/// directory's covers its mode, owner, and the names and hashes of its
+/// children. Timestamps are left out, so copies of a tree hash the same.
+pub async fn merkle_tree<'a, D>(
+    disk: &'a D,
+    root: impl AsRef<Path> + Send,
+    algorithm: HashAlgorithm,
+) -> Result<MerkleTree>
+where
+    D: FloppyDisk<'a>,
+    D::Metadata: FloppyUnixMetadata,
+    D::Permissions: FloppyUnixPermissions,
+{
+    let root = root.as_ref();
+    // Directories in the order they're visited, parents before children, each
+    // with a description of itself and the hashes of its non-directory
+    // children.
+    let mut visited = vec![];
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let metadata = disk.metadata(root.join(&dir)).await?;
+        let mut children = vec![];
+        let mut read_dir = disk.read_dir(root.join(&dir)).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let relative = dir.join(entry.file_name());
+            let path = root.join(&relative);
+            let metadata = disk.symlink_metadata(&path).await?;
+            let mut hasher = algorithm.hasher();
+            if metadata.is_symlink() {
+                hasher.update(b"l ");
+                hasher.update(disk.read_link(&path).await?.as_os_str().as_bytes());
+            } else if metadata.is_dir() {
+                dirs.push(relative);
+                continue;
+            } else {
+                let leaf = format!(
+                    "f {} {}\0{}",
+                    describe(&metadata)?,
+                    metadata.len(),
+                    file_digest(disk, &path, algorithm).await?
+                );
+                hasher.update(leaf.as_bytes());
+            }
+            children.push((entry.file_name(), hasher.finish()));
+        }
+        visited.push((dir, describe(&metadata)?, children));
+    }
+
+    let mut hashes = BTreeMap::new();
+    let mut subdirs: HashMap<PathBuf, Vec<(OsString, String)>> = HashMap::new();
+    for (dir, description, mut children) in visited.into_iter().rev() {
+        // Every subdirectory was visited after this one, so has already been
+        // hashed.
+        children.extend(subdirs.remove(&dir).unwrap_or_default());
+        children.sort();
+        let mut hasher = algorithm.hasher();
+        hasher.update(format!("d {description}\n").as_bytes());
+        for (name, hash) in children {
+            hasher.update(name.as_bytes());
+            hasher.update(format!("\0{hash}\n").as_bytes());
+        }
+        let hash = hasher.finish();
+        if let (Some(parent), Some(name)) = (dir.parent(), dir.file_name()) {
+            subdirs
+                .entry(parent.to_path_buf())
+                .or_default()
+                .push((name.to_owned(), hash.clone()));
+        }
+        hashes.insert(dir, hash);
+    }
+
+    Ok(MerkleTree {
+        root: hashes[Path::new("")].clone(),
+        dirs: hashes,
+    })
+}
+
+fn describe<'a, D, M>(metadata: &M) -> Result<String>
+where
+    D: FloppyDisk<'a>,
+    D::Permissions: FloppyUnixPermissions,
+    M: FloppyMetadata<'a, D> + FloppyUnixMetadata,
+{
+    Ok(format!(
+        "{:o} {}:{}",
+        metadata.permissions().mode() & 0o7777,
+        metadata.uid()?,
+        metadata.gid()?
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_digests() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root/a").await?;
+        fs.write("/root/a/b.txt", "asdf").await?;
+        fs.symlink("a/b.txt", "/root/c").await?;
+        assert_eq!(
+            "f0e4c2f76c58916ec258f246851bea091d14d4247a2fc3e18694461b1816e13b",
+            file_digest(&fs, "/root/a/b.txt", HashAlgorithm::Sha256).await?
+        );
+        assert_eq!(
+            blake3::hash(b"asdf").to_hex().as_str(),
+            file_digest(&fs, "/root/a/b.txt", HashAlgorithm::Blake3).await?
+        );
+
+        let other = MemFloppyDisk::new();
+        other.create_dir_all("/elsewhere/a").await?;
+        other.write("/elsewhere/a/b.txt", "asdf").await?;
+        other.symlink("a/b.txt", "/elsewhere/c").await?;
+        for algorithm in [HashAlgorithm::Sha256, HashAlgorithm::Blake3] {
+            assert_eq!(
+                tree_digest(&fs, "/root", algorithm).await?,
+                tree_digest(&other, "/elsewhere", algorithm).await?
+            );
+        }
+
+        other.write("/elsewhere/a/b.txt", "fdsa").await?;
+        assert_ne!(
+            tree_digest(&fs, "/root", HashAlgorithm::Sha256).await?,
+            tree_digest(&other, "/elsewhere", HashAlgorithm::Sha256).await?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tree_digest_non_utf8() -> Result<()> {
+        // Both of these are U+FFFD when lossily converted.
+        let (ff, fe) = (OsStr::from_bytes(b"\xff"), OsStr::from_bytes(b"\xfe"));
+        let fs = MemFloppyDisk::new();
+        for (root, name) in [("/ff", ff), ("/fe", fe)] {
+            fs.create_dir_all(Path::new(root).join("files")).await?;
+            fs.write(Path::new(root).join("files").join(name), "asdf")
+                .await?;
+            fs.create_dir_all(Path::new(root).join("links")).await?;
+            fs.symlink(Path::new(name), &Path::new(root).join("links/c"))
+                .await?;
+        }
+        assert_ne!(
+            tree_digest(&fs, "/ff/files", HashAlgorithm::Sha256).await?,
+            tree_digest(&fs, "/fe/files", HashAlgorithm::Sha256).await?
+        );
+        assert_ne!(
+            tree_digest(&fs, "/ff/links", HashAlgorithm::Sha256).await?,
+            tree_digest(&fs, "/fe/links", HashAlgorithm::Sha256).await?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_merkle_tree() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root/a/b").await?;
+        fs.create_dir_all("/root/c").await?;
+        fs.write("/root/a/b/d.txt", "asdf").await?;
+        fs.write("/root/c/e.txt", "jkl").await?;
+        let before = merkle_tree(&fs, "/root", HashAlgorithm::Blake3).await?;
+        assert_eq!(4, before.dirs.len());
+        assert_eq!(before.root, before.dirs[Path::new("")]);
+
+        fs.write("/root/a/b/d.txt", "fdsa").await?;
+        let after = merkle_tree(&fs, "/root", HashAlgorithm::Blake3).await?;
+        assert_ne!(before.root, after.root);
+        assert_ne!(before.dirs[Path::new("a")], after.dirs[Path::new("a")]);
+        assert_ne!(before.dirs[Path::new("a/b")], after.dirs[Path::new("a/b")]);
+        assert_eq!(before.dirs[Path::new("c")], after.dirs[Path::new("c")]);
+
+        fs.set_permissions("/root/c/e.txt", FloppyUnixPermissions::from_mode(0o600))
+            .await?;
+        let chmodded = merkle_tree(&fs, "/root", HashAlgorithm::Blake3).await?;
+        assert_ne!(after.dirs[Path::new("c")], chmodded.dirs[Path::new("c")]);
+
+        // Names and targets that lossily convert to the same string don't
+        // hash the same.
+        let (ff, fe) = (OsStr::from_bytes(b"\xff"), OsStr::from_bytes(b"\xfe"));
+        for (root, name) in [("/ff", ff), ("/fe", fe)] {
+            fs.create_dir_all(Path::new(root).join("files")).await?;
+            fs.write(Path::new(root).join("files").join(name), "asdf")
+                .await?;
+            fs.create_dir_all(Path::new(root).join("links")).await?;
+            fs.symlink(Path::new(name), &Path::new(root).join("links/c"))
+                .await?;
+        }
+        let ff = merkle_tree(&fs, "/ff", HashAlgorithm::Blake3).await?;
+        let fe = merkle_tree(&fs, "/fe", HashAlgorithm::Blake3).await?;
+        assert_ne!(ff.dirs[Path::new("files")], fe.dirs[Path::new("files")]);
+        assert_ne!(ff.dirs[Path::new("links")], fe.dirs[Path::new("links")]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tree_digest_cached() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root/a").await?;
+        fs.write("/root/a/b.txt", "asdf").await?;
+        fs.write("/root/c.txt", "jkl").await?;
+        let mut cache = HashCache::new();
+        let digest = tree_digest_cached(&fs, "/root", HashAlgorithm::Sha256, &mut cache).await?;
+        assert_eq!(
+            digest,
+            tree_digest(&fs, "/root", HashAlgorithm::Sha256).await?
+        );
+        assert_eq!(2, cache.entries.len());
+
+        cache.save(&fs, "/cache").await?;
+        let mut cache = HashCache::load(&fs, "/cache").await?;
+        // Unchanged files aren't read again, so a wrong digest sticks.
+        cache.entries.get_mut(Path::new("/root/c.txt")).unwrap().1 = "wrong".into();
+        let stale = tree_digest_cached(&fs, "/root", HashAlgorithm::Sha256, &mut cache).await?;
+        assert_ne!(digest, stale);
+
+        // But changed ones are, and removed ones are forgotten.
+        fs.write("/root/c.txt", "qwerty").await?;
+        fs.remove_file("/root/a/b.txt").await?;
+        assert_eq!(
+            tree_digest(&fs, "/root", HashAlgorithm::Sha256).await?,
+            tree_digest_cached(&fs, "/root", HashAlgorithm::Sha256, &mut cache).await?
+        );
+        assert_eq!(1, cache.entries.len());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The id of `/`. FUSE requires this to be 1.
+pub(crate) const ROOT_ID: u64 = 1;
+
+/// Hands out stable ids for paths, for protocols that refer to files by
+/// number when the disk underneath may not have inode numbers.
+#[derive(Debug)]
+pub(crate) struct FileIds {
+    paths: HashMap<u64, PathBuf>,
+    ids: HashMap<PathBuf, u64>,
+    next: u64,
+}
+
+impl FileIds {
+    pub(crate) fn new() -> Self {
+        let mut ids = Self {
+            paths: HashMap::new(),
+            ids: HashMap::new(),
+            next: ROOT_ID,
+        };
+        ids.id(Path::new("/"));
+        ids
+    }
+
+    pub(crate) fn id(&mut self, path: &Path) -> u64 {
+        if let Some(id) = self.ids.get(path) {
+            return *id;
+        }
+        let id = self.next;
+        self.next += 1;
+        self.paths.insert(id, path.to_path_buf());
+        self.ids.insert(path.to_path_buf(), id);
+        id
+    }
+
+    pub(crate) fn path(&self, id: u64) -> Option<PathBuf> {
+        self.paths.get(&id).cloned()
+    }
+
+    /// Forget `path` and everything under it.
+    pub(crate) fn removed(&mut self, path: &Path) {
+        let ids = &mut self.ids;
+        self.paths.retain(|_, known| {
+            let removed = known.starts_with(path);
+            if removed {
+                ids.remove(known);
+            }
+            !removed
+        });
+    }
+
+    /// Keep the ids of `from` and everything under it, now under `to`.
+    pub(crate) fn renamed(&mut self, from: &Path, to: &Path) {
+        self.removed(to);
+        let mut moved = vec![];
+        for (id, known) in self.paths.iter_mut() {
+            if let Ok(rest) = known.strip_prefix(from) {
+                self.ids.remove(known);
+                *known = to.join(rest);
+                moved.push((known.clone(), *id));
+            }
+        }
+        self.ids.extend(moved);
+    }
+}
@@ -8,8 +8,67 @@ use std::time::SystemTime;
 
 use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
 
+#[cfg(feature = "cache")]
+pub mod access_log;
+#[cfg(any(feature = "tar", feature = "zip"))]
+pub mod archive;
+#[cfg(feature = "cache")]
+pub mod cache;
+#[cfg(feature = "cap-std")]
+pub mod cap_std_fs;
+pub mod capability;
+#[cfg(feature = "cas")]
+pub mod cas;
+pub mod coalesce;
+pub mod copy;
+pub mod debug;
+pub mod diff;
+pub mod find;
+#[cfg(feature = "fuse")]
+pub mod fuse;
+#[cfg(feature = "gitignore")]
+pub mod gitignore;
+#[cfg(feature = "glob")]
+pub mod glob;
+#[cfg(feature = "hash")]
+pub mod hash;
+#[cfg(any(feature = "nfs", feature = "fuse"))]
+mod ids;
+#[cfg(feature = "manifest")]
+pub mod manifest;
 pub mod mem;
+pub mod merge;
+#[cfg(feature = "nfs")]
+pub mod nfs;
+#[cfg(feature = "object-store")]
+pub mod object_store;
+pub mod policy;
+pub mod profile;
+pub mod sealed;
+pub mod secret;
+#[cfg(feature = "http")]
+pub mod serve_dir;
+#[cfg(feature = "hash")]
+pub mod session;
+#[cfg(feature = "sftp")]
+pub mod sftp;
+pub mod sorted;
+pub mod std_fs;
+pub mod sync;
+pub mod temp;
+pub mod testing;
+pub mod tiered;
 pub mod tokio_fs;
+pub mod ttl;
+pub mod usage;
+#[cfg(feature = "verity")]
+pub mod verity;
+#[cfg(feature = "vfs")]
+pub mod vfs;
+pub mod walk;
+#[cfg(feature = "bench")]
+pub mod workload;
+pub mod workspace;
 
 pub mod prelude {
     pub use crate::{
@@ -18,8 +77,16 @@ pub mod prelude {
         FloppyUnixMetadata, FloppyUnixPermissions,
     };
 
+    #[cfg(feature = "cap-std")]
+    pub use crate::cap_std_fs::CapStdFloppyDisk;
+    pub use crate::find::FloppyDiskFindExt;
     pub use crate::mem::MemFloppyDisk;
+    pub use crate::secret::SecretWorkspace;
+    pub use crate::std_fs::StdFloppyDisk;
+    pub use crate::temp::TempFloppyDisk;
     pub use crate::tokio_fs::TokioFloppyDisk;
+    pub use crate::walk::FloppyDiskWalkExt;
+    pub use crate::workspace::Workspace;
 }
 
 #[async_trait::async_trait]
@@ -32,7 +99,6 @@ pub trait FloppyDisk<'a>: Debug + std::marker::Unpin + std::marker::Sized + Send
     type OpenOptions: FloppyOpenOptions<'a, Self> + Send + 'a;
     type Permissions: FloppyPermissions + Send + 'a;
     type ReadDir: FloppyReadDir<'a, Self> + Send + 'a;
-    // type TempDir: FloppyTempDir;
 
     async fn canonicalize<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf>;
 
@@ -106,6 +172,119 @@ pub trait FloppyDisk<'a>: Debug + std::marker::Unpin + std::marker::Sized + Send
         }
         Ok(None)
     }
+
+    /// Open the file at `path` for reading, eg. to stream it somewhere
+    /// without knowing this backend's `OpenOptions`.
+    async fn open_read<P: AsRef<Path> + Send>(&'a self, path: P) -> Result<Self::File> {
+        Self::OpenOptions::new().read(true).open(self, path).await
+    }
+
+    /// Open the file at `path` for writing, creating or truncating it first
+    /// like [`FloppyDisk::write`] does.
+    async fn open_write<P: AsRef<Path> + Send>(&'a self, path: P) -> Result<Self::File> {
+        Self::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self, path)
+            .await
+    }
+
+    /// Append `contents` to the end of the file at `path`, creating it first
+    /// if it doesn't exist.
+    ///
+    /// Backends that can append a whole buffer in one step, so that appends
+    /// racing each other never interleave, should override this.
+    async fn append<P: AsRef<Path> + Send>(
+        &'a self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut file = Self::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(self, path)
+            .await?;
+        file.write_all(contents.as_ref()).await?;
+        file.flush().await
+    }
+
+    /// Cut the file at `path` down to `len` bytes, or extend it with zeroes,
+    /// without having to open it first.
+    async fn truncate<P: AsRef<Path> + Send>(&'a self, path: P, len: u64) -> Result<()> {
+        let mut file = Self::OpenOptions::new()
+            .write(true)
+            .open(self, path)
+            .await?;
+        file.set_len(len).await
+    }
+
+    /// Replace the file at `path` with `contents`, so that readers see either
+    /// the old contents or the new and never part of either. The contents
+    /// are written to a temporary file next to `path`, synced, and renamed
+    /// over it.
+    ///
+    /// Backends that can swap a file's contents in one step should override
+    /// this.
+    async fn write_atomic<P: AsRef<Path> + Send>(
+        &'a self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let (path, contents) = (path.as_ref(), contents.as_ref());
+        let tmp = atomic_temp_path(path)?;
+        let result = async {
+            let mut file = self.open_write(&tmp).await?;
+            file.write_all(contents).await?;
+            file.sync_all().await?;
+            self.rename(tmp.as_path(), path).await
+        }
+        .await;
+        if result.is_err() {
+            let _ = self.remove_file(&tmp).await;
+        }
+        result
+    }
+
+    /// Read up to `len` bytes of the file at `path`, starting from `offset`.
+    /// Less is returned if the file ends first.
+    ///
+    /// Backends that can fetch part of a file without opening it, eg. with a
+    /// ranged request, should override this.
+    async fn read_range<P: AsRef<Path> + Send>(
+        &'a self,
+        path: P,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>> {
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+        let mut file = self.open_read(path).await?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        let mut buf = vec![];
+        (&mut file).take(len).read_to_end(&mut buf).await?;
+        Ok(buf)
+    }
+
+    /// The metadata of each of `paths`, in the same order. Each path
+    /// succeeds or fails on its own.
+    ///
+    /// Backends that can stat many paths at once, or pipeline requests,
+    /// should override this.
+    async fn metadata_many<P: AsRef<Path> + Send + Sync>(
+        &self,
+        paths: &[P],
+    ) -> Vec<Result<Self::Metadata>> {
+        let mut metadata = Vec::with_capacity(paths.len());
+        for path in paths {
+            metadata.push(self.metadata(path).await);
+        }
+        metadata
+    }
 }
 
 #[async_trait::async_trait]
@@ -113,6 +292,167 @@ pub trait FloppyDiskUnixExt {
     async fn chown<P: Into<PathBuf> + Send>(&self, path: P, uid: u32, gid: u32) -> Result<()>;
 }
 
+/// Check whether `a` and `b` refer to the same file on `disk`, eg. because
+/// one is a hard link or symlink to the other.
+///
+/// Files are compared by device and inode number. Backends that don't have
+/// inode numbers fall back to comparing canonicalised paths.
+pub async fn same_file<'a, D: FloppyDisk<'a>>(
+    disk: &D,
+    a: impl AsRef<Path> + Send,
+    b: impl AsRef<Path> + Send,
+) -> Result<bool>
+where
+    D::Metadata: FloppyUnixMetadata,
+{
+    let (a, b) = (a.as_ref(), b.as_ref());
+    let a_metadata = disk.metadata(a).await?;
+    let b_metadata = disk.metadata(b).await?;
+    match (a_metadata.dev(), b_metadata.dev()) {
+        (Ok(a_dev), Ok(b_dev)) if a_dev != b_dev => return Ok(false),
+        (Err(e), _) | (_, Err(e)) if e.kind() != std::io::ErrorKind::Unsupported => return Err(e),
+        _ => {}
+    }
+
+    match (a_metadata.ino(), b_metadata.ino()) {
+        (Ok(a_ino), Ok(b_ino)) => Ok(a_ino == b_ino),
+        (Err(e), _) | (_, Err(e)) if e.kind() != std::io::ErrorKind::Unsupported => Err(e),
+        _ => Ok(disk.canonicalize(a).await? == disk.canonicalize(b).await?),
+    }
+}
+
+/// A hidden, unique sibling of `path` to write to before renaming over it,
+/// eg. `/a/.b.txt.1234abcd.floppy-tmp` for `/a/b.txt`.
+pub(crate) fn atomic_temp_path(path: &Path) -> Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} has no file name", path.display()),
+        )
+    })?;
+    let mut tmp = std::ffi::OsString::from(".");
+    tmp.push(name);
+    tmp.push(format!(".{:016x}.floppy-tmp", rand::random::<u64>()));
+    Ok(path.with_file_name(tmp))
+}
+
+/// Where data or a hole starts at or after `offset` in a file of `len`
+/// bytes with no holes, except the one at its end.
+pub(crate) fn dense_extent(len: u64, offset: u64, data: bool) -> Result<u64> {
+    if offset >= len {
+        return Err(std::io::Error::from_raw_os_error(libc::ENXIO));
+    }
+    Ok(if data { offset } else { len })
+}
+
+/// `path` with the symlinks in it resolved by reading them from `disk`,
+/// rather than the host, so that scoped disks resolve them in their own
+/// namespace. The last component is only resolved if `follow` is set, and
+/// everything from the first component that doesn't exist onwards is kept as
+/// is.
+pub(crate) async fn resolve_symlinks<'a, D: FloppyDisk<'a>>(
+    disk: &D,
+    path: &Path,
+    follow: bool,
+) -> Result<PathBuf> {
+    fn components(path: &Path) -> impl Iterator<Item = OsString> + '_ {
+        path.components()
+            .rev()
+            .map(|component| component.as_os_str().to_os_string())
+    }
+
+    let mut pending: Vec<OsString> = components(path).collect();
+    let mut resolved = PathBuf::new();
+    let mut links = 0;
+    let mut missing = false;
+    while let Some(name) = pending.pop() {
+        if name == "." {
+            continue;
+        } else if name == ".." {
+            resolved.pop();
+            continue;
+        }
+        resolved.push(&name);
+        if missing || name == "/" || (pending.is_empty() && !follow) {
+            continue;
+        }
+        let is_symlink = match disk.symlink_metadata(&resolved).await {
+            Ok(metadata) => metadata.is_symlink(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                missing = true;
+                false
+            }
+            Err(e) => return Err(e),
+        };
+        if is_symlink {
+            links += 1;
+            if links > 40 {
+                return Err(std::io::Error::from_raw_os_error(libc::ELOOP));
+            }
+            let target = disk.read_link(&resolved).await?;
+            resolved.pop();
+            pending.extend(components(&target));
+        }
+    }
+    Ok(resolved)
+}
+
+fn unsupported(what: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("{what} aren't supported by this backend"),
+    )
+}
+
+pub(crate) async fn check_not_same_file<'a, D: FloppyDisk<'a>>(
+    disk: &D,
+    from: &Path,
+    to: &Path,
+) -> Result<()>
+where
+    D::Metadata: FloppyUnixMetadata,
+{
+    if disk.try_exists(to).await? && same_file(disk, from, to).await? {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} and {} are the same file", from.display(), to.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Refuse to move a directory into one of its own descendants, eg.
+/// `rename("/a", "/a/b/c")`. Paths that can't be canonicalised are left for
+/// the backend to reject.
+pub(crate) async fn check_not_into_itself<'a, D: FloppyDisk<'a>>(
+    disk: &D,
+    from: &Path,
+    to: &Path,
+) -> Result<()> {
+    let Ok(from) = disk.canonicalize(from).await else {
+        return Ok(());
+    };
+    let to = match (to.parent(), to.file_name()) {
+        (Some(parent), Some(name)) => match disk.canonicalize(parent).await {
+            Ok(parent) => parent.join(name),
+            Err(_) => return Ok(()),
+        },
+        _ => return Ok(()),
+    };
+
+    if to != from && to.starts_with(&from) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "cannot move {} into its own subdirectory {}",
+                from.display(),
+                to.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
 #[allow(clippy::len_without_is_empty)]
 #[async_trait::async_trait]
 pub trait FloppyMetadata<'a, Disk: FloppyDisk<'a>>: Debug + std::marker::Unpin + Send {
@@ -131,11 +471,36 @@ pub trait FloppyMetadata<'a, Disk: FloppyDisk<'a>>: Debug + std::marker::Unpin +
 pub trait FloppyUnixMetadata {
     fn uid(&self) -> Result<u32>;
     fn gid(&self) -> Result<u32>;
+    /// The id of the device containing the file. Two entries with different
+    /// `dev()`s live on different filesystems, so eg. a `rename` between them
+    /// will fail with `EXDEV`.
+    fn dev(&self) -> Result<u64> {
+        Err(unsupported("device ids"))
+    }
+    /// The inode number of the file, if the backend has one.
+    fn ino(&self) -> Result<u64> {
+        Err(unsupported("inode numbers"))
+    }
+    /// The number of 512-byte blocks allocated to the file.
+    fn blocks(&self) -> Result<u64> {
+        Err(unsupported("block counts"))
+    }
 }
 
 #[async_trait::async_trait]
 pub trait FloppyReadDir<'a, Disk: FloppyDisk<'a>>: Debug + std::marker::Unpin + Send {
     async fn next_entry(&mut self) -> Result<Option<Disk::DirEntry>>;
+
+    /// The remaining entries as a stream, for use with `StreamExt` and
+    /// `TryStreamExt` combinators.
+    fn into_stream(self) -> impl futures::Stream<Item = Result<Disk::DirEntry>> + Send + 'a
+    where
+        Self: Sized + 'a,
+    {
+        futures::stream::try_unfold(self, |mut read_dir| async move {
+            Ok(read_dir.next_entry().await?.map(|entry| (entry, read_dir)))
+        })
+    }
 }
 
 pub trait FloppyPermissions: Debug + std::marker::Unpin + Send {
@@ -179,6 +544,71 @@ pub trait FloppyFile<'a, Disk: FloppyDisk<'a>>:
     async fn try_clone(&'a self) -> Result<Box<Disk::File>>;
     async fn set_permissions(&self, perm: Disk::Permissions) -> Result<()>;
     async fn permissions(&self) -> Result<Disk::Permissions>;
+
+    /// Read into `buf` from `offset`, without moving the cursor, so that
+    /// readers sharing a file don't have to take turns with it.
+    ///
+    /// Unsupported unless the backend overrides it.
+    async fn read_at(&self, _buf: &mut [u8], _offset: u64) -> Result<usize> {
+        Err(unsupported("positional reads"))
+    }
+
+    /// Write `buf` at `offset`, without moving the cursor.
+    ///
+    /// Unsupported unless the backend overrides it.
+    async fn write_at(&self, _buf: &[u8], _offset: u64) -> Result<usize> {
+        Err(unsupported("positional writes"))
+    }
+
+    /// Reserve space for `len` bytes from `offset`, growing the file if it's
+    /// shorter, so that a large write fails up front if there's no room for
+    /// it rather than partway through.
+    ///
+    /// Unsupported unless the backend overrides it.
+    async fn allocate(&self, _offset: u64, _len: u64) -> Result<()> {
+        Err(unsupported("allocations"))
+    }
+
+    /// Where the first data at or after `offset` starts, skipping holes in
+    /// sparse files, like `lseek` with `SEEK_DATA`. The cursor doesn't move.
+    ///
+    /// Backends that don't keep track of holes treat the whole file as
+    /// data.
+    async fn seek_data(&self, offset: u64) -> Result<u64> {
+        dense_extent(self.metadata().await?.len(), offset, true)
+    }
+
+    /// Where the first hole at or after `offset` starts, like `lseek` with
+    /// `SEEK_HOLE`. Every file ends in a hole, so this is at most its length.
+    async fn seek_hole(&self, offset: u64) -> Result<u64> {
+        dense_extent(self.metadata().await?.len(), offset, false)
+    }
+
+    /// Write all of several buffers, eg. a header and a body, in as few
+    /// calls to the backend as it can manage.
+    async fn write_all_vectored(&mut self, bufs: &[std::io::IoSlice<'_>]) -> Result<()> {
+        let mut bufs = bufs.to_vec();
+        let mut bufs = &mut bufs[..];
+        std::io::IoSlice::advance_slices(&mut bufs, 0);
+        while !bufs.is_empty() {
+            match tokio::io::AsyncWriteExt::write_vectored(self, bufs).await {
+                Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+                Ok(written) => std::io::IoSlice::advance_slices(&mut bufs, written),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush any buffered writes and close the file. Unlike dropping it, this
+    /// reports errors from writes that were still in flight.
+    async fn close(mut self) -> Result<()>
+    where
+        Self: Sized,
+    {
+        tokio::io::AsyncWriteExt::flush(&mut self).await
+    }
 }
 
 #[async_trait::async_trait]
@@ -198,9 +628,3 @@ pub trait FloppyFileType: Debug + std::marker::Unpin + Send {
     fn is_file(&self) -> bool;
     fn is_symlink(&self) -> bool;
 }
-
-// pub trait FloppyTempDir:
-//     Debug + AsRef<Path> + AsRef<PathBuf> + Send + Sync + Deref<Target = Path>
-// {
-//     fn path(&self) -> &Path;
-// }
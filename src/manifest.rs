@@ -0,0 +1,355 @@
+//! Manifests of every entry in a tree, eg. for signing packages or checking
+//! that a build is reproducible.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::io::Result;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::diff::EntryKind;
+use crate::hash::{file_digest, HashAlgorithm};
+use crate::{
+    FloppyDirEntry, FloppyDisk, FloppyMetadata, FloppyReadDir, FloppyUnixMetadata,
+    FloppyUnixPermissions,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Every entry under a root, by path relative to it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Manifest {
+    pub entries: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ManifestEntry {
+    pub kind: EntryKind,
+    pub len: u64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: SystemTime,
+    /// The hex sha256 of the file's contents, if it's a file.
+    pub sha256: Option<String>,
+    /// Where the entry points, if it's a symlink.
+    pub target: Option<PathBuf>,
+}
+
+impl Manifest {
+    /// A sha256 over every entry, shaped like a [`merkle_tree`](crate::hash::merkle_tree):
+    /// a file covers its mode, owner, length and contents, a symlink its
+    /// target, and a directory its mode, owner, and the names and hashes of
+    /// its children. The root has no entry, so only covers its children.
+    /// Timestamps are left out. Names and targets are hashed as their raw
+    /// bytes, so ones that aren't UTF-8 can't collide.
+    pub fn merkle_root(&self) -> String {
+        let mut children: HashMap<PathBuf, Vec<(OsString, String)>> = HashMap::new();
+        // Children sort after their parents, so are hashed before them.
+        for (path, entry) in self.entries.iter().rev() {
+            let description = format!("{:o} {}:{}", entry.mode, entry.uid, entry.gid);
+            let hash = match entry.kind {
+                EntryKind::File => hash_leaf(&[format!(
+                    "f {description} {}\0{}",
+                    entry.len,
+                    entry.sha256.as_deref().unwrap_or_default()
+                )
+                .as_bytes()]),
+                EntryKind::Symlink => hash_leaf(&[
+                    b"l ",
+                    entry
+                        .target
+                        .as_deref()
+                        .unwrap_or(Path::new(""))
+                        .as_os_str()
+                        .as_bytes(),
+                ]),
+                EntryKind::Dir => hash_dir(
+                    &format!("d {description}"),
+                    children.remove(path).unwrap_or_default(),
+                ),
+            };
+            if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+                children
+                    .entry(parent.to_path_buf())
+                    .or_default()
+                    .push((name.to_owned(), hash));
+            }
+        }
+        hash_dir("d", children.remove(Path::new("")).unwrap_or_default())
+    }
+}
+
+fn hash_leaf(parts: &[&[u8]]) -> String {
+    let mut hasher = HashAlgorithm::Sha256.hasher();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finish()
+}
+
+fn hash_dir(description: &str, mut children: Vec<(OsString, String)>) -> String {
+    children.sort();
+    let mut hasher = HashAlgorithm::Sha256.hasher();
+    hasher.update(format!("{description}\n").as_bytes());
+    for (name, hash) in children {
+        hasher.update(name.as_bytes());
+        hasher.update(format!("\0{hash}\n").as_bytes());
+    }
+    hasher.finish()
+}
+
+/// Walk everything under `root` on `disk`, hashing the contents of every
+/// file. Files are streamed through the hasher, so they never have to fit in
+/// memory.
+pub async fn generate<'a, D>(disk: &'a D, root: impl AsRef<Path> + Send) -> Result<Manifest>
+where
+    D: FloppyDisk<'a>,
+    D::Metadata: FloppyUnixMetadata,
+    D::Permissions: FloppyUnixPermissions,
+{
+    let root = root.as_ref();
+    let mut manifest = Manifest::default();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let mut read_dir = disk.read_dir(root.join(&dir)).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let relative = dir.join(entry.file_name());
+            let path = root.join(&relative);
+            let metadata = disk.symlink_metadata(&path).await?;
+            let (kind, sha256, target) = if metadata.is_symlink() {
+                (EntryKind::Symlink, None, Some(disk.read_link(&path).await?))
+            } else if metadata.is_dir() {
+                dirs.push(relative.clone());
+                (EntryKind::Dir, None, None)
+            } else {
+                (
+                    EntryKind::File,
+                    Some(file_digest(disk, &path, HashAlgorithm::Sha256).await?),
+                    None,
+                )
+            };
+            manifest.entries.insert(
+                relative,
+                ManifestEntry {
+                    kind,
+                    len: if kind == EntryKind::File {
+                        metadata.len()
+                    } else {
+                        0
+                    },
+                    mode: metadata.permissions().mode() & 0o7777,
+                    uid: metadata.uid()?,
+                    gid: metadata.gid()?,
+                    mtime: metadata.modified()?,
+                    sha256,
+                    target,
+                },
+            );
+        }
+    }
+    Ok(manifest)
+}
+
+/// A way a tree differs from its [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// In the manifest, but not on the disk.
+    Missing(PathBuf),
+    /// On the disk, but not in the manifest.
+    Extra(PathBuf),
+    /// A different kind of entry, or different contents or symlink target.
+    Modified(PathBuf),
+    WrongMode {
+        path: PathBuf,
+        expected: u32,
+        actual: u32,
+    },
+    WrongOwner {
+        path: PathBuf,
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+}
+
+impl Mismatch {
+    pub fn path(&self) -> &Path {
+        match self {
+            Mismatch::Missing(path)
+            | Mismatch::Extra(path)
+            | Mismatch::Modified(path)
+            | Mismatch::WrongMode { path, .. }
+            | Mismatch::WrongOwner { path, .. } => path,
+        }
+    }
+}
+
+/// Check everything under `root` on `disk` against `manifest`, returning
+/// every mismatch sorted by path. Modification times are ignored, since they
+/// rarely survive a download or an unpack.
+pub async fn verify<'a, D>(
+    disk: &'a D,
+    root: impl AsRef<Path> + Send,
+    manifest: &Manifest,
+) -> Result<Vec<Mismatch>>
+where
+    D: FloppyDisk<'a>,
+    D::Metadata: FloppyUnixMetadata,
+    D::Permissions: FloppyUnixPermissions,
+{
+    let mut actual = generate(disk, root).await?.entries;
+    let mut mismatches = vec![];
+    for (path, expected) in &manifest.entries {
+        let Some(actual) = actual.remove(path) else {
+            mismatches.push(Mismatch::Missing(path.clone()));
+            continue;
+        };
+        if (actual.kind, actual.len, &actual.sha256, &actual.target)
+            != (
+                expected.kind,
+                expected.len,
+                &expected.sha256,
+                &expected.target,
+            )
+        {
+            mismatches.push(Mismatch::Modified(path.clone()));
+            continue;
+        }
+        // Symlinks' own modes and owners don't mean anything.
+        if actual.kind == EntryKind::Symlink {
+            continue;
+        }
+        if actual.mode != expected.mode {
+            mismatches.push(Mismatch::WrongMode {
+                path: path.clone(),
+                expected: expected.mode,
+                actual: actual.mode,
+            });
+        }
+        if (actual.uid, actual.gid) != (expected.uid, expected.gid) {
+            mismatches.push(Mismatch::WrongOwner {
+                path: path.clone(),
+                expected: (expected.uid, expected.gid),
+                actual: (actual.uid, actual.gid),
+            });
+        }
+    }
+    mismatches.extend(actual.into_keys().map(Mismatch::Extra));
+    mismatches.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+    use crate::FloppyDiskUnixExt;
+
+    #[tokio::test]
+    async fn test_generate() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root/a").await?;
+        fs.write("/root/a/b.txt", "asdf").await?;
+        fs.set_permissions("/root/a/b.txt", FloppyUnixPermissions::from_mode(0o600))
+            .await?;
+        fs.symlink("a/b.txt", "/root/c").await?;
+
+        let manifest = generate(&fs, "/root").await?;
+        assert_eq!(3, manifest.entries.len());
+        assert_eq!(EntryKind::Dir, manifest.entries[Path::new("a")].kind);
+        let file = &manifest.entries[Path::new("a/b.txt")];
+        assert_eq!(4, file.len);
+        assert_eq!(0o600, file.mode);
+        assert_eq!(
+            Some("f0e4c2f76c58916ec258f246851bea091d14d4247a2fc3e18694461b1816e13b"),
+            file.sha256.as_deref()
+        );
+        assert_eq!(
+            Some(Path::new("a/b.txt")),
+            manifest.entries[Path::new("c")].target.as_deref()
+        );
+
+        // Covers everything but timestamps.
+        let root = manifest.merkle_root();
+        fs.write("/root/a/b.txt", "asdf").await?;
+        assert_eq!(root, generate(&fs, "/root").await?.merkle_root());
+        let mut changed = manifest.clone();
+        changed.entries.get_mut(Path::new("a")).unwrap().mode = 0o700;
+        assert_ne!(root, changed.merkle_root());
+        changed = manifest.clone();
+        changed.entries.remove(Path::new("c"));
+        assert_ne!(root, changed.merkle_root());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_merkle_root_non_utf8() -> Result<()> {
+        use std::ffi::OsStr;
+
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root").await?;
+        fs.write("/root/a.txt", "asdf").await?;
+        fs.symlink("a.txt", "/root/c").await?;
+        let manifest = generate(&fs, "/root").await?;
+
+        // Both of these are U+FFFD when lossily converted.
+        let (ff, fe) = (OsStr::from_bytes(b"\xff"), OsStr::from_bytes(b"\xfe"));
+        let renamed = |name: &OsStr| {
+            let mut renamed = manifest.clone();
+            let entry = renamed.entries.remove(Path::new("a.txt")).unwrap();
+            renamed.entries.insert(PathBuf::from(name), entry);
+            renamed.merkle_root()
+        };
+        assert_ne!(renamed(ff), renamed(fe));
+        let retargeted = |target: &OsStr| {
+            let mut retargeted = manifest.clone();
+            retargeted.entries.get_mut(Path::new("c")).unwrap().target = Some(target.into());
+            retargeted.merkle_root()
+        };
+        assert_ne!(retargeted(ff), retargeted(fe));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root/a").await?;
+        fs.write("/root/a/b.txt", "asdf").await?;
+        fs.write("/root/c.txt", "jkl").await?;
+        fs.write("/root/d.txt", "qwe").await?;
+        let manifest = generate(&fs, "/root").await?;
+        assert!(verify(&fs, "/root", &manifest).await?.is_empty());
+
+        fs.write("/root/a/b.txt", "fdsa").await?;
+        fs.remove_file("/root/c.txt").await?;
+        fs.set_permissions("/root/d.txt", FloppyUnixPermissions::from_mode(0o600))
+            .await?;
+        fs.chown("/root/d.txt", 0, 0).await?;
+        fs.write("/root/e.txt", "extra").await?;
+        assert_eq!(
+            vec![
+                Mismatch::Modified("a/b.txt".into()),
+                Mismatch::Missing("c.txt".into()),
+                Mismatch::WrongMode {
+                    path: "d.txt".into(),
+                    expected: 0o666,
+                    actual: 0o600,
+                },
+                Mismatch::WrongOwner {
+                    path: "d.txt".into(),
+                    expected: (1000, 1000),
+                    actual: (0, 0),
+                },
+                Mismatch::Extra("e.txt".into()),
+            ],
+            verify(&fs, "/root", &manifest).await?
+        );
+
+        Ok(())
+    }
+}
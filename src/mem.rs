@@ -1,27 +1,127 @@
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
 use std::ffi::OsString;
 use std::io::{Read, Result, Seek, Write};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
 use derivative::Derivative;
 use futures::{Future, TryStreamExt};
-use rsfs_tokio::unix_ext::{GenFSExt, PermissionsExt};
+use rsfs_tokio::unix_ext::{FileExt, GenFSExt, PermissionsExt};
 use rsfs_tokio::{DirEntry, File, FileType, GenFS, Metadata, OpenOptions};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 pub type InMemoryUnixFS = rsfs_tokio::mem::unix::FS;
 
 // TODO: DirBuilder, OpenOptions
+use crate::sealed::{Contents, SealedFloppyDisk, SealedKind, SealedNode};
 use crate::{
-    FloppyDirBuilder, FloppyDirEntry, FloppyDisk, FloppyDiskUnixExt, FloppyFile, FloppyFileType,
-    FloppyMetadata, FloppyOpenOptions, FloppyPermissions, FloppyReadDir, FloppyUnixMetadata,
-    FloppyUnixPermissions,
+    atomic_temp_path, check_not_into_itself, check_not_same_file, FloppyDirBuilder, FloppyDirEntry,
+    FloppyDisk, FloppyDiskUnixExt, FloppyFile, FloppyFileType, FloppyMetadata, FloppyOpenOptions,
+    FloppyPermissions, FloppyReadDir, FloppyUnixMetadata, FloppyUnixPermissions,
 };
 
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct MemFloppyDisk {
     fs: InMemoryUnixFS,
+    dev: u64,
+    #[derivative(Debug = "ignore")]
+    snapshots: Mutex<BTreeMap<String, Arc<Snapshot>>>,
+    #[derivative(Debug = "ignore")]
+    inodes: Arc<Mutex<Inodes>>,
+}
+
+/// Source of synthetic device ids, so that every `MemFloppyDisk` looks like
+/// its own filesystem.
+pub(crate) static NEXT_DEV: AtomicU64 = AtomicU64::new(1);
+
+/// Synthetic inode numbers, by canonical path. rsfs shares inodes between
+/// hard links but doesn't number them, so a path is numbered the first time
+/// it's looked at, and the number follows it through links, renames and
+/// removals.
+#[derive(Debug, Default)]
+struct Inodes {
+    next: u64,
+    by_path: BTreeMap<PathBuf, u64>,
+    /// Modified times rsfs can't set, by inode, each with the modified time
+    /// rsfs had when it was set. One stands until rsfs's own time moves on,
+    /// ie. until the entry is changed again.
+    modified: BTreeMap<u64, (SystemTime, SystemTime)>,
+}
+
+impl Inodes {
+    fn get(&mut self, path: &Path) -> u64 {
+        if let Some(ino) = self.by_path.get(path) {
+            return *ino;
+        }
+        self.next += 1;
+        self.by_path.insert(path.to_path_buf(), self.next);
+        self.next
+    }
+
+    fn link(&mut self, src: &Path, dst: &Path) {
+        let ino = self.get(src);
+        self.by_path.insert(dst.to_path_buf(), ino);
+    }
+
+    /// Forget `path` and everything under it, returning what was forgotten.
+    fn remove(&mut self, path: &Path) -> Vec<(PathBuf, u64)> {
+        // Paths sort component-wise, so everything under `path` directly
+        // follows it.
+        let under: Vec<PathBuf> = self
+            .by_path
+            .range(path.to_path_buf()..)
+            .take_while(|(under, _)| under.starts_with(path))
+            .map(|(under, _)| under.clone())
+            .collect();
+        under
+            .into_iter()
+            .filter_map(|under| self.by_path.remove(&under).map(|ino| (under, ino)))
+            .collect()
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) {
+        let moved = self.remove(from);
+        self.remove(to);
+        for (path, ino) in moved {
+            if let Ok(rest) = path.strip_prefix(from) {
+                self.by_path.insert(to.join(rest), ino);
+            }
+        }
+    }
+
+    fn set_modified(
+        &mut self,
+        path: &Path,
+        metadata: &rsfs_tokio::mem::unix::Metadata,
+        modified: SystemTime,
+    ) -> Result<()> {
+        let ino = self.get(path);
+        self.modified.insert(ino, (modified, metadata.modified()?));
+        Ok(())
+    }
+
+    fn metadata(
+        &self,
+        metadata: rsfs_tokio::mem::unix::Metadata,
+        dev: u64,
+        ino: u64,
+    ) -> MemMetadata {
+        let modified = self
+            .modified
+            .get(&ino)
+            .filter(|(_, at)| metadata.modified().ok() == Some(*at))
+            .map(|(modified, _)| *modified);
+        MemMetadata {
+            metadata,
+            dev,
+            ino,
+            modified,
+        }
+    }
 }
 
 impl MemFloppyDisk {
@@ -29,6 +129,483 @@ impl MemFloppyDisk {
     pub fn new() -> Self {
         Self {
             fs: InMemoryUnixFS::new(),
+            dev: NEXT_DEV.fetch_add(1, Ordering::Relaxed),
+            snapshots: Mutex::new(BTreeMap::new()),
+            inodes: Arc::new(Mutex::new(Inodes::default())),
+        }
+    }
+
+    /// Save the current contents of the disk as the snapshot `name`.
+    pub async fn snapshot<S: Into<String>>(&self, name: S) -> Result<()> {
+        let name = name.into();
+        let snapshot = self.capture().await?;
+        match self.snapshots.lock().unwrap().entry(name) {
+            Entry::Occupied(entry) => Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("snapshot {} already exists", entry.key()),
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(Arc::new(snapshot));
+                Ok(())
+            }
+        }
+    }
+
+    /// The names of all snapshots, in sorted order.
+    pub fn list_snapshots(&self) -> Vec<String> {
+        self.snapshots.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn delete_snapshot(&self, name: &str) -> Result<()> {
+        self.snapshots
+            .lock()
+            .unwrap()
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| no_such_snapshot(name))
+    }
+
+    /// What changed going from snapshot `from` to snapshot `to`, sorted by
+    /// path.
+    pub fn diff_snapshots(&self, from: &str, to: &str) -> Result<Vec<SnapshotChange>> {
+        let (from, to) = (self.get_snapshot(from)?, self.get_snapshot(to)?);
+        Ok(diff_snapshots(&from, &to))
+    }
+
+    /// Write the changes from snapshot `lower` to snapshot `upper` to the
+    /// real directory `upperdir` as an overlayfs upper layer, and create an
+    /// empty `workdir` to mount it with. Removed paths become whiteouts, and
+    /// directories that replace a non-directory are marked opaque, which
+    /// needs permission to set `trusted.*` xattrs. File modes are kept, but
+    /// ownership is not.
+    pub async fn export_overlay_layer<P: AsRef<Path>>(
+        &self,
+        lower: &str,
+        upper: &str,
+        upperdir: P,
+        workdir: P,
+    ) -> Result<()> {
+        let (lower, upper) = (self.get_snapshot(lower)?, self.get_snapshot(upper)?);
+        let upperdir = upperdir.as_ref();
+        tokio::fs::create_dir_all(upperdir).await?;
+        tokio::fs::create_dir_all(workdir).await?;
+
+        // Anything under a path that has been whited out or replaced is
+        // already hidden by it.
+        let mut hidden: Vec<PathBuf> = vec![];
+        for change in diff_snapshots(&lower, &upper) {
+            let path = change.path();
+            if hidden.iter().any(|hidden| path.starts_with(hidden)) {
+                continue;
+            }
+            create_overlay_parents(&upper, upperdir, path).await?;
+            let target = upperdir.join(path.strip_prefix("/").unwrap_or(path));
+            let was_dir = matches!(lower.get(path), Some(SnapshotNode::Dir { .. }));
+            match upper.get(path) {
+                None => {
+                    create_whiteout(target).await?;
+                    hidden.push(path.to_path_buf());
+                }
+                Some(node) => {
+                    let is_dir = matches!(node, SnapshotNode::Dir { .. });
+                    if was_dir && !is_dir {
+                        hidden.push(path.to_path_buf());
+                    }
+                    write_overlay_node(node, &target).await?;
+                    if is_dir && lower.contains_key(path) && !was_dir {
+                        set_opaque(target).await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the contents of the disk with the snapshot `name`. The
+    /// snapshot is kept, so it can be rolled back to again. Policies are not
+    /// applied while restoring.
+    pub async fn rollback_to(&self, name: &str) -> Result<()> {
+        let snapshot = self.get_snapshot(name)?;
+
+        let mut read_dir = self.read_dir("/").await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                self.fs.remove_dir_all(entry.path()).await?;
+            } else {
+                self.fs.remove_file(entry.path()).await?;
+            }
+        }
+        self.inodes.lock().unwrap().by_path.clear();
+
+        // Parents sort before their children, so directories exist before
+        // anything is created in them.
+        for (path, node) in snapshot.iter() {
+            match node {
+                SnapshotNode::Dir { .. } => self.fs.create_dir(path).await?,
+                SnapshotNode::File { contents, .. } => {
+                    let mut file = self.fs.create_file(path).await?;
+                    file.write_all(contents).await?;
+                }
+                SnapshotNode::Symlink { target } => self.fs.symlink(target, path).await?,
+            }
+        }
+        // Permissions go last, so that read-only directories can still be
+        // filled in.
+        for (path, node) in snapshot.iter().rev() {
+            if let SnapshotNode::Dir { owner } | SnapshotNode::File { owner, .. } = node {
+                self.fs
+                    .set_permissions(path, rsfs_tokio::mem::Permissions::from_mode(owner.mode))
+                    .await?;
+                self.fs
+                    .set_ownership(path.clone(), owner.uid, owner.gid)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy `root` on `disk`, and everything under it, into a new in-memory
+    /// disk at the same path. Modes, ownership, modified times, and symlinks
+    /// are kept as-is. A modified time lasts until the entry is next changed,
+    /// when it moves on as usual.
+    pub async fn from_disk<'a, D>(disk: &'a D, root: impl AsRef<Path> + Send) -> Result<Self>
+    where
+        D: FloppyDisk<'a>,
+        D::Metadata: FloppyUnixMetadata,
+        D::Permissions: FloppyUnixPermissions,
+    {
+        let mem = Self::new();
+        let root = root.as_ref();
+        if let Some(parent) = root.parent() {
+            mem.fs.create_dir_all(parent).await?;
+        }
+
+        // Owners go last, so that read-only directories can still be filled
+        // in.
+        let mut owners = vec![];
+        let mut times = vec![];
+        let mut stack = vec![root.to_path_buf()];
+        while let Some(path) = stack.pop() {
+            let metadata = disk.symlink_metadata(&path).await?;
+            if metadata.is_symlink() {
+                mem.fs.symlink(disk.read_link(&path).await?, &path).await?;
+                times.push((path, metadata.modified()?));
+                continue;
+            }
+            if metadata.is_dir() {
+                mem.fs.create_dir_all(&path).await?;
+                let mut read_dir = disk.read_dir(&path).await?;
+                while let Some(entry) = read_dir.next_entry().await? {
+                    stack.push(path.join(entry.file_name()));
+                }
+            } else {
+                let mut reader = D::OpenOptions::new().read(true).open(disk, &path).await?;
+                let mut file = mem.fs.create_file(&path).await?;
+                tokio::io::copy(&mut reader, &mut file).await?;
+                file.flush().await?;
+            }
+            let owner = SnapshotOwner {
+                mode: metadata.permissions().mode(),
+                uid: metadata.uid()?,
+                gid: metadata.gid()?,
+            };
+            times.push((path.clone(), metadata.modified()?));
+            owners.push((path, owner));
+        }
+
+        for (path, owner) in owners.into_iter().rev() {
+            mem.fs
+                .set_permissions(&path, rsfs_tokio::mem::Permissions::from_mode(owner.mode))
+                .await?;
+            mem.fs.set_ownership(path, owner.uid, owner.gid).await?;
+        }
+        // Once everything's in place, so that nothing moves them on.
+        for (path, modified) in times {
+            let metadata = mem.fs.symlink_metadata(&path).await?;
+            let inode_path = mem.inode_path(&path).await?;
+            mem.inodes
+                .lock()
+                .unwrap()
+                .set_modified(&inode_path, &metadata, modified)?;
+        }
+        Ok(mem)
+    }
+
+    /// An immutable copy of the disk, for serving from many tasks at once
+    /// without taking any locks. Later changes to this disk don't affect it.
+    pub async fn seal(&self) -> Result<SealedFloppyDisk> {
+        let mut nodes = BTreeMap::new();
+        let root = self.symlink_metadata("/").await?;
+        let owner = |metadata: &MemMetadata| -> Result<_> {
+            Ok((
+                metadata.permissions().mode(),
+                metadata.uid()?,
+                metadata.gid()?,
+            ))
+        };
+        let (mode, uid, gid) = owner(&root)?;
+        let kind = SealedKind::Dir;
+        nodes.insert(
+            PathBuf::from("/"),
+            SealedNode {
+                kind,
+                mode,
+                uid,
+                gid,
+            },
+        );
+        for (path, node) in self.capture().await? {
+            let (mode, uid, gid) = owner(&self.symlink_metadata(&path).await?)?;
+            let kind = match node {
+                SnapshotNode::Dir { .. } => SealedKind::Dir,
+                SnapshotNode::File { contents, .. } => SealedKind::File(Contents::owned(contents)),
+                SnapshotNode::Symlink { target } => SealedKind::Symlink(target),
+            };
+            nodes.insert(
+                path,
+                SealedNode {
+                    kind,
+                    mode,
+                    uid,
+                    gid,
+                },
+            );
+        }
+        Ok(SealedFloppyDisk::new(nodes, self.dev))
+    }
+
+    /// `path` made absolute, with the symlinks in it resolved; the last one
+    /// only if `follow` is set. rsfs's own `canonicalize` walks up from the
+    /// entry instead, and entries don't learn their new parent when they're
+    /// renamed.
+    async fn resolve(&self, path: &Path, follow: bool) -> Result<PathBuf> {
+        fn components(path: &Path) -> impl Iterator<Item = OsString> + '_ {
+            path.components()
+                .rev()
+                .map(|component| component.as_os_str().to_os_string())
+        }
+
+        let mut pending: Vec<OsString> = components(path).collect();
+        let mut resolved = PathBuf::from("/");
+        let mut links = 0;
+        while let Some(name) = pending.pop() {
+            if name == "/" {
+                resolved = PathBuf::from("/");
+                continue;
+            } else if name == "." {
+                continue;
+            } else if name == ".." {
+                resolved.pop();
+                continue;
+            }
+            resolved.push(&name);
+            if pending.is_empty() && !follow {
+                break;
+            }
+            if self
+                .fs
+                .symlink_metadata(&resolved)
+                .await?
+                .file_type()
+                .is_symlink()
+            {
+                links += 1;
+                if links > 40 {
+                    return Err(std::io::Error::from_raw_os_error(libc::ELOOP));
+                }
+                let target = self.fs.read_link(&resolved).await?;
+                resolved.pop();
+                pending.extend(components(&target));
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Where `path` itself, rather than what it links to, is numbered in
+    /// `inodes`.
+    async fn inode_path(&self, path: &Path) -> Result<PathBuf> {
+        self.resolve(path, false).await
+    }
+
+    fn get_snapshot(&self, name: &str) -> Result<Arc<Snapshot>> {
+        self.snapshots
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| no_such_snapshot(name))
+    }
+
+    async fn capture(&self) -> Result<Snapshot> {
+        let mut snapshot = Snapshot::new();
+        let mut dirs = vec![PathBuf::from("/")];
+        while let Some(dir) = dirs.pop() {
+            let mut read_dir = self.read_dir(&dir).await?;
+            while let Some(entry) = read_dir.next_entry().await? {
+                let path = entry.path();
+                let metadata = self.symlink_metadata(&path).await?;
+                let owner = SnapshotOwner {
+                    mode: metadata.permissions().mode(),
+                    uid: metadata.uid()?,
+                    gid: metadata.gid()?,
+                };
+                let node = if metadata.is_symlink() {
+                    SnapshotNode::Symlink {
+                        target: self.read_link(&path).await?,
+                    }
+                } else if metadata.is_dir() {
+                    dirs.push(path.clone());
+                    SnapshotNode::Dir { owner }
+                } else {
+                    SnapshotNode::File {
+                        contents: self.read(&path).await?,
+                        owner,
+                    }
+                };
+                snapshot.insert(path, node);
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+fn diff_snapshots(from: &Snapshot, to: &Snapshot) -> Vec<SnapshotChange> {
+    let mut changes = vec![];
+    for (path, node) in from.iter() {
+        match to.get(path) {
+            Some(other) if other != node => changes.push(SnapshotChange::Modified(path.clone())),
+            Some(_) => {}
+            None => changes.push(SnapshotChange::Removed(path.clone())),
+        }
+    }
+    for path in to.keys().filter(|path| !from.contains_key(*path)) {
+        changes.push(SnapshotChange::Added(path.clone()));
+    }
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    changes
+}
+
+/// Create the directories above `path` in an overlay layer that don't exist
+/// yet, with their modes from `snapshot`.
+async fn create_overlay_parents(snapshot: &Snapshot, upperdir: &Path, path: &Path) -> Result<()> {
+    let mut ancestors: Vec<&Path> = path.ancestors().skip(1).collect();
+    ancestors.reverse();
+    for ancestor in ancestors {
+        let target = upperdir.join(ancestor.strip_prefix("/").unwrap_or(ancestor));
+        if tokio::fs::try_exists(&target).await? {
+            continue;
+        }
+        match snapshot.get(ancestor) {
+            Some(node @ SnapshotNode::Dir { .. }) => write_overlay_node(node, &target).await?,
+            _ => tokio::fs::create_dir(&target).await?,
+        }
+    }
+    Ok(())
+}
+
+async fn write_overlay_node(node: &SnapshotNode, target: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    match node {
+        SnapshotNode::Dir { owner } => {
+            if !tokio::fs::try_exists(target).await? {
+                tokio::fs::create_dir(target).await?;
+            }
+            tokio::fs::set_permissions(target, std::fs::Permissions::from_mode(owner.mode)).await
+        }
+        SnapshotNode::File { contents, owner } => {
+            tokio::fs::write(target, contents).await?;
+            tokio::fs::set_permissions(target, std::fs::Permissions::from_mode(owner.mode)).await
+        }
+        SnapshotNode::Symlink { target: link } => tokio::fs::symlink(link, target).await,
+    }
+}
+
+/// Overlayfs whiteouts are character devices with device number 0/0.
+async fn create_whiteout(path: PathBuf) -> Result<()> {
+    let path = path_to_cstring(path)?;
+    tokio::task::spawn_blocking(move || {
+        if unsafe { libc::mknod(path.as_ptr(), libc::S_IFCHR, libc::makedev(0, 0)) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    })
+    .await?
+}
+
+async fn set_opaque(path: PathBuf) -> Result<()> {
+    let path = path_to_cstring(path)?;
+    tokio::task::spawn_blocking(move || {
+        let name = c"trusted.overlay.opaque";
+        let value = b"y";
+        let result = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+            )
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    })
+    .await?
+}
+
+fn path_to_cstring(path: PathBuf) -> Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStringExt;
+
+    std::ffi::CString::new(path.into_os_string().into_vec())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+}
+
+fn no_such_snapshot(name: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("no snapshot named {name}"),
+    )
+}
+
+type Snapshot = BTreeMap<PathBuf, SnapshotNode>;
+
+#[derive(Debug, PartialEq, Eq)]
+enum SnapshotNode {
+    Dir {
+        owner: SnapshotOwner,
+    },
+    File {
+        contents: Vec<u8>,
+        owner: SnapshotOwner,
+    },
+    Symlink {
+        target: PathBuf,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct SnapshotOwner {
+    mode: u32,
+    uid: u32,
+    gid: u32,
+}
+
+/// A difference between two snapshots of a [`MemFloppyDisk`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotChange {
+    Added(PathBuf),
+    Removed(PathBuf),
+    Modified(PathBuf),
+}
+
+impl SnapshotChange {
+    pub fn path(&self) -> &Path {
+        match self {
+            SnapshotChange::Added(path)
+            | SnapshotChange::Removed(path)
+            | SnapshotChange::Modified(path) => path,
         }
     }
 }
@@ -45,10 +622,13 @@ impl<'a> FloppyDisk<'a> for MemFloppyDisk {
     type ReadDir = MemReadDir;
 
     async fn canonicalize<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
-        self.fs.canonicalize(path).await
+        let resolved = self.resolve(path.as_ref(), true).await?;
+        self.fs.symlink_metadata(&resolved).await?;
+        Ok(resolved)
     }
 
     async fn copy<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<u64> {
+        check_not_same_file(self, from.as_ref(), to.as_ref()).await?;
         self.fs.copy(from, to).await
     }
 
@@ -60,13 +640,20 @@ impl<'a> FloppyDisk<'a> for MemFloppyDisk {
         self.fs.create_dir_all(path).await
     }
 
-    async fn hard_link<P: AsRef<Path> + Send>(&self, _src: P, _dst: P) -> Result<()> {
-        unimplemented!("hard links are not yet supported")
+    async fn hard_link<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        let inode_src = self.inode_path(src.as_ref()).await?;
+        let inode_dst = self.inode_path(dst.as_ref()).await?;
+        self.fs.hard_link(src, dst).await?;
+        self.inodes.lock().unwrap().link(&inode_src, &inode_dst);
+        Ok(())
     }
 
     async fn metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
-        let metadata = self.fs.metadata(path).await?;
-        Ok(Self::Metadata { metadata })
+        let metadata = self.fs.metadata(path.as_ref()).await?;
+        let inode_path = self.resolve(path.as_ref(), true).await?;
+        let mut inodes = self.inodes.lock().unwrap();
+        let ino = inodes.get(&inode_path);
+        Ok(inodes.metadata(metadata, self.dev, ino))
     }
 
     async fn read<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>> {
@@ -79,7 +666,14 @@ impl<'a> FloppyDisk<'a> for MemFloppyDisk {
     }
 
     async fn read_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::ReadDir> {
-        self.fs.read_dir(path).await.map(MemReadDir::new)
+        let read_dir = self.fs.read_dir(path.as_ref()).await?;
+        let dir = self.resolve(path.as_ref(), true).await?;
+        Ok(MemReadDir::new(
+            read_dir,
+            self.dev,
+            self.inodes.clone(),
+            dir,
+        ))
     }
 
     async fn read_link<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
@@ -95,19 +689,33 @@ impl<'a> FloppyDisk<'a> for MemFloppyDisk {
     }
 
     async fn remove_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
-        self.fs.remove_dir(path).await
+        let inode_path = self.inode_path(path.as_ref()).await?;
+        self.fs.remove_dir(path).await?;
+        self.inodes.lock().unwrap().remove(&inode_path);
+        Ok(())
     }
 
     async fn remove_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
-        self.fs.remove_dir_all(path).await
+        let inode_path = self.inode_path(path.as_ref()).await?;
+        self.fs.remove_dir_all(path).await?;
+        self.inodes.lock().unwrap().remove(&inode_path);
+        Ok(())
     }
 
     async fn remove_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
-        self.fs.remove_file(path).await
+        let inode_path = self.inode_path(path.as_ref()).await?;
+        self.fs.remove_file(path).await?;
+        self.inodes.lock().unwrap().remove(&inode_path);
+        Ok(())
     }
 
     async fn rename<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<()> {
-        self.fs.rename(from, to).await
+        check_not_into_itself(self, from.as_ref(), to.as_ref()).await?;
+        let inode_from = self.inode_path(from.as_ref()).await?;
+        let inode_to = self.inode_path(to.as_ref()).await?;
+        self.fs.rename(from, to).await?;
+        self.inodes.lock().unwrap().rename(&inode_from, &inode_to);
+        Ok(())
     }
 
     async fn set_permissions<P: AsRef<Path> + Send>(
@@ -125,10 +733,11 @@ impl<'a> FloppyDisk<'a> for MemFloppyDisk {
     }
 
     async fn symlink_metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
-        self.fs
-            .symlink_metadata(path)
-            .await
-            .map(|metadata| Self::Metadata { metadata })
+        let metadata = self.fs.symlink_metadata(path.as_ref()).await?;
+        let inode_path = self.inode_path(path.as_ref()).await?;
+        let mut inodes = self.inodes.lock().unwrap();
+        let ino = inodes.get(&inode_path);
+        Ok(inodes.metadata(metadata, self.dev, ino))
     }
 
     async fn try_exists<P: AsRef<Path> + Send>(&self, path: P) -> Result<bool> {
@@ -141,8 +750,57 @@ impl<'a> FloppyDisk<'a> for MemFloppyDisk {
         contents: impl AsRef<[u8]> + Send,
     ) -> Result<()> {
         let mut file = self.fs.create_file(path).await?;
-        let contents = contents.as_ref();
+        file.write_all(contents.as_ref()).await?;
+        Ok(())
+    }
+
+    async fn truncate<P: AsRef<Path> + Send>(&'a self, path: P, len: u64) -> Result<()> {
+        let mut options = self.fs.new_openopts();
+        options.write(true);
+        options.open(path.as_ref()).await?.set_len(len).await
+    }
+
+    /// The new contents are written off to the side and renamed into place
+    /// under the disk's lock, so there's nothing to sync.
+    async fn write_atomic<P: AsRef<Path> + Send>(
+        &'a self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        let (path, contents) = (path.as_ref(), contents.as_ref());
+        let tmp = atomic_temp_path(path)?;
+        let mut file = self.fs.create_file(&tmp).await?;
         file.write_all(contents).await?;
+        let inode_tmp = self.inode_path(&tmp).await?;
+        let inode_path = self.inode_path(path).await?;
+        if let Err(e) = self.fs.rename(&tmp, path).await {
+            let _ = self.fs.remove_file(&tmp).await;
+            return Err(e);
+        }
+        self.inodes.lock().unwrap().rename(&inode_tmp, &inode_path);
+        Ok(())
+    }
+
+    /// All of `contents` is written at the end of the file while holding its
+    /// lock, so appends from other tasks never land in the middle of it.
+    async fn append<P: AsRef<Path> + Send>(
+        &'a self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        let contents = contents.as_ref();
+        let mut file = MemOpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(self, path)
+            .await?;
+        let written = AsyncWriteExt::write(&mut file, contents).await?;
+        if written < contents.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::WriteZero,
+                "append was cut short",
+            ));
+        }
         Ok(())
     }
 
@@ -163,10 +821,16 @@ impl FloppyDiskUnixExt for MemFloppyDisk {
     }
 }
 
+/// Writes go straight to memory, so dropping the file without
+/// [closing](FloppyFile::close) it loses nothing.
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct MemFile {
     file: rsfs_tokio::mem::unix::File,
+    dev: u64,
+    ino: u64,
+    #[derivative(Debug = "ignore")]
+    inodes: Arc<Mutex<Inodes>>,
 }
 
 #[async_trait::async_trait]
@@ -184,14 +848,20 @@ impl<'a> FloppyFile<'a, MemFloppyDisk> for MemFile {
     }
 
     async fn metadata(&self) -> Result<<MemFloppyDisk as FloppyDisk>::Metadata> {
-        Ok(MemMetadata {
-            metadata: self.file.metadata().await?,
-        })
+        let metadata = self.file.metadata().await?;
+        Ok(self
+            .inodes
+            .lock()
+            .unwrap()
+            .metadata(metadata, self.dev, self.ino))
     }
 
     async fn try_clone(&'a self) -> Result<Box<Self>> {
         Ok(Box::new(Self {
             file: self.file.try_clone().await?,
+            dev: self.dev,
+            ino: self.ino,
+            inodes: self.inodes.clone(),
         }))
     }
 
@@ -209,6 +879,26 @@ impl<'a> FloppyFile<'a, MemFloppyDisk> for MemFile {
             mode: self.file.metadata().await?.permissions().mode(),
         })
     }
+
+    async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.file.read_at(buf, offset).await
+    }
+
+    async fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        self.file.write_at(buf, offset).await
+    }
+
+    /// Files are single buffers, so reserving space is growing the buffer.
+    async fn allocate(&self, offset: u64, len: u64) -> Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let end = offset.saturating_add(len);
+        if self.file.metadata().await?.len() < end {
+            self.file.set_len(end).await?;
+        }
+        Ok(())
+    }
 }
 
 impl AsyncSeek for MemFile {
@@ -251,6 +941,21 @@ impl AsyncWrite for MemFile {
         file.poll_write(cx, buf)
     }
 
+    /// Writes go straight into the file's buffer, so the buffers are joined
+    /// and written with a single extend.
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> std::task::Poll<Result<usize>> {
+        let buf: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter().copied()).collect();
+        self.poll_write(cx, &buf)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
     fn poll_flush(
         mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
@@ -328,11 +1033,15 @@ impl FloppyUnixPermissions for MemPermissions {
 #[derive(Debug)]
 pub struct MemMetadata {
     metadata: rsfs_tokio::mem::unix::Metadata,
+    dev: u64,
+    ino: u64,
+    /// Set when the modified time was carried over from another disk.
+    modified: Option<SystemTime>,
 }
 
 #[async_trait::async_trait]
 impl<'a> FloppyMetadata<'a, MemFloppyDisk> for MemMetadata {
-    fn file_type(&self) -> <MemFloppyDisk as FloppyDisk>::FileType {
+    fn file_type(&self) -> <MemFloppyDisk as FloppyDisk<'a>>::FileType {
         MemFileType(self.metadata.file_type())
     }
 
@@ -352,14 +1061,17 @@ impl<'a> FloppyMetadata<'a, MemFloppyDisk> for MemMetadata {
         self.metadata.len()
     }
 
-    fn permissions(&self) -> <MemFloppyDisk as FloppyDisk>::Permissions {
+    fn permissions(&self) -> <MemFloppyDisk as FloppyDisk<'a>>::Permissions {
         MemPermissions {
             mode: self.metadata.permissions().mode(),
         }
     }
 
     fn modified(&self) -> Result<SystemTime> {
-        self.metadata.modified()
+        match self.modified {
+            Some(modified) => Ok(modified),
+            None => self.metadata.modified(),
+        }
     }
 
     fn accessed(&self) -> Result<SystemTime> {
@@ -379,6 +1091,20 @@ impl FloppyUnixMetadata for MemMetadata {
     fn gid(&self) -> Result<u32> {
         self.metadata.gid()
     }
+
+    fn dev(&self) -> Result<u64> {
+        Ok(self.dev)
+    }
+
+    fn ino(&self) -> Result<u64> {
+        Ok(self.ino)
+    }
+
+    /// Files take exactly as much memory as their contents, rounded up here
+    /// to whole blocks.
+    fn blocks(&self) -> Result<u64> {
+        Ok(self.metadata.len().div_ceil(512))
+    }
 }
 
 #[derive(Debug)]
@@ -398,14 +1124,30 @@ impl FloppyFileType for MemFileType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Derivative)]
+#[derivative(Debug)]
 pub struct MemReadDir {
     read_dir: rsfs_tokio::mem::unix::ReadDir,
+    dev: u64,
+    #[derivative(Debug = "ignore")]
+    inodes: Arc<Mutex<Inodes>>,
+    /// The canonical path of the directory, for numbering its entries.
+    dir: PathBuf,
 }
 
 impl MemReadDir {
-    fn new(read_dir: rsfs_tokio::mem::unix::ReadDir) -> Self {
-        Self { read_dir }
+    fn new(
+        read_dir: rsfs_tokio::mem::unix::ReadDir,
+        dev: u64,
+        inodes: Arc<Mutex<Inodes>>,
+        dir: PathBuf,
+    ) -> Self {
+        Self {
+            read_dir,
+            dev,
+            inodes,
+            dir,
+        }
     }
 }
 
@@ -413,7 +1155,15 @@ impl MemReadDir {
 impl<'a> FloppyReadDir<'a, MemFloppyDisk> for MemReadDir {
     async fn next_entry(&mut self) -> Result<Option<<MemFloppyDisk as FloppyDisk>::DirEntry>> {
         match self.read_dir.try_next().await {
-            Ok(Some(Some(entry))) => Ok(Some(MemDirEntry { entry })),
+            Ok(Some(Some(entry))) => {
+                let inode_path = self.dir.join(entry.file_name());
+                Ok(Some(MemDirEntry {
+                    entry,
+                    dev: self.dev,
+                    ino: self.inodes.lock().unwrap().get(&inode_path),
+                    inodes: self.inodes.clone(),
+                }))
+            }
             Ok(Some(None)) => Ok(None),
             Ok(None) => Ok(None),
             Err(e) => Err(e),
@@ -421,9 +1171,14 @@ impl<'a> FloppyReadDir<'a, MemFloppyDisk> for MemReadDir {
     }
 }
 
-#[derive(Debug)]
+#[derive(Derivative)]
+#[derivative(Debug)]
 pub struct MemDirEntry {
     entry: rsfs_tokio::mem::unix::DirEntry,
+    dev: u64,
+    ino: u64,
+    #[derivative(Debug = "ignore")]
+    inodes: Arc<Mutex<Inodes>>,
 }
 
 #[async_trait::async_trait]
@@ -435,9 +1190,12 @@ impl<'a> FloppyDirEntry<'a, MemFloppyDisk> for MemDirEntry {
         self.entry.file_name()
     }
     async fn metadata(&self) -> Result<<MemFloppyDisk as FloppyDisk>::Metadata> {
-        Ok(MemMetadata {
-            metadata: self.entry.metadata().await?,
-        })
+        let metadata = self.entry.metadata().await?;
+        Ok(self
+            .inodes
+            .lock()
+            .unwrap()
+            .metadata(metadata, self.dev, self.ino))
     }
     async fn file_type(&self) -> Result<<MemFloppyDisk as FloppyDisk>::FileType> {
         Ok(MemFileType(self.entry.file_type().await?))
@@ -445,7 +1203,7 @@ impl<'a> FloppyDirEntry<'a, MemFloppyDisk> for MemDirEntry {
 
     #[cfg(unix)]
     fn ino(&self) -> u64 {
-        unimplemented!("not currently supported")
+        self.ino
     }
 }
 
@@ -544,8 +1302,14 @@ impl<'a> FloppyOpenOptions<'a, MemFloppyDisk> for MemOpenOptions {
         options.truncate(self.truncate);
         options.create(self.create);
         options.create_new(self.create_new);
-        let file = options.open(path).await?;
-        Ok(MemFile { file })
+        let file = options.open(path.as_ref()).await?;
+        let inode_path = disk.resolve(path.as_ref(), true).await?;
+        Ok(MemFile {
+            file,
+            dev: disk.dev,
+            ino: disk.inodes.lock().unwrap().get(&inode_path),
+            inodes: disk.inodes.clone(),
+        })
     }
 }
 
@@ -614,6 +1378,115 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_read_range() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.write("/test.txt", "asdfjkl").await?;
+        assert_eq!(b"fjk", &fs.read_range("/test.txt", 3, 3).await?[..]);
+        assert_eq!(b"kl", &fs.read_range("/test.txt", 5, 10).await?[..]);
+        assert!(fs.read_range("/test.txt", 10, 1).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_from_disk() -> Result<()> {
+        let src = crate::temp::TempFloppyDisk::new().await?;
+        src.create_dir_all("/src/a").await?;
+        src.write("/src/a/b.txt", "asdf").await?;
+        src.set_permissions("/src/a/b.txt", FloppyUnixPermissions::from_mode(0o640))
+            .await?;
+        let past = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        std::fs::File::options()
+            .write(true)
+            .open(src.path().join("src/a/b.txt"))?
+            .set_modified(past)?;
+        let fs = MemFloppyDisk::from_disk(&*src, "/src").await?;
+        assert_eq!("asdf", fs.read_to_string("/src/a/b.txt").await?);
+        let metadata = fs.metadata("/src/a/b.txt").await?;
+        assert_eq!(0o640, metadata.permissions().mode() & 0o777);
+        let owner = src.metadata("/src/a/b.txt").await?;
+        assert_eq!(owner.uid()?, metadata.uid()?);
+
+        // Modified times come across, until the entry next changes.
+        assert_eq!(past, metadata.modified()?);
+        let dir = src.metadata("/src/a").await?;
+        assert_eq!(dir.modified()?, fs.metadata("/src/a").await?.modified()?);
+        let mut entries = fs.read_dir("/src/a").await?;
+        let entry = entries.next_entry().await?.unwrap();
+        assert_eq!(past, entry.metadata().await?.modified()?);
+        let file = MemOpenOptions::new()
+            .read(true)
+            .open(&fs, "/src/a/b.txt")
+            .await?;
+        assert_eq!(past, file.metadata().await?.modified()?);
+        fs.write("/src/a/b.txt", "hjkl").await?;
+        assert!(fs.metadata("/src/a/b.txt").await?.modified()? > past);
+
+        let src = MemFloppyDisk::new();
+        src.create_dir("/a").await?;
+        src.symlink("../b", "/a/c").await?;
+        src.chown("/a", 1000, 1000).await?;
+        let fs = MemFloppyDisk::from_disk(&src, "/").await?;
+        assert_eq!(PathBuf::from("../b"), fs.read_link("/a/c").await?);
+        assert_eq!(1000, fs.metadata("/a").await?.gid()?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_onto_itself() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.write("/test.txt", "asdf").await?;
+        fs.symlink("/test.txt", "/test2.txt").await?;
+        assert!(fs.copy("/test.txt", "/test.txt").await.is_err());
+        assert!(fs.copy("/test.txt", "/test2.txt").await.is_err());
+        assert_eq!("asdf", fs.read_to_string("/test.txt").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_same_file() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.write("/test.txt", "asdf").await?;
+        fs.write("/test2.txt", "asdf").await?;
+        fs.symlink("/test.txt", "/test3.txt").await?;
+        assert!(same_file(&fs, "/test.txt", "/test.txt").await?);
+        assert!(same_file(&fs, "/test.txt", "/test3.txt").await?);
+        assert!(!same_file(&fs, "/test.txt", "/test2.txt").await?);
+
+        // Inode numbers follow files through links and renames.
+        fs.hard_link("/test.txt", "/test4.txt").await?;
+        assert!(same_file(&fs, "/test3.txt", "/test4.txt").await?);
+        fs.rename("/test.txt", "/test5.txt").await?;
+        assert!(same_file(&fs, "/test4.txt", "/test5.txt").await?);
+        fs.create_dir("/dir").await?;
+        fs.rename("/test5.txt", "/dir/test.txt").await?;
+        fs.rename("/dir", "/dir2").await?;
+        assert!(same_file(&fs, "/test4.txt", "/dir2/test.txt").await?);
+        assert_eq!(
+            PathBuf::from("/dir2/test.txt"),
+            fs.canonicalize("/dir2/test.txt").await?
+        );
+        fs.remove_file("/test4.txt").await?;
+        fs.write("/test4.txt", "asdf").await?;
+        assert!(!same_file(&fs, "/test4.txt", "/dir2/test.txt").await?);
+
+        let ino = fs.metadata("/dir2/test.txt").await?.ino()?;
+        let mut read_dir = fs.read_dir("/dir2").await?;
+        let entry = read_dir.next_entry().await?.unwrap();
+        assert_eq!(ino, entry.ino());
+        assert_eq!(ino, entry.metadata().await?.ino()?);
+        let file = MemOpenOptions::new()
+            .read(true)
+            .open(&fs, "/dir2/test.txt")
+            .await?;
+        assert_eq!(ino, file.metadata().await?.ino()?);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_create_dir() -> Result<()> {
         let fs = MemFloppyDisk::new();
@@ -634,15 +1507,21 @@ mod tests {
         Ok(())
     }
 
-    // #[tokio::test]
-    // async fn test_hard_link() -> Result<()> {
-    //     let mut fs = MemFloppyDisk::new();
-    //     fs.write("/test.txt", "asdf").await?;
-    //     fs.hard_link("/test.txt", "/test2.txt").await?;
-    //     assert_eq!("asdf", fs.read_to_string("/test2.txt").await?);
+    #[tokio::test]
+    async fn test_hard_link() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.write("/test.txt", "asdf").await?;
+        fs.hard_link("/test.txt", "/test2.txt").await?;
+        assert_eq!("asdf", fs.read_to_string("/test2.txt").await?);
 
-    //     Ok(())
-    // }
+        // One file under two names.
+        fs.write("/test2.txt", "jkl").await?;
+        assert_eq!("jkl", fs.read_to_string("/test.txt").await?);
+        fs.remove_file("/test.txt").await?;
+        assert_eq!("jkl", fs.read_to_string("/test2.txt").await?);
+
+        Ok(())
+    }
 
     #[tokio::test]
     async fn test_metadata() -> Result<()> {
@@ -665,6 +1544,17 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_metadata_many() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.write("/a.txt", "asdf").await?;
+        let metadata = fs.metadata_many(&["/a.txt", "/b.txt"]).await;
+        assert_eq!(4, metadata[0].as_ref().unwrap().len());
+        assert!(metadata[1].is_err());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_read_dir() -> Result<()> {
         let fs = MemFloppyDisk::new();
@@ -688,6 +1578,30 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_read_dir_stream() -> Result<()> {
+        use futures::TryStreamExt;
+
+        let fs = MemFloppyDisk::new();
+        fs.write("/a.txt", "asdf").await?;
+        fs.write("/b.txt", "jkl").await?;
+        fs.create_dir("/c").await?;
+        let mut names: Vec<_> = fs
+            .read_dir("/")
+            .await?
+            .into_stream()
+            .try_filter_map(|entry| async move {
+                let is_file = entry.file_type().await?.is_file();
+                Ok(is_file.then(|| entry.file_name()))
+            })
+            .try_collect()
+            .await?;
+        names.sort();
+        assert_eq!(vec!["a.txt", "b.txt"], names);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_read_link() -> Result<()> {
         let fs = MemFloppyDisk::new();
@@ -753,6 +1667,18 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_rename_into_itself() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/test/a").await?;
+        fs.write("/test/a/test.txt", "asdf").await?;
+        assert!(fs.rename("/test", "/test/a/b").await.is_err());
+        assert!(fs.rename("/test", "/test/a/../b").await.is_err());
+        assert_eq!("asdf", fs.read_to_string("/test/a/test.txt").await?);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_set_permissions() -> Result<()> {
         let fs = MemFloppyDisk::new();
@@ -796,6 +1722,292 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_append() -> Result<()> {
+        let fs = Arc::new(MemFloppyDisk::new());
+        fs.append("/log.txt", "start\n").await?;
+
+        let tasks: Vec<_> = (0..8u8)
+            .map(|i| {
+                let fs = fs.clone();
+                tokio::spawn(async move {
+                    let line = [vec![b'a' + i; 4096], vec![b'\n']].concat();
+                    for _ in 0..16 {
+                        fs.append("/log.txt", &line).await?;
+                    }
+                    Ok::<_, std::io::Error>(())
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await??;
+        }
+
+        let log = fs.read_to_string("/log.txt").await?;
+        let lines: Vec<_> = log.lines().collect();
+        assert_eq!("start", lines[0]);
+        assert_eq!(1 + 8 * 16, lines.len());
+        for line in &lines[1..] {
+            assert_eq!(4096, line.len());
+            assert!(line.bytes().all(|b| b == line.as_bytes()[0]));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_truncate() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.write("/test.txt", "asdfjkl").await?;
+        fs.truncate("/test.txt", 4).await?;
+        assert_eq!("asdf", fs.read_to_string("/test.txt").await?);
+        fs.truncate("/test.txt", 6).await?;
+        assert_eq!(b"asdf\0\0", &fs.read("/test.txt").await?[..]);
+        assert!(fs.truncate("/missing.txt", 0).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_write_atomic() -> Result<()> {
+        let fs = Arc::new(MemFloppyDisk::new());
+        fs.create_dir("/etc").await?;
+        fs.write_atomic("/etc/app.conf", vec![b'a'; 65536]).await?;
+
+        let reader = {
+            let fs = fs.clone();
+            tokio::spawn(async move {
+                for _ in 0..200 {
+                    let contents = fs.read("/etc/app.conf").await?;
+                    assert_eq!(65536, contents.len());
+                    assert!(contents.iter().all(|b| *b == contents[0]));
+                }
+                Ok::<_, std::io::Error>(())
+            })
+        };
+        for i in 0..200u8 {
+            fs.write_atomic("/etc/app.conf", vec![b'a' + i % 26; 65536])
+                .await?;
+        }
+        reader.await??;
+
+        let mut entries = fs.read_dir("/etc").await?;
+        let entry = entries.next_entry().await?.unwrap();
+        assert_eq!("app.conf", entry.file_name().to_str().unwrap());
+        assert!(entries.next_entry().await?.is_none());
+        assert!(fs.write_atomic("/", "asdf").await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dev_is_per_disk() -> Result<()> {
+        let a = MemFloppyDisk::new();
+        let b = MemFloppyDisk::new();
+        a.write("/test.txt", "asdf").await?;
+        b.write("/test.txt", "asdf").await?;
+
+        let a_dev = a.metadata("/test.txt").await?.dev()?;
+        assert_eq!(a_dev, a.metadata("/").await?.dev()?);
+        assert_ne!(a_dev, b.metadata("/test.txt").await?.dev()?);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_snapshots() -> Result<()> {
+        let fs = Arc::new(MemFloppyDisk::new());
+        fs.write("/a.txt", "asdf").await?;
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let fs = fs.clone();
+                tokio::spawn(async move { fs.snapshot("x").await })
+            })
+            .collect();
+        let mut taken = 0;
+        for task in tasks {
+            match task.await? {
+                Ok(()) => taken += 1,
+                Err(e) => assert_eq!(std::io::ErrorKind::AlreadyExists, e.kind()),
+            }
+        }
+        assert_eq!(1, taken);
+        assert_eq!(vec!["x"], fs.list_snapshots());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshots() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/a/b").await?;
+        fs.write("/a/b/c.txt", "asdf").await?;
+        fs.write("/a/d.txt", "asdf").await?;
+        fs.snapshot("before").await?;
+        assert!(fs.snapshot("before").await.is_err());
+
+        fs.write("/a/b/c.txt", "hjkl").await?;
+        fs.remove_file("/a/d.txt").await?;
+        fs.write("/e.txt", "asdf").await?;
+        fs.snapshot("after").await?;
+        assert_eq!(vec!["after", "before"], fs.list_snapshots());
+
+        assert_eq!(
+            vec![
+                SnapshotChange::Modified(PathBuf::from("/a/b/c.txt")),
+                SnapshotChange::Removed(PathBuf::from("/a/d.txt")),
+                SnapshotChange::Added(PathBuf::from("/e.txt")),
+            ],
+            fs.diff_snapshots("before", "after")?
+        );
+
+        fs.rollback_to("before").await?;
+        assert_eq!("asdf", fs.read_to_string("/a/b/c.txt").await?);
+        assert_eq!("asdf", fs.read_to_string("/a/d.txt").await?);
+        assert!(!fs.try_exists("/e.txt").await?);
+
+        fs.rollback_to("after").await?;
+        assert_eq!("hjkl", fs.read_to_string("/a/b/c.txt").await?);
+        assert!(fs.try_exists("/e.txt").await?);
+
+        fs.delete_snapshot("after")?;
+        assert!(fs.rollback_to("after").await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_overlay_layer() -> Result<()> {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/etc/old").await?;
+        fs.write("/etc/old/a.conf", "asdf").await?;
+        fs.write("/etc/hosts", "asdf").await?;
+        fs.write("/etc/motd", "asdf").await?;
+        fs.snapshot("lower").await?;
+
+        fs.remove_dir_all("/etc/old").await?;
+        fs.write("/etc/hosts", "hjkl").await?;
+        fs.create_dir("/var").await?;
+        fs.write("/var/new.txt", "hjkl").await?;
+        fs.snapshot("upper").await?;
+
+        let root = PathBuf::from(format!("/tmp/floppy-overlay-{}", rand::random::<u64>()));
+        fs.export_overlay_layer("lower", "upper", root.join("upper"), root.join("work"))
+            .await?;
+
+        assert_eq!(
+            "hjkl",
+            tokio::fs::read_to_string(root.join("upper/etc/hosts")).await?
+        );
+        assert_eq!(
+            "hjkl",
+            tokio::fs::read_to_string(root.join("upper/var/new.txt")).await?
+        );
+        assert!(!tokio::fs::try_exists(root.join("upper/etc/motd")).await?);
+        let whiteout = tokio::fs::symlink_metadata(root.join("upper/etc/old")).await?;
+        assert!(whiteout.file_type().is_char_device());
+        assert_eq!(0, whiteout.rdev());
+        assert!(tokio::fs::read_dir(root.join("work"))
+            .await?
+            .next_entry()
+            .await?
+            .is_none());
+
+        tokio::fs::remove_dir_all(root).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_open_read_and_open_write() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.write("/a", "asdfasdf").await?;
+        let mut file = fs.open_write("/a").await?;
+        AsyncWriteExt::write_all(&mut file, b"jkl").await?;
+        file.close().await?;
+
+        let mut contents = String::new();
+        AsyncReadExt::read_to_string(&mut fs.open_read("/a").await?, &mut contents).await?;
+        assert_eq!("jkl", contents);
+        assert!(fs.open_read("/b").await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_at_and_write_at() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.write("/a", "asdf").await?;
+        let file = MemOpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&fs, "/a")
+            .await?;
+
+        // Readers sharing the file each read from their own offsets.
+        let (mut first, mut second) = ([0; 2], [0; 2]);
+        let (a, b) = tokio::join!(file.read_at(&mut first, 0), file.read_at(&mut second, 2));
+        assert_eq!((2, 2), (a?, b?));
+        assert_eq!((b"as", b"df"), (&first, &second));
+
+        // Writing past the end fills the gap with zeroes.
+        file.write_at(b"x", 6).await?;
+        assert_eq!(b"asdf\0\0x", &fs.read("/a").await?[..]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_seek_data_and_hole() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.write("/a", "asdf").await?;
+        let file = MemOpenOptions::new().read(true).open(&fs, "/a").await?;
+        assert_eq!(2, file.seek_data(2).await?);
+        assert_eq!(4, file.seek_hole(0).await?);
+        assert!(file.seek_data(4).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_allocate() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.write("/a", "asdf").await?;
+        let file = MemOpenOptions::new().write(true).open(&fs, "/a").await?;
+        file.allocate(2, 4).await?;
+        assert_eq!(b"asdf\0\0", &fs.read("/a").await?[..]);
+        // Space that's already there is left alone.
+        file.allocate(0, 2).await?;
+        assert_eq!(6, fs.metadata("/a").await?.len());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_all_vectored() -> Result<()> {
+        use std::io::IoSlice;
+
+        let fs = MemFloppyDisk::new();
+        let mut file = MemOpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&fs, "/a")
+            .await?;
+        assert!(AsyncWrite::is_write_vectored(&file));
+        // `std::io::Write` is in scope here too, so these are spelled out.
+        let bufs = [
+            IoSlice::new(b"head"),
+            IoSlice::new(b""),
+            IoSlice::new(b"body"),
+        ];
+        FloppyFile::write_all_vectored(&mut file, &bufs).await?;
+        assert_eq!(b"headbody", &fs.read("/a").await?[..]);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_dir_builder() -> Result<()> {
         let fs = MemFloppyDisk::new();
@@ -0,0 +1,128 @@
+//! Three-way merges of text, like `diff3`.
+
+/// The result of [`merge3`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Merge {
+    /// Both sides' changes, which didn't overlap.
+    Clean(String),
+    /// Both sides' changes, with git-style conflict markers around the lines
+    /// both sides changed differently.
+    Conflicted(String),
+}
+
+/// Merge the changes from `base` to `ours` and from `base` to `theirs`, line
+/// by line.
+pub fn merge3(base: &str, ours: &str, theirs: &str) -> Merge {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let in_ours = matching_lines(&base, &ours);
+    let in_theirs = matching_lines(&base, &theirs);
+
+    let mut merged = String::new();
+    let mut conflicted = false;
+    let (mut i, mut a, mut b) = (0, 0, 0);
+    loop {
+        // Lines that are the same in all three.
+        while i < base.len() && in_ours[i] == Some(a) && in_theirs[i] == Some(b) {
+            merged.push_str(base[i]);
+            (i, a, b) = (i + 1, a + 1, b + 1);
+        }
+        if i == base.len() && a == ours.len() && b == theirs.len() {
+            break;
+        }
+
+        // Up to the next line that's unchanged on both sides, or the end.
+        let next = (i..base.len()).find(|&j| in_ours[j].is_some() && in_theirs[j].is_some());
+        let (j, next_a, next_b) = match next {
+            Some(j) => (j, in_ours[j].unwrap(), in_theirs[j].unwrap()),
+            None => (base.len(), ours.len(), theirs.len()),
+        };
+        let (base_chunk, ours_chunk, theirs_chunk) =
+            (&base[i..j], &ours[a..next_a], &theirs[b..next_b]);
+        if ours_chunk == base_chunk || ours_chunk == theirs_chunk {
+            merged.extend(theirs_chunk.iter().copied());
+        } else if theirs_chunk == base_chunk {
+            merged.extend(ours_chunk.iter().copied());
+        } else {
+            conflicted = true;
+            merged.push_str("<<<<<<< ours\n");
+            push_lines(&mut merged, ours_chunk);
+            merged.push_str("=======\n");
+            push_lines(&mut merged, theirs_chunk);
+            merged.push_str(">>>>>>> theirs\n");
+        }
+        (i, a, b) = (j, next_a, next_b);
+    }
+
+    if conflicted {
+        Merge::Conflicted(merged)
+    } else {
+        Merge::Clean(merged)
+    }
+}
+
+/// Lines inside conflict markers always end in a newline, so the markers
+/// stay on their own lines.
+fn push_lines(merged: &mut String, lines: &[&str]) {
+    for line in lines {
+        merged.push_str(line);
+        if !line.ends_with('\n') {
+            merged.push('\n');
+        }
+    }
+}
+
+/// For each line of `base`, where it is in `other`, if it's part of their
+/// longest common subsequence.
+fn matching_lines(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut lcs = vec![vec![0u32; other.len() + 1]; base.len() + 1];
+    for i in (0..base.len()).rev() {
+        for j in (0..other.len()).rev() {
+            lcs[i][j] = if base[i] == other[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut matches = vec![None; base.len()];
+    let (mut i, mut j) = (0, 0);
+    while i < base.len() && j < other.len() {
+        if base[i] == other[j] {
+            matches[i] = Some(j);
+            (i, j) = (i + 1, j + 1);
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge3() {
+        let base = "a\nb\nc\nd\n";
+        assert_eq!(
+            Merge::Clean("A\nb\nc\nD\ne\n".to_string()),
+            merge3(base, "A\nb\nc\nd\n", "a\nb\nc\nD\ne\n")
+        );
+        // Both sides making the same change isn't a conflict.
+        assert_eq!(
+            Merge::Clean("a\nB\nc\nd\n".to_string()),
+            merge3(base, "a\nB\nc\nd\n", "a\nB\nc\nd\n")
+        );
+        assert_eq!(
+            Merge::Conflicted(
+                "a\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\nc\nd\n".to_string()
+            ),
+            merge3(base, "a\nours\nc\nd\n", "a\ntheirs\nc\nd\n")
+        );
+    }
+}
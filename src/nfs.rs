@@ -0,0 +1,462 @@
+//! Exporting a [`FloppyDisk`] over NFSv3 with `nfsserve`, so that it can be
+//! mounted and poked at with normal tools.
+//!
+//! ```no_run
+//! # use floppy_disk::prelude::*;
+//! # async fn example() -> std::io::Result<()> {
+//! let fs = MemFloppyDisk::new();
+//! floppy_disk::nfs::serve(fs, "127.0.0.1:11111").await?;
+//! // mount -t nfs -o nolocks,vers=3,tcp,port=11111,mountport=11111,soft 127.0.0.1:/ mnt/
+//! # Ok(())
+//! # }
+//! ```
+
+use std::ffi::OsStr;
+use std::io::{ErrorKind, Result, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nfsserve::nfs::{
+    fattr3, fileid3, filename3, ftype3, nfspath3, nfsstat3, nfstime3, sattr3, set_gid3, set_mode3,
+    set_size3, set_uid3,
+};
+use nfsserve::tcp::{NFSTcp, NFSTcpListener};
+use nfsserve::vfs::{DirEntry, NFSFileSystem, ReadDirResult, VFSCapabilities};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tracing::debug;
+
+use crate::ids::{FileIds, ROOT_ID};
+use crate::{
+    FloppyDirEntry, FloppyDisk, FloppyFile, FloppyMetadata, FloppyOpenOptions, FloppyReadDir,
+    FloppyUnixMetadata, FloppyUnixPermissions,
+};
+
+/// Serve `disk` over NFSv3 on `addr`, eg. `127.0.0.1:11111`, until an error
+/// occurs.
+pub async fn serve<D>(disk: D, addr: &str) -> Result<()>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+{
+    let listener = NFSTcpListener::bind(addr, FloppyNfs::new(disk)).await?;
+    debug!("serving nfs on port {}", listener.get_listen_port());
+    listener.handle_forever().await
+}
+
+/// An [`NFSFileSystem`] backed by a [`FloppyDisk`].
+///
+/// NFS refers to files by id, and not every disk has inode numbers, so ids are
+/// handed out as paths are looked up and forgotten when they're removed. Ids
+/// don't survive restarting the server.
+#[derive(Debug)]
+pub struct FloppyNfs<D> {
+    disk: D,
+    ids: Mutex<FileIds>,
+}
+
+impl<D> FloppyNfs<D> {
+    pub fn new(disk: D) -> Self {
+        Self {
+            disk,
+            ids: Mutex::new(FileIds::new()),
+        }
+    }
+
+    pub fn disk(&self) -> &D {
+        &self.disk
+    }
+
+    fn path(&self, id: fileid3) -> std::result::Result<PathBuf, nfsstat3> {
+        self.ids
+            .lock()
+            .unwrap()
+            .path(id)
+            .ok_or(nfsstat3::NFS3ERR_STALE)
+    }
+
+    fn child(
+        &self,
+        dirid: fileid3,
+        filename: &filename3,
+    ) -> std::result::Result<PathBuf, nfsstat3> {
+        Ok(self.path(dirid)?.join(OsStr::from_bytes(&filename.0)))
+    }
+
+    fn id(&self, path: &Path) -> fileid3 {
+        self.ids.lock().unwrap().id(path)
+    }
+}
+
+fn nfs_error(e: std::io::Error) -> nfsstat3 {
+    match e.kind() {
+        ErrorKind::NotFound => nfsstat3::NFS3ERR_NOENT,
+        ErrorKind::PermissionDenied => nfsstat3::NFS3ERR_ACCES,
+        ErrorKind::AlreadyExists => nfsstat3::NFS3ERR_EXIST,
+        ErrorKind::InvalidInput => nfsstat3::NFS3ERR_INVAL,
+        ErrorKind::FileTooLarge => nfsstat3::NFS3ERR_FBIG,
+        ErrorKind::Unsupported => nfsstat3::NFS3ERR_NOTSUPP,
+        ErrorKind::DirectoryNotEmpty => nfsstat3::NFS3ERR_NOTEMPTY,
+        ErrorKind::NotADirectory => nfsstat3::NFS3ERR_NOTDIR,
+        ErrorKind::IsADirectory => nfsstat3::NFS3ERR_ISDIR,
+        ErrorKind::ReadOnlyFilesystem => nfsstat3::NFS3ERR_ROFS,
+        _ => nfsstat3::NFS3ERR_IO,
+    }
+}
+
+fn nfs_time(time: Result<SystemTime>) -> nfstime3 {
+    let since_epoch = time
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    nfstime3 {
+        seconds: since_epoch.as_secs() as u32,
+        nseconds: since_epoch.subsec_nanos(),
+    }
+}
+
+impl<D> FloppyNfs<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+{
+    async fn attr(&self, id: fileid3, path: &Path) -> std::result::Result<fattr3, nfsstat3> {
+        let metadata = self.disk.symlink_metadata(path).await.map_err(nfs_error)?;
+        let ftype = if metadata.is_symlink() {
+            ftype3::NF3LNK
+        } else if metadata.is_dir() {
+            ftype3::NF3DIR
+        } else {
+            ftype3::NF3REG
+        };
+        Ok(fattr3 {
+            ftype,
+            mode: metadata.permissions().mode() & 0o7777,
+            nlink: 1,
+            uid: metadata.uid().unwrap_or(0),
+            gid: metadata.gid().unwrap_or(0),
+            size: metadata.len(),
+            used: metadata.len(),
+            fileid: id,
+            atime: nfs_time(metadata.accessed()),
+            mtime: nfs_time(metadata.modified()),
+            ctime: nfs_time(metadata.modified()),
+            ..Default::default()
+        })
+    }
+
+    async fn open<'a, F>(
+        &'a self,
+        path: &Path,
+        options: F,
+    ) -> std::result::Result<<D as FloppyDisk<'a>>::File, nfsstat3>
+    where
+        F: FnOnce(<D as FloppyDisk<'a>>::OpenOptions) -> <D as FloppyDisk<'a>>::OpenOptions,
+    {
+        options(FloppyOpenOptions::new())
+            .open(&self.disk, path)
+            .await
+            .map_err(nfs_error)
+    }
+
+    async fn apply(&self, path: &Path, setattr: &sattr3) -> std::result::Result<(), nfsstat3> {
+        if let set_uid3::uid(_) = setattr.uid {
+            return Err(nfsstat3::NFS3ERR_NOTSUPP);
+        }
+        if let set_gid3::gid(_) = setattr.gid {
+            return Err(nfsstat3::NFS3ERR_NOTSUPP);
+        }
+        if let set_mode3::mode(mode) = setattr.mode {
+            self.disk
+                .set_permissions(path, FloppyUnixPermissions::from_mode(mode))
+                .await
+                .map_err(nfs_error)?;
+        }
+        if let set_size3::size(size) = setattr.size {
+            let mut file = self.open(path, |options| options.write(true)).await?;
+            file.set_len(size).await.map_err(nfs_error)?;
+        }
+        // Disks can't have their timestamps set, so time changes are dropped.
+        Ok(())
+    }
+
+    async fn created(&self, path: PathBuf) -> std::result::Result<(fileid3, fattr3), nfsstat3> {
+        let id = self.id(&path);
+        Ok((id, self.attr(id, &path).await?))
+    }
+}
+
+#[async_trait::async_trait]
+impl<D> NFSFileSystem for FloppyNfs<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+{
+    fn capabilities(&self) -> VFSCapabilities {
+        VFSCapabilities::ReadWrite
+    }
+
+    fn root_dir(&self) -> fileid3 {
+        ROOT_ID
+    }
+
+    async fn lookup(
+        &self,
+        dirid: fileid3,
+        filename: &filename3,
+    ) -> std::result::Result<fileid3, nfsstat3> {
+        let path = match filename.0.as_slice() {
+            b"." => self.path(dirid)?,
+            b".." => {
+                let dir = self.path(dirid)?;
+                dir.parent().map(Path::to_path_buf).unwrap_or(dir)
+            }
+            _ => self.child(dirid, filename)?,
+        };
+        self.disk.symlink_metadata(&path).await.map_err(nfs_error)?;
+        Ok(self.id(&path))
+    }
+
+    async fn getattr(&self, id: fileid3) -> std::result::Result<fattr3, nfsstat3> {
+        let path = self.path(id)?;
+        self.attr(id, &path).await
+    }
+
+    async fn setattr(&self, id: fileid3, setattr: sattr3) -> std::result::Result<fattr3, nfsstat3> {
+        let path = self.path(id)?;
+        self.apply(&path, &setattr).await?;
+        self.attr(id, &path).await
+    }
+
+    async fn read(
+        &self,
+        id: fileid3,
+        offset: u64,
+        count: u32,
+    ) -> std::result::Result<(Vec<u8>, bool), nfsstat3> {
+        let path = self.path(id)?;
+        let len = self.disk.metadata(&path).await.map_err(nfs_error)?.len();
+        let buf = self
+            .disk
+            .read_range(&path, offset, count as u64)
+            .await
+            .map_err(nfs_error)?;
+        let eof = offset + buf.len() as u64 >= len;
+        Ok((buf, eof))
+    }
+
+    async fn write(
+        &self,
+        id: fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> std::result::Result<fattr3, nfsstat3> {
+        let path = self.path(id)?;
+        let mut file = self.open(&path, |options| options.write(true)).await?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(nfs_error)?;
+        file.write_all(data).await.map_err(nfs_error)?;
+        file.flush().await.map_err(nfs_error)?;
+        drop(file);
+        self.attr(id, &path).await
+    }
+
+    async fn create(
+        &self,
+        dirid: fileid3,
+        filename: &filename3,
+        attr: sattr3,
+    ) -> std::result::Result<(fileid3, fattr3), nfsstat3> {
+        let path = self.child(dirid, filename)?;
+        let file = self
+            .open(&path, |options| {
+                options.write(true).create(true).truncate(true)
+            })
+            .await?;
+        drop(file);
+        self.apply(&path, &attr).await?;
+        self.created(path).await
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: fileid3,
+        filename: &filename3,
+    ) -> std::result::Result<fileid3, nfsstat3> {
+        let path = self.child(dirid, filename)?;
+        let file = self
+            .open(&path, |options| options.write(true).create_new(true))
+            .await?;
+        drop(file);
+        Ok(self.id(&path))
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: fileid3,
+        dirname: &filename3,
+    ) -> std::result::Result<(fileid3, fattr3), nfsstat3> {
+        let path = self.child(dirid, dirname)?;
+        self.disk.create_dir(&path).await.map_err(nfs_error)?;
+        self.created(path).await
+    }
+
+    async fn remove(
+        &self,
+        dirid: fileid3,
+        filename: &filename3,
+    ) -> std::result::Result<(), nfsstat3> {
+        let path = self.child(dirid, filename)?;
+        let metadata = self.disk.symlink_metadata(&path).await.map_err(nfs_error)?;
+        if metadata.is_dir() {
+            self.disk.remove_dir(&path).await.map_err(nfs_error)?;
+        } else {
+            self.disk.remove_file(&path).await.map_err(nfs_error)?;
+        }
+        self.ids.lock().unwrap().removed(&path);
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: fileid3,
+        from_filename: &filename3,
+        to_dirid: fileid3,
+        to_filename: &filename3,
+    ) -> std::result::Result<(), nfsstat3> {
+        let from = self.child(from_dirid, from_filename)?;
+        let to = self.child(to_dirid, to_filename)?;
+        self.disk.rename(&from, &to).await.map_err(nfs_error)?;
+        self.ids.lock().unwrap().renamed(&from, &to);
+        Ok(())
+    }
+
+    async fn readdir(
+        &self,
+        dirid: fileid3,
+        start_after: fileid3,
+        max_entries: usize,
+    ) -> std::result::Result<ReadDirResult, nfsstat3> {
+        let dir = self.path(dirid)?;
+        let mut read_dir = self.disk.read_dir(&dir).await.map_err(nfs_error)?;
+        let mut names = vec![];
+        while let Some(entry) = read_dir.next_entry().await.map_err(nfs_error)? {
+            names.push(entry.file_name());
+        }
+        // Listings need to be in a stable order for `start_after` to work.
+        names.sort();
+
+        let mut entries = vec![];
+        let mut started = start_after == 0;
+        for name in names.iter() {
+            let path = dir.join(name);
+            let id = self.id(&path);
+            if !started {
+                started = id == start_after;
+                continue;
+            }
+            if entries.len() == max_entries {
+                return Ok(ReadDirResult {
+                    entries,
+                    end: false,
+                });
+            }
+            entries.push(DirEntry {
+                fileid: id,
+                name: name.as_bytes().into(),
+                attr: self.attr(id, &path).await?,
+            });
+        }
+        Ok(ReadDirResult { entries, end: true })
+    }
+
+    async fn symlink(
+        &self,
+        dirid: fileid3,
+        linkname: &filename3,
+        symlink: &nfspath3,
+        _attr: &sattr3,
+    ) -> std::result::Result<(fileid3, fattr3), nfsstat3> {
+        let path = self.child(dirid, linkname)?;
+        let target = PathBuf::from(OsStr::from_bytes(&symlink.0));
+        self.disk.symlink(&target, &path).await.map_err(nfs_error)?;
+        self.created(path).await
+    }
+
+    async fn readlink(&self, id: fileid3) -> std::result::Result<nfspath3, nfsstat3> {
+        let path = self.path(id)?;
+        let target = self.disk.read_link(&path).await.map_err(nfs_error)?;
+        Ok(target.as_os_str().as_bytes().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    fn name(name: &str) -> filename3 {
+        name.as_bytes().into()
+    }
+
+    #[tokio::test]
+    async fn test_nfs_operations() -> Result<()> {
+        let nfs = FloppyNfs::new(MemFloppyDisk::new());
+        let root = nfs.root_dir();
+
+        let (dir, attr) = nfs.mkdir(root, &name("a")).await.unwrap();
+        assert!(matches!(attr.ftype, ftype3::NF3DIR));
+        let (file, _) = nfs
+            .create(dir, &name("b.txt"), sattr3::default())
+            .await
+            .unwrap();
+        let attr = nfs.write(file, 0, b"asdf").await.unwrap();
+        assert_eq!(4, attr.size);
+        nfs.write(file, 2, b"jkl").await.unwrap();
+
+        assert_eq!((b"as".to_vec(), false), nfs.read(file, 0, 2).await.unwrap());
+        assert_eq!(
+            (b"sjkl".to_vec(), true),
+            nfs.read(file, 1, 10).await.unwrap()
+        );
+        assert_eq!(file, nfs.lookup(dir, &name("b.txt")).await.unwrap());
+        assert_eq!(root, nfs.lookup(dir, &name("..")).await.unwrap());
+        assert!(matches!(
+            nfs.lookup(dir, &name("c.txt")).await,
+            Err(nfsstat3::NFS3ERR_NOENT)
+        ));
+
+        nfs.create(dir, &name("a.txt"), sattr3::default())
+            .await
+            .unwrap();
+        let listing = nfs.readdir(dir, 0, 1).await.unwrap();
+        assert!(!listing.end);
+        assert_eq!(b"a.txt", listing.entries[0].name.as_ref());
+        let listing = nfs
+            .readdir(dir, listing.entries[0].fileid, 10)
+            .await
+            .unwrap();
+        assert!(listing.end);
+        assert_eq!(1, listing.entries.len());
+        assert_eq!(file, listing.entries[0].fileid);
+
+        nfs.rename(dir, &name("b.txt"), root, &name("c.txt"))
+            .await
+            .unwrap();
+        assert_eq!(PathBuf::from("/c.txt"), nfs.path(file).unwrap());
+        assert_eq!("asjkl", nfs.disk().read_to_string("/c.txt").await?);
+
+        nfs.remove(root, &name("c.txt")).await.unwrap();
+        assert!(matches!(
+            nfs.getattr(file).await,
+            Err(nfsstat3::NFS3ERR_STALE)
+        ));
+
+        Ok(())
+    }
+}
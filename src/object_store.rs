@@ -0,0 +1,440 @@
+//! Reading and writing a [`FloppyDisk`] through the `object_store` API, so
+//! that eg. datafusion and parquet readers can use files on any disk.
+
+use std::fmt::Display;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ::object_store::path::Path;
+use ::object_store::{
+    CopyMode, CopyOptions, Error, GetOptions, GetResult, GetResultPayload, ListResult,
+    MultipartUpload, ObjectMeta, ObjectStore, PutMode, PutMultipartOptions, PutOptions, PutPayload,
+    PutResult, Result, UploadPart,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, StreamExt};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    FloppyDirEntry, FloppyDisk, FloppyFileType, FloppyMetadata, FloppyOpenOptions, FloppyReadDir,
+};
+
+const STORE: &str = "FloppyObjectStore";
+
+/// An [`ObjectStore`] backed by the files under `root` on a disk. Object
+/// paths map onto file paths, and directories are created as needed.
+///
+/// ETags are derived from a file's size and modification time.
+#[derive(Debug)]
+pub struct FloppyObjectStore<D> {
+    disk: Arc<D>,
+    root: PathBuf,
+}
+
+impl<D> FloppyObjectStore<D> {
+    pub fn new<P: Into<PathBuf>>(disk: Arc<D>, root: P) -> Self {
+        Self {
+            disk,
+            root: root.into(),
+        }
+    }
+
+    fn disk_path(&self, location: &Path) -> PathBuf {
+        let mut path = self.root.clone();
+        path.extend(location.parts().map(|part| part.as_ref().to_string()));
+        path
+    }
+}
+
+impl<D> Display for FloppyObjectStore<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FloppyObjectStore({})", self.root.display())
+    }
+}
+
+fn store_error(location: &Path, e: std::io::Error) -> Error {
+    match e.kind() {
+        ErrorKind::NotFound => Error::NotFound {
+            path: location.to_string(),
+            source: Box::new(e),
+        },
+        ErrorKind::AlreadyExists => Error::AlreadyExists {
+            path: location.to_string(),
+            source: Box::new(e),
+        },
+        _ => Error::Generic {
+            store: STORE,
+            source: Box::new(e),
+        },
+    }
+}
+
+fn e_tag(size: u64, modified: SystemTime) -> String {
+    let modified = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_nanos())
+        .unwrap_or(0);
+    format!("\"{size:x}-{modified:x}\"")
+}
+
+async fn object_meta<D>(disk: &D, path: &std::path::Path, location: Path) -> Result<ObjectMeta>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    let metadata = disk
+        .metadata(path)
+        .await
+        .map_err(|e| store_error(&location, e))?;
+    if metadata.is_dir() {
+        return Err(Error::NotFound {
+            path: location.to_string(),
+            source: format!("{} is a directory", path.display()).into(),
+        });
+    }
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    Ok(ObjectMeta {
+        location,
+        last_modified: modified.into(),
+        size: metadata.len(),
+        e_tag: Some(e_tag(metadata.len(), modified)),
+        version: None,
+    })
+}
+
+/// Write a whole object, creating its parent directories.
+async fn put<D>(
+    disk: &D,
+    path: &std::path::Path,
+    location: &Path,
+    contents: &[u8],
+    create: bool,
+) -> Result<PutResult>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    let io = async {
+        if let Some(parent) = path.parent() {
+            disk.create_dir_all(parent).await?;
+        }
+        let options: <D as FloppyDisk<'_>>::OpenOptions = FloppyOpenOptions::new();
+        let mut file = options
+            .write(true)
+            .create(true)
+            .create_new(create)
+            .truncate(true)
+            .open(disk, path)
+            .await?;
+        file.write_all(contents).await?;
+        file.flush().await
+    };
+    io.await.map_err(|e| store_error(location, e))?;
+    let meta = object_meta(disk, path, location.clone()).await?;
+    Ok(PutResult {
+        e_tag: meta.e_tag,
+        version: None,
+        extensions: Default::default(),
+    })
+}
+
+/// Every file under `dir`, as object metadata.
+async fn walk<D>(disk: Arc<D>, root: PathBuf, dir: PathBuf) -> Result<Vec<ObjectMeta>>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    let mut objects = vec![];
+    let mut dirs = vec![dir];
+    while let Some(dir) = dirs.pop() {
+        let mut read_dir = match disk.read_dir(&dir).await {
+            Ok(read_dir) => read_dir,
+            // Listing a prefix that doesn't exist isn't an error.
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(store_error(&Path::default(), e)),
+        };
+        while let Some(entry) = read_dir
+            .next_entry()
+            .await
+            .map_err(|e| store_error(&Path::default(), e))?
+        {
+            let path = entry.path();
+            let file_type = entry
+                .file_type()
+                .await
+                .map_err(|e| store_error(&Path::default(), e))?;
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if file_type.is_file() {
+                objects.push(object_meta(&*disk, &path, location(&root, &path)?).await?);
+            }
+        }
+    }
+    objects.sort_by(|a, b| a.location.cmp(&b.location));
+    Ok(objects)
+}
+
+fn location(root: &std::path::Path, path: &std::path::Path) -> Result<Path> {
+    let relative = path.strip_prefix(root).map_err(|e| Error::Generic {
+        store: STORE,
+        source: Box::new(e),
+    })?;
+    Ok(Path::from_iter(
+        relative
+            .iter()
+            .map(|part| part.to_string_lossy().into_owned()),
+    ))
+}
+
+#[async_trait]
+impl<D> ObjectStore for FloppyObjectStore<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        let create = match opts.mode {
+            PutMode::Overwrite => false,
+            PutMode::Create => true,
+            PutMode::Update(_) => {
+                return Err(Error::NotImplemented {
+                    operation: "put_opts with PutMode::Update".into(),
+                    implementer: STORE.into(),
+                })
+            }
+        };
+        let contents = Bytes::from(payload);
+        put(
+            &*self.disk,
+            &self.disk_path(location),
+            location,
+            &contents,
+            create,
+        )
+        .await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        _opts: PutMultipartOptions,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        Ok(Box::new(FloppyUpload {
+            disk: self.disk.clone(),
+            path: self.disk_path(location),
+            location: location.clone(),
+            parts: vec![],
+        }))
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        let path = self.disk_path(location);
+        let meta = object_meta(&*self.disk, &path, location.clone()).await?;
+        options.check_preconditions(&meta)?;
+        let range = match &options.range {
+            Some(range) => range.as_range(meta.size).map_err(|e| Error::Generic {
+                store: STORE,
+                source: Box::new(e),
+            })?,
+            None => 0..meta.size,
+        };
+
+        let mut data = vec![];
+        if !options.head {
+            data = self
+                .disk
+                .read_range(&path, range.start, range.end - range.start)
+                .await
+                .map_err(|e| store_error(location, e))?;
+        }
+        let payload = stream::once(async move { Ok(Bytes::from(data)) }).boxed();
+
+        Ok(GetResult {
+            payload: GetResultPayload::Stream(payload),
+            meta,
+            range,
+            attributes: Default::default(),
+            extensions: Default::default(),
+        })
+    }
+
+    fn delete_stream(
+        &self,
+        locations: BoxStream<'static, Result<Path>>,
+    ) -> BoxStream<'static, Result<Path>> {
+        let disk = self.disk.clone();
+        let root = self.root.clone();
+        locations
+            .then(move |location| {
+                let disk = disk.clone();
+                let root = root.clone();
+                async move {
+                    let location = location?;
+                    let store = FloppyObjectStore { disk, root };
+                    store
+                        .disk
+                        .remove_file(store.disk_path(&location))
+                        .await
+                        .map_err(|e| store_error(&location, e))?;
+                    Ok(location)
+                }
+            })
+            .boxed()
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'static, Result<ObjectMeta>> {
+        let disk = self.disk.clone();
+        let root = self.root.clone();
+        let dir = self.disk_path(prefix.unwrap_or(&Path::default()));
+        stream::once(walk(disk, root, dir))
+            .flat_map(|objects| match objects {
+                Ok(objects) => stream::iter(objects.into_iter().map(Ok)).boxed(),
+                Err(e) => stream::once(async { Err(e) }).boxed(),
+            })
+            .boxed()
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        let prefix = prefix.cloned().unwrap_or_default();
+        let dir = self.disk_path(&prefix);
+        let mut objects = vec![];
+        let mut common_prefixes = vec![];
+        let mut read_dir = match self.disk.read_dir(&dir).await {
+            Ok(read_dir) => Some(read_dir),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(store_error(&prefix, e)),
+        };
+        while let Some(read_dir) = read_dir.as_mut() {
+            let Some(entry) = read_dir
+                .next_entry()
+                .await
+                .map_err(|e| store_error(&prefix, e))?
+            else {
+                break;
+            };
+            let path = entry.path();
+            let file_type = entry
+                .file_type()
+                .await
+                .map_err(|e| store_error(&prefix, e))?;
+            if file_type.is_dir() {
+                common_prefixes.push(location(&self.root, &path)?);
+            } else if file_type.is_file() {
+                objects.push(object_meta(&*self.disk, &path, location(&self.root, &path)?).await?);
+            }
+        }
+        objects.sort_by(|a, b| a.location.cmp(&b.location));
+        common_prefixes.sort();
+        Ok(ListResult {
+            common_prefixes,
+            objects,
+            extensions: Default::default(),
+        })
+    }
+
+    async fn copy_opts(&self, from: &Path, to: &Path, options: CopyOptions) -> Result<()> {
+        let contents = self
+            .disk
+            .read(self.disk_path(from))
+            .await
+            .map_err(|e| store_error(from, e))?;
+        let create = matches!(options.mode, CopyMode::Create);
+        put(&*self.disk, &self.disk_path(to), to, &contents, create).await?;
+        Ok(())
+    }
+}
+
+/// Parts are buffered in memory, and the object is written in one go when the
+/// upload completes.
+#[derive(Debug)]
+struct FloppyUpload<D> {
+    disk: Arc<D>,
+    path: PathBuf,
+    location: Path,
+    parts: Vec<PutPayload>,
+}
+
+#[async_trait]
+impl<D> MultipartUpload for FloppyUpload<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    fn put_part(&mut self, payload: PutPayload) -> UploadPart {
+        self.parts.push(payload);
+        Box::pin(async { Ok(()) })
+    }
+
+    async fn complete(&mut self) -> Result<PutResult> {
+        let mut contents = vec![];
+        for part in self.parts.drain(..) {
+            for chunk in &part {
+                contents.extend_from_slice(chunk);
+            }
+        }
+        put(&*self.disk, &self.path, &self.location, &contents, false).await
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        self.parts.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::object_store::{ObjectStoreExt, PutPayload};
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_object_store() -> Result<()> {
+        let disk = Arc::new(MemFloppyDisk::new());
+        disk.create_dir("/data").await.unwrap();
+        let store = FloppyObjectStore::new(disk.clone(), "/data");
+
+        let a = Path::from("a/b/c.parquet");
+        store
+            .put(&a, PutPayload::from_static(b"0123456789"))
+            .await?;
+        assert_eq!(
+            "0123456789",
+            disk.read_to_string("/data/a/b/c.parquet").await.unwrap()
+        );
+        assert_eq!(10, store.head(&a).await?.size);
+        assert_eq!(&b"234"[..], &store.get_range(&a, 2..5).await?[..]);
+        assert_eq!(&b"0123456789"[..], &store.get(&a).await?.bytes().await?[..]);
+
+        let mut upload = store.put_multipart(&Path::from("a/d.txt")).await?;
+        upload.put_part(PutPayload::from_static(b"as")).await?;
+        upload.put_part(PutPayload::from_static(b"df")).await?;
+        upload.complete().await?;
+        store
+            .copy(&Path::from("a/d.txt"), &Path::from("e.txt"))
+            .await?;
+        assert!(store
+            .copy_if_not_exists(&Path::from("a/d.txt"), &Path::from("e.txt"))
+            .await
+            .is_err());
+
+        let listed: Vec<String> = store
+            .list(Some(&Path::from("a")))
+            .map_ok(|meta| meta.location.to_string())
+            .try_collect()
+            .await?;
+        assert_eq!(vec!["a/b/c.parquet", "a/d.txt"], listed);
+        let listed = store.list_with_delimiter(None).await?;
+        assert_eq!(vec![Path::from("a")], listed.common_prefixes);
+        assert_eq!(Path::from("e.txt"), listed.objects[0].location);
+
+        store.delete(&a).await?;
+        assert!(matches!(store.head(&a).await, Err(Error::NotFound { .. })));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1297 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io::{Error, ErrorKind, IoSlice, Result, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::{
+    resolve_symlinks, FloppyDirEntry, FloppyDisk, FloppyDiskUnixExt, FloppyFile, FloppyMetadata,
+    FloppyOpenOptions, FloppyReadDir, FloppyUnixMetadata,
+};
+
+/// Restrictions on what may be written to a disk. Policies are enforced by
+/// wrapping a disk, any disk, in a [`PolicyDisk`].
+///
+/// ```rust
+/// # use floppy_disk::prelude::*;
+/// # use floppy_disk::policy::{FloppyPolicy, PolicyDisk};
+/// let fs = PolicyDisk::new(
+///     MemFloppyDisk::new(),
+///     FloppyPolicy::new()
+///         .max_file_size(16 * 1024 * 1024)
+///         .max_write_size(64 * 1024)
+///         .deny_extension("/uploads", "exe")
+///         .deny_magic("/uploads", b"\x7fELF".to_vec()),
+/// );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FloppyPolicy {
+    max_file_size: Option<u64>,
+    max_write_size: Option<usize>,
+    content_rules: Vec<ContentRule>,
+    write_once: Vec<WriteOnceRule>,
+}
+
+#[derive(Clone, Debug)]
+struct WriteOnceRule {
+    prefix: PathBuf,
+    until: Option<SystemTime>,
+}
+
+#[derive(Clone, Debug)]
+struct ContentRule {
+    prefix: PathBuf,
+    kind: ContentKind,
+}
+
+#[derive(Clone, Debug)]
+enum ContentKind {
+    Extension(String),
+    Magic(Vec<u8>),
+}
+
+impl FloppyPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Files may not grow beyond `max_file_size` bytes.
+    pub fn max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = Some(max_file_size);
+        self
+    }
+
+    /// A single write may not be larger than `max_write_size` bytes.
+    pub fn max_write_size(mut self, max_write_size: usize) -> Self {
+        self.max_write_size = Some(max_write_size);
+        self
+    }
+
+    /// Files under `prefix` may not be written with the extension `extension`.
+    /// Extensions are compared case-insensitively.
+    pub fn deny_extension<P: Into<PathBuf>, E: AsRef<OsStr>>(
+        mut self,
+        prefix: P,
+        extension: E,
+    ) -> Self {
+        let extension = extension.as_ref().to_string_lossy();
+        self.content_rules.push(ContentRule {
+            prefix: prefix.into(),
+            kind: ContentKind::Extension(extension.trim_start_matches('.').to_lowercase()),
+        });
+        self
+    }
+
+    /// Files under `prefix` may not start with the bytes `magic`.
+    pub fn deny_magic<P: Into<PathBuf>>(mut self, prefix: P, magic: Vec<u8>) -> Self {
+        self.content_rules.push(ContentRule {
+            prefix: prefix.into(),
+            kind: ContentKind::Magic(magic),
+        });
+        self
+    }
+
+    /// Files under `prefix` may be created, but never modified, moved or
+    /// deleted afterwards, whether by name or through a symlink. Hard links
+    /// to or from files under `prefix` are refused.
+    pub fn write_once<P: Into<PathBuf>>(mut self, prefix: P) -> Self {
+        self.write_once.push(WriteOnceRule {
+            prefix: prefix.into(),
+            until: None,
+        });
+        self
+    }
+
+    /// Like [`FloppyPolicy::write_once`], but only until `until` has passed.
+    pub fn write_once_until<P: Into<PathBuf>>(mut self, prefix: P, until: SystemTime) -> Self {
+        self.write_once.push(WriteOnceRule {
+            prefix: prefix.into(),
+            until: Some(until),
+        });
+        self
+    }
+
+    pub(crate) fn check_write(&self, path: &Path, offset: u64, len: usize) -> Result<()> {
+        if let Some(max_write_size) = self.max_write_size {
+            if len > max_write_size {
+                return Err(Error::new(
+                    ErrorKind::FileTooLarge,
+                    format!(
+                        "write of {len} bytes to {} exceeds the maximum write size of {max_write_size} bytes",
+                        path.display()
+                    ),
+                ));
+            }
+        }
+        self.check_file_size(path, offset.saturating_add(len as u64))
+    }
+
+    pub(crate) fn check_file_size(&self, path: &Path, size: u64) -> Result<()> {
+        if let Some(max_file_size) = self.max_file_size {
+            if size > max_file_size {
+                return Err(Error::new(
+                    ErrorKind::FileTooLarge,
+                    format!(
+                        "{} would grow to {size} bytes, exceeding the maximum file size of {max_file_size} bytes",
+                        path.display()
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks writing all of `contents` to `path` in one go.
+    pub(crate) fn check_contents(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        self.check_path(path)?;
+        self.check_write(path, 0, contents.len())?;
+        self.check_magic(path, contents)
+    }
+
+    /// Checks that `path` may be opened for writing.
+    pub(crate) fn check_path(&self, path: &Path) -> Result<()> {
+        let extension = match path.extension() {
+            Some(extension) => extension.to_string_lossy().to_lowercase(),
+            None => return Ok(()),
+        };
+        for rule in self.rules_for(path) {
+            if let ContentKind::Extension(ref denied) = rule.kind {
+                if *denied == extension {
+                    return Err(Error::new(
+                        ErrorKind::PermissionDenied,
+                        format!(
+                            "{} may not be written: .{extension} files are not allowed under {}",
+                            path.display(),
+                            rule.prefix.display()
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the first bytes of a file against the magic numbers denied for
+    /// `path`.
+    pub(crate) fn check_magic(&self, path: &Path, head: &[u8]) -> Result<()> {
+        for rule in self.rules_for(path) {
+            if let ContentKind::Magic(ref magic) = rule.kind {
+                if head.starts_with(magic) {
+                    return Err(Error::new(
+                        ErrorKind::PermissionDenied,
+                        format!(
+                            "{} may not be written: its contents are not allowed under {}",
+                            path.display(),
+                            rule.prefix.display()
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// How many leading bytes of a file at `path` need to be seen to check it
+    /// against the denied magic numbers.
+    pub(crate) fn magic_len(&self, path: &Path) -> usize {
+        self.rules_for(path)
+            .filter_map(|rule| match rule.kind {
+                ContentKind::Magic(ref magic) => Some(magic.len()),
+                ContentKind::Extension(_) => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Checks that `path` may be removed or moved away. Directories are
+    /// refused if they contain a write-once prefix.
+    pub(crate) fn check_remove(&self, path: &Path) -> Result<()> {
+        let path = normalise(path);
+        let now = SystemTime::now();
+        for rule in self.write_once.iter().filter(|rule| rule.is_active(now)) {
+            let prefix = normalise(&rule.prefix);
+            if path.starts_with(&prefix) || prefix.starts_with(&path) {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!(
+                        "{} may not be removed: files under {} are write-once",
+                        path.display(),
+                        rule.prefix.display()
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_write_once(&self, path: &Path) -> bool {
+        let path = normalise(path);
+        let now = SystemTime::now();
+        self.write_once
+            .iter()
+            .any(|rule| rule.is_active(now) && path.starts_with(normalise(&rule.prefix)))
+    }
+
+    fn rules_for<'a>(&'a self, path: &Path) -> impl Iterator<Item = &'a ContentRule> {
+        let path = normalise(path);
+        self.content_rules
+            .iter()
+            .filter(move |rule| path.starts_with(normalise(&rule.prefix)))
+    }
+}
+
+impl WriteOnceRule {
+    fn is_active(&self, now: SystemTime) -> bool {
+        self.until.map(|until| now < until).unwrap_or(true)
+    }
+}
+
+/// Checks that `path` may be opened for writing or replaced, ie. that it isn't
+/// an existing write-once file, whether by name or through a symlink.
+pub(crate) async fn check_overwrite<'a, D: FloppyDisk<'a>>(
+    disk: &D,
+    policy: &FloppyPolicy,
+    path: &Path,
+) -> Result<()> {
+    let write_once = policy.is_write_once(path)
+        || (!policy.write_once.is_empty()
+            && policy.is_write_once(&resolve_symlinks(disk, path, true).await?));
+    if write_once && disk.try_exists(path).await? {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} may not be modified: it is write-once", path.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that `path` may be removed or moved away, with the symlinks in it
+/// resolved, so that eg. `/link/a.log` can't be removed when `/link` points
+/// into a write-once prefix. A symlink itself can still be removed.
+pub(crate) async fn check_remove<'a, D: FloppyDisk<'a>>(
+    disk: &D,
+    policy: &FloppyPolicy,
+    path: &Path,
+) -> Result<()> {
+    policy.check_remove(path)?;
+    if policy.write_once.is_empty() {
+        return Ok(());
+    }
+    policy.check_remove(&resolve_symlinks(disk, path, false).await?)
+}
+
+/// Checks that a hard link from `src` to `dst` wouldn't give a file a name
+/// its extension or contents are denied under, give a write-once file a name
+/// it could be modified under, or give a write-once name to a file that can
+/// be modified under another.
+pub(crate) async fn check_hard_link<'a, D: FloppyDisk<'a>>(
+    disk: &D,
+    policy: &FloppyPolicy,
+    src: &Path,
+    dst: &Path,
+) -> Result<()> {
+    policy.check_path(dst)?;
+    if policy.magic_len(dst) > 0 && disk.metadata(src).await?.is_file() {
+        policy.check_magic(dst, &disk.read(src).await?)?;
+    }
+    if policy.write_once.is_empty() {
+        return Ok(());
+    }
+    let paths = [
+        src.to_path_buf(),
+        resolve_symlinks(disk, src, true).await?,
+        dst.to_path_buf(),
+        resolve_symlinks(disk, dst, false).await?,
+    ];
+    if paths.iter().any(|path| policy.is_write_once(path)) {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "{} may not be linked to {}: one of them is write-once",
+                dst.display(),
+                src.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that the file at `from` may be copied or moved to `to`. The source
+/// is only read if there are magic numbers to check it against.
+pub(crate) async fn check_transfer<'a, D: FloppyDisk<'a>>(
+    disk: &D,
+    policy: &FloppyPolicy,
+    from: &Path,
+    to: &Path,
+) -> Result<()> {
+    policy.check_path(to)?;
+    check_overwrite(disk, policy, to).await?;
+    if policy.magic_len(to) > 0 && disk.metadata(from).await?.is_file() {
+        policy.check_magic(to, &disk.read(from).await?)?;
+    }
+    Ok(())
+}
+
+/// Lexically resolves `.` and `..`, so that eg. `/a/../uploads/x` is still
+/// matched by rules for `/uploads`.
+pub(crate) fn normalise(path: &Path) -> PathBuf {
+    let mut normalised = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalised.pop();
+            }
+            component => normalised.push(component),
+        }
+    }
+    normalised
+}
+
+/// Tracks the write position of an open file so that the disk's policy can be
+/// checked before each write reaches the backend.
+///
+/// The leading bytes of the file are kept around for as long as they could
+/// match a denied magic number.
+#[derive(Clone, Debug)]
+pub(crate) struct PolicyGuard {
+    policy: Arc<FloppyPolicy>,
+    path: PathBuf,
+    position: u64,
+    head: Vec<u8>,
+    head_known: bool,
+    magic_len: usize,
+}
+
+impl PolicyGuard {
+    pub(crate) fn new(policy: Arc<FloppyPolicy>, path: PathBuf, position: u64) -> Self {
+        let magic_len = policy.magic_len(&path);
+        Self {
+            policy,
+            path,
+            position,
+            head: vec![],
+            head_known: true,
+            magic_len,
+        }
+    }
+
+    /// Read in what's already at the start of the file, for files opened for
+    /// writing without being truncated, so that a write can't finish a magic
+    /// number that's partly there already. If it can't be read, eg. because
+    /// the file is write-only, writes to the start of the file are refused.
+    pub(crate) async fn load_head<'a, D: FloppyDisk<'a> + Sync>(&mut self, disk: &'a D) {
+        if self.magic_len == 0 {
+            return;
+        }
+        match disk.read_range(&self.path, 0, self.magic_len as u64).await {
+            Ok(head) => self.head = head,
+            Err(_) => self.head_known = false,
+        }
+    }
+
+    pub(crate) fn check_write(&self, buf: &[u8]) -> Result<()> {
+        self.check_write_at(buf, self.position)
+    }
+
+    /// Positional writes don't move the cursor, and are checked without
+    /// updating what's known about the start of the file.
+    pub(crate) fn check_write_at(&self, buf: &[u8], offset: u64) -> Result<()> {
+        self.policy.check_write(&self.path, offset, buf.len())?;
+        self.check_head_known(offset)?;
+        match self.head_after(offset, buf) {
+            Some(head) => self.policy.check_magic(&self.path, &head),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_write_vectored(&self, bufs: &[IoSlice<'_>]) -> Result<()> {
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        self.policy.check_write(&self.path, self.position, len)?;
+        self.check_head_known(self.position)?;
+        // Only the leading bytes can make up a magic number.
+        match self.head_after(self.position, &leading(bufs, self.magic_len)) {
+            Some(head) => self.policy.check_magic(&self.path, &head),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_set_len(&self, size: u64) -> Result<()> {
+        self.policy.check_file_size(&self.path, size)
+    }
+
+    pub(crate) fn wrote(&mut self, buf: &[u8]) {
+        if let Some(head) = self.head_after(self.position, buf) {
+            self.head = head;
+        }
+        self.position += buf.len() as u64;
+    }
+
+    pub(crate) fn wrote_vectored(&mut self, bufs: &[IoSlice<'_>], written: usize) {
+        let buf = leading(bufs, written.min(self.magic_len));
+        if let Some(head) = self.head_after(self.position, &buf) {
+            self.head = head;
+        }
+        self.position += written as u64;
+    }
+
+    fn check_head_known(&self, offset: u64) -> Result<()> {
+        if self.head_known || offset >= self.magic_len as u64 {
+            return Ok(());
+        }
+        Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "can't check writes to the start of {} for denied magic numbers",
+                self.path.display()
+            ),
+        ))
+    }
+
+    /// What the start of the file looks like after writing `buf` at
+    /// `offset`, or `None` if the write is past it.
+    fn head_after(&self, offset: u64, buf: &[u8]) -> Option<Vec<u8>> {
+        if offset >= self.magic_len as u64 || !self.head_known {
+            return None;
+        }
+        let position = offset as usize;
+        let buf = &buf[..buf.len().min(self.magic_len - position)];
+        // The head is all of the file up to `magic_len`, so anything between
+        // its end and `offset` is a hole, which reads as zeroes.
+        let mut head = self.head.clone();
+        head.resize(head.len().max(position + buf.len()), 0);
+        head[position..position + buf.len()].copy_from_slice(buf);
+        Some(head)
+    }
+
+    pub(crate) fn seeked(&mut self, position: u64) {
+        self.position = position;
+    }
+}
+
+/// Up to the first `len` bytes of `bufs`, as if they were one buffer.
+fn leading(bufs: &[IoSlice<'_>], len: usize) -> Vec<u8> {
+    let mut leading = Vec::with_capacity(len);
+    for buf in bufs {
+        let wanted = len - leading.len();
+        leading.extend_from_slice(&buf[..wanted.min(buf.len())]);
+    }
+    leading
+}
+
+/// A disk that enforces a [`FloppyPolicy`] on everything written to `D`,
+/// whatever the backend, failing anything the policy doesn't allow before it
+/// reaches `D`.
+#[derive(Debug)]
+pub struct PolicyDisk<D> {
+    disk: D,
+    policy: Arc<FloppyPolicy>,
+}
+
+impl<D> PolicyDisk<D> {
+    pub fn new(disk: D, policy: FloppyPolicy) -> Self {
+        Self {
+            disk,
+            policy: Arc::new(policy),
+        }
+    }
+
+    pub fn policy(&self) -> &FloppyPolicy {
+        &self.policy
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyDisk<'a> for PolicyDisk<D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirBuilder: Sync,
+    D::DirEntry: Sync,
+    D::File: Sync,
+    D::OpenOptions: Sync,
+    D::Permissions: 'static,
+{
+    type DirBuilder = D::DirBuilder;
+    type DirEntry = PolicyDirEntry<'a, D>;
+    type File = PolicyFile<'a, D>;
+    type FileType = D::FileType;
+    type Metadata = PolicyMetadata<'a, D>;
+    type OpenOptions = PolicyOpenOptions<'a, D>;
+    type Permissions = D::Permissions;
+    type ReadDir = PolicyReadDir<'a, D>;
+
+    async fn canonicalize<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        self.disk.canonicalize(path).await
+    }
+
+    async fn copy<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<u64> {
+        check_transfer(&self.disk, &self.policy, from.as_ref(), to.as_ref()).await?;
+        let len = self.disk.metadata(from.as_ref()).await?.len();
+        self.policy.check_file_size(to.as_ref(), len)?;
+        self.disk.copy(from, to).await
+    }
+
+    async fn create_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.disk.create_dir(path).await
+    }
+
+    async fn create_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.disk.create_dir_all(path).await
+    }
+
+    async fn hard_link<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        check_hard_link(&self.disk, &self.policy, src.as_ref(), dst.as_ref()).await?;
+        self.disk.hard_link(src, dst).await
+    }
+
+    async fn metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        self.disk.metadata(path).await.map(PolicyMetadata)
+    }
+
+    async fn read<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>> {
+        self.disk.read(path).await
+    }
+
+    async fn read_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::ReadDir> {
+        self.disk.read_dir(path).await.map(PolicyReadDir)
+    }
+
+    async fn read_link<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        self.disk.read_link(path).await
+    }
+
+    async fn read_to_string<P: AsRef<Path> + Send>(&self, path: P) -> Result<String> {
+        self.disk.read_to_string(path).await
+    }
+
+    async fn remove_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        check_remove(&self.disk, &self.policy, path.as_ref()).await?;
+        self.disk.remove_dir(path).await
+    }
+
+    async fn remove_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        check_remove(&self.disk, &self.policy, path.as_ref()).await?;
+        self.disk.remove_dir_all(path).await
+    }
+
+    async fn remove_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        check_remove(&self.disk, &self.policy, path.as_ref()).await?;
+        self.disk.remove_file(path).await
+    }
+
+    async fn rename<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<()> {
+        check_remove(&self.disk, &self.policy, from.as_ref()).await?;
+        check_transfer(&self.disk, &self.policy, from.as_ref(), to.as_ref()).await?;
+        self.disk.rename(from, to).await
+    }
+
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        perm: Self::Permissions,
+    ) -> Result<()> {
+        self.disk.set_permissions(path, perm).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        self.policy.check_path(dst.as_ref())?;
+        self.disk.symlink(src, dst).await
+    }
+
+    async fn symlink_metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        self.disk.symlink_metadata(path).await.map(PolicyMetadata)
+    }
+
+    async fn try_exists<P: AsRef<Path> + Send>(&self, path: P) -> Result<bool> {
+        self.disk.try_exists(path).await
+    }
+
+    async fn write<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        self.policy
+            .check_contents(path.as_ref(), contents.as_ref())?;
+        check_overwrite(&self.disk, &self.policy, path.as_ref()).await?;
+        self.disk.write(path, contents).await
+    }
+
+    fn new_dir_builder(&'a self) -> Self::DirBuilder {
+        self.disk.new_dir_builder()
+    }
+
+    /// Checked as if written through a file opened for appending, then left
+    /// to `D`, so that appends stay as atomic as `D` makes them.
+    async fn append<P: AsRef<Path> + Send>(
+        &'a self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        self.policy.check_path(path.as_ref())?;
+        check_overwrite(&self.disk, &self.policy, path.as_ref()).await?;
+        let len = match self.disk.metadata(path.as_ref()).await {
+            Ok(metadata) => Some(metadata.len()),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let mut guard = PolicyGuard::new(
+            self.policy.clone(),
+            path.as_ref().to_path_buf(),
+            len.unwrap_or(0),
+        );
+        if len.is_some() {
+            guard.load_head(&self.disk).await;
+        }
+        guard.check_write(contents.as_ref())?;
+        self.disk.append(path, contents).await
+    }
+
+    async fn truncate<P: AsRef<Path> + Send>(&'a self, path: P, len: u64) -> Result<()> {
+        self.policy.check_path(path.as_ref())?;
+        self.policy.check_file_size(path.as_ref(), len)?;
+        check_overwrite(&self.disk, &self.policy, path.as_ref()).await?;
+        self.disk.truncate(path, len).await
+    }
+
+    async fn write_atomic<P: AsRef<Path> + Send>(
+        &'a self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        self.policy
+            .check_contents(path.as_ref(), contents.as_ref())?;
+        check_overwrite(&self.disk, &self.policy, path.as_ref()).await?;
+        self.disk.write_atomic(path, contents).await
+    }
+
+    async fn read_range<P: AsRef<Path> + Send>(
+        &'a self,
+        path: P,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>> {
+        self.disk.read_range(path, offset, len).await
+    }
+
+    async fn metadata_many<P: AsRef<Path> + Send + Sync>(
+        &self,
+        paths: &[P],
+    ) -> Vec<Result<Self::Metadata>> {
+        self.disk
+            .metadata_many(paths)
+            .await
+            .into_iter()
+            .map(|metadata| metadata.map(PolicyMetadata))
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyDiskUnixExt for PolicyDisk<D>
+where
+    D: FloppyDisk<'a> + FloppyDiskUnixExt + Send + Sync,
+{
+    async fn chown<P: Into<PathBuf> + Send>(&self, path: P, uid: u32, gid: u32) -> Result<()> {
+        self.disk.chown(path, uid, gid).await
+    }
+}
+
+pub struct PolicyDirEntry<'a, D: FloppyDisk<'a>>(D::DirEntry);
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for PolicyDirEntry<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PolicyDirEntry").field(&self.0).finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyDirEntry<'a, PolicyDisk<D>> for PolicyDirEntry<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirBuilder: Sync,
+    D::DirEntry: Sync,
+    D::File: Sync,
+    D::OpenOptions: Sync,
+    D::Permissions: 'static,
+{
+    fn path(&self) -> PathBuf {
+        self.0.path()
+    }
+
+    fn file_name(&self) -> OsString {
+        self.0.file_name()
+    }
+
+    async fn metadata(&self) -> Result<PolicyMetadata<'a, D>> {
+        self.0.metadata().await.map(PolicyMetadata)
+    }
+
+    async fn file_type(&self) -> Result<D::FileType> {
+        self.0.file_type().await
+    }
+
+    #[cfg(unix)]
+    fn ino(&self) -> u64 {
+        self.0.ino()
+    }
+}
+
+pub struct PolicyReadDir<'a, D: FloppyDisk<'a>>(D::ReadDir);
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for PolicyReadDir<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PolicyReadDir").field(&self.0).finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyReadDir<'a, PolicyDisk<D>> for PolicyReadDir<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirBuilder: Sync,
+    D::DirEntry: Sync,
+    D::File: Sync,
+    D::OpenOptions: Sync,
+    D::Permissions: 'static,
+{
+    async fn next_entry(&mut self) -> Result<Option<PolicyDirEntry<'a, D>>> {
+        Ok(self.0.next_entry().await?.map(PolicyDirEntry))
+    }
+}
+
+pub struct PolicyMetadata<'a, D: FloppyDisk<'a>>(D::Metadata);
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for PolicyMetadata<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PolicyMetadata").field(&self.0).finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyMetadata<'a, PolicyDisk<D>> for PolicyMetadata<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirBuilder: Sync,
+    D::DirEntry: Sync,
+    D::File: Sync,
+    D::OpenOptions: Sync,
+    D::Permissions: 'static,
+{
+    fn file_type(&self) -> D::FileType {
+        self.0.file_type()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.0.is_dir()
+    }
+
+    fn is_file(&self) -> bool {
+        self.0.is_file()
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.0.is_symlink()
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len()
+    }
+
+    fn permissions(&self) -> D::Permissions {
+        self.0.permissions()
+    }
+
+    fn modified(&self) -> Result<SystemTime> {
+        self.0.modified()
+    }
+
+    fn accessed(&self) -> Result<SystemTime> {
+        self.0.accessed()
+    }
+
+    fn created(&self) -> Result<SystemTime> {
+        self.0.created()
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> FloppyUnixMetadata for PolicyMetadata<'a, D>
+where
+    D::Metadata: FloppyUnixMetadata,
+{
+    fn uid(&self) -> Result<u32> {
+        self.0.uid()
+    }
+
+    fn gid(&self) -> Result<u32> {
+        self.0.gid()
+    }
+
+    fn dev(&self) -> Result<u64> {
+        self.0.dev()
+    }
+
+    fn ino(&self) -> Result<u64> {
+        self.0.ino()
+    }
+
+    fn blocks(&self) -> Result<u64> {
+        self.0.blocks()
+    }
+}
+
+pub struct PolicyOpenOptions<'a, D: FloppyDisk<'a>> {
+    options: D::OpenOptions,
+    write: bool,
+    append: bool,
+    truncate: bool,
+}
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for PolicyOpenOptions<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicyOpenOptions")
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyOpenOptions<'a, PolicyDisk<D>> for PolicyOpenOptions<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirBuilder: Sync,
+    D::DirEntry: Sync,
+    D::File: Sync,
+    D::OpenOptions: Sync,
+    D::Permissions: 'static,
+{
+    fn new() -> Self {
+        Self {
+            options: D::OpenOptions::new(),
+            write: false,
+            append: false,
+            truncate: false,
+        }
+    }
+
+    fn read(mut self, read: bool) -> Self {
+        self.options = self.options.read(read);
+        self
+    }
+
+    fn write(mut self, write: bool) -> Self {
+        self.options = self.options.write(write);
+        self.write = write;
+        self
+    }
+
+    fn append(mut self, append: bool) -> Self {
+        self.options = self.options.append(append);
+        self.append = append;
+        self
+    }
+
+    fn truncate(mut self, truncate: bool) -> Self {
+        self.options = self.options.truncate(truncate);
+        self.truncate = truncate;
+        self
+    }
+
+    fn create(mut self, create: bool) -> Self {
+        self.options = self.options.create(create);
+        self
+    }
+
+    fn create_new(mut self, create_new: bool) -> Self {
+        self.options = self.options.create_new(create_new);
+        self
+    }
+
+    async fn open<P: AsRef<Path> + Send>(
+        &self,
+        disk: &'a PolicyDisk<D>,
+        path: P,
+    ) -> Result<PolicyFile<'a, D>> {
+        // As with `CapabilityOpenOptions::open`, the file isn't opened until
+        // this is awaited, after the checks.
+        let path = path.as_ref().to_path_buf();
+        let (writing, append, truncate) = (self.write || self.append, self.append, self.truncate);
+        let opening = self.options.open(&disk.disk, path.clone());
+        if writing {
+            disk.policy.check_path(&path)?;
+            check_overwrite(&disk.disk, &disk.policy, &path).await?;
+        }
+        let file = opening.await?;
+        let position = if append {
+            file.metadata().await?.len()
+        } else {
+            0
+        };
+        let mut guard = PolicyGuard::new(disk.policy.clone(), path, position);
+        if writing && !truncate {
+            guard.load_head(&disk.disk).await;
+        }
+        Ok(PolicyFile { file, guard })
+    }
+}
+
+/// An open file on a [`PolicyDisk`]. Every write is checked before it
+/// reaches `D`'s file.
+pub struct PolicyFile<'a, D: FloppyDisk<'a>> {
+    file: D::File,
+    guard: PolicyGuard,
+}
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for PolicyFile<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicyFile")
+            .field("file", &self.file)
+            .field("guard", &self.guard)
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyFile<'a, PolicyDisk<D>> for PolicyFile<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirBuilder: Sync,
+    D::DirEntry: Sync,
+    D::File: Sync,
+    D::OpenOptions: Sync,
+    D::Permissions: 'static,
+{
+    async fn sync_all(&mut self) -> Result<()> {
+        self.file.sync_all().await
+    }
+
+    async fn sync_data(&mut self) -> Result<()> {
+        self.file.sync_data().await
+    }
+
+    async fn set_len(&mut self, size: u64) -> Result<()> {
+        self.guard.check_set_len(size)?;
+        self.file.set_len(size).await
+    }
+
+    async fn metadata(&self) -> Result<PolicyMetadata<'a, D>> {
+        self.file.metadata().await.map(PolicyMetadata)
+    }
+
+    async fn try_clone(&'a self) -> Result<Box<PolicyFile<'a, D>>> {
+        let guard = self.guard.clone();
+        Ok(Box::new(PolicyFile {
+            file: *self.file.try_clone().await?,
+            guard,
+        }))
+    }
+
+    async fn set_permissions(&self, perm: D::Permissions) -> Result<()> {
+        self.file.set_permissions(perm).await
+    }
+
+    async fn permissions(&self) -> Result<D::Permissions> {
+        self.file.permissions().await
+    }
+
+    async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.file.read_at(buf, offset).await
+    }
+
+    async fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        self.guard.check_write_at(buf, offset)?;
+        self.file.write_at(buf, offset).await
+    }
+
+    async fn allocate(&self, offset: u64, len: u64) -> Result<()> {
+        self.guard.check_set_len(offset.saturating_add(len))?;
+        self.file.allocate(offset, len).await
+    }
+
+    async fn seek_data(&self, offset: u64) -> Result<u64> {
+        self.file.seek_data(offset).await
+    }
+
+    async fn seek_hole(&self, offset: u64) -> Result<u64> {
+        self.file.seek_hole(offset).await
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> AsyncRead for PolicyFile<'a, D> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_read(cx, buf)
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> AsyncWrite for PolicyFile<'a, D> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        this.guard.check_write(buf)?;
+        let result = Pin::new(&mut this.file).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            this.guard.wrote(&buf[..written]);
+        }
+        result
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        this.guard.check_write_vectored(bufs)?;
+        let result = Pin::new(&mut this.file).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = result {
+            this.guard.wrote_vectored(bufs, written);
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.file.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_shutdown(cx)
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> AsyncSeek for PolicyFile<'a, D> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> Result<()> {
+        Pin::new(&mut self.get_mut().file).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.file).poll_complete(cx);
+        if let Poll::Ready(Ok(position)) = result {
+            this.guard.seeked(position);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+    use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+    #[test]
+    fn test_unlimited_by_default() {
+        let policy = FloppyPolicy::new();
+        assert!(policy
+            .check_write(Path::new("/a"), u64::MAX, usize::MAX)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_limits() {
+        let policy = FloppyPolicy::new().max_file_size(10).max_write_size(4);
+        assert!(policy.check_write(Path::new("/a"), 0, 4).is_ok());
+        assert!(policy.check_write(Path::new("/a"), 0, 5).is_err());
+        assert!(policy.check_write(Path::new("/a"), 6, 4).is_ok());
+        assert!(policy.check_write(Path::new("/a"), 7, 4).is_err());
+        assert!(policy.check_file_size(Path::new("/a"), 11).is_err());
+    }
+
+    #[test]
+    fn test_content_rules() {
+        let policy = FloppyPolicy::new()
+            .deny_extension("/uploads", ".EXE")
+            .deny_magic("/uploads", b"MZ".to_vec());
+        assert!(policy.check_path(Path::new("/uploads/a.exe")).is_err());
+        assert!(policy.check_path(Path::new("/uploads/b/a.Exe")).is_err());
+        assert!(policy.check_path(Path::new("/x/../uploads/a.exe")).is_err());
+        assert!(policy.check_path(Path::new("/uploads/a.txt")).is_ok());
+        assert!(policy.check_path(Path::new("/uploads.exe")).is_ok());
+        assert!(policy.check_path(Path::new("/other/a.exe")).is_ok());
+
+        assert!(policy
+            .check_contents(Path::new("/uploads/a"), b"MZ..")
+            .is_err());
+        assert!(policy
+            .check_contents(Path::new("/other/a"), b"MZ..")
+            .is_ok());
+
+        let mut guard = PolicyGuard::new(Arc::new(policy), PathBuf::from("/uploads/a"), 0);
+        assert!(guard.check_write(b"M").is_ok());
+        guard.wrote(b"M");
+        assert!(guard.check_write(b"Z").is_err());
+        assert!(guard.check_write(b"A").is_ok());
+        // Writing past the end of the file leaves a hole of zeroes before it.
+        assert!(guard.check_write_at(b"Z", 2).is_ok());
+    }
+
+    #[test]
+    fn test_write_once_removal() {
+        let past = SystemTime::now() - std::time::Duration::from_secs(60);
+        let policy = FloppyPolicy::new()
+            .write_once("/audit/logs")
+            .write_once_until("/old", past);
+        assert!(policy.check_remove(Path::new("/audit/logs/a")).is_err());
+        assert!(policy.check_remove(Path::new("/audit")).is_err());
+        assert!(policy.check_remove(Path::new("/audit/other")).is_ok());
+        assert!(policy.check_remove(Path::new("/old/a")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_policy_limits() -> Result<()> {
+        let fs = PolicyDisk::new(
+            MemFloppyDisk::new(),
+            FloppyPolicy::new().max_file_size(8).max_write_size(4),
+        );
+        assert!(fs.write("/test.txt", "asdfg").await.is_err());
+        fs.write("/test.txt", "asdf").await?;
+
+        let mut file = PolicyOpenOptions::new()
+            .append(true)
+            .open(&fs, "/test.txt")
+            .await?;
+        AsyncWriteExt::write_all(&mut file, b"asdf").await?;
+        assert!(AsyncWriteExt::write_all(&mut file, b"a").await.is_err());
+        assert!(file.set_len(9).await.is_err());
+        assert!(file.allocate(4, 5).await.is_err());
+        assert!(file.write_at(b"a", 8).await.is_err());
+        assert!(fs.truncate("/test.txt", 9).await.is_err());
+        assert!(fs.append("/test.txt", "a").await.is_err());
+        assert_eq!("asdfasdf", fs.read_to_string("/test.txt").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_content_policy() -> Result<()> {
+        let fs = PolicyDisk::new(
+            MemFloppyDisk::new(),
+            FloppyPolicy::new()
+                .deny_extension("/uploads", "exe")
+                .deny_magic("/uploads", b"\x7fELF".to_vec()),
+        );
+        fs.create_dir("/uploads").await?;
+        assert!(fs.write("/uploads/a.exe", "asdf").await.is_err());
+        assert!(fs.write("/uploads/a", "\x7fELF").await.is_err());
+        fs.write("/a.exe", "\x7fELF").await?;
+        fs.write("/uploads/a.txt", "asdf").await?;
+
+        assert!(PolicyOpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&fs, "/uploads/b.exe")
+            .await
+            .is_err());
+        let mut file = PolicyOpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&fs, "/uploads/b")
+            .await?;
+        AsyncWriteExt::write_all(&mut file, b"\x7fE").await?;
+        assert!(AsyncWriteExt::write_all(&mut file, b"LF").await.is_err());
+
+        assert!(fs.copy("/a.exe", "/uploads/c").await.is_err());
+        assert!(fs.rename("/uploads/a.txt", "/uploads/a.exe").await.is_err());
+        fs.rename("/uploads/a.txt", "/a.txt").await?;
+        assert!(fs.symlink("/a.txt", "/uploads/d.exe").await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_once_policy() -> Result<()> {
+        let fs = PolicyDisk::new(
+            MemFloppyDisk::new(),
+            FloppyPolicy::new().write_once("/audit"),
+        );
+        fs.create_dir("/audit").await?;
+        fs.write("/audit/a.log", "asdf").await?;
+        assert!(fs.write("/audit/a.log", "hjkl").await.is_err());
+        assert!(PolicyOpenOptions::new()
+            .append(true)
+            .open(&fs, "/audit/a.log")
+            .await
+            .is_err());
+        assert!(fs.remove_file("/audit/a.log").await.is_err());
+        assert!(fs.rename("/audit/a.log", "/a.log").await.is_err());
+        assert!(fs.remove_dir_all("/audit").await.is_err());
+
+        fs.write("/b.log", "hjkl").await?;
+        assert!(fs.copy("/b.log", "/audit/a.log").await.is_err());
+        fs.copy("/b.log", "/audit/b.log").await?;
+        assert_eq!("asdf", fs.read_to_string("/audit/a.log").await?);
+
+        // Symlinks into the write-once subtree don't get around it.
+        fs.symlink("/audit", "/link").await?;
+        fs.symlink("audit/a.log", "/alias.log").await?;
+        assert!(fs.write("/link/a.log", "hjkl").await.is_err());
+        assert!(fs.write("/alias.log", "hjkl").await.is_err());
+        assert!(fs.remove_file("/link/a.log").await.is_err());
+        assert!(fs.rename("/link/a.log", "/a.log").await.is_err());
+        assert_eq!("asdf", fs.read_to_string("/audit/a.log").await?);
+        // The symlinks themselves are fair game.
+        fs.remove_file("/alias.log").await?;
+        fs.remove_file("/link").await?;
+
+        fs.create_dir("/audit/empty").await?;
+        assert!(fs.remove_dir("/audit/empty").await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_magic_across_opens() -> Result<()> {
+        let fs = PolicyDisk::new(
+            MemFloppyDisk::new(),
+            FloppyPolicy::new().deny_magic("/uploads", b"MZ".to_vec()),
+        );
+        fs.create_dir("/uploads").await?;
+        fs.write("/uploads/a", "M").await?;
+
+        // The "M" from before is still at the start of the file.
+        let mut file = PolicyOpenOptions::new()
+            .write(true)
+            .open(&fs, "/uploads/a")
+            .await?;
+        AsyncSeekExt::seek(&mut file, SeekFrom::Start(1)).await?;
+        assert!(AsyncWriteExt::write_all(&mut file, b"Z").await.is_err());
+        assert!(file.write_at(b"Z", 1).await.is_err());
+        assert!(file.write_at(b"A", 1).await.is_ok());
+        assert_eq!(b"MA", &fs.read("/uploads/a").await?[..]);
+
+        // Truncating throws it away.
+        let mut file = PolicyOpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&fs, "/uploads/a")
+            .await?;
+        AsyncWriteExt::write_all(&mut file, b"AZ").await?;
+
+        // A denied magic number split across buffers is still caught.
+        let mut file = PolicyOpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&fs, "/uploads/b")
+            .await?;
+        let bufs = [IoSlice::new(b"M"), IoSlice::new(b"Z...")];
+        assert!(FloppyFile::write_all_vectored(&mut file, &bufs)
+            .await
+            .is_err());
+        fs.write("/uploads/c", "M").await?;
+        assert!(fs.append("/uploads/c", "Z").await.is_err());
+
+        Ok(())
+    }
+}
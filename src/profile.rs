@@ -0,0 +1,220 @@
+//! Sampling profiles of what's being done to a disk, and by whom, to find the
+//! code path that's hammering it.
+//!
+//! Callers are told apart by the `tracing` span they're in: each sample is
+//! filed under the target and name of the innermost span current when the
+//! operation started. Profiles can be dumped in the folded format that
+//! `flamegraph.pl` and `inferno` read, with one frame for the caller and one
+//! for the operation.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Totals for one operation made from one caller.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileEntry {
+    /// How many of the operations were sampled.
+    pub count: u64,
+    pub time: Duration,
+    /// Bytes read or written, for operations that move data.
+    pub bytes: u64,
+}
+
+#[derive(Debug)]
+pub struct Profiler {
+    sample_every: u64,
+    seen: AtomicU64,
+    entries: Mutex<BTreeMap<(String, &'static str), ProfileEntry>>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            sample_every: 1,
+            seen: AtomicU64::new(0),
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Profiler {
+    /// A profiler that samples every operation.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only sample one in every `n` operations, so that busy disks aren't
+    /// slowed down by profiling them. Operations that aren't sampled cost one
+    /// atomic increment.
+    pub fn with_sample_every(mut self, n: u64) -> Self {
+        self.sample_every = n.max(1);
+        self
+    }
+
+    /// Start timing `op`, if it's to be sampled. It's recorded when the
+    /// returned sample is dropped.
+    pub(crate) fn sample(profiler: &Option<Arc<Profiler>>, op: &'static str) -> ProfileSample {
+        let sampled = profiler.as_ref().filter(|profiler| {
+            profiler.seen.fetch_add(1, Ordering::Relaxed) % profiler.sample_every == 0
+        });
+        ProfileSample {
+            started: sampled.map(|profiler| (profiler.clone(), caller(), Instant::now())),
+            op,
+            bytes: 0,
+        }
+    }
+
+    /// Totals per caller and operation.
+    pub fn entries(&self) -> BTreeMap<(String, &'static str), ProfileEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// The profile in folded format, eg. `my_app::sync;read 1234`, weighted
+    /// by time in microseconds.
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for ((caller, op), entry) in self.entries.lock().unwrap().iter() {
+            out.push_str(&format!("{caller};{op} {}\n", entry.time.as_micros()));
+        }
+        out
+    }
+
+    /// Forget everything sampled so far.
+    pub fn reset(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// The innermost span that's current, as `target::name`.
+fn caller() -> String {
+    match tracing::Span::current().metadata() {
+        Some(metadata) => format!("{}::{}", metadata.target(), metadata.name()),
+        None => "(no span)".to_string(),
+    }
+}
+
+pub(crate) struct ProfileSample {
+    started: Option<(Arc<Profiler>, String, Instant)>,
+    op: &'static str,
+    bytes: u64,
+}
+
+impl ProfileSample {
+    pub(crate) fn bytes(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+    }
+}
+
+impl Drop for ProfileSample {
+    fn drop(&mut self) {
+        let Some((profiler, caller, started)) = self.started.take() else {
+            return;
+        };
+        let mut entries = profiler.entries.lock().unwrap();
+        let entry = entries.entry((caller, self.op)).or_default();
+        entry.count += 1;
+        entry.time += started.elapsed();
+        entry.bytes += self.bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicUsize;
+
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+    use tracing_core::span::Current;
+
+    use super::*;
+    use crate::tokio_fs::TokioFloppyDisk;
+    use crate::FloppyDisk;
+
+    /// Just enough of a subscriber for spans to be current.
+    #[derive(Default)]
+    struct SpanStack {
+        next: AtomicUsize,
+        spans: Mutex<Vec<(Id, &'static Metadata<'static>)>>,
+        entered: Mutex<Vec<Id>>,
+    }
+
+    impl Subscriber for SpanStack {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let id = Id::from_u64(self.next.fetch_add(1, Ordering::Relaxed) as u64 + 1);
+            let mut spans = self.spans.lock().unwrap();
+            spans.push((id.clone(), span.metadata()));
+            id
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, span: &Id) {
+            self.entered.lock().unwrap().push(span.clone());
+        }
+
+        fn exit(&self, _: &Id) {
+            self.entered.lock().unwrap().pop();
+        }
+
+        fn current_span(&self) -> Current {
+            let entered = self.entered.lock().unwrap();
+            let spans = self.spans.lock().unwrap();
+            match entered.last() {
+                Some(id) => {
+                    let (_, metadata) = spans.iter().find(|(span, _)| span == id).unwrap();
+                    Current::new(id.clone(), metadata)
+                }
+                None => Current::none(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_profiler() -> std::io::Result<()> {
+        let _guard = tracing::subscriber::set_default(SpanStack::default());
+        let profiler = Arc::new(Profiler::new());
+        let fs = TokioFloppyDisk::new(Some(PathBuf::from("/tmp"))).with_profiler(profiler.clone());
+        let path = format!("/floppy-profile-{}", rand::random::<u64>());
+
+        {
+            let _span = tracing::info_span!("uploads").entered();
+            fs.write(&path, "asdf").await?;
+            fs.read(&path).await?;
+            fs.read(&path).await?;
+        }
+        fs.remove_file(&path).await?;
+
+        let entries = profiler.entries();
+        let reads = &entries[&("floppy_disk::profile::tests::uploads".to_string(), "read")];
+        assert_eq!(2, reads.count);
+        assert_eq!(8, reads.bytes);
+        assert!(entries.contains_key(&("(no span)".to_string(), "remove_file")));
+        assert!(profiler
+            .folded()
+            .lines()
+            .any(|line| line.starts_with("floppy_disk::profile::tests::uploads;write ")));
+
+        let profiler = Arc::new(Profiler::new().with_sample_every(2));
+        let fs = TokioFloppyDisk::new(None).with_profiler(profiler.clone());
+        for _ in 0..4 {
+            fs.metadata("/tmp").await?;
+        }
+        assert_eq!(
+            2,
+            profiler.entries()[&("(no span)".to_string(), "metadata")].count
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1241 @@
+//! Immutable copies of in-memory disks, for serving fixed trees of files to
+//! many tasks at once. See [`MemFloppyDisk::seal`](crate::mem::MemFloppyDisk::seal).
+//!
+//! Sealed disks can be baked into archives, eg. at build time, and loaded
+//! again without copying any file contents out of the archive:
+//!
+//! ```text
+//! magic "FLOPSEAL", version: u32, entries: u64, index length: u64
+//! index: per entry, path length: u32, path, kind: u8, mode: u32, uid: u32,
+//!        gid: u32, offset: u64 and length: u64 of its data in the arena
+//! arena: file contents and symlink targets, back to back
+//! ```
+//!
+//! Integers are little-endian.
+
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io::{Error, ErrorKind, Result, SeekFrom};
+use std::ops::Range;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::diff::EntryKind;
+use crate::mem::NEXT_DEV;
+use crate::*;
+
+/// How many symlinks are followed resolving one path before giving up, as
+/// on Linux.
+const MAX_SYMLINKS: usize = 40;
+
+const ARCHIVE_MAGIC: &[u8; 8] = b"FLOPSEAL";
+const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub(crate) struct SealedNode {
+    pub(crate) kind: SealedKind,
+    pub(crate) mode: u32,
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+}
+
+#[derive(Debug)]
+pub(crate) enum SealedKind {
+    Dir,
+    File(Contents),
+    Symlink(PathBuf),
+}
+
+/// A file's contents: either its own buffer, or a range of a loaded
+/// archive's.
+#[derive(Clone)]
+pub(crate) struct Contents {
+    buf: Arc<dyn AsRef<[u8]> + Send + Sync>,
+    range: Range<usize>,
+}
+
+impl Contents {
+    pub(crate) fn owned(contents: Vec<u8>) -> Self {
+        let range = 0..contents.len();
+        Self {
+            buf: Arc::new(contents),
+            range,
+        }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &(*self.buf).as_ref()[self.range.clone()]
+    }
+
+    fn len(&self) -> usize {
+        self.range.len()
+    }
+}
+
+impl fmt::Debug for Contents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Contents")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct SealedTree {
+    /// Every entry by absolute path, each with its inode number.
+    nodes: BTreeMap<PathBuf, (u64, SealedNode)>,
+    dev: u64,
+    sealed_at: SystemTime,
+}
+
+/// A read-only disk whose contents can never change. Reads take no locks,
+/// and clones share the same contents, so it can be handed to any number of
+/// tasks. Anything that would change it fails with
+/// [`ErrorKind::ReadOnlyFilesystem`].
+///
+/// Every entry's times are when it was sealed.
+#[derive(Debug, Clone)]
+pub struct SealedFloppyDisk {
+    tree: Arc<SealedTree>,
+}
+
+impl SealedFloppyDisk {
+    pub(crate) fn new(nodes: BTreeMap<PathBuf, SealedNode>, dev: u64) -> Self {
+        let nodes = nodes
+            .into_iter()
+            .enumerate()
+            .map(|(ino, (path, node))| (path, (ino as u64 + 1, node)))
+            .collect();
+        Self {
+            tree: Arc::new(SealedTree {
+                nodes,
+                dev,
+                sealed_at: SystemTime::now(),
+            }),
+        }
+    }
+
+    /// Find the entry at `path`, following symlinks on the way there, and
+    /// at the end too if `follow` is set.
+    fn resolve(&self, path: &Path, follow: bool) -> Result<(PathBuf, &(u64, SealedNode))> {
+        let mut resolved = PathBuf::from("/");
+        let mut pending: Vec<OsString> = components(path);
+        pending.reverse();
+        let mut followed = 0;
+        while let Some(name) = pending.pop() {
+            if name == ".." {
+                resolved.pop();
+                continue;
+            }
+            resolved.push(&name);
+            let (_, node) = self
+                .tree
+                .nodes
+                .get(&resolved)
+                .ok_or_else(|| not_found(path))?;
+            let SealedKind::Symlink(target) = &node.kind else {
+                continue;
+            };
+            if pending.is_empty() && !follow {
+                break;
+            }
+            followed += 1;
+            if followed > MAX_SYMLINKS {
+                return Err(Error::from_raw_os_error(libc::ELOOP));
+            }
+            resolved.pop();
+            if target.is_absolute() {
+                resolved = PathBuf::from("/");
+            }
+            pending.extend(components(target).into_iter().rev());
+        }
+        let entry = self
+            .tree
+            .nodes
+            .get(&resolved)
+            .ok_or_else(|| not_found(path))?;
+        Ok((resolved, entry))
+    }
+
+    fn metadata_of(&self, (ino, node): &(u64, SealedNode)) -> SealedMetadata {
+        let (kind, len) = match &node.kind {
+            SealedKind::Dir => (EntryKind::Dir, 0),
+            SealedKind::File(contents) => (EntryKind::File, contents.len() as u64),
+            SealedKind::Symlink(target) => (EntryKind::Symlink, target.as_os_str().len() as u64),
+        };
+        SealedMetadata {
+            kind,
+            len,
+            mode: node.mode,
+            uid: node.uid,
+            gid: node.gid,
+            dev: self.tree.dev,
+            ino: *ino,
+            time: self.tree.sealed_at,
+        }
+    }
+
+    fn contents(&self, path: &Path) -> Result<Contents> {
+        let (_, (_, node)) = self.resolve(path, true)?;
+        match &node.kind {
+            SealedKind::File(contents) => Ok(contents.clone()),
+            _ => Err(Error::new(
+                ErrorKind::IsADirectory,
+                format!("{} is a directory", path.display()),
+            )),
+        }
+    }
+}
+
+impl SealedFloppyDisk {
+    /// Bake the disk into an archive, with an index of every entry followed
+    /// by all of their contents in one contiguous arena. Load it again with
+    /// [`SealedFloppyDisk::load_archive`].
+    pub fn to_archive(&self) -> Vec<u8> {
+        let mut index = vec![];
+        let mut arena = vec![];
+        for (path, (_, node)) in &self.tree.nodes {
+            let (kind, data) = match &node.kind {
+                SealedKind::Dir => (0u8, &[][..]),
+                SealedKind::File(contents) => (1, contents.as_slice()),
+                SealedKind::Symlink(target) => (2, target.as_os_str().as_bytes()),
+            };
+            let path = path.as_os_str().as_bytes();
+            index.extend_from_slice(&(path.len() as u32).to_le_bytes());
+            index.extend_from_slice(path);
+            index.push(kind);
+            for field in [node.mode, node.uid, node.gid] {
+                index.extend_from_slice(&field.to_le_bytes());
+            }
+            index.extend_from_slice(&(arena.len() as u64).to_le_bytes());
+            index.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            arena.extend_from_slice(data);
+        }
+
+        let mut archive = Vec::with_capacity(28 + index.len() + arena.len());
+        archive.extend_from_slice(ARCHIVE_MAGIC);
+        archive.extend_from_slice(&ARCHIVE_VERSION.to_le_bytes());
+        archive.extend_from_slice(&(self.tree.nodes.len() as u64).to_le_bytes());
+        archive.extend_from_slice(&(index.len() as u64).to_le_bytes());
+        archive.extend_from_slice(&index);
+        archive.extend_from_slice(&arena);
+        archive
+    }
+
+    /// Load an archive made by [`SealedFloppyDisk::to_archive`]. Files are
+    /// read straight out of `archive` rather than copied, so it can be eg. a
+    /// `&'static [u8]` from `include_bytes!`, or a memory-mapped file.
+    pub fn load_archive(archive: impl AsRef<[u8]> + Send + Sync + 'static) -> Result<Self> {
+        let buf: Arc<dyn AsRef<[u8]> + Send + Sync> = Arc::new(archive);
+        let mut reader = ArchiveReader {
+            bytes: (*buf).as_ref(),
+            position: 0,
+        };
+        if reader.take(8)? != ARCHIVE_MAGIC {
+            return Err(invalid_archive("not a sealed disk archive"));
+        }
+        let version = reader.u32()?;
+        if version != ARCHIVE_VERSION {
+            return Err(invalid_archive(&format!(
+                "unsupported archive version {version}"
+            )));
+        }
+        let entries = reader.u64()?;
+        let arena_start = reader
+            .usize()?
+            .checked_add(reader.position)
+            .ok_or_else(|| invalid_archive("index is too long"))?;
+
+        let mut nodes = BTreeMap::new();
+        for _ in 0..entries {
+            let path_len =
+                usize::try_from(reader.u32()?).map_err(|_| invalid_archive("path is too long"))?;
+            let path = PathBuf::from(OsStr::from_bytes(reader.take(path_len)?));
+            let kind = reader.take(1)?[0];
+            let (mode, uid, gid) = (reader.u32()?, reader.u32()?, reader.u32()?);
+            let (offset, len) = (reader.usize()?, reader.usize()?);
+            let range = arena_start
+                .checked_add(offset)
+                .and_then(|start| Some(start..start.checked_add(len)?))
+                .ok_or_else(|| invalid_archive("entry is out of range"))?;
+            let Some(data) = reader.bytes.get(range.clone()) else {
+                return Err(invalid_archive("truncated archive"));
+            };
+            let kind = match kind {
+                0 => SealedKind::Dir,
+                1 => SealedKind::File(Contents {
+                    buf: buf.clone(),
+                    range,
+                }),
+                2 => SealedKind::Symlink(PathBuf::from(OsStr::from_bytes(data))),
+                _ => return Err(invalid_archive(&format!("unknown entry kind {kind}"))),
+            };
+            nodes.insert(
+                path,
+                SealedNode {
+                    kind,
+                    mode,
+                    uid,
+                    gid,
+                },
+            );
+        }
+        if !nodes.contains_key(Path::new("/")) {
+            return Err(invalid_archive("archive has no root directory"));
+        }
+        Ok(Self::new(nodes, NEXT_DEV.fetch_add(1, Ordering::Relaxed)))
+    }
+}
+
+struct ArchiveReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ArchiveReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .position
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.position..end))
+            .ok_or_else(|| invalid_archive("truncated archive"))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// A length or offset, which has to fit in memory.
+    fn usize(&mut self) -> Result<usize> {
+        usize::try_from(self.u64()?).map_err(|_| invalid_archive("length is out of range"))
+    }
+}
+
+fn invalid_archive(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, reason.to_string())
+}
+
+/// The names in `path`, with `.` dropped and the root ignored.
+fn components(path: &Path) -> Vec<OsString> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_os_string()),
+            Component::ParentDir => Some(OsString::from("..")),
+            _ => None,
+        })
+        .collect()
+}
+
+fn not_found(path: &Path) -> Error {
+    Error::new(
+        ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+}
+
+fn read_only() -> Error {
+    Error::new(ErrorKind::ReadOnlyFilesystem, "sealed disks are read-only")
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyDisk<'a> for SealedFloppyDisk {
+    type DirBuilder = SealedDirBuilder;
+    type DirEntry = SealedDirEntry;
+    type File = SealedFile;
+    type FileType = SealedFileType;
+    type Metadata = SealedMetadata;
+    type OpenOptions = SealedOpenOptions;
+    type Permissions = SealedPermissions;
+    type ReadDir = SealedReadDir;
+
+    async fn canonicalize<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        Ok(self.resolve(path.as_ref(), true)?.0)
+    }
+
+    async fn copy<P: AsRef<Path> + Send>(&self, _from: P, _to: P) -> Result<u64> {
+        Err(read_only())
+    }
+
+    async fn create_dir<P: AsRef<Path> + Send>(&self, _path: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn create_dir_all<P: AsRef<Path> + Send>(&self, _path: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn hard_link<P: AsRef<Path> + Send>(&self, _src: P, _dst: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        let (_, entry) = self.resolve(path.as_ref(), true)?;
+        Ok(self.metadata_of(entry))
+    }
+
+    async fn read<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>> {
+        Ok(self.contents(path.as_ref())?.as_slice().to_vec())
+    }
+
+    async fn read_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::ReadDir> {
+        let (dir, (_, node)) = self.resolve(path.as_ref(), true)?;
+        if !matches!(node.kind, SealedKind::Dir) {
+            return Err(Error::new(
+                ErrorKind::NotADirectory,
+                format!("{} is not a directory", path.as_ref().display()),
+            ));
+        }
+        // Everything under a directory sorts straight after it.
+        let entries: Vec<_> = self
+            .tree
+            .nodes
+            .range(dir.clone()..)
+            .skip(1)
+            .take_while(|(child, _)| child.starts_with(&dir))
+            .filter(|(child, _)| child.parent() == Some(&dir))
+            .map(|(child, entry)| SealedDirEntry {
+                path: path.as_ref().join(child.file_name().unwrap()),
+                metadata: self.metadata_of(entry),
+            })
+            .collect();
+        Ok(SealedReadDir(entries.into_iter()))
+    }
+
+    async fn read_link<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        let (_, (_, node)) = self.resolve(path.as_ref(), false)?;
+        match &node.kind {
+            SealedKind::Symlink(target) => Ok(target.clone()),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a symlink", path.as_ref().display()),
+            )),
+        }
+    }
+
+    async fn read_to_string<P: AsRef<Path> + Send>(&self, path: P) -> Result<String> {
+        String::from_utf8(self.read(path).await?).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    async fn remove_dir<P: AsRef<Path> + Send>(&self, _path: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn remove_dir_all<P: AsRef<Path> + Send>(&self, _path: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn remove_file<P: AsRef<Path> + Send>(&self, _path: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn rename<P: AsRef<Path> + Send>(&self, _from: P, _to: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        _path: P,
+        _perm: Self::Permissions,
+    ) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn symlink<P: AsRef<Path> + Send>(&self, _src: P, _dst: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn symlink_metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        let (_, entry) = self.resolve(path.as_ref(), false)?;
+        Ok(self.metadata_of(entry))
+    }
+
+    async fn try_exists<P: AsRef<Path> + Send>(&self, path: P) -> Result<bool> {
+        match self.resolve(path.as_ref(), true) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn write<P: AsRef<Path> + Send>(
+        &self,
+        _path: P,
+        _contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn new_dir_builder(&'a self) -> Self::DirBuilder {
+        SealedDirBuilder
+    }
+}
+
+#[async_trait::async_trait]
+impl FloppyDiskUnixExt for SealedFloppyDisk {
+    async fn chown<P: Into<PathBuf> + Send>(&self, _path: P, _uid: u32, _gid: u32) -> Result<()> {
+        Err(read_only())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SealedMetadata {
+    kind: EntryKind,
+    len: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    dev: u64,
+    ino: u64,
+    time: SystemTime,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyMetadata<'a, SealedFloppyDisk> for SealedMetadata {
+    fn file_type(&self) -> SealedFileType {
+        SealedFileType(self.kind)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Dir
+    }
+
+    fn is_file(&self) -> bool {
+        self.kind == EntryKind::File
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.kind == EntryKind::Symlink
+    }
+
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn permissions(&self) -> SealedPermissions {
+        SealedPermissions { mode: self.mode }
+    }
+
+    fn modified(&self) -> Result<SystemTime> {
+        Ok(self.time)
+    }
+
+    fn accessed(&self) -> Result<SystemTime> {
+        Ok(self.time)
+    }
+
+    fn created(&self) -> Result<SystemTime> {
+        Ok(self.time)
+    }
+}
+
+impl FloppyUnixMetadata for SealedMetadata {
+    fn uid(&self) -> Result<u32> {
+        Ok(self.uid)
+    }
+
+    fn gid(&self) -> Result<u32> {
+        Ok(self.gid)
+    }
+
+    fn dev(&self) -> Result<u64> {
+        Ok(self.dev)
+    }
+
+    fn ino(&self) -> Result<u64> {
+        Ok(self.ino)
+    }
+
+    fn blocks(&self) -> Result<u64> {
+        Ok(self.len.div_ceil(512))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SealedFileType(EntryKind);
+
+impl FloppyFileType for SealedFileType {
+    fn is_dir(&self) -> bool {
+        self.0 == EntryKind::Dir
+    }
+
+    fn is_file(&self) -> bool {
+        self.0 == EntryKind::File
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.0 == EntryKind::Symlink
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealedPermissions {
+    mode: u32,
+}
+
+impl FloppyPermissions for SealedPermissions {
+    fn readonly(&self) -> bool {
+        self.mode & 0o222 == 0
+    }
+
+    fn set_readonly(&mut self, readonly: bool) {
+        if readonly {
+            self.mode &= !0o222;
+        } else {
+            self.mode |= 0o222;
+        }
+    }
+}
+
+impl FloppyUnixPermissions for SealedPermissions {
+    fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    fn set_mode(&mut self, mode: u32) {
+        self.mode = mode;
+    }
+
+    fn from_mode(mode: u32) -> Self {
+        Self { mode }
+    }
+}
+
+#[derive(Debug)]
+pub struct SealedDirEntry {
+    path: PathBuf,
+    metadata: SealedMetadata,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyDirEntry<'a, SealedFloppyDisk> for SealedDirEntry {
+    fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn file_name(&self) -> OsString {
+        self.path.file_name().unwrap().to_os_string()
+    }
+
+    async fn metadata(&self) -> Result<SealedMetadata> {
+        Ok(self.metadata.clone())
+    }
+
+    async fn file_type(&self) -> Result<SealedFileType> {
+        Ok(SealedFileType(self.metadata.kind))
+    }
+
+    #[cfg(unix)]
+    fn ino(&self) -> u64 {
+        self.metadata.ino
+    }
+}
+
+#[derive(Debug)]
+pub struct SealedReadDir(std::vec::IntoIter<SealedDirEntry>);
+
+#[async_trait::async_trait]
+impl<'a> FloppyReadDir<'a, SealedFloppyDisk> for SealedReadDir {
+    async fn next_entry(&mut self) -> Result<Option<SealedDirEntry>> {
+        Ok(self.0.next())
+    }
+}
+
+/// Directories can't be created on a sealed disk, so this only fails.
+#[derive(Debug)]
+pub struct SealedDirBuilder;
+
+#[async_trait::async_trait]
+impl FloppyDirBuilder for SealedDirBuilder {
+    fn recursive(&mut self, _recursive: bool) -> &mut Self {
+        self
+    }
+
+    async fn create<P: AsRef<Path> + Send>(&self, _path: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    #[cfg(unix)]
+    fn mode(&mut self, _mode: u32) -> &mut Self {
+        self
+    }
+}
+
+/// Files can only be opened for reading; asking for anything else fails.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SealedOpenOptions {
+    write: bool,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyOpenOptions<'a, SealedFloppyDisk> for SealedOpenOptions {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(self, _read: bool) -> Self {
+        self
+    }
+
+    fn write(mut self, write: bool) -> Self {
+        self.write |= write;
+        self
+    }
+
+    fn append(mut self, append: bool) -> Self {
+        self.write |= append;
+        self
+    }
+
+    fn truncate(mut self, truncate: bool) -> Self {
+        self.write |= truncate;
+        self
+    }
+
+    fn create(mut self, create: bool) -> Self {
+        self.write |= create;
+        self
+    }
+
+    fn create_new(mut self, create_new: bool) -> Self {
+        self.write |= create_new;
+        self
+    }
+
+    async fn open<P: AsRef<Path> + Send>(
+        &self,
+        disk: &'a SealedFloppyDisk,
+        path: P,
+    ) -> Result<SealedFile> {
+        if self.write {
+            return Err(read_only());
+        }
+        let (_, entry) = disk.resolve(path.as_ref(), true)?;
+        Ok(SealedFile {
+            contents: disk.contents(path.as_ref())?,
+            metadata: disk.metadata_of(entry),
+            position: 0,
+        })
+    }
+}
+
+/// An open file on a sealed disk. Its contents are shared with the disk,
+/// not copied.
+#[derive(Debug)]
+pub struct SealedFile {
+    contents: Contents,
+    metadata: SealedMetadata,
+    position: u64,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyFile<'a, SealedFloppyDisk> for SealedFile {
+    async fn sync_all(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn sync_data(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn set_len(&mut self, _size: u64) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn metadata(&self) -> Result<SealedMetadata> {
+        Ok(self.metadata.clone())
+    }
+
+    async fn try_clone(&'a self) -> Result<Box<SealedFile>> {
+        Ok(Box::new(SealedFile {
+            contents: self.contents.clone(),
+            metadata: self.metadata.clone(),
+            position: self.position,
+        }))
+    }
+
+    async fn set_permissions(&self, _perm: SealedPermissions) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn permissions(&self) -> Result<SealedPermissions> {
+        Ok(self.metadata.permissions())
+    }
+
+    async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let contents = self.contents.as_slice();
+        let start = (offset as usize).min(contents.len());
+        let read = buf.len().min(contents.len() - start);
+        buf[..read].copy_from_slice(&contents[start..start + read]);
+        Ok(read)
+    }
+
+    async fn write_at(&self, _buf: &[u8], _offset: u64) -> Result<usize> {
+        Err(read_only())
+    }
+
+    async fn allocate(&self, _offset: u64, _len: u64) -> Result<()> {
+        Err(read_only())
+    }
+}
+
+impl AsyncRead for SealedFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        let contents = this.contents.as_slice();
+        let start = (this.position as usize).min(contents.len());
+        let read = buf.remaining().min(contents.len() - start);
+        buf.put_slice(&contents[start..start + read]);
+        this.position += read as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for SealedFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> Result<()> {
+        let this = self.get_mut();
+        let (base, offset) = match position {
+            SeekFrom::Start(offset) => {
+                this.position = offset;
+                return Ok(());
+            }
+            SeekFrom::End(offset) => (this.contents.len() as u64, offset),
+            SeekFrom::Current(offset) => (this.position, offset),
+        };
+        this.position = base
+            .checked_add_signed(offset)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "seek to a negative position"))?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+impl AsyncWrite for SealedFile {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, _buf: &[u8]) -> Poll<Result<usize>> {
+        Poll::Ready(Err(read_only()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use std::os::fd::AsRawFd;
+
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+    use crate::temp::TempFloppyDisk;
+
+    #[tokio::test]
+    async fn test_sealed_disk() -> Result<()> {
+        let mem = MemFloppyDisk::new();
+        mem.create_dir_all("/assets/css").await?;
+        mem.write("/assets/index.html", "<html>").await?;
+        mem.write("/assets/css/site.css", "body {}").await?;
+        mem.symlink("css/site.css", "/assets/style.css").await?;
+        mem.symlink("/assets/css", "/assets/styles").await?;
+        mem.set_permissions(
+            "/assets/index.html",
+            FloppyUnixPermissions::from_mode(0o644),
+        )
+        .await?;
+        let sealed = mem.seal().await?;
+        // Sealing copies, so the original can carry on changing.
+        mem.write("/assets/index.html", "changed").await?;
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let sealed = sealed.clone();
+                tokio::spawn(async move { sealed.read_to_string("/assets/style.css").await })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!("body {}", task.await??);
+        }
+
+        assert_eq!("<html>", sealed.read_to_string("/assets/index.html").await?);
+        assert_eq!(
+            "body {}",
+            sealed
+                .read_to_string("/assets/styles/../css/site.css")
+                .await?
+        );
+        let metadata = sealed.metadata("/assets/index.html").await?;
+        assert_eq!(0o644, metadata.permissions().mode() & 0o777);
+        assert!(sealed
+            .symlink_metadata("/assets/styles")
+            .await?
+            .is_symlink());
+        assert!(sealed.metadata("/assets/styles").await?.is_dir());
+        assert_eq!(
+            PathBuf::from("/assets/css/site.css"),
+            sealed.canonicalize("/assets/style.css").await?
+        );
+
+        let mut names = vec![];
+        let mut read_dir = sealed.read_dir("/assets").await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        assert_eq!(vec!["css", "index.html", "style.css", "styles"], names);
+
+        let mut file = SealedOpenOptions::new()
+            .read(true)
+            .open(&sealed, "/assets/index.html")
+            .await?;
+        file.seek(SeekFrom::Start(2)).await?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await?;
+        assert_eq!("tml>", contents);
+
+        let denied = sealed.write("/assets/new", "").await.unwrap_err();
+        assert_eq!(ErrorKind::ReadOnlyFilesystem, denied.kind());
+        assert!(SealedOpenOptions::new()
+            .write(true)
+            .open(&sealed, "/assets/index.html")
+            .await
+            .is_err());
+        assert!(!sealed.try_exists("/missing").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sealed_symlinks() -> Result<()> {
+        let mem = MemFloppyDisk::new();
+        mem.create_dir_all("/assets/css").await?;
+        mem.write("/assets/css/site.css", "body {}").await?;
+        mem.symlink("/loop-b", "/loop-a").await?;
+        mem.symlink("/loop-a", "/loop-b").await?;
+        mem.symlink("self", "/self").await?;
+        mem.symlink("../../../assets/css/site.css", "/assets/css/escape")
+            .await?;
+        mem.symlink("site.css", "/assets/css/linked.css").await?;
+        mem.symlink("/missing", "/dangling").await?;
+        let sealed = mem.seal().await?;
+
+        for looped in ["/loop-a", "/loop-b", "/self", "/self/child"] {
+            let error = sealed.read(looped).await.unwrap_err();
+            assert_eq!(Some(libc::ELOOP), error.raw_os_error(), "{looped}");
+        }
+        let error = sealed.try_exists("/loop-a").await.unwrap_err();
+        assert_eq!(Some(libc::ELOOP), error.raw_os_error());
+        // Looking at a looping link, rather than through it, is fine.
+        assert!(sealed.symlink_metadata("/loop-a").await?.is_symlink());
+        assert_eq!(PathBuf::from("/loop-b"), sealed.read_link("/loop-a").await?);
+
+        // `..` stops at the root, as it does on a real filesystem.
+        assert_eq!(
+            "body {}",
+            sealed.read_to_string("/assets/css/escape").await?
+        );
+        assert_eq!(
+            "body {}",
+            sealed.read_to_string("/../../assets/css/site.css").await?
+        );
+        // Links within links are followed the whole way.
+        assert_eq!(
+            PathBuf::from("/assets/css/site.css"),
+            sealed.canonicalize("/assets/css/linked.css").await?
+        );
+
+        let error = sealed.read("/dangling").await.unwrap_err();
+        assert_eq!(ErrorKind::NotFound, error.kind());
+        assert!(!sealed.try_exists("/dangling").await?);
+        assert!(sealed.symlink_metadata("/dangling").await?.is_symlink());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sealed_concurrent_files() -> Result<()> {
+        let mem = MemFloppyDisk::new();
+        mem.write("/a.txt", "0123456789").await?;
+        let sealed = mem.seal().await?;
+
+        let mut file = SealedOpenOptions::new()
+            .read(true)
+            .open(&sealed, "/a.txt")
+            .await?;
+        file.seek(SeekFrom::Start(4)).await?;
+        let mut clone = file.try_clone().await?;
+        // Clones start where the original was, then move on their own.
+        let mut buf = [0; 3];
+        clone.read_exact(&mut buf).await?;
+        assert_eq!(b"456", &buf);
+        assert_eq!(7, clone.stream_position().await?);
+        let mut other = SealedOpenOptions::new()
+            .read(true)
+            .open(&sealed, "/a.txt")
+            .await?;
+        other.read_exact(&mut buf).await?;
+        assert_eq!(b"012", &buf);
+        assert_eq!(4, file.stream_position().await?);
+
+        // Reads at an offset leave the cursor alone, and stop at the end.
+        let mut buf = [0; 8];
+        assert_eq!(4, file.read_at(&mut buf, 6).await?);
+        assert_eq!(b"6789", &buf[..4]);
+        assert_eq!(0, file.read_at(&mut buf, 100).await?);
+        assert_eq!(4, file.stream_position().await?);
+        file.seek(SeekFrom::End(-2)).await?;
+        let mut rest = String::new();
+        file.read_to_string(&mut rest).await?;
+        assert_eq!("89", rest);
+        let error = file.seek(SeekFrom::Current(-100)).await.unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, error.kind());
+
+        let sealed = Arc::new(sealed);
+        let tasks: Vec<_> = (0..16u64)
+            .map(|i| {
+                let sealed = sealed.clone();
+                tokio::spawn(async move {
+                    let mut file = SealedOpenOptions::new()
+                        .read(true)
+                        .open(&*sealed, "/a.txt")
+                        .await?;
+                    file.seek(SeekFrom::Start(i % 10)).await?;
+                    let mut contents = String::new();
+                    file.read_to_string(&mut contents).await?;
+                    Ok::<_, Error>((i, contents))
+                })
+            })
+            .collect();
+        for task in tasks {
+            let (i, contents) = task.await??;
+            assert_eq!(&"0123456789"[(i % 10) as usize..], contents);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sealed_errors() -> Result<()> {
+        let mem = MemFloppyDisk::new();
+        mem.create_dir("/dir").await?;
+        mem.write("/dir/a.txt", "asdf").await?;
+        mem.symlink("a.txt", "/dir/link").await?;
+        let sealed = mem.seal().await?;
+
+        let error = sealed.read("/dir").await.unwrap_err();
+        assert_eq!(ErrorKind::IsADirectory, error.kind());
+        let error = sealed.read_dir("/dir/a.txt").await.unwrap_err();
+        assert_eq!(ErrorKind::NotADirectory, error.kind());
+        let error = sealed.read_link("/dir/a.txt").await.unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, error.kind());
+        for missing in ["/missing", "/dir/missing", "/dir/a.txt/child"] {
+            let error = sealed.metadata(missing).await.unwrap_err();
+            assert_eq!(ErrorKind::NotFound, error.kind(), "{missing}");
+        }
+        let error = SealedOpenOptions::new()
+            .read(true)
+            .open(&sealed, "/missing")
+            .await
+            .unwrap_err();
+        assert_eq!(ErrorKind::NotFound, error.kind());
+        let error = SealedOpenOptions::new()
+            .read(true)
+            .open(&sealed, "/dir")
+            .await
+            .unwrap_err();
+        assert_eq!(ErrorKind::IsADirectory, error.kind());
+
+        let errors = [
+            sealed.create_dir("/new").await.unwrap_err(),
+            sealed.remove_file("/dir/a.txt").await.unwrap_err(),
+            sealed.remove_dir_all("/dir").await.unwrap_err(),
+            sealed.rename("/dir/a.txt", "/b.txt").await.unwrap_err(),
+            sealed.symlink("/dir", "/other").await.unwrap_err(),
+            sealed.hard_link("/dir/a.txt", "/b.txt").await.unwrap_err(),
+            sealed.copy("/dir/a.txt", "/b.txt").await.unwrap_err(),
+            sealed
+                .set_permissions("/dir/a.txt", FloppyUnixPermissions::from_mode(0o600))
+                .await
+                .unwrap_err(),
+            sealed.chown("/dir/a.txt", 1, 1).await.unwrap_err(),
+        ];
+        for error in errors {
+            assert_eq!(ErrorKind::ReadOnlyFilesystem, error.kind());
+        }
+        // Asking to write fails even for files that are already there.
+        let options = SealedOpenOptions::new().read(true);
+        for options in [
+            options.write(true),
+            options.append(true),
+            options.truncate(true),
+            options.create(true),
+            options.create_new(true),
+        ] {
+            let error = options.open(&sealed, "/dir/link").await.unwrap_err();
+            assert_eq!(ErrorKind::ReadOnlyFilesystem, error.kind());
+        }
+
+        let mut file = options.open(&sealed, "/dir/link").await?;
+        assert_eq!(
+            ErrorKind::ReadOnlyFilesystem,
+            file.write_at(b"x", 0).await.unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::ReadOnlyFilesystem,
+            file.set_len(0).await.unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::ReadOnlyFilesystem,
+            file.write_all(b"x").await.unwrap_err().kind()
+        );
+        // Nothing changed underneath.
+        assert_eq!("asdf", sealed.read_to_string("/dir/a.txt").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_archive() -> Result<()> {
+        let mem = MemFloppyDisk::new();
+        mem.create_dir_all("/assets/css").await?;
+        mem.write("/assets/index.html", "<html>").await?;
+        mem.write("/assets/css/site.css", "body {}").await?;
+        mem.symlink("css/site.css", "/assets/style.css").await?;
+        mem.set_permissions(
+            "/assets/index.html",
+            FloppyUnixPermissions::from_mode(0o600),
+        )
+        .await?;
+        let archive = mem.seal().await?.to_archive();
+
+        // Loading borrows from the archive, as it would from include_bytes!.
+        let loaded = SealedFloppyDisk::load_archive(&*Vec::leak(archive.clone()))?;
+        assert_eq!("<html>", loaded.read_to_string("/assets/index.html").await?);
+        assert_eq!("body {}", loaded.read_to_string("/assets/style.css").await?);
+        let metadata = loaded.metadata("/assets/index.html").await?;
+        assert_eq!(0o600, metadata.permissions().mode() & 0o777);
+        assert_eq!(6, metadata.len());
+        assert_eq!(
+            PathBuf::from("css/site.css"),
+            loaded.read_link("/assets/style.css").await?
+        );
+        assert_eq!(archive, loaded.to_archive());
+
+        let owned = SealedFloppyDisk::load_archive(archive.clone())?;
+        assert!(owned.metadata("/assets/css").await?.is_dir());
+
+        for corrupt in [
+            &archive[..archive.len() - 1],
+            &archive[..20],
+            b"not an archive",
+        ] {
+            let error = SealedFloppyDisk::load_archive(corrupt.to_vec()).unwrap_err();
+            assert_eq!(ErrorKind::InvalidData, error.kind());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_malformed_archive() -> Result<()> {
+        let mem = MemFloppyDisk::new();
+        mem.write("/a.txt", "asdf").await?;
+        let archive = mem.seal().await?.to_archive();
+        // The index length, and the first entry's offset and length, which
+        // is the root directory's.
+        let index_len = 20..28;
+        let path_len = u32::from_le_bytes(archive[28..32].try_into().unwrap()) as usize;
+        let offset = 32 + path_len + 13;
+        let (offset, len) = (offset..offset + 8, offset + 8..offset + 16);
+
+        for (field, value) in [
+            (index_len.clone(), u64::MAX),
+            (index_len, u64::MAX - 27),
+            (offset.clone(), u64::MAX),
+            (len.clone(), u64::MAX),
+            (len, 1 << 40),
+        ] {
+            let mut corrupt = archive.clone();
+            corrupt[field].copy_from_slice(&value.to_le_bytes());
+            let error = SealedFloppyDisk::load_archive(corrupt).unwrap_err();
+            assert_eq!(ErrorKind::InvalidData, error.kind());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_archive_memory_mapped() -> Result<()> {
+        /// A read-only mapping of a whole file.
+        struct Mapped(*mut libc::c_void, usize);
+
+        // The mapping is never written to, and only unmapped on drop.
+        unsafe impl Send for Mapped {}
+        unsafe impl Sync for Mapped {}
+
+        impl AsRef<[u8]> for Mapped {
+            fn as_ref(&self) -> &[u8] {
+                unsafe { std::slice::from_raw_parts(self.0 as *const u8, self.1) }
+            }
+        }
+
+        impl Drop for Mapped {
+            fn drop(&mut self) {
+                unsafe { libc::munmap(self.0, self.1) };
+            }
+        }
+
+        let mem = MemFloppyDisk::new();
+        mem.create_dir("/assets").await?;
+        mem.write("/assets/index.html", "<html>").await?;
+        let archive = mem.seal().await?.to_archive();
+        let temp = TempFloppyDisk::new().await?;
+        temp.write("/disk.sealed", &archive).await?;
+
+        let file = std::fs::File::open(temp.path().join("disk.sealed"))?;
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                archive.len(),
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        let loaded = SealedFloppyDisk::load_archive(Mapped(ptr, archive.len()))?;
+        // The mapping outlives both the file and its directory.
+        drop(file);
+        temp.close().await?;
+        assert_eq!("<html>", loaded.read_to_string("/assets/index.html").await?);
+        assert_eq!(archive, loaded.to_archive());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,688 @@
+use std::ffi::OsString;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use futures::FutureExt;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use tracing::debug;
+
+use crate::mem::{
+    MemDirBuilder, MemDirEntry, MemFile, MemFileType, MemFloppyDisk, MemMetadata, MemOpenOptions,
+    MemPermissions, MemReadDir,
+};
+use crate::*;
+
+/// An in-memory workspace for private keys, tokens, and the like. File
+/// contents are overwritten with zeroes before they're let go of: when the
+/// file is removed, renamed over, rewritten or truncated, and when the
+/// workspace is [closed](SecretWorkspace::close) or
+/// [`SecretWorkspace::wipe`]d.
+///
+/// Nothing is ever written to the real filesystem, but the memory isn't
+/// locked, so it can still be swapped out. A file that grows past its buffer
+/// is moved to a bigger one, and the old one is freed without being zeroed,
+/// so write each secret in one go.
+///
+/// Only the [`FloppyDisk`] operations are exposed, not the rest of
+/// [`MemFloppyDisk`]'s API, so the contents can't be snapshotted, sealed or
+/// exported somewhere `wipe` can't reach. Hard links aren't supported either,
+/// so that zeroing a file can't reach through to another name for it.
+#[derive(Debug)]
+pub struct SecretWorkspace {
+    disk: MemFloppyDisk,
+    closed: bool,
+}
+
+impl SecretWorkspace {
+    pub fn new() -> Self {
+        Self {
+            disk: MemFloppyDisk::new(),
+            closed: false,
+        }
+    }
+
+    /// Zero and remove every file in the workspace, leaving it empty.
+    pub async fn wipe(&self) -> Result<()> {
+        let mut entries = vec![];
+        let mut read_dir = self.disk.read_dir("/").await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            entries.push(entry.path());
+        }
+        for path in entries {
+            if self.disk.symlink_metadata(&path).await?.is_dir() {
+                self.remove_dir_all(&path).await?;
+            } else {
+                self.remove_file(&path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Wipe the workspace and drop it. Dropping it without closing it only
+    /// wipes it if that can be done without waiting, so prefer this.
+    pub async fn close(mut self) -> Result<()> {
+        self.closed = true;
+        self.wipe().await
+    }
+}
+
+impl SecretWorkspace {
+    /// Every file at or under `path`, opened so that they can still be
+    /// zeroed after they've been removed or replaced. Symlinks aren't
+    /// followed.
+    async fn open_files(&self, path: &Path) -> Result<Vec<MemFile>> {
+        let mut files = vec![];
+        let mut pending = vec![path.to_path_buf()];
+        while let Some(path) = pending.pop() {
+            let metadata = match self.disk.symlink_metadata(&path).await {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            if metadata.is_file() {
+                let file = <MemOpenOptions as FloppyOpenOptions<MemFloppyDisk>>::new()
+                    .write(true)
+                    .open(&self.disk, &path)
+                    .await?;
+                files.push(file);
+            } else if metadata.is_dir() {
+                let mut read_dir = self.disk.read_dir(&path).await?;
+                while let Some(entry) = read_dir.next_entry().await? {
+                    pending.push(entry.path());
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Zero the file at `path` from `offset` on, before it's cut short or
+    /// rewritten in place, unless it's the same file as `unless`.
+    async fn zero_in_place(&self, path: &Path, offset: u64, unless: Option<&Path>) -> Result<()> {
+        match self.disk.metadata(path).await {
+            Ok(metadata) if metadata.is_file() => {}
+            _ => return Ok(()),
+        }
+        if let Some(unless) = unless {
+            if same_file(&self.disk, path, unless).await? {
+                return Ok(());
+            }
+        }
+        let file = <MemOpenOptions as FloppyOpenOptions<MemFloppyDisk>>::new()
+            .write(true)
+            .open(&self.disk, path)
+            .await?;
+        zero(&file, offset).await
+    }
+}
+
+/// Overwrite `file` with zeroes from `offset` to its end, without resizing
+/// it.
+async fn zero(file: &MemFile, offset: u64) -> Result<()> {
+    let len = FloppyFile::<MemFloppyDisk>::metadata(file).await?.len();
+    if offset < len {
+        debug!("zeroing {} bytes", len - offset);
+        FloppyFile::<MemFloppyDisk>::write_at(file, &vec![0; (len - offset) as usize], offset)
+            .await?;
+    }
+    Ok(())
+}
+
+impl Default for SecretWorkspace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SecretWorkspace {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        // Blocking on the wipe could deadlock a current-thread runtime, so
+        // it's only polled once. The in-memory disk doesn't normally wait.
+        match self.wipe().now_or_never() {
+            Some(Ok(())) => {}
+            Some(Err(e)) => debug!("failed to wipe secret workspace: {e}"),
+            None => debug!("secret workspace was dropped without being wiped"),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyDisk<'a> for SecretWorkspace {
+    type DirBuilder = MemDirBuilder<'a>;
+    type DirEntry = SecretDirEntry;
+    type File = SecretFile;
+    type FileType = MemFileType;
+    type Metadata = SecretMetadata;
+    type OpenOptions = SecretOpenOptions;
+    type Permissions = MemPermissions;
+    type ReadDir = SecretReadDir;
+
+    async fn canonicalize<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        self.disk.canonicalize(path).await
+    }
+
+    async fn copy<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<u64> {
+        self.zero_in_place(to.as_ref(), 0, Some(from.as_ref()))
+            .await?;
+        self.disk.copy(from, to).await
+    }
+
+    async fn create_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.disk.create_dir(path).await
+    }
+
+    async fn create_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.disk.create_dir_all(path).await
+    }
+
+    async fn hard_link<P: AsRef<Path> + Send>(&self, _src: P, _dst: P) -> Result<()> {
+        Err(unsupported("hard links"))
+    }
+
+    async fn metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        self.disk.metadata(path).await.map(SecretMetadata)
+    }
+
+    async fn read<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>> {
+        self.disk.read(path).await
+    }
+
+    async fn read_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::ReadDir> {
+        self.disk.read_dir(path).await.map(SecretReadDir)
+    }
+
+    async fn read_link<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        self.disk.read_link(path).await
+    }
+
+    async fn read_to_string<P: AsRef<Path> + Send>(&self, path: P) -> Result<String> {
+        self.disk.read_to_string(path).await
+    }
+
+    async fn remove_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.disk.remove_dir(path).await
+    }
+
+    async fn remove_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let files = self.open_files(path.as_ref()).await?;
+        self.disk.remove_dir_all(path).await?;
+        for file in files {
+            zero(&file, 0).await?;
+        }
+        Ok(())
+    }
+
+    async fn remove_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let metadata = self.disk.symlink_metadata(path.as_ref()).await?;
+        let files = if metadata.is_file() {
+            self.open_files(path.as_ref()).await?
+        } else {
+            vec![]
+        };
+        self.disk.remove_file(path).await?;
+        for file in files {
+            zero(&file, 0).await?;
+        }
+        Ok(())
+    }
+
+    async fn rename<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let replaced = match self.disk.symlink_metadata(to).await {
+            Ok(metadata) if metadata.is_file() && !same_file(&self.disk, from, to).await? => {
+                self.open_files(to).await?
+            }
+            _ => vec![],
+        };
+        self.disk.rename(from, to).await?;
+        for file in replaced {
+            zero(&file, 0).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        perm: Self::Permissions,
+    ) -> Result<()> {
+        self.disk.set_permissions(path, perm).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        self.disk.symlink(src, dst).await
+    }
+
+    async fn symlink_metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        self.disk.symlink_metadata(path).await.map(SecretMetadata)
+    }
+
+    async fn try_exists<P: AsRef<Path> + Send>(&self, path: P) -> Result<bool> {
+        self.disk.try_exists(path).await
+    }
+
+    async fn write<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        self.zero_in_place(path.as_ref(), 0, None).await?;
+        self.disk.write(path, contents).await
+    }
+
+    async fn truncate<P: AsRef<Path> + Send>(&'a self, path: P, len: u64) -> Result<()> {
+        self.zero_in_place(path.as_ref(), len, None).await?;
+        self.disk.truncate(path, len).await
+    }
+
+    async fn write_atomic<P: AsRef<Path> + Send>(
+        &'a self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        let replaced = self.open_files(path.as_ref()).await?;
+        self.disk.write_atomic(path, contents).await?;
+        for file in replaced {
+            zero(&file, 0).await?;
+        }
+        Ok(())
+    }
+
+    async fn append<P: AsRef<Path> + Send>(
+        &'a self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        self.disk.append(path, contents).await
+    }
+
+    fn new_dir_builder(&'a self) -> Self::DirBuilder {
+        self.disk.new_dir_builder()
+    }
+}
+
+#[async_trait::async_trait]
+impl FloppyDiskUnixExt for SecretWorkspace {
+    async fn chown<P: Into<PathBuf> + Send>(&self, path: P, uid: u32, gid: u32) -> Result<()> {
+        self.disk.chown(path, uid, gid).await
+    }
+}
+
+#[derive(Debug)]
+pub struct SecretMetadata(MemMetadata);
+
+impl<'a> FloppyMetadata<'a, SecretWorkspace> for SecretMetadata {
+    fn file_type(&self) -> MemFileType {
+        FloppyMetadata::<MemFloppyDisk>::file_type(&self.0)
+    }
+
+    fn is_dir(&self) -> bool {
+        FloppyMetadata::<MemFloppyDisk>::is_dir(&self.0)
+    }
+
+    fn is_file(&self) -> bool {
+        FloppyMetadata::<MemFloppyDisk>::is_file(&self.0)
+    }
+
+    fn is_symlink(&self) -> bool {
+        FloppyMetadata::<MemFloppyDisk>::is_symlink(&self.0)
+    }
+
+    fn len(&self) -> u64 {
+        FloppyMetadata::<MemFloppyDisk>::len(&self.0)
+    }
+
+    fn permissions(&self) -> MemPermissions {
+        FloppyMetadata::<MemFloppyDisk>::permissions(&self.0)
+    }
+
+    fn modified(&self) -> Result<SystemTime> {
+        FloppyMetadata::<MemFloppyDisk>::modified(&self.0)
+    }
+
+    fn accessed(&self) -> Result<SystemTime> {
+        FloppyMetadata::<MemFloppyDisk>::accessed(&self.0)
+    }
+
+    fn created(&self) -> Result<SystemTime> {
+        FloppyMetadata::<MemFloppyDisk>::created(&self.0)
+    }
+}
+
+impl FloppyUnixMetadata for SecretMetadata {
+    fn uid(&self) -> Result<u32> {
+        self.0.uid()
+    }
+
+    fn gid(&self) -> Result<u32> {
+        self.0.gid()
+    }
+
+    fn dev(&self) -> Result<u64> {
+        self.0.dev()
+    }
+
+    fn ino(&self) -> Result<u64> {
+        self.0.ino()
+    }
+
+    fn blocks(&self) -> Result<u64> {
+        self.0.blocks()
+    }
+}
+
+#[derive(Debug)]
+pub struct SecretReadDir(MemReadDir);
+
+#[async_trait::async_trait]
+impl<'a> FloppyReadDir<'a, SecretWorkspace> for SecretReadDir {
+    async fn next_entry(&mut self) -> Result<Option<SecretDirEntry>> {
+        let entry = FloppyReadDir::<MemFloppyDisk>::next_entry(&mut self.0).await?;
+        Ok(entry.map(SecretDirEntry))
+    }
+}
+
+#[derive(Debug)]
+pub struct SecretDirEntry(MemDirEntry);
+
+#[async_trait::async_trait]
+impl<'a> FloppyDirEntry<'a, SecretWorkspace> for SecretDirEntry {
+    fn path(&self) -> PathBuf {
+        FloppyDirEntry::<MemFloppyDisk>::path(&self.0)
+    }
+
+    fn file_name(&self) -> OsString {
+        FloppyDirEntry::<MemFloppyDisk>::file_name(&self.0)
+    }
+
+    async fn metadata(&self) -> Result<SecretMetadata> {
+        FloppyDirEntry::<MemFloppyDisk>::metadata(&self.0)
+            .await
+            .map(SecretMetadata)
+    }
+
+    async fn file_type(&self) -> Result<MemFileType> {
+        FloppyDirEntry::<MemFloppyDisk>::file_type(&self.0).await
+    }
+
+    #[cfg(unix)]
+    fn ino(&self) -> u64 {
+        FloppyDirEntry::<MemFloppyDisk>::ino(&self.0)
+    }
+}
+
+/// Opening a file with [`truncate`](FloppyOpenOptions::truncate) zeroes it
+/// first.
+#[derive(Debug, Copy, Clone)]
+pub struct SecretOpenOptions {
+    options: MemOpenOptions,
+    truncate: bool,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyOpenOptions<'a, SecretWorkspace> for SecretOpenOptions {
+    fn new() -> Self {
+        Self {
+            options: FloppyOpenOptions::<MemFloppyDisk>::new(),
+            truncate: false,
+        }
+    }
+
+    fn read(mut self, read: bool) -> Self {
+        self.options = FloppyOpenOptions::<MemFloppyDisk>::read(self.options, read);
+        self
+    }
+
+    fn write(mut self, write: bool) -> Self {
+        self.options = FloppyOpenOptions::<MemFloppyDisk>::write(self.options, write);
+        self
+    }
+
+    fn append(mut self, append: bool) -> Self {
+        self.options = FloppyOpenOptions::<MemFloppyDisk>::append(self.options, append);
+        self
+    }
+
+    fn truncate(mut self, truncate: bool) -> Self {
+        self.options = FloppyOpenOptions::<MemFloppyDisk>::truncate(self.options, truncate);
+        self.truncate = truncate;
+        self
+    }
+
+    fn create(mut self, create: bool) -> Self {
+        self.options = FloppyOpenOptions::<MemFloppyDisk>::create(self.options, create);
+        self
+    }
+
+    fn create_new(mut self, create_new: bool) -> Self {
+        self.options = FloppyOpenOptions::<MemFloppyDisk>::create_new(self.options, create_new);
+        self
+    }
+
+    async fn open<P: AsRef<Path> + Send>(
+        &self,
+        disk: &'a SecretWorkspace,
+        path: P,
+    ) -> Result<SecretFile> {
+        if self.truncate {
+            disk.zero_in_place(path.as_ref(), 0, None).await?;
+        }
+        self.options.open(&disk.disk, path).await.map(SecretFile)
+    }
+}
+
+#[derive(Debug)]
+pub struct SecretFile(MemFile);
+
+#[async_trait::async_trait]
+impl<'a> FloppyFile<'a, SecretWorkspace> for SecretFile {
+    async fn sync_all(&mut self) -> Result<()> {
+        FloppyFile::<MemFloppyDisk>::sync_all(&mut self.0).await
+    }
+
+    async fn sync_data(&mut self) -> Result<()> {
+        FloppyFile::<MemFloppyDisk>::sync_data(&mut self.0).await
+    }
+
+    /// Anything cut off the end is zeroed first.
+    async fn set_len(&mut self, size: u64) -> Result<()> {
+        zero(&self.0, size).await?;
+        FloppyFile::<MemFloppyDisk>::set_len(&mut self.0, size).await
+    }
+
+    async fn metadata(&self) -> Result<SecretMetadata> {
+        FloppyFile::<MemFloppyDisk>::metadata(&self.0)
+            .await
+            .map(SecretMetadata)
+    }
+
+    async fn try_clone(&'a self) -> Result<Box<SecretFile>> {
+        let file = FloppyFile::<MemFloppyDisk>::try_clone(&self.0).await?;
+        Ok(Box::new(SecretFile(*file)))
+    }
+
+    async fn set_permissions(&self, perm: MemPermissions) -> Result<()> {
+        FloppyFile::<MemFloppyDisk>::set_permissions(&self.0, perm).await
+    }
+
+    async fn permissions(&self) -> Result<MemPermissions> {
+        FloppyFile::<MemFloppyDisk>::permissions(&self.0).await
+    }
+
+    async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        FloppyFile::<MemFloppyDisk>::read_at(&self.0, buf, offset).await
+    }
+
+    async fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        FloppyFile::<MemFloppyDisk>::write_at(&self.0, buf, offset).await
+    }
+
+    async fn allocate(&self, offset: u64, len: u64) -> Result<()> {
+        FloppyFile::<MemFloppyDisk>::allocate(&self.0, offset, len).await
+    }
+}
+
+impl AsyncRead for SecretFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for SecretFile {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.0.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+    }
+}
+
+impl AsyncSeek for SecretFile {
+    fn start_seek(self: Pin<&mut Self>, position: std::io::SeekFrom) -> Result<()> {
+        Pin::new(&mut self.get_mut().0).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        Pin::new(&mut self.get_mut().0).poll_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wipe() -> Result<()> {
+        let fs = SecretWorkspace::new();
+        fs.create_dir_all("/keys/a").await?;
+        fs.write("/keys/a/id_ed25519", "very secret").await?;
+        fs.write("/token", "also secret").await?;
+        fs.symlink("/token", "/link").await?;
+
+        // Keep a handle open to see the contents after they're zeroed.
+        let mut file = SecretOpenOptions::new()
+            .read(true)
+            .open(&fs, "/token")
+            .await?;
+        fs.wipe().await?;
+
+        let mut contents = vec![];
+        tokio::io::AsyncReadExt::read_to_end(&mut file, &mut contents).await?;
+        assert_eq!(vec![0; 11], contents);
+        assert!(fs.read_dir("/").await?.next_entry().await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zeroed_when_replaced() -> Result<()> {
+        async fn open(fs: &SecretWorkspace, path: &str) -> Result<SecretFile> {
+            SecretOpenOptions::new().read(true).open(fs, path).await
+        }
+
+        async fn read_all(mut file: SecretFile) -> Result<Vec<u8>> {
+            let mut contents = vec![];
+            tokio::io::AsyncReadExt::read_to_end(&mut file, &mut contents).await?;
+            Ok(contents)
+        }
+
+        let fs = SecretWorkspace::new();
+        fs.create_dir("/keys").await?;
+        for path in ["/a", "/b", "/c", "/keys/d"] {
+            fs.write(path, "secret").await?;
+        }
+
+        let a = open(&fs, "/a").await?;
+        fs.remove_file("/a").await?;
+        assert_eq!(vec![0; 6], read_all(a).await?);
+
+        let b = open(&fs, "/b").await?;
+        fs.rename("/c", "/b").await?;
+        assert_eq!(vec![0; 6], read_all(b).await?);
+        assert_eq!("secret", fs.read_to_string("/b").await?);
+        fs.rename("/b", "/b").await?;
+        assert_eq!("secret", fs.read_to_string("/b").await?);
+
+        let b = open(&fs, "/b").await?;
+        fs.write_atomic("/b", "new").await?;
+        assert_eq!(vec![0; 6], read_all(b).await?);
+
+        let d = open(&fs, "/keys/d").await?;
+        fs.remove_dir_all("/keys").await?;
+        assert_eq!(vec![0; 6], read_all(d).await?);
+
+        let err = fs.hard_link("/b", "/e").await.unwrap_err();
+        assert_eq!(ErrorKind::Unsupported, err.kind());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_close() -> Result<()> {
+        let fs = SecretWorkspace::new();
+        fs.write("/token", "secret").await?;
+        let mut file = SecretOpenOptions::new()
+            .read(true)
+            .open(&fs, "/token")
+            .await?;
+        fs.close().await?;
+
+        let mut contents = vec![];
+        tokio::io::AsyncReadExt::read_to_end(&mut file, &mut contents).await?;
+        assert_eq!(vec![0; 6], contents);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_drop_in_runtime() -> Result<()> {
+        let fs = SecretWorkspace::new();
+        fs.write("/token", "secret").await?;
+        let mut file = SecretOpenOptions::new()
+            .read(true)
+            .open(&fs, "/token")
+            .await?;
+        drop(fs);
+
+        let mut contents = vec![];
+        tokio::io::AsyncReadExt::read_to_end(&mut file, &mut contents).await?;
+        assert_eq!(vec![0; 6], contents);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_without_runtime() -> Result<()> {
+        let fs = SecretWorkspace::new();
+        futures::executor::block_on(fs.write("/token", "secret"))?;
+        drop(fs);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,428 @@
+//! Serving static files from a [`FloppyDisk`] over HTTP, in the style of
+//! `tower-http`'s `ServeDir`.
+//!
+//! [`FloppyServeDir`] is a tower [`Service`], so it can be used as a fallback
+//! service in axum:
+//!
+//! ```ignore
+//! let assets = FloppyServeDir::new(Arc::new(disk), "/assets");
+//! let app = axum::Router::new().fallback_service(assets);
+//! ```
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::io::{ErrorKind, Result};
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use http::header::{self, HeaderMap, HeaderValue};
+use http::{Method, Request, Response, StatusCode};
+use http_body_util::Full;
+use tower_service::Service;
+use tracing::debug;
+
+use crate::{FloppyDirEntry, FloppyDisk, FloppyFileType, FloppyMetadata, FloppyReadDir};
+
+/// Serves files under `root` on a disk, with support for conditional
+/// requests (`If-None-Match`, `If-Modified-Since`), single byte ranges, and
+/// `index.html` for directories.
+#[derive(Debug)]
+pub struct FloppyServeDir<D> {
+    disk: Arc<D>,
+    root: PathBuf,
+    listings: bool,
+}
+
+impl<D> Clone for FloppyServeDir<D> {
+    fn clone(&self) -> Self {
+        Self {
+            disk: self.disk.clone(),
+            root: self.root.clone(),
+            listings: self.listings,
+        }
+    }
+}
+
+impl<D> FloppyServeDir<D> {
+    pub fn new<P: Into<PathBuf>>(disk: Arc<D>, root: P) -> Self {
+        Self {
+            disk,
+            root: root.into(),
+            listings: false,
+        }
+    }
+
+    /// Respond with a listing of directories that don't have an
+    /// `index.html`, instead of a 404.
+    pub fn with_listings(mut self, listings: bool) -> Self {
+        self.listings = listings;
+        self
+    }
+}
+
+impl<D, B> Service<Request<B>> for FloppyServeDir<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    type Response = Response<Full<Bytes>>;
+    type Error = Infallible;
+    type Future =
+        Pin<Box<dyn Future<Output = std::result::Result<Self::Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<std::result::Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let this = self.clone();
+        let method = req.method().clone();
+        let uri_path = req.uri().path().to_string();
+        let headers = req.headers().clone();
+        Box::pin(async move {
+            let response = match this.serve(&method, &uri_path, &headers).await {
+                Ok(response) => response,
+                Err(e) => {
+                    debug!("failed to serve {uri_path}: {e}");
+                    let status = match e.kind() {
+                        ErrorKind::NotFound | ErrorKind::NotADirectory => StatusCode::NOT_FOUND,
+                        ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+                        _ => StatusCode::INTERNAL_SERVER_ERROR,
+                    };
+                    empty(status)
+                }
+            };
+            Ok(response)
+        })
+    }
+}
+
+impl<D> FloppyServeDir<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    async fn serve(
+        &self,
+        method: &Method,
+        uri_path: &str,
+        headers: &HeaderMap,
+    ) -> Result<Response<Full<Bytes>>> {
+        if method != Method::GET && method != Method::HEAD {
+            let mut response = empty(StatusCode::METHOD_NOT_ALLOWED);
+            response
+                .headers_mut()
+                .insert(header::ALLOW, HeaderValue::from_static("GET, HEAD"));
+            return Ok(response);
+        }
+        let Some(mut path) = self.resolve(uri_path) else {
+            return Ok(empty(StatusCode::NOT_FOUND));
+        };
+
+        let mut metadata = self.disk.metadata(&path).await?;
+        if metadata.is_dir() {
+            if !uri_path.ends_with('/') {
+                let mut response = empty(StatusCode::TEMPORARY_REDIRECT);
+                let location = HeaderValue::try_from(format!("{uri_path}/"))
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?;
+                response.headers_mut().insert(header::LOCATION, location);
+                return Ok(response);
+            }
+            let index = path.join("index.html");
+            match self.disk.metadata(&index).await {
+                Ok(index_metadata) => {
+                    path = index;
+                    metadata = index_metadata;
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound && self.listings => {
+                    return self.listing(method, uri_path, &path).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let len = metadata.len();
+        let modified = metadata.modified().ok();
+        let etag = etag(len, modified);
+        if not_modified(headers, &etag, modified) {
+            let mut response = empty(StatusCode::NOT_MODIFIED);
+            response.headers_mut().insert(header::ETAG, etag);
+            return Ok(response);
+        }
+
+        let (status, start, end) = match headers
+            .get(header::RANGE)
+            .map(|range| parse_range(range, len))
+        {
+            None | Some(Range::Ignored) => (StatusCode::OK, 0, len),
+            Some(Range::Bytes(start, end)) => (StatusCode::PARTIAL_CONTENT, start, end),
+            Some(Range::Unsatisfiable) => {
+                let mut response = empty(StatusCode::RANGE_NOT_SATISFIABLE);
+                response.headers_mut().insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::try_from(format!("bytes */{len}")).unwrap(),
+                );
+                return Ok(response);
+            }
+        };
+
+        let mut body = vec![];
+        if method == Method::GET {
+            body = self.disk.read_range(&path, start, end - start).await?;
+        }
+
+        let mut response = Response::new(Full::new(Bytes::from(body)));
+        *response.status_mut() = status;
+        let response_headers = response.headers_mut();
+        let mime = mime_guess::from_path(&path).first_or_octet_stream();
+        response_headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::try_from(mime.as_ref()).unwrap(),
+        );
+        response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(end - start));
+        response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        response_headers.insert(header::ETAG, etag);
+        if let Some(modified) = modified {
+            response_headers.insert(
+                header::LAST_MODIFIED,
+                HeaderValue::try_from(httpdate::fmt_http_date(modified)).unwrap(),
+            );
+        }
+        if status == StatusCode::PARTIAL_CONTENT {
+            response_headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::try_from(format!("bytes {start}-{}/{len}", end - 1)).unwrap(),
+            );
+        }
+        Ok(response)
+    }
+
+    async fn listing(
+        &self,
+        method: &Method,
+        uri_path: &str,
+        dir: &Path,
+    ) -> Result<Response<Full<Bytes>>> {
+        let mut names = vec![];
+        let mut read_dir = self.disk.read_dir(dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let mut name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type().await?.is_dir() {
+                name.push('/');
+            }
+            names.push(name);
+        }
+        names.sort();
+
+        let title = escape_html(uri_path);
+        let mut html = format!("<!DOCTYPE html>\n<title>{title}</title>\n<h1>{title}</h1>\n<ul>\n");
+        for name in names {
+            let name = escape_html(&name);
+            html.push_str(&format!("<li><a href=\"{name}\">{name}</a></li>\n"));
+        }
+        html.push_str("</ul>\n");
+
+        let len = html.len();
+        let body = if method == Method::GET {
+            Bytes::from(html)
+        } else {
+            Bytes::new()
+        };
+        let mut response = Response::new(Full::new(body));
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        response
+            .headers_mut()
+            .insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+        Ok(response)
+    }
+
+    /// The path on the disk for a request path, or `None` if it would escape
+    /// the root.
+    fn resolve(&self, uri_path: &str) -> Option<PathBuf> {
+        let decoded = percent_decode(uri_path)?;
+        let mut path = self.root.clone();
+        for component in Path::new(&decoded).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir | Component::Prefix(_) => return None,
+            }
+        }
+        Some(path)
+    }
+}
+
+enum Range {
+    /// A half-open range of bytes.
+    Bytes(u64, u64),
+    Unsatisfiable,
+    /// Malformed or multipart ranges, which get the whole file instead.
+    Ignored,
+}
+
+fn parse_range(value: &HeaderValue, len: u64) -> Range {
+    let Some(spec) = value.to_str().ok().and_then(|v| v.strip_prefix("bytes=")) else {
+        return Range::Ignored;
+    };
+    if spec.contains(',') {
+        return Range::Ignored;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Range::Ignored;
+    };
+    let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => (start, end.saturating_add(1).min(len)),
+        (Ok(start), Err(_)) if end.is_empty() => (start, len),
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => (len.saturating_sub(suffix), len),
+        (Err(_), Ok(_)) if start.is_empty() => return Range::Unsatisfiable,
+        _ => return Range::Ignored,
+    };
+    if start >= len {
+        return Range::Unsatisfiable;
+    }
+    Range::Bytes(start, end)
+}
+
+fn etag(len: u64, modified: Option<SystemTime>) -> HeaderValue {
+    let modified = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_nanos())
+        .unwrap_or(0);
+    HeaderValue::try_from(format!("\"{len:x}-{modified:x}\"")).unwrap()
+}
+
+fn not_modified(headers: &HeaderMap, etag: &HeaderValue, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        let Ok(if_none_match) = if_none_match.to_str() else {
+            return false;
+        };
+        return if_none_match.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/").as_bytes() == etag.as_bytes()
+        });
+    }
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| httpdate::parse_http_date(since).ok());
+    match (since, modified) {
+        // HTTP dates only have whole seconds.
+        (Some(since), Some(modified)) => {
+            let secs = |time: SystemTime| {
+                time.duration_since(UNIX_EPOCH)
+                    .map(|since| since.as_secs())
+                    .unwrap_or(0)
+            };
+            secs(modified) <= secs(since)
+        }
+        _ => false,
+    }
+}
+
+fn empty(status: StatusCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::new()));
+    *response.status_mut() = status;
+    response
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    async fn get(
+        service: &mut FloppyServeDir<MemFloppyDisk>,
+        path: &str,
+        headers: &[(header::HeaderName, &str)],
+    ) -> (StatusCode, HeaderMap, Bytes) {
+        let mut req = Request::builder().uri(path);
+        for (name, value) in headers {
+            req = req.header(name, *value);
+        }
+        let response = service.call(req.body(()).unwrap()).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        (parts.status, parts.headers, body)
+    }
+
+    #[tokio::test]
+    async fn test_serve_dir() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/assets/docs").await?;
+        fs.write("/assets/index.html", "<h1>hi</h1>").await?;
+        fs.write("/assets/app.js", "0123456789").await?;
+        fs.write("/assets/docs/a b.txt", "asdf").await?;
+        fs.write("/secret.txt", "nope").await?;
+        let mut service = FloppyServeDir::new(Arc::new(fs), "/assets").with_listings(true);
+
+        let (status, headers, body) = get(&mut service, "/app.js", &[]).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("text/javascript", headers[header::CONTENT_TYPE]);
+        assert_eq!(&b"0123456789"[..], &body[..]);
+
+        let etag = headers[header::ETAG].to_str().unwrap().to_string();
+        let (status, _, body) =
+            get(&mut service, "/app.js", &[(header::IF_NONE_MATCH, &etag)]).await;
+        assert_eq!(StatusCode::NOT_MODIFIED, status);
+        assert!(body.is_empty());
+
+        let (status, headers, body) =
+            get(&mut service, "/app.js", &[(header::RANGE, "bytes=2-4")]).await;
+        assert_eq!(StatusCode::PARTIAL_CONTENT, status);
+        assert_eq!("bytes 2-4/10", headers[header::CONTENT_RANGE]);
+        assert_eq!(&b"234"[..], &body[..]);
+        let (_, _, body) = get(&mut service, "/app.js", &[(header::RANGE, "bytes=-3")]).await;
+        assert_eq!(&b"789"[..], &body[..]);
+        let (status, _, _) = get(&mut service, "/app.js", &[(header::RANGE, "bytes=10-")]).await;
+        assert_eq!(StatusCode::RANGE_NOT_SATISFIABLE, status);
+
+        let (_, _, body) = get(&mut service, "/", &[]).await;
+        assert_eq!(&b"<h1>hi</h1>"[..], &body[..]);
+        let (status, headers, _) = get(&mut service, "/docs", &[]).await;
+        assert_eq!(StatusCode::TEMPORARY_REDIRECT, status);
+        assert_eq!("/docs/", headers[header::LOCATION]);
+        let (status, _, body) = get(&mut service, "/docs/", &[]).await;
+        assert_eq!(StatusCode::OK, status);
+        assert!(String::from_utf8_lossy(&body).contains("a b.txt"));
+        let (_, _, body) = get(&mut service, "/docs/a%20b.txt", &[]).await;
+        assert_eq!(&b"asdf"[..], &body[..]);
+
+        let (status, _, _) = get(&mut service, "/missing", &[]).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        let (status, _, _) = get(&mut service, "/../secret.txt", &[]).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+
+        Ok(())
+    }
+}
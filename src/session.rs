@@ -0,0 +1,399 @@
+//! Keeping two disks in sync continuously, with [`two_way`] syncs whenever
+//! either side changes.
+//!
+//! None of the backends can notify about changes, so both sides are polled.
+//! Each poll walks both trees and compares the paths, sizes and mtimes in
+//! them with the last poll's, which is much cheaper than a sync.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{Error, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{broadcast, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::sync::{two_way, SyncState, TwoWayOptions, TwoWayReport};
+use crate::walk::FloppyWalkDir;
+use crate::{FloppyDisk, FloppyMetadata, FloppyUnixPermissions};
+
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+    poll_interval: Duration,
+    debounce: Duration,
+    two_way: TwoWayOptions,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(1),
+            debounce: Duration::from_secs(2),
+            two_way: TwoWayOptions::default(),
+        }
+    }
+}
+
+impl SessionOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// How long both sides must go unchanged before a sync. Bursts of writes
+    /// are synced once, after they're done.
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    pub fn with_two_way(mut self, two_way: TwoWayOptions) -> Self {
+        self.two_way = two_way;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionStatus {
+    /// Both sides are in sync as of the last poll.
+    Idle,
+    /// Something changed, and will be synced once the debounce is up.
+    Pending,
+    Syncing,
+    /// The last poll or sync failed. It'll be retried on the next poll.
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    /// A sync finished. Syncs that found nothing to do aren't reported.
+    Synced(TwoWayReport),
+    Failed(Arc<Error>),
+}
+
+/// A background task running [`two_way`] syncs between two disks. The
+/// [`SyncState`] it was started with is kept up to date, and handed back by
+/// [`SyncSession::stop`] to be saved for next time.
+pub struct SyncSession {
+    status: watch::Receiver<SessionStatus>,
+    events: broadcast::Sender<SessionEvent>,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<SyncState>,
+}
+
+impl SyncSession {
+    pub fn spawn<S, T>(
+        src: Arc<S>,
+        src_root: impl Into<PathBuf>,
+        dst: Arc<T>,
+        dst_root: impl Into<PathBuf>,
+        state: SyncState,
+        options: SessionOptions,
+    ) -> Self
+    where
+        S: for<'a> FloppyDisk<'a> + Sync + 'static,
+        T: for<'a> FloppyDisk<'a> + Sync + 'static,
+        for<'a> <S as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+        for<'a> <T as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+    {
+        let (status_tx, status) = watch::channel(SessionStatus::Pending);
+        let (events, _) = broadcast::channel(64);
+        let (stop, stop_rx) = oneshot::channel();
+        let task = tokio::spawn(run(
+            src,
+            src_root.into(),
+            dst,
+            dst_root.into(),
+            state,
+            options,
+            status_tx,
+            events.clone(),
+            stop_rx,
+        ));
+        Self {
+            status,
+            events,
+            stop,
+            task,
+        }
+    }
+
+    pub fn status(&self) -> SessionStatus {
+        *self.status.borrow()
+    }
+
+    /// Wait until the status is `status`.
+    pub async fn wait_for(&mut self, status: SessionStatus) {
+        // The sender only goes away when the task does, and then there's
+        // nothing left to wait for.
+        let _ = self.status.wait_for(|s| *s == status).await;
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Stop syncing, after any sync in progress finishes.
+    pub async fn stop(self) -> Result<SyncState> {
+        let _ = self.stop.send(());
+        self.task.await.map_err(Error::other)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run<S, T>(
+    src: Arc<S>,
+    src_root: PathBuf,
+    dst: Arc<T>,
+    dst_root: PathBuf,
+    mut state: SyncState,
+    options: SessionOptions,
+    status: watch::Sender<SessionStatus>,
+    events: broadcast::Sender<SessionEvent>,
+    mut stop: oneshot::Receiver<()>,
+) -> SyncState
+where
+    S: for<'a> FloppyDisk<'a> + Sync + 'static,
+    T: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <S as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+    for<'a> <T as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+{
+    let mut interval = tokio::time::interval(options.poll_interval);
+    // What both sides looked like after the last sync, and on the last poll.
+    let mut synced = None;
+    let mut seen = None;
+    let mut changed_at = Instant::now();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = &mut stop => return state,
+        }
+
+        let current = match fingerprint(&*src, &src_root, &*dst, &dst_root).await {
+            Ok(current) => current,
+            Err(e) => {
+                fail(&status, &events, e);
+                continue;
+            }
+        };
+        if seen != Some(current) {
+            seen = Some(current);
+            changed_at = Instant::now();
+        }
+        if synced == seen {
+            status.send_replace(SessionStatus::Idle);
+            continue;
+        }
+        if changed_at.elapsed() < options.debounce {
+            status.send_replace(SessionStatus::Pending);
+            continue;
+        }
+
+        status.send_replace(SessionStatus::Syncing);
+        let report = two_way(
+            &*src,
+            &src_root,
+            &*dst,
+            &dst_root,
+            &mut state,
+            &options.two_way,
+        )
+        .await;
+        match report {
+            // Nothing was written, so both sides were in sync as of the poll.
+            // Anything written since then will show up on the next one.
+            Ok(report) if report == TwoWayReport::default() => {
+                synced = Some(current);
+                status.send_replace(SessionStatus::Idle);
+            }
+            Ok(report) => {
+                debug!("synced {} and {}", src_root.display(), dst_root.display());
+                let _ = events.send(SessionEvent::Synced(report));
+                // What the sync wrote can't be told apart from what was
+                // written during it, so it's confirmed with another sync on
+                // the next poll, without waiting out the debounce again. That
+                // one finds nothing to do, unless something was written.
+                match fingerprint(&*src, &src_root, &*dst, &dst_root).await {
+                    Ok(current) => (synced, seen) = (None, Some(current)),
+                    Err(e) => fail(&status, &events, e),
+                }
+            }
+            Err(e) => fail(&status, &events, e),
+        }
+    }
+}
+
+fn fail(status: &watch::Sender<SessionStatus>, events: &broadcast::Sender<SessionEvent>, e: Error) {
+    debug!("sync session failed: {e}");
+    status.send_replace(SessionStatus::Failed);
+    let _ = events.send(SessionEvent::Failed(Arc::new(e)));
+}
+
+/// A hash of every path under both roots, with its kind, size and mtime.
+async fn fingerprint<'a, 'b, S, T>(
+    src: &'a S,
+    src_root: &Path,
+    dst: &'b T,
+    dst_root: &Path,
+) -> Result<u64>
+where
+    S: FloppyDisk<'a>,
+    T: FloppyDisk<'b>,
+{
+    let mut hasher = DefaultHasher::new();
+    hash_tree(src, src_root, &mut hasher).await?;
+    hash_tree(dst, dst_root, &mut hasher).await?;
+    Ok(hasher.finish())
+}
+
+async fn hash_tree<'a, D: FloppyDisk<'a>>(
+    disk: &'a D,
+    root: &Path,
+    hasher: &mut DefaultHasher,
+) -> Result<()> {
+    // Roots that don't exist yet are created by the first sync.
+    if !disk.try_exists(root).await? {
+        return Ok(());
+    }
+    let mut entries = vec![];
+    let mut walk = FloppyWalkDir::new(disk, root);
+    while let Some(entry) = walk.next_entry().await? {
+        let metadata = entry.metadata();
+        entries.push((
+            entry.path().to_path_buf(),
+            metadata.is_dir(),
+            metadata.is_symlink(),
+            metadata.len(),
+            metadata.modified()?,
+        ));
+    }
+    // Walks aren't in any particular order.
+    entries.sort();
+    entries.hash(hasher);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_sync_session() -> Result<()> {
+        let (src, dst) = (
+            Arc::new(MemFloppyDisk::new()),
+            Arc::new(MemFloppyDisk::new()),
+        );
+        src.create_dir_all("/src").await?;
+        src.write("/src/a.txt", "asdf").await?;
+
+        let options = SessionOptions::new()
+            .with_poll_interval(Duration::from_millis(5))
+            .with_debounce(Duration::from_millis(20));
+        let mut session = SyncSession::spawn(
+            src.clone(),
+            "/src",
+            dst.clone(),
+            "/dst",
+            SyncState::new(),
+            options,
+        );
+        let mut events = session.subscribe();
+        match events.recv().await.unwrap() {
+            SessionEvent::Synced(report) => {
+                assert_eq!(vec![PathBuf::from("a.txt")], report.copied_to_dst)
+            }
+            event => panic!("unexpected event: {event:?}"),
+        }
+        session.wait_for(SessionStatus::Idle).await;
+        assert_eq!("asdf", dst.read_to_string("/dst/a.txt").await?);
+
+        dst.write("/dst/b.txt", "jkl").await?;
+        match events.recv().await.unwrap() {
+            SessionEvent::Synced(report) => {
+                assert_eq!(vec![PathBuf::from("b.txt")], report.copied_to_src)
+            }
+            event => panic!("unexpected event: {event:?}"),
+        }
+        assert_eq!("jkl", src.read_to_string("/src/b.txt").await?);
+
+        let state = session.stop().await?;
+        assert_ne!(SyncState::new(), state);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_session_write_during_sync() -> Result<()> {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        use crate::sync::{ConflictPolicy, Resolution};
+
+        let (src, dst) = (
+            Arc::new(MemFloppyDisk::new()),
+            Arc::new(MemFloppyDisk::new()),
+        );
+        src.create_dir_all("/src").await?;
+        src.write("/src/a.txt", "ours").await?;
+        dst.create_dir_all("/dst").await?;
+        dst.write("/dst/a.txt", "theirs").await?;
+
+        // The conflict over a.txt is settled in the middle of the sync, which
+        // is when a user writes c.txt.
+        let written = Arc::new(AtomicBool::new(false));
+        let callback = {
+            let (src, written) = (src.clone(), written.clone());
+            move |_: &crate::sync::Conflict| {
+                if !written.swap(true, Ordering::SeqCst) {
+                    futures::executor::block_on(src.write("/src/c.txt", "during")).unwrap();
+                }
+                Resolution::KeepSource
+            }
+        };
+        let options = SessionOptions::new()
+            .with_poll_interval(Duration::from_millis(5))
+            .with_debounce(Duration::from_millis(20))
+            .with_two_way(
+                TwoWayOptions::new()
+                    .with_conflict_policy(ConflictPolicy::Callback(Arc::new(callback))),
+            );
+        let session = SyncSession::spawn(
+            src.clone(),
+            "/src",
+            dst.clone(),
+            "/dst",
+            SyncState::new(),
+            options,
+        );
+        let mut events = session.subscribe();
+        let synced = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match events.recv().await.unwrap() {
+                    SessionEvent::Synced(report)
+                        if report.copied_to_dst.contains(&PathBuf::from("c.txt")) =>
+                    {
+                        break
+                    }
+                    SessionEvent::Synced(_) => {}
+                    event => panic!("unexpected event: {event:?}"),
+                }
+            }
+        })
+        .await;
+        assert!(synced.is_ok(), "c.txt was never synced");
+        assert!(written.load(Ordering::SeqCst));
+        assert_eq!("during", dst.read_to_string("/dst/c.txt").await?);
+        assert_eq!("ours", dst.read_to_string("/dst/a.txt").await?);
+        session.stop().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,508 @@
+//! Serving a [`FloppyDisk`] over SFTP, via `russh-sftp`.
+//!
+//! [`FloppySftp`] is an SFTP subsystem handler. Hand it to
+//! `russh_sftp::server::run` with the channel stream of an SSH session that
+//! requested the `sftp` subsystem:
+//!
+//! ```ignore
+//! async fn subsystem_request(&mut self, channel: ChannelId, name: &str, session: &mut Session) -> Result<(), Self::Error> {
+//!     if name == "sftp" {
+//!         let channel = self.take_channel(channel);
+//!         russh_sftp::server::run(channel.into_stream(), FloppySftp::new(self.disk.clone())).await;
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Clients only ever see the disk, so a scoped disk gives them a sandbox on the
+//! host filesystem.
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use russh_sftp::protocol::{
+    Attrs, Data, File, FileAttributes, FileMode, Handle, Name, OpenFlags, Status, StatusCode,
+    Version,
+};
+use russh_sftp::server::{Handler, StatusReply};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tracing::debug;
+
+use crate::{
+    FloppyDirEntry, FloppyDisk, FloppyFile, FloppyMetadata, FloppyOpenOptions, FloppyReadDir,
+    FloppyUnixMetadata, FloppyUnixPermissions,
+};
+
+type Result<T> = std::result::Result<T, StatusReply>;
+
+/// An SFTP (version 3) handler backed by a [`FloppyDisk`]. Create one per
+/// session.
+#[derive(Debug)]
+pub struct FloppySftp<D> {
+    disk: Arc<D>,
+    handles: HashMap<String, OpenHandle>,
+    next_handle: u64,
+}
+
+#[derive(Debug)]
+enum OpenHandle {
+    File { path: PathBuf, append: bool },
+    Dir { path: PathBuf, read: bool },
+}
+
+impl<D> FloppySftp<D> {
+    pub fn new(disk: Arc<D>) -> Self {
+        Self {
+            disk,
+            handles: HashMap::new(),
+            next_handle: 0,
+        }
+    }
+
+    fn add_handle(&mut self, id: u32, handle: OpenHandle) -> Handle {
+        let name = self.next_handle.to_string();
+        self.next_handle += 1;
+        self.handles.insert(name.clone(), handle);
+        Handle { id, handle: name }
+    }
+
+    fn file_handle(&self, handle: &str) -> Result<(&Path, bool)> {
+        match self.handles.get(handle) {
+            Some(OpenHandle::File { path, append }) => Ok((path, *append)),
+            _ => Err(StatusCode::Failure.with_message(format!("invalid handle {handle}"))),
+        }
+    }
+}
+
+fn sftp_error(e: Error) -> StatusReply {
+    debug!("sftp operation failed: {e}");
+    let status_code = match e.kind() {
+        ErrorKind::NotFound => StatusCode::NoSuchFile,
+        ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+        ErrorKind::Unsupported => StatusCode::OpUnsupported,
+        _ => StatusCode::Failure,
+    };
+    status_code.with_message(e.to_string())
+}
+
+fn ok(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".into(),
+        language_tag: "en-US".into(),
+    }
+}
+
+/// Resolve `path` against `/`, without touching the disk. Clients can't get
+/// above the root with `..`.
+fn resolve(path: &str) -> PathBuf {
+    let mut resolved = PathBuf::from("/");
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    resolved
+}
+
+impl<D> FloppySftp<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+{
+    async fn attrs(&self, path: &Path, follow: bool) -> Result<FileAttributes> {
+        let metadata = if follow {
+            self.disk.metadata(path).await
+        } else {
+            self.disk.symlink_metadata(path).await
+        }
+        .map_err(sftp_error)?;
+        let kind = if metadata.is_symlink() {
+            FileMode::LNK
+        } else if metadata.is_dir() {
+            FileMode::DIR
+        } else {
+            FileMode::REG
+        };
+        let secs = |time: std::io::Result<std::time::SystemTime>| {
+            time.ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|since| since.as_secs() as u32)
+        };
+        Ok(FileAttributes {
+            size: Some(metadata.len()),
+            uid: metadata.uid().ok(),
+            user: None,
+            gid: metadata.gid().ok(),
+            group: None,
+            permissions: Some((metadata.permissions().mode() & 0o7777) | kind.bits()),
+            atime: secs(metadata.accessed()),
+            mtime: secs(metadata.modified()),
+        })
+    }
+
+    async fn open_file<'a, F>(
+        &'a self,
+        path: &Path,
+        options: F,
+    ) -> Result<<D as FloppyDisk<'a>>::File>
+    where
+        F: FnOnce(<D as FloppyDisk<'a>>::OpenOptions) -> <D as FloppyDisk<'a>>::OpenOptions,
+    {
+        options(FloppyOpenOptions::new())
+            .open(&*self.disk, path)
+            .await
+            .map_err(sftp_error)
+    }
+
+    async fn apply(&self, path: &Path, attrs: &FileAttributes) -> Result<()> {
+        if attrs.uid.is_some() || attrs.gid.is_some() {
+            let metadata = self.disk.metadata(path).await.map_err(sftp_error)?;
+            if attrs
+                .uid
+                .is_some_and(|uid| metadata.uid().ok() != Some(uid))
+                || attrs
+                    .gid
+                    .is_some_and(|gid| metadata.gid().ok() != Some(gid))
+            {
+                return Err(
+                    StatusCode::OpUnsupported.with_message("changing ownership is not supported")
+                );
+            }
+        }
+        if let Some(mode) = attrs.permissions {
+            self.disk
+                .set_permissions(path, FloppyUnixPermissions::from_mode(mode & 0o7777))
+                .await
+                .map_err(sftp_error)?;
+        }
+        if let Some(size) = attrs.size {
+            let mut file = self.open_file(path, |options| options.write(true)).await?;
+            file.set_len(size).await.map_err(sftp_error)?;
+        }
+        // Disks can't have their timestamps set, so time changes are dropped.
+        Ok(())
+    }
+}
+
+impl<D> Handler for FloppySftp<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+{
+    type Error = StatusReply;
+
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported.into()
+    }
+
+    async fn init(
+        &mut self,
+        _version: u32,
+        _extensions: HashMap<String, String>,
+    ) -> Result<Version> {
+        Ok(Version::new())
+    }
+
+    async fn open(
+        &mut self,
+        id: u32,
+        filename: String,
+        pflags: OpenFlags,
+        attrs: FileAttributes,
+    ) -> Result<Handle> {
+        let path = resolve(&filename);
+        let created = pflags.contains(OpenFlags::CREATE)
+            && !self.disk.try_exists(&path).await.map_err(sftp_error)?;
+        let file = self
+            .open_file(&path, |options| {
+                options
+                    .read(pflags.contains(OpenFlags::READ))
+                    .write(pflags.contains(OpenFlags::WRITE))
+                    .append(pflags.contains(OpenFlags::APPEND))
+                    .create(pflags.contains(OpenFlags::CREATE))
+                    .create_new(pflags.contains(OpenFlags::CREATE | OpenFlags::EXCLUDE))
+                    .truncate(pflags.contains(OpenFlags::TRUNCATE))
+            })
+            .await?;
+        drop(file);
+        if created {
+            if let Some(mode) = attrs.permissions {
+                self.disk
+                    .set_permissions(&path, FloppyUnixPermissions::from_mode(mode & 0o7777))
+                    .await
+                    .map_err(sftp_error)?;
+            }
+        }
+        let append = pflags.contains(OpenFlags::APPEND);
+        Ok(self.add_handle(id, OpenHandle::File { path, append }))
+    }
+
+    async fn close(&mut self, id: u32, handle: String) -> Result<Status> {
+        match self.handles.remove(&handle) {
+            Some(_) => Ok(ok(id)),
+            None => Err(StatusCode::Failure.with_message(format!("invalid handle {handle}"))),
+        }
+    }
+
+    async fn read(&mut self, id: u32, handle: String, offset: u64, len: u32) -> Result<Data> {
+        let (path, _) = self.file_handle(&handle)?;
+        let data = self
+            .disk
+            .read_range(path, offset, len as u64)
+            .await
+            .map_err(sftp_error)?;
+        if data.is_empty() && len > 0 {
+            return Err(StatusCode::Eof.into());
+        }
+        Ok(Data { id, data })
+    }
+
+    async fn write(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Status> {
+        let (path, append) = self.file_handle(&handle)?;
+        let mut file = self
+            .open_file(path, |options| options.write(true).append(append))
+            .await?;
+        if !append {
+            file.seek(SeekFrom::Start(offset))
+                .await
+                .map_err(sftp_error)?;
+        }
+        file.write_all(&data).await.map_err(sftp_error)?;
+        file.flush().await.map_err(sftp_error)?;
+        Ok(ok(id))
+    }
+
+    async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs> {
+        let attrs = self.attrs(&resolve(&path), false).await?;
+        Ok(Attrs { id, attrs })
+    }
+
+    async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs> {
+        let path = match self.handles.get(&handle) {
+            Some(OpenHandle::File { path, .. } | OpenHandle::Dir { path, .. }) => path.clone(),
+            None => {
+                return Err(StatusCode::Failure.with_message(format!("invalid handle {handle}")))
+            }
+        };
+        let attrs = self.attrs(&path, true).await?;
+        Ok(Attrs { id, attrs })
+    }
+
+    async fn setstat(&mut self, id: u32, path: String, attrs: FileAttributes) -> Result<Status> {
+        self.apply(&resolve(&path), &attrs).await?;
+        Ok(ok(id))
+    }
+
+    async fn fsetstat(&mut self, id: u32, handle: String, attrs: FileAttributes) -> Result<Status> {
+        let path = self.file_handle(&handle)?.0.to_path_buf();
+        self.apply(&path, &attrs).await?;
+        Ok(ok(id))
+    }
+
+    async fn opendir(&mut self, id: u32, path: String) -> Result<Handle> {
+        let path = resolve(&path);
+        if !self
+            .disk
+            .metadata(&path)
+            .await
+            .map_err(sftp_error)?
+            .is_dir()
+        {
+            return Err(
+                StatusCode::Failure.with_message(format!("{} is not a directory", path.display()))
+            );
+        }
+        Ok(self.add_handle(id, OpenHandle::Dir { path, read: false }))
+    }
+
+    /// Directories are listed in one go, followed by an EOF.
+    async fn readdir(&mut self, id: u32, handle: String) -> Result<Name> {
+        let path = match self.handles.get_mut(&handle) {
+            Some(OpenHandle::Dir { read: true, .. }) => return Err(StatusCode::Eof.into()),
+            Some(OpenHandle::Dir { path, read }) => {
+                *read = true;
+                path.clone()
+            }
+            _ => return Err(StatusCode::Failure.with_message(format!("invalid handle {handle}"))),
+        };
+        let mut files = vec![];
+        let mut read_dir = self.disk.read_dir(&path).await.map_err(sftp_error)?;
+        while let Some(entry) = read_dir.next_entry().await.map_err(sftp_error)? {
+            let attrs = self.attrs(&entry.path(), false).await?;
+            files.push(File::new(entry.file_name().to_string_lossy(), attrs));
+        }
+        files.sort_by(|a, b| a.filename.cmp(&b.filename));
+        Ok(Name { id, files })
+    }
+
+    async fn remove(&mut self, id: u32, filename: String) -> Result<Status> {
+        self.disk
+            .remove_file(resolve(&filename))
+            .await
+            .map_err(sftp_error)?;
+        Ok(ok(id))
+    }
+
+    async fn mkdir(&mut self, id: u32, path: String, attrs: FileAttributes) -> Result<Status> {
+        let path = resolve(&path);
+        self.disk.create_dir(&path).await.map_err(sftp_error)?;
+        if let Some(mode) = attrs.permissions {
+            self.disk
+                .set_permissions(&path, FloppyUnixPermissions::from_mode(mode & 0o7777))
+                .await
+                .map_err(sftp_error)?;
+        }
+        Ok(ok(id))
+    }
+
+    async fn rmdir(&mut self, id: u32, path: String) -> Result<Status> {
+        self.disk
+            .remove_dir(resolve(&path))
+            .await
+            .map_err(sftp_error)?;
+        Ok(ok(id))
+    }
+
+    async fn realpath(&mut self, id: u32, path: String) -> Result<Name> {
+        let path = resolve(&path);
+        Ok(Name {
+            id,
+            files: vec![File::dummy(path.to_string_lossy())],
+        })
+    }
+
+    async fn stat(&mut self, id: u32, path: String) -> Result<Attrs> {
+        let attrs = self.attrs(&resolve(&path), true).await?;
+        Ok(Attrs { id, attrs })
+    }
+
+    async fn rename(&mut self, id: u32, oldpath: String, newpath: String) -> Result<Status> {
+        self.disk
+            .rename(resolve(&oldpath), resolve(&newpath))
+            .await
+            .map_err(sftp_error)?;
+        Ok(ok(id))
+    }
+
+    async fn readlink(&mut self, id: u32, path: String) -> Result<Name> {
+        let target = self
+            .disk
+            .read_link(resolve(&path))
+            .await
+            .map_err(sftp_error)?;
+        Ok(Name {
+            id,
+            files: vec![File::dummy(target.to_string_lossy())],
+        })
+    }
+
+    async fn symlink(&mut self, id: u32, linkpath: String, targetpath: String) -> Result<Status> {
+        // OpenSSH sends the arguments in the opposite order to the spec, and
+        // every other client follows it, so `linkpath` is really the target.
+        self.disk
+            .symlink(Path::new(&linkpath), &resolve(&targetpath))
+            .await
+            .map_err(sftp_error)?;
+        Ok(ok(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_sftp_operations() -> std::io::Result<()> {
+        let disk = Arc::new(MemFloppyDisk::new());
+        let mut sftp = FloppySftp::new(disk.clone());
+        let fail = |e: StatusReply| Error::other(format!("{e:?}"));
+
+        let home = sftp.realpath(0, "../a/./..".into()).await.map_err(fail)?;
+        assert_eq!("/", home.files[0].filename);
+
+        let mut attrs = FileAttributes::empty();
+        attrs.permissions = Some(0o700);
+        sftp.mkdir(1, "/a".into(), attrs).await.map_err(fail)?;
+        let handle = sftp
+            .open(
+                2,
+                "a/b.txt".into(),
+                OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+                FileAttributes::empty(),
+            )
+            .await
+            .map_err(fail)?
+            .handle;
+        sftp.write(3, handle.clone(), 0, b"asdf".to_vec())
+            .await
+            .map_err(fail)?;
+        sftp.write(4, handle.clone(), 2, b"jkl".to_vec())
+            .await
+            .map_err(fail)?;
+        let data = sftp.read(5, handle.clone(), 1, 3).await.map_err(fail)?.data;
+        assert_eq!(b"sjk".to_vec(), data);
+        let eof = sftp.read(6, handle.clone(), 5, 3).await.unwrap_err();
+        assert_eq!(StatusCode::Eof, eof.status_code);
+        assert_eq!(
+            Some(5),
+            sftp.fstat(7, handle.clone())
+                .await
+                .map_err(fail)?
+                .attrs
+                .size
+        );
+        sftp.close(8, handle).await.map_err(fail)?;
+        assert_eq!("asjkl", disk.read_to_string("/a/b.txt").await?);
+
+        let attrs = sftp.stat(9, "/a".into()).await.map_err(fail)?.attrs;
+        assert!(attrs.is_dir());
+        assert_eq!(0o700, attrs.permissions.unwrap() & 0o7777);
+
+        sftp.symlink(10, "b.txt".into(), "/a/c".into())
+            .await
+            .map_err(fail)?;
+        let target = sftp.readlink(11, "/a/c".into()).await.map_err(fail)?;
+        assert_eq!("b.txt", target.files[0].filename);
+
+        let dir = sftp.opendir(12, "/a".into()).await.map_err(fail)?.handle;
+        let names: Vec<String> = sftp
+            .readdir(13, dir.clone())
+            .await
+            .map_err(fail)?
+            .files
+            .into_iter()
+            .map(|file| file.filename)
+            .collect();
+        assert_eq!(vec!["b.txt", "c"], names);
+        let eof = sftp.readdir(14, dir.clone()).await.unwrap_err();
+        assert_eq!(StatusCode::Eof, eof.status_code);
+        sftp.close(15, dir).await.map_err(fail)?;
+
+        sftp.rename(16, "/a/b.txt".into(), "/d.txt".into())
+            .await
+            .map_err(fail)?;
+        sftp.remove(17, "/d.txt".into()).await.map_err(fail)?;
+        let missing = sftp.stat(18, "/d.txt".into()).await.unwrap_err();
+        assert_eq!(StatusCode::NoSuchFile, missing.status_code);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+//! Directory listings in a fixed order. Backends list directories in
+//! whatever order they store them in, which differs between them; these
+//! don't.
+
+use std::io::Result;
+use std::path::Path;
+
+use crate::{FloppyDirEntry, FloppyDisk, FloppyReadDir};
+
+/// A directory's entries, sorted by file name. Names are compared bytewise,
+/// so `B` comes before `a`.
+pub struct SortedReadDir<'a, D: FloppyDisk<'a>> {
+    entries: std::vec::IntoIter<D::DirEntry>,
+}
+
+impl<'a, D: FloppyDisk<'a>> SortedReadDir<'a, D> {
+    /// Read all of `read_dir`'s entries, and sort them.
+    pub async fn new(mut read_dir: D::ReadDir) -> Result<Self> {
+        let mut entries = vec![];
+        while let Some(entry) = read_dir.next_entry().await? {
+            entries.push(entry);
+        }
+        entries.sort_by_key(|entry| entry.file_name());
+        Ok(Self {
+            entries: entries.into_iter(),
+        })
+    }
+
+    /// How many entries are left.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.len() == 0
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D: FloppyDisk<'a>> FloppyReadDir<'a, D> for SortedReadDir<'a, D> {
+    async fn next_entry(&mut self) -> Result<Option<D::DirEntry>> {
+        Ok(self.entries.next())
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> std::fmt::Debug for SortedReadDir<'a, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SortedReadDir")
+            .field("entries", &self.entries)
+            .finish()
+    }
+}
+
+/// Like [`FloppyDisk::read_dir`], but in the same order on every backend.
+pub async fn read_dir_sorted<'a, D: FloppyDisk<'a>>(
+    disk: &D,
+    path: impl AsRef<Path> + Send,
+) -> Result<SortedReadDir<'a, D>> {
+    SortedReadDir::new(disk.read_dir(path).await?).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+    use crate::temp::TempFloppyDisk;
+
+    async fn names<'a, D: FloppyDisk<'a>>(disk: &D) -> Result<Vec<String>> {
+        let mut read_dir = read_dir_sorted(disk, "/dir").await?;
+        let mut names = vec![];
+        while let Some(entry) = read_dir.next_entry().await? {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        Ok(names)
+    }
+
+    #[tokio::test]
+    async fn test_read_dir_sorted() -> Result<()> {
+        let mem = MemFloppyDisk::new();
+        let temp = TempFloppyDisk::new().await?;
+        for name in ["b", "a", "C", "c2", "c10"] {
+            mem.create_dir_all("/dir").await?;
+            mem.write(format!("/dir/{name}"), "").await?;
+            temp.create_dir_all("/dir").await?;
+            temp.write(format!("/dir/{name}"), "").await?;
+        }
+        let expected = vec!["C", "a", "b", "c10", "c2"];
+        assert_eq!(expected, names(&mem).await?);
+        assert_eq!(expected, names(&*temp).await?);
+
+        Ok(())
+    }
+}
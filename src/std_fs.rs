@@ -0,0 +1,971 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::fs::{DirBuilder, DirEntry, FileType, Metadata, OpenOptions, Permissions, ReadDir};
+use std::future::Future;
+use std::io::{ErrorKind, IoSlice, Read, Seek, SeekFrom, Write};
+use std::os::unix::prelude::PermissionsExt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use futures::ready;
+use tokio::io::{AsyncWriteExt, ReadBuf};
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::*;
+
+/// A [`FloppyDisk`] backed by `std::fs`. Every call is run on tokio's
+/// blocking thread pool via `spawn_blocking`.
+#[derive(Default, Debug)]
+pub struct StdFloppyDisk;
+
+impl StdFloppyDisk {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await?
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyDisk<'a> for StdFloppyDisk {
+    type DirBuilder = StdDirBuilder;
+    type DirEntry = StdDirEntry;
+    type File = StdFile;
+    type FileType = StdFileType;
+    type Metadata = StdMetadata;
+    type OpenOptions = StdOpenOptions;
+    type Permissions = StdPermissions;
+    type ReadDir = StdReadDir;
+
+    async fn canonicalize<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        let path = path.as_ref().to_path_buf();
+        debug!("canonicalise {}", path.display());
+        blocking(move || std::fs::canonicalize(path)).await
+    }
+
+    async fn copy<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<u64> {
+        check_not_same_file(self, from.as_ref(), to.as_ref()).await?;
+        let (from, to) = (from.as_ref().to_path_buf(), to.as_ref().to_path_buf());
+        debug!("copy {} -> {}", from.display(), to.display());
+        blocking(move || std::fs::copy(from, to)).await
+    }
+
+    async fn create_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        debug!("create_dir {}", path.display());
+        blocking(move || std::fs::create_dir(path)).await
+    }
+
+    async fn create_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        debug!("create_dir_all {}", path.display());
+        blocking(move || std::fs::create_dir_all(path)).await
+    }
+
+    async fn hard_link<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        let (src, dst) = (src.as_ref().to_path_buf(), dst.as_ref().to_path_buf());
+        debug!("hard_link {} -> {}", src.display(), dst.display());
+        blocking(move || std::fs::hard_link(src, dst)).await
+    }
+
+    async fn metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        let path = path.as_ref().to_path_buf();
+        debug!("metadata {}", path.display());
+        blocking(move || std::fs::metadata(path).map(StdMetadata)).await
+    }
+
+    async fn read<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>> {
+        let path = path.as_ref().to_path_buf();
+        debug!("read {}", path.display());
+        blocking(move || std::fs::read(path)).await
+    }
+
+    async fn read_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::ReadDir> {
+        let path = path.as_ref().to_path_buf();
+        debug!("read_dir {}", path.display());
+        blocking(move || std::fs::read_dir(path).map(|read_dir| StdReadDir(Some(read_dir)))).await
+    }
+
+    async fn read_link<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        let path = path.as_ref().to_path_buf();
+        debug!("read_link {}", path.display());
+        blocking(move || std::fs::read_link(path)).await
+    }
+
+    async fn read_to_string<P: AsRef<Path> + Send>(&self, path: P) -> Result<String> {
+        let path = path.as_ref().to_path_buf();
+        debug!("read_to_string {}", path.display());
+        blocking(move || std::fs::read_to_string(path)).await
+    }
+
+    async fn remove_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        debug!("remove_dir {}", path.display());
+        blocking(move || std::fs::remove_dir(path)).await
+    }
+
+    async fn remove_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        debug!("remove_dir_all {}", path.display());
+        blocking(move || std::fs::remove_dir_all(path)).await
+    }
+
+    async fn remove_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        debug!("remove_file {}", path.display());
+        blocking(move || std::fs::remove_file(path)).await
+    }
+
+    async fn rename<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<()> {
+        check_not_into_itself(self, from.as_ref(), to.as_ref()).await?;
+        let (from, to) = (from.as_ref().to_path_buf(), to.as_ref().to_path_buf());
+        debug!("rename {} -> {}", from.display(), to.display());
+        blocking(move || std::fs::rename(from, to)).await
+    }
+
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        perm: Self::Permissions,
+    ) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        debug!("set_permissions {}", path.display());
+        blocking(move || std::fs::set_permissions(path, perm.0)).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        let (src, dst) = (src.as_ref().to_path_buf(), dst.as_ref().to_path_buf());
+        debug!("symlink {} -> {}", src.display(), dst.display());
+        blocking(move || std::os::unix::fs::symlink(src, dst)).await
+    }
+
+    async fn symlink_metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        let path = path.as_ref().to_path_buf();
+        debug!("symlink_metadata {}", path.display());
+        blocking(move || std::fs::symlink_metadata(path).map(StdMetadata)).await
+    }
+
+    async fn try_exists<P: AsRef<Path> + Send>(&self, path: P) -> Result<bool> {
+        let path = path.as_ref().to_path_buf();
+        debug!("try_exists {}", path.display());
+        blocking(move || path.try_exists()).await
+    }
+
+    async fn write<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        let contents = contents.as_ref().to_vec();
+        debug!("write {}", path.display());
+        blocking(move || std::fs::write(path, contents)).await
+    }
+
+    fn new_dir_builder(&'a self) -> Self::DirBuilder {
+        StdDirBuilder {
+            recursive: false,
+            mode: 0o777,
+        }
+    }
+}
+
+#[cfg(unix)]
+#[async_trait::async_trait]
+impl FloppyDiskUnixExt for StdFloppyDisk {
+    async fn chown<P: Into<PathBuf> + Send>(&self, path: P, uid: u32, gid: u32) -> Result<()> {
+        let path = path.into();
+        debug!("chown {}", path.display());
+        blocking(move || std::os::unix::fs::chown(path, Some(uid), Some(gid))).await
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct StdMetadata(#[doc(hidden)] Metadata);
+
+#[async_trait::async_trait]
+impl<'a> FloppyMetadata<'a, StdFloppyDisk> for StdMetadata {
+    fn file_type(&self) -> <StdFloppyDisk as FloppyDisk<'a>>::FileType {
+        StdFileType(self.0.file_type())
+    }
+
+    fn is_dir(&self) -> bool {
+        self.0.is_dir()
+    }
+
+    fn is_file(&self) -> bool {
+        self.0.is_file()
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.0.is_symlink()
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len()
+    }
+
+    fn permissions(&self) -> <StdFloppyDisk as FloppyDisk<'a>>::Permissions {
+        StdPermissions(self.0.permissions())
+    }
+
+    fn modified(&self) -> Result<SystemTime> {
+        self.0.modified()
+    }
+
+    fn accessed(&self) -> Result<SystemTime> {
+        self.0.accessed()
+    }
+
+    fn created(&self) -> Result<SystemTime> {
+        self.0.created()
+    }
+}
+
+#[cfg(unix)]
+impl FloppyUnixMetadata for StdMetadata {
+    fn uid(&self) -> Result<u32> {
+        use std::os::unix::prelude::MetadataExt;
+        Ok(self.0.uid())
+    }
+
+    fn gid(&self) -> Result<u32> {
+        use std::os::unix::prelude::MetadataExt;
+        Ok(self.0.gid())
+    }
+
+    fn dev(&self) -> Result<u64> {
+        use std::os::unix::prelude::MetadataExt;
+        Ok(self.0.dev())
+    }
+
+    fn ino(&self) -> Result<u64> {
+        use std::os::unix::prelude::MetadataExt;
+        Ok(self.0.ino())
+    }
+
+    fn blocks(&self) -> Result<u64> {
+        use std::os::unix::prelude::MetadataExt;
+        Ok(self.0.blocks())
+    }
+}
+
+/// `std::fs::ReadDir` is a blocking iterator, so it's moved onto the blocking
+/// pool for each call to `next_entry` and put back afterwards.
+#[derive(Debug)]
+pub struct StdReadDir(#[doc(hidden)] Option<ReadDir>);
+
+#[async_trait::async_trait]
+impl<'a> FloppyReadDir<'a, StdFloppyDisk> for StdReadDir {
+    async fn next_entry(&mut self) -> Result<Option<<StdFloppyDisk as FloppyDisk<'a>>::DirEntry>> {
+        let mut read_dir = match self.0.take() {
+            Some(read_dir) => read_dir,
+            None => return Ok(None),
+        };
+        let (read_dir, entry) = tokio::task::spawn_blocking(move || {
+            let entry = read_dir.next();
+            (read_dir, entry)
+        })
+        .await?;
+        self.0 = Some(read_dir);
+        entry
+            .transpose()
+            .map(|entry| entry.map(|entry| StdDirEntry(Arc::new(entry))))
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct StdPermissions(#[doc(hidden)] Permissions);
+
+impl FloppyPermissions for StdPermissions {
+    fn readonly(&self) -> bool {
+        self.0.readonly()
+    }
+
+    fn set_readonly(&mut self, readonly: bool) {
+        self.0.set_readonly(readonly)
+    }
+}
+
+#[cfg(unix)]
+impl FloppyUnixPermissions for StdPermissions {
+    fn mode(&self) -> u32 {
+        self.0.mode()
+    }
+
+    fn set_mode(&mut self, mode: u32) {
+        self.0.set_mode(mode)
+    }
+
+    fn from_mode(mode: u32) -> Self {
+        Self(Permissions::from_mode(mode))
+    }
+}
+
+#[derive(Debug)]
+pub struct StdDirBuilder {
+    recursive: bool,
+    mode: u32,
+}
+
+#[async_trait::async_trait]
+impl FloppyDirBuilder for StdDirBuilder {
+    fn recursive(&mut self, recursive: bool) -> &mut Self {
+        self.recursive = recursive;
+        self
+    }
+
+    async fn create<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        use std::os::unix::fs::DirBuilderExt;
+
+        let path = path.as_ref().to_path_buf();
+        let (recursive, mode) = (self.recursive, self.mode);
+        blocking(move || {
+            DirBuilder::new()
+                .recursive(recursive)
+                .mode(mode)
+                .create(path)
+        })
+        .await
+    }
+
+    fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct StdDirEntry(#[doc(hidden)] Arc<DirEntry>);
+
+#[async_trait::async_trait]
+impl<'a> FloppyDirEntry<'a, StdFloppyDisk> for StdDirEntry {
+    fn file_name(&self) -> OsString {
+        self.0.file_name()
+    }
+
+    async fn file_type(&self) -> Result<<StdFloppyDisk as FloppyDisk<'a>>::FileType> {
+        let entry = self.0.clone();
+        blocking(move || entry.file_type().map(StdFileType)).await
+    }
+
+    async fn metadata(&self) -> Result<StdMetadata> {
+        let entry = self.0.clone();
+        blocking(move || entry.metadata().map(StdMetadata)).await
+    }
+
+    fn path(&self) -> PathBuf {
+        self.0.path()
+    }
+
+    #[cfg(unix)]
+    fn ino(&self) -> u64 {
+        use std::os::unix::fs::DirEntryExt;
+        self.0.ino()
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct StdFileType(#[doc(hidden)] FileType);
+
+impl FloppyFileType for StdFileType {
+    fn is_dir(&self) -> bool {
+        self.0.is_dir()
+    }
+
+    fn is_file(&self) -> bool {
+        self.0.is_file()
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.0.is_symlink()
+    }
+}
+
+#[derive(Debug)]
+pub struct StdOpenOptions {
+    options: OpenOptions,
+    append: bool,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyOpenOptions<'a, StdFloppyDisk> for StdOpenOptions {
+    fn new() -> Self {
+        Self {
+            options: OpenOptions::new(),
+            append: false,
+        }
+    }
+
+    fn read(mut self, read: bool) -> Self {
+        self.options.read(read);
+        self
+    }
+
+    fn write(mut self, write: bool) -> Self {
+        self.options.write(write);
+        self
+    }
+
+    fn append(mut self, append: bool) -> Self {
+        self.options.append(append);
+        self.append = append;
+        self
+    }
+
+    fn truncate(mut self, truncate: bool) -> Self {
+        self.options.truncate(truncate);
+        self
+    }
+
+    fn create(mut self, create: bool) -> Self {
+        self.options.create(create);
+        self
+    }
+
+    fn create_new(mut self, create_new: bool) -> Self {
+        self.options.create_new(create_new);
+        self
+    }
+
+    async fn open<P: AsRef<Path> + Send>(
+        &self,
+        _disk: &'a StdFloppyDisk,
+        path: P,
+    ) -> Result<<StdFloppyDisk as FloppyDisk<'a>>::File> {
+        let path = path.as_ref().to_path_buf();
+        let options = self.options.clone();
+        debug!("opening {}", path.display());
+        let append = self.append;
+        let (file, position) = blocking(move || {
+            let file = options.open(path)?;
+            let position = if append { file.metadata()?.len() } else { 0 };
+            Ok((file, position))
+        })
+        .await?;
+        Ok(StdFile {
+            file: Arc::new(file),
+            state: State::Idle(Buf::default()),
+            write_error: None,
+            position,
+        })
+    }
+}
+
+/// Files are used through `std::fs`, with every read, write and seek run on
+/// tokio's blocking pool rather than stalling the runtime. Writes are copied
+/// and reported done straight away, so as with
+/// [`TokioFile`](crate::tokio_fs::TokioFile), dropping the file without
+/// [closing](FloppyFile::close) it loses errors from the last write.
+#[derive(Debug)]
+pub struct StdFile {
+    file: Arc<File>,
+    state: State,
+    /// The error from a write that finished while waiting on something else,
+    /// for the next write or flush to return.
+    write_error: Option<std::io::Error>,
+    /// Where the last seek left the cursor.
+    position: u64,
+}
+
+/// The most that's read or written in one trip to the blocking pool.
+const MAX_BUF: usize = 2 * 1024 * 1024;
+
+#[derive(Debug)]
+enum State {
+    Idle(Buf),
+    Busy(JoinHandle<(Operation, Buf)>),
+}
+
+#[derive(Debug)]
+enum Operation {
+    Read(Result<usize>),
+    Write(Result<()>),
+    Seek(Result<u64>),
+}
+
+/// Bytes read ahead of the caller, or waiting to be written.
+#[derive(Debug, Default)]
+struct Buf {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl Buf {
+    fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.position = 0;
+    }
+
+    fn copy_to(&mut self, dst: &mut ReadBuf<'_>) {
+        let len = (self.data.len() - self.position).min(dst.remaining());
+        dst.put_slice(&self.data[self.position..self.position + len]);
+        self.position += len;
+        if self.is_empty() {
+            self.clear();
+        }
+    }
+
+    fn copy_from(&mut self, bufs: &[IoSlice<'_>]) -> usize {
+        for buf in bufs {
+            let len = buf.len().min(MAX_BUF - self.data.len());
+            self.data.extend_from_slice(&buf[..len]);
+        }
+        self.data.len()
+    }
+
+    /// Forget whatever was read ahead, returning how far the file's cursor
+    /// has to go back to match.
+    fn discard_read(&mut self) -> i64 {
+        let ahead = self.data.len() - self.position;
+        self.clear();
+        -(ahead as i64)
+    }
+
+    fn read_from(&mut self, mut file: &File, len: usize) -> Result<usize> {
+        self.data.resize(len.min(MAX_BUF), 0);
+        let result = loop {
+            match file.read(&mut self.data) {
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        };
+        self.data.truncate(*result.as_ref().unwrap_or(&0));
+        result
+    }
+
+    fn write_to(&mut self, mut file: &File) -> Result<()> {
+        let result = file.write_all(&self.data[self.position..]);
+        self.clear();
+        result
+    }
+}
+
+impl StdFile {
+    /// Wait for anything in flight, and put the cursor back where the caller
+    /// left it, before using the file directly.
+    async fn settle(&mut self) -> Result<()> {
+        AsyncWriteExt::flush(self).await?;
+        if let State::Idle(buf) = &mut self.state {
+            let back = buf.discard_read();
+            if back != 0 {
+                let file = self.file.clone();
+                blocking(move || (&*file).seek(SeekFrom::Current(back))).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_write_bufs(
+        &mut self,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize>> {
+        if let Some(error) = self.write_error.take() {
+            return Poll::Ready(Err(error));
+        }
+        loop {
+            match &mut self.state {
+                State::Idle(buf) => {
+                    let mut buf = std::mem::take(buf);
+                    let back = buf.discard_read();
+                    let written = buf.copy_from(bufs);
+                    let file = self.file.clone();
+                    self.state = State::Busy(tokio::task::spawn_blocking(move || {
+                        let result = if back != 0 {
+                            (&*file)
+                                .seek(SeekFrom::Current(back))
+                                .and_then(|_| buf.write_to(&file))
+                        } else {
+                            buf.write_to(&file)
+                        };
+                        (Operation::Write(result), buf)
+                    }));
+                    return Poll::Ready(Ok(written));
+                }
+                State::Busy(handle) => {
+                    let (operation, buf) = ready!(Pin::new(handle).poll(cx))?;
+                    self.state = State::Idle(buf);
+                    // Anything read ahead is discarded above.
+                    if let Operation::Write(Err(error)) = operation {
+                        return Poll::Ready(Err(error));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyFile<'a, StdFloppyDisk> for StdFile {
+    async fn sync_all(&mut self) -> Result<()> {
+        self.settle().await?;
+        let file = self.file.clone();
+        blocking(move || file.sync_all()).await
+    }
+
+    async fn sync_data(&mut self) -> Result<()> {
+        self.settle().await?;
+        let file = self.file.clone();
+        blocking(move || file.sync_data()).await
+    }
+
+    async fn set_len(&mut self, size: u64) -> Result<()> {
+        self.settle().await?;
+        let file = self.file.clone();
+        blocking(move || file.set_len(size)).await
+    }
+
+    async fn metadata(&self) -> Result<<StdFloppyDisk as FloppyDisk<'a>>::Metadata> {
+        let file = self.file.clone();
+        blocking(move || file.metadata().map(StdMetadata)).await
+    }
+
+    async fn try_clone(&'a self) -> Result<Box<Self>> {
+        let file = self.file.clone();
+        let file = blocking(move || file.try_clone()).await?;
+        Ok(Box::new(StdFile {
+            file: Arc::new(file),
+            state: State::Idle(Buf::default()),
+            write_error: None,
+            position: self.position,
+        }))
+    }
+
+    async fn set_permissions(
+        &self,
+        perm: <StdFloppyDisk as FloppyDisk>::Permissions,
+    ) -> Result<()> {
+        let file = self.file.clone();
+        blocking(move || file.set_permissions(perm.0)).await
+    }
+
+    async fn permissions(&self) -> Result<<StdFloppyDisk as FloppyDisk<'a>>::Permissions> {
+        let file = self.file.clone();
+        blocking(move || {
+            file.metadata()
+                .map(|metadata| StdPermissions(metadata.permissions()))
+        })
+        .await
+    }
+
+    async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        crate::tokio_fs::read_at_std(self.file.clone(), buf, offset).await
+    }
+
+    async fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        crate::tokio_fs::write_at_std(self.file.clone(), buf, offset).await
+    }
+
+    async fn allocate(&self, offset: u64, len: u64) -> Result<()> {
+        crate::tokio_fs::allocate_std(self.file.clone(), offset, len).await
+    }
+
+    async fn seek_data(&self, offset: u64) -> Result<u64> {
+        crate::tokio_fs::seek_extent_std(self.file.clone(), offset, true).await
+    }
+
+    async fn seek_hole(&self, offset: u64) -> Result<u64> {
+        crate::tokio_fs::seek_extent_std(self.file.clone(), offset, false).await
+    }
+}
+
+impl AsyncRead for StdFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        dst: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        loop {
+            match &mut this.state {
+                State::Idle(buf) => {
+                    if !buf.is_empty() || dst.remaining() == 0 {
+                        buf.copy_to(dst);
+                        return Poll::Ready(Ok(()));
+                    }
+                    let mut buf = std::mem::take(buf);
+                    let file = this.file.clone();
+                    let len = dst.remaining();
+                    this.state = State::Busy(tokio::task::spawn_blocking(move || {
+                        let result = buf.read_from(&file, len);
+                        (Operation::Read(result), buf)
+                    }));
+                }
+                State::Busy(handle) => {
+                    let (operation, mut buf) = ready!(Pin::new(handle).poll(cx))?;
+                    match operation {
+                        Operation::Read(Ok(_)) => {
+                            buf.copy_to(dst);
+                            this.state = State::Idle(buf);
+                            return Poll::Ready(Ok(()));
+                        }
+                        Operation::Read(Err(error)) => {
+                            this.state = State::Idle(buf);
+                            return Poll::Ready(Err(error));
+                        }
+                        Operation::Write(result) => {
+                            this.state = State::Idle(buf);
+                            if let Err(error) = result {
+                                this.write_error = Some(error);
+                            }
+                        }
+                        Operation::Seek(_) => this.state = State::Idle(buf),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl AsyncSeek for StdFile {
+    fn start_seek(self: Pin<&mut Self>, mut position: SeekFrom) -> Result<()> {
+        let this = self.get_mut();
+        let State::Idle(buf) = &mut this.state else {
+            return Err(std::io::Error::other(
+                "other file operation is pending, call poll_complete before start_seek",
+            ));
+        };
+        let mut buf = std::mem::take(buf);
+        let back = buf.discard_read();
+        if let SeekFrom::Current(offset) = &mut position {
+            *offset += back;
+        }
+        let file = this.file.clone();
+        this.state = State::Busy(tokio::task::spawn_blocking(move || {
+            (Operation::Seek((&*file).seek(position)), buf)
+        }));
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        let this = self.get_mut();
+        loop {
+            let State::Busy(handle) = &mut this.state else {
+                return Poll::Ready(Ok(this.position));
+            };
+            let (operation, buf) = ready!(Pin::new(handle).poll(cx))?;
+            this.state = State::Idle(buf);
+            match operation {
+                Operation::Seek(result) => {
+                    if let Ok(position) = result {
+                        this.position = position;
+                    }
+                    return Poll::Ready(result);
+                }
+                Operation::Write(Err(error)) => this.write_error = Some(error),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl AsyncWrite for StdFile {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.get_mut().poll_write_bufs(cx, &[IoSlice::new(buf)])
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize>> {
+        self.get_mut().poll_write_bufs(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        if let Some(error) = this.write_error.take() {
+            return Poll::Ready(Err(error));
+        }
+        let State::Busy(handle) = &mut this.state else {
+            return Poll::Ready(Ok(()));
+        };
+        let (operation, buf) = ready!(Pin::new(handle).poll(cx))?;
+        this.state = State::Idle(buf);
+        match operation {
+            Operation::Write(result) => Poll::Ready(result),
+            _ => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::policy::{FloppyPolicy, PolicyDisk, PolicyOpenOptions};
+
+    #[tokio::test]
+    async fn test_std_floppy_disk() -> std::io::Result<()> {
+        let fs = StdFloppyDisk::new();
+        let dir = PathBuf::from(format!("/tmp/floppy-std-{}", rand::random::<u64>()));
+        fs.create_dir_all(dir.join("a/b")).await?;
+        fs.write(dir.join("a/b/c.txt"), "asdf").await?;
+        assert_eq!("asdf", fs.read_to_string(dir.join("a/b/c.txt")).await?);
+
+        let mut entries = fs.read_dir(dir.join("a")).await?;
+        let entry = entries.next_entry().await?.unwrap();
+        assert_eq!("b", entry.file_name().to_str().unwrap());
+        assert!(entry.file_type().await?.is_dir());
+        assert!(entries.next_entry().await?.is_none());
+
+        fs.remove_dir_all(&dir).await?;
+        assert!(!fs.try_exists(&dir).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_and_rename_onto_itself() -> std::io::Result<()> {
+        let fs = StdFloppyDisk::new();
+        let dir = PathBuf::from(format!("/tmp/floppy-std-{}", rand::random::<u64>()));
+        fs.create_dir_all(dir.join("a")).await?;
+        fs.write(dir.join("a/test.txt"), "asdf").await?;
+
+        assert!(fs
+            .copy(dir.join("a/test.txt"), dir.join("a/test.txt"))
+            .await
+            .is_err());
+        assert!(fs.rename(&dir, &dir.join("a/b")).await.is_err());
+        assert_eq!("asdf", fs.read_to_string(dir.join("a/test.txt")).await?);
+
+        fs.remove_dir_all(&dir).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_file_cursor() -> std::io::Result<()> {
+        use tokio::io::AsyncSeekExt;
+
+        let fs = StdFloppyDisk::new();
+        let path = PathBuf::from(format!("/tmp/floppy-std-{}", rand::random::<u64>()));
+        let mut file = StdOpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&fs, &path)
+            .await?;
+        file.write_all(b"hello world").await?;
+        assert_eq!(0, file.seek(SeekFrom::Start(0)).await?);
+
+        // The read fills more than asked for, which writes and seeks have to
+        // account for.
+        let mut hello = [0; 5];
+        file.read_exact(&mut hello).await?;
+        assert_eq!(b"hello", &hello);
+        file.write_all(b"!").await?;
+        assert_eq!(8, file.seek(SeekFrom::Current(2)).await?);
+        let mut rest = String::new();
+        file.read_to_string(&mut rest).await?;
+        assert_eq!("rld", rest);
+
+        file.set_len(5).await?;
+        file.seek(SeekFrom::Start(0)).await?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await?;
+        assert_eq!("hello", contents);
+        file.close().await?;
+
+        fs.remove_file(&path).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_policy_limits() -> std::io::Result<()> {
+        let fs = PolicyDisk::new(StdFloppyDisk::new(), FloppyPolicy::new().max_file_size(8));
+        let path = PathBuf::from(format!("/tmp/floppy-std-{}", rand::random::<u64>()));
+        fs.write(&path, "asdf").await?;
+
+        let mut file = PolicyOpenOptions::new()
+            .append(true)
+            .open(&fs, &path)
+            .await?;
+        file.write_all(b"asdf").await?;
+        assert!(file.write_all(b"a").await.is_err());
+        file.flush().await?;
+        drop(file);
+        assert_eq!("asdfasdf", fs.read_to_string(&path).await?);
+
+        fs.remove_file(&path).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_content_policy_links() -> std::io::Result<()> {
+        let root = PathBuf::from(format!("/tmp/floppy-std-{}", rand::random::<u64>()));
+        let fs = PolicyDisk::new(
+            StdFloppyDisk::new(),
+            FloppyPolicy::new().deny_extension(root.join("denied"), "exe"),
+        );
+        fs.create_dir_all(root.join("denied")).await?;
+        fs.write(root.join("ok.bin"), "asdf").await?;
+
+        assert!(fs
+            .hard_link(root.join("ok.bin"), root.join("denied/x.exe"))
+            .await
+            .is_err());
+        assert!(fs
+            .symlink(root.join("ok.bin"), root.join("denied/y.exe"))
+            .await
+            .is_err());
+        assert!(!fs.try_exists(root.join("denied/x.exe")).await?);
+
+        fs.remove_dir_all(&root).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_open_options() -> std::io::Result<()> {
+        let fs = StdFloppyDisk::new();
+        let path = PathBuf::from(format!("/tmp/floppy-std-{}", rand::random::<u64>()));
+
+        let mut file = StdOpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&fs, &path)
+            .await?;
+        file.write_all(b"asdf").await?;
+        file.sync_all().await?;
+        drop(file);
+
+        let mut file = StdOpenOptions::new().read(true).open(&fs, &path).await?;
+        let mut buf = String::new();
+        file.read_to_string(&mut buf).await?;
+        assert_eq!("asdf", buf);
+
+        fs.remove_file(&path).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+//! Bringing one disk's tree up to date with another's, copying only what
+//! changed, or keeping two trees in step with each other (`hash` feature).
+
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use tokio::io::AsyncReadExt;
+use tracing::debug;
+
+use crate::copy::{copy_all, CopyOptions};
+use crate::{
+    FloppyDirEntry, FloppyDisk, FloppyMetadata, FloppyOpenOptions, FloppyReadDir,
+    FloppyUnixPermissions,
+};
+
+#[cfg(feature = "hash")]
+mod two_way;
+
+#[cfg(feature = "hash")]
+pub use self::two_way::{
+    two_way, Conflict, ConflictPolicy, Resolution, SyncState, TextMerge, TwoWayOptions,
+    TwoWayReport, MAX_MERGE_LEN,
+};
+
+/// How [`mirror`] decides whether a file has changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncCompare {
+    /// A file is unchanged if it's the same size at both ends, and the
+    /// destination's copy is at least as new as the source's. Disks can't set
+    /// modification times, so a copied file is always newer than its source.
+    #[default]
+    SizeAndMtime,
+    /// A file is unchanged if its contents are the same at both ends. This
+    /// reads every file that's the same size on both disks.
+    Contents,
+}
+
+/// How [`mirror`] syncs a tree.
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    compare: SyncCompare,
+    delete: bool,
+}
+
+impl SyncOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_compare(mut self, compare: SyncCompare) -> Self {
+        self.compare = compare;
+        self
+    }
+
+    /// Remove files and directories from the destination that aren't in the
+    /// source.
+    pub fn with_delete(mut self, delete: bool) -> Self {
+        self.delete = delete;
+        self
+    }
+}
+
+/// What [`mirror`] changed, as paths relative to the roots.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    pub copied: Vec<PathBuf>,
+    pub deleted: Vec<PathBuf>,
+}
+
+/// Make `dst_root` on `dst` match `src_root` on `src`, copying only the files
+/// and symlinks that differ.
+pub async fn mirror<'a, 'b, S, T>(
+    src: &'a S,
+    src_root: impl AsRef<Path> + Send,
+    dst: &'b T,
+    dst_root: impl AsRef<Path> + Send,
+    options: &SyncOptions,
+) -> Result<SyncReport>
+where
+    S: FloppyDisk<'a>,
+    T: FloppyDisk<'b>,
+    S::Permissions: FloppyUnixPermissions,
+    T::Permissions: FloppyUnixPermissions,
+{
+    let (src_root, dst_root) = (src_root.as_ref(), dst_root.as_ref());
+    let mut report = SyncReport::default();
+    dst.create_dir_all(dst_root).await?;
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let mut names = HashSet::new();
+        let mut read_dir = src.read_dir(src_root.join(&dir)).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let relative = dir.join(entry.file_name());
+            names.insert(entry.file_name());
+            let (from, to) = (src_root.join(&relative), dst_root.join(&relative));
+            let metadata = src.symlink_metadata(&from).await?;
+            let existing = match dst.symlink_metadata(&to).await {
+                Ok(existing) => Some(existing),
+                Err(e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+
+            if metadata.is_dir() {
+                match existing {
+                    Some(existing) if existing.is_dir() => {}
+                    Some(_) => {
+                        dst.remove_file(&to).await?;
+                        dst.create_dir(&to).await?;
+                    }
+                    None => dst.create_dir(&to).await?,
+                }
+                dirs.push(relative);
+                continue;
+            }
+
+            let unchanged = match existing {
+                Some(existing) if existing.is_dir() => {
+                    dst.remove_dir_all(&to).await?;
+                    false
+                }
+                Some(existing) if metadata.is_symlink() => {
+                    existing.is_symlink()
+                        && src.read_link(&from).await? == dst.read_link(&to).await?
+                }
+                Some(existing) if existing.is_file() => match options.compare {
+                    SyncCompare::SizeAndMtime => {
+                        existing.len() == metadata.len()
+                            && existing.modified()? >= metadata.modified()?
+                    }
+                    SyncCompare::Contents => {
+                        existing.len() == metadata.len()
+                            && same_contents(src, &from, dst, &to).await?
+                    }
+                },
+                // A symlink where a file should be would be written through.
+                Some(_) if !metadata.is_symlink() => {
+                    dst.remove_file(&to).await?;
+                    false
+                }
+                _ => false,
+            };
+            if !unchanged {
+                debug!("syncing {}", relative.display());
+                copy_all(src, &from, dst, &to, &CopyOptions::new()).await?;
+                report.copied.push(relative);
+            }
+        }
+
+        if options.delete {
+            report
+                .deleted
+                .extend(delete_extraneous(dst, dst_root, &dir, &names).await?);
+        }
+    }
+    Ok(report)
+}
+
+/// Remove everything in `dir` on `dst` that isn't in `names`.
+async fn delete_extraneous<'a, D: FloppyDisk<'a>>(
+    dst: &D,
+    dst_root: &Path,
+    dir: &Path,
+    names: &HashSet<OsString>,
+) -> Result<Vec<PathBuf>> {
+    let mut deleted = vec![];
+    let mut read_dir = dst.read_dir(dst_root.join(dir)).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        if names.contains(&entry.file_name()) {
+            continue;
+        }
+        let relative = dir.join(entry.file_name());
+        debug!("deleting {}", relative.display());
+        if dst.symlink_metadata(entry.path()).await?.is_dir() {
+            dst.remove_dir_all(entry.path()).await?;
+        } else {
+            dst.remove_file(entry.path()).await?;
+        }
+        deleted.push(relative);
+    }
+    deleted.sort();
+    Ok(deleted)
+}
+
+async fn same_contents<'a, 'b, S: FloppyDisk<'a>, T: FloppyDisk<'b>>(
+    src: &'a S,
+    from: &Path,
+    dst: &'b T,
+    to: &Path,
+) -> Result<bool> {
+    let mut a = S::OpenOptions::new().read(true).open(src, from).await?;
+    let mut b = T::OpenOptions::new().read(true).open(dst, to).await?;
+    let (mut a_buf, mut b_buf) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+    loop {
+        let n = a.read(&mut a_buf).await?;
+        if n == 0 {
+            return Ok(b.read(&mut b_buf[..1]).await? == 0);
+        }
+        if b.read_exact(&mut b_buf[..n]).await.is_err() || a_buf[..n] != b_buf[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_mirror() -> Result<()> {
+        let src = MemFloppyDisk::new();
+        src.create_dir_all("/src/a/b").await?;
+        src.write("/src/a/b/c.txt", "asdf").await?;
+        src.write("/src/d.txt", "jkl").await?;
+        src.symlink("d.txt", "/src/e").await?;
+
+        let dst = MemFloppyDisk::new();
+        let options = SyncOptions::new().with_delete(true);
+        let report = mirror(&src, "/src", &dst, "/dst", &options).await?;
+        assert_eq!(3, report.copied.len());
+        assert_eq!("asdf", dst.read_to_string("/dst/a/b/c.txt").await?);
+
+        // Nothing changed, so nothing is copied.
+        let report = mirror(&src, "/src", &dst, "/dst", &options).await?;
+        assert_eq!(SyncReport::default(), report);
+
+        src.write("/src/d.txt", "qwe").await?;
+        src.remove_dir_all("/src/a").await?;
+        dst.write("/dst/extra.txt", "extra").await?;
+        let options = options.with_compare(SyncCompare::Contents);
+        let report = mirror(&src, "/src", &dst, "/dst", &options).await?;
+        assert_eq!(vec![PathBuf::from("d.txt")], report.copied);
+        assert_eq!(
+            vec![PathBuf::from("a"), PathBuf::from("extra.txt")],
+            report.deleted
+        );
+        assert_eq!("qwe", dst.read_to_string("/dst/d.txt").await?);
+        assert!(!dst.try_exists("/dst/a").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mirror_replaces_symlinks() -> Result<()> {
+        let src = MemFloppyDisk::new();
+        src.create_dir("/src").await?;
+        src.write("/src/f", "new").await?;
+
+        let dst = MemFloppyDisk::new();
+        dst.create_dir("/dst").await?;
+        dst.write("/outside", "untouched").await?;
+        dst.symlink("/outside", "/dst/f").await?;
+        mirror(&src, "/src", &dst, "/dst", &SyncOptions::new()).await?;
+        assert!(dst.symlink_metadata("/dst/f").await?.is_file());
+        assert_eq!("new", dst.read_to_string("/dst/f").await?);
+        assert_eq!("untouched", dst.read_to_string("/outside").await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,585 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use tracing::debug;
+
+use crate::copy::{copy_all, CopyOptions};
+use crate::hash::{file_digest, HashAlgorithm};
+use crate::merge::{merge3, Merge};
+use crate::walk::FloppyWalkDir;
+use crate::{FloppyDisk, FloppyMetadata, FloppyUnixPermissions};
+
+/// What a pair of trees held after a [`two_way`] sync, so the next sync can
+/// tell which side changed. Keep it somewhere outside both trees, or it'll be
+/// synced along with them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncState {
+    /// A digest of each file or symlink, by path relative to the roots.
+    entries: BTreeMap<PathBuf, String>,
+    /// The contents of small text files, to merge later changes against.
+    /// Only kept when merging text.
+    bases: BTreeMap<PathBuf, String>,
+}
+
+impl SyncState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load state saved with [`SyncState::save`]. If there's nothing at
+    /// `path`, the trees have never been synced, and the state is empty.
+    pub async fn load<'a, D: FloppyDisk<'a>>(
+        disk: &'a D,
+        path: impl AsRef<Path> + Send,
+    ) -> Result<Self> {
+        let data = match disk.read(path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        // NUL-separated digests, paths, and bases, since neither paths nor
+        // mergeable text can contain NUL. Bases are empty if there isn't one,
+        // or start with `=` if there is.
+        let mut state = Self::default();
+        let mut fields = data.split(|&b| b == 0);
+        while let (Some(digest), Some(path), Some(base)) =
+            (fields.next(), fields.next(), fields.next())
+        {
+            let path = PathBuf::from(OsStr::from_bytes(path));
+            let digest =
+                std::str::from_utf8(digest).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            if let Some(base) = base.strip_prefix(b"=") {
+                let base =
+                    std::str::from_utf8(base).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                state.bases.insert(path.clone(), base.to_string());
+            }
+            state.entries.insert(path, digest.to_string());
+        }
+        Ok(state)
+    }
+
+    pub async fn save<'a, D: FloppyDisk<'a>>(
+        &self,
+        disk: &'a D,
+        path: impl AsRef<Path> + Send,
+    ) -> Result<()> {
+        let mut data = vec![];
+        for (path, digest) in &self.entries {
+            data.extend_from_slice(digest.as_bytes());
+            data.push(0);
+            data.extend_from_slice(path.as_os_str().as_bytes());
+            data.push(0);
+            if let Some(base) = self.bases.get(path) {
+                data.push(b'=');
+                data.extend_from_slice(base.as_bytes());
+            }
+            data.push(0);
+        }
+        disk.write(path, data).await
+    }
+
+    /// The base to merge `path` against, if there is one and it's what both
+    /// sides held at the last sync.
+    fn base(&self, path: &Path) -> Option<&str> {
+        let base = self.bases.get(path)?;
+        let mut hasher = HashAlgorithm::Sha256.hasher();
+        hasher.update(base.as_bytes());
+        (self.entries.get(path) == Some(&hasher.finish())).then_some(base.as_str())
+    }
+}
+
+/// A path that changed on both sides since the last sync.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub path: PathBuf,
+    /// When the source's copy was last modified, or `None` if it was deleted.
+    pub src_modified: Option<SystemTime>,
+    /// When the destination's copy was last modified, or `None` if it was
+    /// deleted.
+    pub dst_modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    KeepSource,
+    KeepDestination,
+    /// Keep the source's copy, and move the destination's alongside it on
+    /// both sides, with this suffix added to its name.
+    KeepBoth(String),
+}
+
+/// How [`two_way`] settles conflicts.
+#[derive(Clone, Default)]
+pub enum ConflictPolicy {
+    /// Keep whichever copy was modified most recently. A modified copy always
+    /// beats a deletion.
+    #[default]
+    NewestWins,
+    SourceWins,
+    /// Always [`Resolution::KeepBoth`], with this suffix.
+    RenameWithSuffix(String),
+    Callback(Arc<dyn Fn(&Conflict) -> Resolution + Send + Sync>),
+}
+
+impl std::fmt::Debug for ConflictPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NewestWins => write!(f, "NewestWins"),
+            Self::SourceWins => write!(f, "SourceWins"),
+            Self::RenameWithSuffix(suffix) => {
+                f.debug_tuple("RenameWithSuffix").field(suffix).finish()
+            }
+            Self::Callback(_) => write!(f, "Callback(..)"),
+        }
+    }
+}
+
+/// Whether [`two_way`] merges text files that changed on both sides, before
+/// falling back to its [`ConflictPolicy`]. Only UTF-8 files of up to
+/// [`MAX_MERGE_LEN`] bytes are merged, and only if they've been through a
+/// sync with merging on, which keeps a copy of them to merge against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextMerge {
+    #[default]
+    Off,
+    /// Merge if the two sides changed different lines.
+    Clean,
+    /// Merge, leaving git-style conflict markers around lines the two sides
+    /// changed differently, on both sides.
+    WithMarkers,
+}
+
+/// The largest text file [`two_way`] will merge.
+pub const MAX_MERGE_LEN: usize = 64 * 1024;
+
+/// How [`two_way`] syncs a pair of trees.
+#[derive(Debug, Clone, Default)]
+pub struct TwoWayOptions {
+    conflicts: ConflictPolicy,
+    text_merge: TextMerge,
+}
+
+impl TwoWayOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_conflict_policy(mut self, conflicts: ConflictPolicy) -> Self {
+        self.conflicts = conflicts;
+        self
+    }
+
+    pub fn with_text_merge(mut self, text_merge: TextMerge) -> Self {
+        self.text_merge = text_merge;
+        self
+    }
+}
+
+/// What [`two_way`] changed, as paths relative to the roots.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TwoWayReport {
+    pub copied_to_src: Vec<PathBuf>,
+    pub copied_to_dst: Vec<PathBuf>,
+    pub deleted_from_src: Vec<PathBuf>,
+    pub deleted_from_dst: Vec<PathBuf>,
+    pub conflicts: Vec<PathBuf>,
+    /// Conflicts that were settled by merging text, written to both sides.
+    pub merged: Vec<PathBuf>,
+}
+
+/// Sync `src_root` on `src` and `dst_root` on `dst` with each other. Changes
+/// made on either side since the sync that produced `state` are copied to the
+/// other, and `state` is updated to match. Paths that changed on both sides
+/// are settled with the options' [`ConflictPolicy`].
+///
+/// Files and symlinks are compared by content, so timestamps don't matter.
+/// Directories are created as needed, but empty ones are left alone.
+pub async fn two_way<'a, 'b, S, T>(
+    src: &'a S,
+    src_root: impl AsRef<Path> + Send,
+    dst: &'b T,
+    dst_root: impl AsRef<Path> + Send,
+    state: &mut SyncState,
+    options: &TwoWayOptions,
+) -> Result<TwoWayReport>
+where
+    S: FloppyDisk<'a>,
+    T: FloppyDisk<'b>,
+    S::Permissions: FloppyUnixPermissions,
+    T::Permissions: FloppyUnixPermissions,
+{
+    let (src_root, dst_root) = (src_root.as_ref(), dst_root.as_ref());
+    src.create_dir_all(src_root).await?;
+    dst.create_dir_all(dst_root).await?;
+    let src_digests = digests(src, src_root).await?;
+    let dst_digests = digests(dst, dst_root).await?;
+    let paths: BTreeSet<_> = src_digests
+        .keys()
+        .chain(dst_digests.keys())
+        .chain(state.entries.keys())
+        .cloned()
+        .collect();
+
+    let mut report = TwoWayReport::default();
+    for path in paths {
+        let (ours, theirs) = (src_digests.get(&path), dst_digests.get(&path));
+        let base = state.entries.get(&path);
+        let resolution = if ours == theirs {
+            None
+        } else if ours == base {
+            Some(Resolution::KeepDestination)
+        } else if theirs == base {
+            Some(Resolution::KeepSource)
+        } else {
+            debug!("conflict at {}", path.display());
+            report.conflicts.push(path.clone());
+            let (from, to) = (src_root.join(&path), dst_root.join(&path));
+            if let Some(merged) =
+                merge_text(src, &from, dst, &to, state.base(&path), options).await?
+            {
+                debug!("merged {}", path.display());
+                src.write(&from, &merged).await?;
+                dst.write(&to, &merged).await?;
+                report.merged.push(path.clone());
+                let mut hasher = HashAlgorithm::Sha256.hasher();
+                hasher.update(merged.as_bytes());
+                state.entries.insert(path.clone(), hasher.finish());
+                state.bases.insert(path, merged);
+                continue;
+            }
+            let conflict = Conflict {
+                path: path.clone(),
+                src_modified: modified(src, &from).await?,
+                dst_modified: modified(dst, &to).await?,
+            };
+            Some(resolve(&conflict, &options.conflicts))
+        };
+
+        let digest = match resolution {
+            None => ours,
+            Some(Resolution::KeepSource) => {
+                replace(src, src_root, dst, dst_root, &path, ours.is_some()).await?;
+                let changed = if ours.is_some() {
+                    &mut report.copied_to_dst
+                } else {
+                    &mut report.deleted_from_dst
+                };
+                changed.push(path.clone());
+                ours
+            }
+            Some(Resolution::KeepDestination) => {
+                replace(dst, dst_root, src, src_root, &path, theirs.is_some()).await?;
+                let changed = if theirs.is_some() {
+                    &mut report.copied_to_src
+                } else {
+                    &mut report.deleted_from_src
+                };
+                changed.push(path.clone());
+                theirs
+            }
+            Some(Resolution::KeepBoth(suffix)) => {
+                let mut renamed = path.clone().into_os_string();
+                renamed.push(&suffix);
+                let renamed = PathBuf::from(renamed);
+                if let Some(theirs) = theirs {
+                    dst.rename(dst_root.join(&path), dst_root.join(&renamed))
+                        .await?;
+                    replace(dst, dst_root, src, src_root, &renamed, true).await?;
+                    report.copied_to_src.push(renamed.clone());
+                    state.entries.insert(renamed, theirs.clone());
+                }
+                if ours.is_some() {
+                    replace(src, src_root, dst, dst_root, &path, true).await?;
+                    report.copied_to_dst.push(path.clone());
+                }
+                ours
+            }
+        };
+        match digest {
+            Some(digest) => {
+                state.entries.insert(path.clone(), digest.clone());
+                if options.text_merge != TextMerge::Off && state.base(&path).is_none() {
+                    match read_text(src, &src_root.join(&path)).await? {
+                        Some(text) => state.bases.insert(path, text),
+                        None => state.bases.remove(&path),
+                    };
+                }
+            }
+            None => {
+                state.entries.remove(&path);
+                state.bases.remove(&path);
+            }
+        };
+    }
+    Ok(report)
+}
+
+/// Merge both sides' copies of a file against `base`, if that's possible and
+/// the options allow the result.
+async fn merge_text<'a, 'b, S: FloppyDisk<'a>, T: FloppyDisk<'b>>(
+    src: &'a S,
+    from: &Path,
+    dst: &'b T,
+    to: &Path,
+    base: Option<&str>,
+    options: &TwoWayOptions,
+) -> Result<Option<String>> {
+    if options.text_merge == TextMerge::Off {
+        return Ok(None);
+    }
+    let Some(base) = base else {
+        return Ok(None);
+    };
+    let (Some(ours), Some(theirs)) = (read_text(src, from).await?, read_text(dst, to).await?)
+    else {
+        return Ok(None);
+    };
+    Ok(match (merge3(base, &ours, &theirs), options.text_merge) {
+        (Merge::Clean(merged), _) | (Merge::Conflicted(merged), TextMerge::WithMarkers) => {
+            Some(merged)
+        }
+        _ => None,
+    })
+}
+
+/// The contents of the file at `path`, if it's a mergeable text file.
+async fn read_text<'a, D: FloppyDisk<'a>>(disk: &'a D, path: &Path) -> Result<Option<String>> {
+    let metadata = match disk.symlink_metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if !metadata.is_file() || metadata.len() > MAX_MERGE_LEN as u64 {
+        return Ok(None);
+    }
+    Ok(String::from_utf8(disk.read(path).await?)
+        .ok()
+        .filter(|text| !text.contains('\0')))
+}
+
+fn resolve(conflict: &Conflict, policy: &ConflictPolicy) -> Resolution {
+    match policy {
+        ConflictPolicy::NewestWins => {
+            if conflict.src_modified >= conflict.dst_modified {
+                Resolution::KeepSource
+            } else {
+                Resolution::KeepDestination
+            }
+        }
+        ConflictPolicy::SourceWins => Resolution::KeepSource,
+        ConflictPolicy::RenameWithSuffix(suffix) => Resolution::KeepBoth(suffix.clone()),
+        ConflictPolicy::Callback(callback) => callback(conflict),
+    }
+}
+
+/// Make `path` under `to_root` on `to` match `path` under `from_root` on
+/// `from`, copying it if it `exists` there and removing it if not.
+async fn replace<'a, 'b, S, T>(
+    from: &'a S,
+    from_root: &Path,
+    to: &'b T,
+    to_root: &Path,
+    path: &Path,
+    exists: bool,
+) -> Result<()>
+where
+    S: FloppyDisk<'a>,
+    T: FloppyDisk<'b>,
+    S::Permissions: FloppyUnixPermissions,
+    T::Permissions: FloppyUnixPermissions,
+{
+    let target = to_root.join(path);
+    if !exists {
+        debug!("deleting {}", target.display());
+        return match to.remove_file(&target).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    debug!("syncing {}", path.display());
+    if let Some(parent) = target.parent() {
+        to.create_dir_all(parent).await?;
+    }
+    // Whatever is there is replaced, rather than written through if it's a
+    // symlink.
+    match to.symlink_metadata(&target).await {
+        Ok(existing) if !existing.is_dir() => to.remove_file(&target).await?,
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    copy_all(from, from_root.join(path), to, target, &CopyOptions::new())
+        .await
+        .map(|_| ())
+}
+
+/// A digest of every file and symlink under `root`.
+async fn digests<'a, D: FloppyDisk<'a>>(
+    disk: &'a D,
+    root: &Path,
+) -> Result<BTreeMap<PathBuf, String>> {
+    let mut digests = BTreeMap::new();
+    let mut walk = FloppyWalkDir::new(disk, root);
+    while let Some(entry) = walk.next_entry().await? {
+        let digest = if entry.metadata().is_symlink() {
+            format!("symlink:{}", disk.read_link(entry.path()).await?.display())
+        } else if entry.metadata().is_dir() {
+            continue;
+        } else {
+            file_digest(disk, entry.path(), HashAlgorithm::Sha256).await?
+        };
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        digests.insert(relative.to_path_buf(), digest);
+    }
+    Ok(digests)
+}
+
+async fn modified<'a, D: FloppyDisk<'a>>(disk: &D, path: &Path) -> Result<Option<SystemTime>> {
+    match disk.symlink_metadata(path).await {
+        Ok(metadata) => metadata.modified().map(Some),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_two_way() -> Result<()> {
+        let (src, dst) = (MemFloppyDisk::new(), MemFloppyDisk::new());
+        let mut state = SyncState::new();
+        let options = TwoWayOptions::new();
+        src.create_dir_all("/src/a").await?;
+        src.write("/src/a/b.txt", "asdf").await?;
+        let report = two_way(&src, "/src", &dst, "/dst", &mut state, &options).await?;
+        assert_eq!(vec![PathBuf::from("a/b.txt")], report.copied_to_dst);
+
+        // Changes flow both ways, deletions included.
+        src.write("/src/a/b.txt", "jkl").await?;
+        dst.write("/dst/c.txt", "qwe").await?;
+        two_way(&src, "/src", &dst, "/dst", &mut state, &options).await?;
+        assert_eq!("jkl", dst.read_to_string("/dst/a/b.txt").await?);
+        assert_eq!("qwe", src.read_to_string("/src/c.txt").await?);
+        dst.remove_file("/dst/c.txt").await?;
+        let report = two_way(&src, "/src", &dst, "/dst", &mut state, &options).await?;
+        assert_eq!(vec![PathBuf::from("c.txt")], report.deleted_from_src);
+        assert!(!src.try_exists("/src/c.txt").await?);
+
+        // State survives being saved and loaded.
+        state.save(&src, "/state").await?;
+        let mut state = SyncState::load(&src, "/state").await?;
+        let report = two_way(&src, "/src", &dst, "/dst", &mut state, &options).await?;
+        assert_eq!(TwoWayReport::default(), report);
+
+        src.write("/src/a/b.txt", "ours").await?;
+        dst.write("/dst/a/b.txt", "theirs").await?;
+        let options = TwoWayOptions::new()
+            .with_conflict_policy(ConflictPolicy::RenameWithSuffix(".conflict".into()));
+        let report = two_way(&src, "/src", &dst, "/dst", &mut state, &options).await?;
+        assert_eq!(vec![PathBuf::from("a/b.txt")], report.conflicts);
+        for (disk, root) in [(&src, "/src"), (&dst, "/dst")] {
+            assert_eq!(
+                "ours",
+                disk.read_to_string(format!("{root}/a/b.txt")).await?
+            );
+            assert_eq!(
+                "theirs",
+                disk.read_to_string(format!("{root}/a/b.txt.conflict"))
+                    .await?
+            );
+        }
+
+        src.write("/src/a/b.txt", "ours again").await?;
+        dst.write("/dst/a/b.txt", "theirs again").await?;
+        let options = TwoWayOptions::new().with_conflict_policy(ConflictPolicy::Callback(
+            Arc::new(|conflict| {
+                assert_eq!(Path::new("a/b.txt"), conflict.path);
+                Resolution::KeepDestination
+            }),
+        ));
+        two_way(&src, "/src", &dst, "/dst", &mut state, &options).await?;
+        assert_eq!("theirs again", src.read_to_string("/src/a/b.txt").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_two_way_replaces_symlinks() -> Result<()> {
+        let (src, dst) = (MemFloppyDisk::new(), MemFloppyDisk::new());
+        let mut state = SyncState::new();
+        let options = TwoWayOptions::new();
+        src.create_dir("/src").await?;
+        src.write("/src/f", "asdf").await?;
+        two_way(&src, "/src", &dst, "/dst", &mut state, &options).await?;
+
+        // The destination's file is swapped for a symlink out of the tree,
+        // then the source changes and wins.
+        dst.write("/outside", "untouched").await?;
+        dst.remove_file("/dst/f").await?;
+        dst.symlink("/outside", "/dst/f").await?;
+        src.write("/src/f", "new").await?;
+        let options = options.with_conflict_policy(ConflictPolicy::SourceWins);
+        two_way(&src, "/src", &dst, "/dst", &mut state, &options).await?;
+        assert!(dst.symlink_metadata("/dst/f").await?.is_file());
+        assert_eq!("new", dst.read_to_string("/dst/f").await?);
+        assert_eq!("untouched", dst.read_to_string("/outside").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_two_way_text_merge() -> Result<()> {
+        let (src, dst) = (MemFloppyDisk::new(), MemFloppyDisk::new());
+        let mut state = SyncState::new();
+        let options = TwoWayOptions::new()
+            .with_conflict_policy(ConflictPolicy::SourceWins)
+            .with_text_merge(TextMerge::Clean);
+        src.create_dir("/src").await?;
+        src.write("/src/config", "a = 1\nb = 2\nc = 3\n").await?;
+        two_way(&src, "/src", &dst, "/dst", &mut state, &options).await?;
+        state.save(&src, "/state").await?;
+        let mut state = SyncState::load(&src, "/state").await?;
+
+        src.write("/src/config", "a = 10\nb = 2\nc = 3\n").await?;
+        dst.write("/dst/config", "a = 1\nb = 2\nc = 30\n").await?;
+        let report = two_way(&src, "/src", &dst, "/dst", &mut state, &options).await?;
+        assert_eq!(vec![PathBuf::from("config")], report.merged);
+        for (disk, root) in [(&src, "/src"), (&dst, "/dst")] {
+            assert_eq!(
+                "a = 10\nb = 2\nc = 30\n",
+                disk.read_to_string(format!("{root}/config")).await?
+            );
+        }
+
+        // Overlapping changes fall back to the conflict policy...
+        src.write("/src/config", "a = 100\nb = 2\nc = 30\n").await?;
+        dst.write("/dst/config", "a = 1000\nb = 2\nc = 30\n")
+            .await?;
+        let report = two_way(&src, "/src", &dst, "/dst", &mut state, &options).await?;
+        assert!(report.merged.is_empty());
+        assert_eq!(
+            "a = 100\nb = 2\nc = 30\n",
+            dst.read_to_string("/dst/config").await?
+        );
+
+        // ...unless conflict markers are allowed.
+        src.write("/src/config", "a = 1\nb = 2\nc = 30\n").await?;
+        dst.write("/dst/config", "a = 2\nb = 2\nc = 30\n").await?;
+        let options = options.with_text_merge(TextMerge::WithMarkers);
+        two_way(&src, "/src", &dst, "/dst", &mut state, &options).await?;
+        assert_eq!(
+            "<<<<<<< ours\na = 1\n=======\na = 2\n>>>>>>> theirs\nb = 2\nc = 30\n",
+            src.read_to_string("/src/config").await?
+        );
+
+        Ok(())
+    }
+}
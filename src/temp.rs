@@ -0,0 +1,127 @@
+use std::io::{ErrorKind, Result};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use crate::tokio_fs::TokioFloppyDisk;
+
+/// A [`TokioFloppyDisk`] scoped to a fresh temporary directory, which is
+/// removed when the disk is dropped. Use [`TempFloppyDisk::close`] to remove
+/// it without blocking, and to see any errors from doing so.
+///
+/// The disk derefs to the underlying `TokioFloppyDisk`, so it can be used
+/// anywhere a `FloppyDisk` is expected with `&*disk`.
+#[derive(Debug)]
+pub struct TempFloppyDisk {
+    disk: TokioFloppyDisk,
+    path: PathBuf,
+    closed: bool,
+}
+
+impl TempFloppyDisk {
+    pub async fn new() -> Result<Self> {
+        Self::new_in(std::env::temp_dir()).await
+    }
+
+    /// Create the temporary directory under `parent` instead of the system's
+    /// temporary directory.
+    pub async fn new_in<P: AsRef<Path>>(parent: P) -> Result<Self> {
+        loop {
+            let path = parent
+                .as_ref()
+                .join(format!("floppy-disk-{:016x}", rand::random::<u64>()));
+            match tokio::fs::create_dir(&path).await {
+                Ok(()) => {
+                    debug!("created temp disk at {}", path.display());
+                    return Ok(Self {
+                        disk: TokioFloppyDisk::new(Some(path.clone())),
+                        path,
+                        closed: false,
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The temporary directory on the real filesystem.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leave the temporary directory in place instead of removing it, eg. to
+    /// look at after a failure. Returns its path on the real filesystem.
+    pub fn keep(mut self) -> PathBuf {
+        self.disarm();
+        self.path.clone()
+    }
+
+    pub(crate) fn disarm(&mut self) {
+        self.closed = true;
+    }
+
+    pub async fn close(mut self) -> Result<()> {
+        self.closed = true;
+        debug!("removing temp disk at {}", self.path.display());
+        tokio::fs::remove_dir_all(&self.path).await
+    }
+}
+
+impl Deref for TempFloppyDisk {
+    type Target = TokioFloppyDisk;
+
+    fn deref(&self) -> &Self::Target {
+        &self.disk
+    }
+}
+
+impl Drop for TempFloppyDisk {
+    fn drop(&mut self) {
+        if !self.closed {
+            debug!("removing temp disk at {}", self.path.display());
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+
+    #[tokio::test]
+    async fn test_temp_floppy_disk() -> Result<()> {
+        let fs = TempFloppyDisk::new().await?;
+        fs.create_dir_all("/a/b").await?;
+        fs.write("/a/b/c.txt", "asdf").await?;
+        let path = fs.path().to_path_buf();
+        assert_eq!(
+            "asdf",
+            tokio::fs::read_to_string(path.join("a/b/c.txt")).await?
+        );
+
+        drop(fs);
+        assert!(!tokio::fs::try_exists(&path).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_close() -> Result<()> {
+        async fn write_to<'a, D: FloppyDisk<'a>>(fs: &D) -> Result<()> {
+            fs.write("/test.txt", "asdf").await
+        }
+
+        let fs = TempFloppyDisk::new().await?;
+        write_to(&*fs).await?;
+        let path = fs.path().to_path_buf();
+        assert!(tokio::fs::try_exists(path.join("test.txt")).await?);
+
+        fs.close().await?;
+        assert!(!tokio::fs::try_exists(&path).await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+//! Assertions about the contents of disks, for tests.
+//!
+//! The macros await, so they can only be used in async tests.
+
+use std::fmt::Write;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::diff::{tree_diff, Change, DiffOptions, EntryInfo, EntryKind};
+use crate::{FloppyDirEntry, FloppyDisk, FloppyMetadata, FloppyReadDir, FloppyUnixPermissions};
+
+/// An entry that [`assert_tree_matches!`] expects to find.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    Dir,
+    File(Vec<u8>),
+    Symlink(PathBuf),
+}
+
+impl Expected {
+    pub fn file(contents: impl AsRef<[u8]>) -> Self {
+        Self::File(contents.as_ref().to_vec())
+    }
+
+    pub fn symlink(target: impl Into<PathBuf>) -> Self {
+        Self::Symlink(target.into())
+    }
+}
+
+/// Assert that two disks hold the same trees. Takes either two disks, which
+/// are compared from their roots, or a disk and root for each side.
+///
+/// On failure, every path that differs is listed, with line diffs of text
+/// files.
+#[macro_export]
+macro_rules! assert_disk_eq {
+    ($a:expr, $b:expr $(,)?) => {
+        $crate::assert_disk_eq!($a, "/", $b, "/")
+    };
+    ($a:expr, $a_root:expr, $b:expr, $b_root:expr $(,)?) => {
+        match $crate::testing::disk_mismatches(&$a, $a_root, &$b, $b_root).await {
+            Ok(None) => {}
+            Ok(Some(mismatches)) => panic!("disks differ:\n{mismatches}"),
+            Err(e) => panic!("couldn't compare disks: {e}"),
+        }
+    };
+}
+
+/// Assert that the tree under `root` on a disk is exactly `expected`, a list
+/// of `(path, Expected)` pairs with paths relative to `root`.
+///
+/// ```ignore
+/// assert_tree_matches!(disk, "/out", [
+///     ("bin", Expected::Dir),
+///     ("bin/app", Expected::file("#!/bin/sh\n")),
+///     ("app", Expected::symlink("bin/app")),
+/// ]);
+/// ```
+#[macro_export]
+macro_rules! assert_tree_matches {
+    ($disk:expr, $root:expr, $expected:expr $(,)?) => {
+        match $crate::testing::tree_mismatches(&$disk, $root, $expected).await {
+            Ok(None) => {}
+            Ok(Some(mismatches)) => panic!("tree doesn't match:\n{mismatches}"),
+            Err(e) => panic!("couldn't read tree: {e}"),
+        }
+    };
+}
+
+/// The differences between two trees, formatted for [`assert_disk_eq!`].
+#[doc(hidden)]
+pub async fn disk_mismatches<'a, 'b, A, B>(
+    a: &'a A,
+    a_root: impl AsRef<Path> + Send,
+    b: &'b B,
+    b_root: impl AsRef<Path> + Send,
+) -> Result<Option<String>>
+where
+    A: FloppyDisk<'a>,
+    B: FloppyDisk<'b>,
+    A::Permissions: FloppyUnixPermissions,
+    B::Permissions: FloppyUnixPermissions,
+{
+    let options = DiffOptions::new().with_content_diffs(true);
+    let diff = tree_diff(a, a_root, b, b_root, &options).await?;
+    if diff.is_empty() {
+        return Ok(None);
+    }
+
+    let mut out = String::new();
+    for (path, change) in &diff.changes {
+        let path = path.display();
+        let (summary, diff) = match change {
+            Change::Added(info) => (format!("only in right: {path} ({})", describe(info)), None),
+            Change::Removed(info) => (format!("only in left:  {path} ({})", describe(info)), None),
+            Change::Modified {
+                before,
+                after,
+                diff,
+            } => (
+                format!(
+                    "differs:       {path} ({} vs. {})",
+                    describe(before),
+                    describe(after)
+                ),
+                diff.as_deref(),
+            ),
+        };
+        writeln!(out, "  {summary}").unwrap();
+        for line in diff.unwrap_or_default().lines() {
+            writeln!(out, "      {line}").unwrap();
+        }
+    }
+    Ok(Some(out))
+}
+
+/// The differences between a tree and what was expected, formatted for
+/// [`assert_tree_matches!`].
+#[doc(hidden)]
+pub async fn tree_mismatches<'a, D, P>(
+    disk: &'a D,
+    root: impl AsRef<Path> + Send,
+    expected: impl IntoIterator<Item = (P, Expected)>,
+) -> Result<Option<String>>
+where
+    D: FloppyDisk<'a>,
+    P: AsRef<Path>,
+{
+    let root = root.as_ref();
+    let mut out = String::new();
+    let mut expected_paths = vec![];
+    for (path, expected) in expected {
+        let path = path.as_ref();
+        expected_paths.push(path.to_path_buf());
+        let full = root.join(path);
+        let metadata = match disk.symlink_metadata(&full).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                writeln!(out, "  missing:    {}", path.display()).unwrap();
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let found = if metadata.is_symlink() {
+            Expected::Symlink(disk.read_link(&full).await?)
+        } else if metadata.is_dir() {
+            Expected::Dir
+        } else {
+            Expected::File(disk.read(&full).await?)
+        };
+        if found != expected {
+            writeln!(
+                out,
+                "  mismatched: {} (expected {}, found {})",
+                path.display(),
+                describe_expected(&expected),
+                describe_expected(&found)
+            )
+            .unwrap();
+        }
+    }
+
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut read_dir = disk.read_dir(&dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+            if disk.symlink_metadata(&path).await?.is_dir() {
+                dirs.push(path.clone());
+            }
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            if !expected_paths.iter().any(|expected| expected == relative) {
+                writeln!(out, "  unexpected: {}", relative.display()).unwrap();
+            }
+        }
+    }
+
+    Ok((!out.is_empty()).then_some(out))
+}
+
+fn describe(info: &EntryInfo) -> String {
+    match info.kind {
+        EntryKind::Dir => format!("directory, mode {:o}", info.mode),
+        EntryKind::File => format!("file, {} bytes, mode {:o}", info.len, info.mode),
+        EntryKind::Symlink => format!(
+            "symlink to {}",
+            info.target.as_deref().unwrap_or(Path::new("")).display()
+        ),
+    }
+}
+
+fn describe_expected(expected: &Expected) -> String {
+    match expected {
+        Expected::Dir => "a directory".to_string(),
+        Expected::File(contents) => match std::str::from_utf8(contents) {
+            Ok(contents) => format!("file {contents:?}"),
+            Err(_) => format!("a {}-byte binary file", contents.len()),
+        },
+        Expected::Symlink(target) => format!("symlink to {}", target.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_assertions() -> Result<()> {
+        let a = MemFloppyDisk::new();
+        a.create_dir("/bin").await?;
+        a.write("/bin/app", "#!/bin/sh\n").await?;
+        a.symlink("bin/app", "/app").await?;
+        let b = MemFloppyDisk::new();
+        crate::copy::copy_all(&a, "/", &b, "/", &Default::default()).await?;
+
+        assert_disk_eq!(a, b);
+        assert_tree_matches!(
+            a,
+            "/",
+            [
+                ("bin", Expected::Dir),
+                ("bin/app", Expected::file("#!/bin/sh\n")),
+                ("app", Expected::symlink("bin/app")),
+            ]
+        );
+
+        b.write("/bin/app", "#!/bin/bash\n").await?;
+        let mismatches = disk_mismatches(&a, "/", &b, "/").await?.unwrap();
+        assert!(mismatches.contains("differs:       bin/app"));
+        assert!(mismatches.contains("-#!/bin/sh\n      +#!/bin/bash"));
+
+        let mismatches = tree_mismatches(&b, "/", [("bin/app", Expected::file("#!/bin/sh\n"))])
+            .await?
+            .unwrap();
+        assert!(mismatches.contains("mismatched: bin/app"));
+        assert!(mismatches.contains("unexpected: app"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1377 @@
+//! A fast in-memory layer over a slower disk. A [`TieredFloppyDisk`] keeps
+//! files that were just written or recently read in a [`MemFloppyDisk`],
+//! writes them back to the backing disk from a background task, and reads
+//! through to it on a miss.
+//!
+//! ```rust,no_run
+//! # use floppy_disk::prelude::*;
+//! # use floppy_disk::tiered::{TieredFloppyDisk, TieredOptions};
+//! # async fn example() -> std::io::Result<()> {
+//! let disk = TieredFloppyDisk::new(
+//!     TokioFloppyDisk::new(Some("/var/build".into())),
+//!     TieredOptions::new(),
+//! );
+//! disk.write("/scratch.o", "...").await?;
+//! // Removed before it was ever flushed, so it never reached /var/build.
+//! disk.remove_file("/scratch.o").await?;
+//! disk.flush().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Only file contents are held back. Directories, symlinks, removals and
+//! renames go straight to the backing disk, with anything they touch
+//! flushed first where that matters.
+//!
+//! Files are cached by the path they were used with, so a file reached
+//! through two different symlinked paths is cached twice until it's
+//! flushed. Anything that hasn't been flushed is lost if the disk is
+//! dropped without calling [`TieredFloppyDisk::flush`].
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt;
+use std::io::{Error, ErrorKind, IoSlice, Result};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::mem::{MemFile, MemFloppyDisk, MemMetadata, MemOpenOptions, MemPermissions};
+use crate::policy::normalise;
+use crate::*;
+
+#[derive(Debug, Clone)]
+pub struct TieredOptions {
+    flush_delay: Duration,
+    max_hot_bytes: u64,
+}
+
+impl Default for TieredOptions {
+    fn default() -> Self {
+        Self {
+            flush_delay: Duration::from_secs(1),
+            max_hot_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl TieredOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long a written file is only kept in memory before it's flushed.
+    /// Files removed sooner than this never reach the backing disk.
+    pub fn with_flush_delay(mut self, flush_delay: Duration) -> Self {
+        self.flush_delay = flush_delay;
+        self
+    }
+
+    /// How many bytes of files to keep in memory. Past this, the least
+    /// recently used files that have already been flushed, and aren't open,
+    /// are dropped from memory.
+    pub fn with_max_hot_bytes(mut self, max_hot_bytes: u64) -> Self {
+        self.max_hot_bytes = max_hot_bytes;
+        self
+    }
+}
+
+/// A [`MemFloppyDisk`] in front of a backing disk. See the [module
+/// docs](self).
+#[derive(Debug)]
+pub struct TieredFloppyDisk<D> {
+    tiers: Arc<Tiers<D>>,
+    flusher: JoinHandle<()>,
+}
+
+#[derive(Debug)]
+struct Tiers<D> {
+    hot: MemFloppyDisk,
+    cold: D,
+    options: TieredOptions,
+    files: Mutex<HashMap<PathBuf, HotFile>>,
+    /// Held while moving files between the layers, so that eg. a flush can't
+    /// bring back a file that was removed while it was being written back.
+    moving: tokio::sync::Mutex<()>,
+}
+
+#[derive(Debug)]
+struct HotFile {
+    len: u64,
+    used: Instant,
+    /// When the file was first changed since it was last flushed.
+    dirty: Option<Instant>,
+    /// Open handles, which keep the file from being dropped from memory.
+    open: usize,
+    /// Whether its mode was changed since it was last flushed. Otherwise the
+    /// backing disk's mode is left alone.
+    chmod: bool,
+    /// The device and inode numbers of its copy on the backing disk, once
+    /// it has one.
+    cold: Option<(u64, u64)>,
+}
+
+impl<D> TieredFloppyDisk<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+{
+    /// Layer memory over `cold`, flushing to it from a background task. Must
+    /// be called from within a tokio runtime.
+    pub fn new(cold: D, options: TieredOptions) -> Self {
+        let tiers = Arc::new(Tiers {
+            hot: MemFloppyDisk::new(),
+            cold,
+            options,
+            files: Mutex::new(HashMap::new()),
+            moving: tokio::sync::Mutex::new(()),
+        });
+        let flusher = tokio::spawn({
+            let tiers = tiers.clone();
+            async move {
+                let delay = tiers.options.flush_delay;
+                let mut interval = tokio::time::interval(delay.max(Duration::from_millis(1)));
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = tiers.flush_older_than(delay).await {
+                        debug!("failed to flush to the backing disk: {e}");
+                    }
+                }
+            }
+        });
+        Self { tiers, flusher }
+    }
+
+    /// Write every file that's only in memory back to the backing disk now.
+    pub async fn flush(&self) -> Result<()> {
+        self.tiers.flush_older_than(Duration::ZERO).await
+    }
+
+    pub fn backing(&self) -> &D {
+        &self.tiers.cold
+    }
+}
+
+impl<D> Drop for TieredFloppyDisk<D> {
+    fn drop(&mut self) {
+        self.flusher.abort();
+    }
+}
+
+/// What a file in memory takes from its copy on the backing disk.
+struct ColdMetadata {
+    mode: u32,
+    owner: Option<(u32, u32)>,
+    ids: Option<(u64, u64)>,
+}
+
+impl ColdMetadata {
+    fn new<'a, D>(metadata: &D::Metadata) -> Self
+    where
+        D: FloppyDisk<'a>,
+        D::Metadata: FloppyUnixMetadata,
+        D::Permissions: FloppyUnixPermissions,
+    {
+        Self {
+            mode: metadata.permissions().mode(),
+            owner: metadata.uid().ok().zip(metadata.gid().ok()),
+            ids: metadata.dev().ok().zip(metadata.ino().ok()),
+        }
+    }
+}
+
+impl<D> Tiers<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+{
+    /// Whether `path` is in memory, marking it as used if it is.
+    fn touch(&self, path: &Path) -> bool {
+        match self.files.lock().unwrap().get_mut(path) {
+            Some(file) => {
+                file.used = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn track(&self, path: &Path, len: u64, dirty: bool) {
+        let mut files = self.files.lock().unwrap();
+        let file = files.entry(path.to_path_buf()).or_insert(HotFile {
+            len,
+            used: Instant::now(),
+            dirty: None,
+            open: 0,
+            chmod: false,
+            cold: None,
+        });
+        file.len = len;
+        file.used = Instant::now();
+        if dirty {
+            file.dirty.get_or_insert_with(Instant::now);
+        } else {
+            file.dirty = None;
+        }
+    }
+
+    fn dirtied(&self, path: &Path) {
+        if let Some(file) = self.files.lock().unwrap().get_mut(path) {
+            file.used = Instant::now();
+            file.dirty.get_or_insert_with(Instant::now);
+        }
+    }
+
+    fn chmodded(&self, path: &Path) {
+        if let Some(file) = self.files.lock().unwrap().get_mut(path) {
+            file.used = Instant::now();
+            file.dirty.get_or_insert_with(Instant::now);
+            file.chmod = true;
+        }
+    }
+
+    /// Give the copy of `path` in memory the mode and owner of the one on
+    /// the backing disk, and remember where that one is, so that the file
+    /// looks the same in either layer.
+    async fn take_cold_metadata(&self, path: &Path, mode: bool) -> Result<()> {
+        let cold = ColdMetadata::new::<D>(&self.cold.metadata(path).await?);
+        if mode {
+            self.hot
+                .set_permissions(path, FloppyUnixPermissions::from_mode(cold.mode))
+                .await?;
+        }
+        if let Some((uid, gid)) = cold.owner {
+            self.hot.chown(path, uid, gid).await?;
+        }
+        if let Some(file) = self.files.lock().unwrap().get_mut(path) {
+            file.cold = cold.ids;
+        }
+        Ok(())
+    }
+
+    fn opened(&self, path: &Path, open: bool) {
+        if let Some(file) = self.files.lock().unwrap().get_mut(path) {
+            if open {
+                file.open += 1;
+            } else {
+                file.open = file.open.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Make `path`'s parent in memory, if it's a directory on the backing
+    /// disk.
+    async fn hot_parent(&self, path: &Path) -> Result<()> {
+        let Some(parent) = path.parent() else {
+            return Ok(());
+        };
+        if self.hot.try_exists(parent).await? {
+            return Ok(());
+        }
+        if !self.cold.metadata(parent).await?.is_dir() {
+            return Err(Error::from_raw_os_error(libc::ENOTDIR));
+        }
+        self.hot.create_dir_all(parent).await
+    }
+
+    /// Bring the file at `path` into memory from the backing disk, unless
+    /// it's there already, returning whether it exists at all. Call with
+    /// `moving` held.
+    async fn fault_in(&self, path: &Path) -> Result<bool> {
+        if self.touch(path) {
+            return Ok(true);
+        }
+        let data = match self.cold.read(path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        self.hot_parent(path).await?;
+        self.hot.write(path, &data).await?;
+        self.track(path, data.len() as u64, false);
+        self.take_cold_metadata(path, true).await?;
+        self.evict(path).await;
+        Ok(true)
+    }
+
+    /// Drop least recently used files from memory until they fit, other than
+    /// `keep`, or files that are open or haven't been flushed. Call with
+    /// `moving` held.
+    async fn evict(&self, keep: &Path) {
+        let victims = {
+            let mut files = self.files.lock().unwrap();
+            let mut total: u64 = files.values().map(|file| file.len).sum();
+            let mut candidates: Vec<_> = files
+                .iter()
+                .filter(|(path, file)| {
+                    file.dirty.is_none() && file.open == 0 && path.as_path() != keep
+                })
+                .map(|(path, file)| (file.used, path.clone()))
+                .collect();
+            candidates.sort();
+            let mut victims = vec![];
+            for (_, path) in candidates {
+                if total <= self.options.max_hot_bytes {
+                    break;
+                }
+                total -= files.remove(&path).map(|file| file.len).unwrap_or(0);
+                victims.push(path);
+            }
+            victims
+        };
+        for path in victims {
+            let _ = self.hot.remove_file(&path).await;
+        }
+    }
+
+    async fn flush_older_than(&self, age: Duration) -> Result<()> {
+        let _moving = self.moving.lock().await;
+        let due: Vec<PathBuf> = self
+            .files
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, file)| file.dirty.is_some_and(|dirty| dirty.elapsed() >= age))
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in due {
+            self.flush_file(&path).await?;
+        }
+        self.evict(Path::new("")).await;
+        Ok(())
+    }
+
+    /// Flush everything at or under `path`. Call with `moving` held.
+    async fn flush_under(&self, path: &Path) -> Result<()> {
+        let due: Vec<PathBuf> = self
+            .files
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(key, file)| key.starts_with(path) && file.dirty.is_some())
+            .map(|(key, _)| key.clone())
+            .collect();
+        for path in due {
+            self.flush_file(&path).await?;
+        }
+        Ok(())
+    }
+
+    /// Write the file at `path` back, if it's changed. Call with `moving`
+    /// held.
+    async fn flush_file(&self, path: &Path) -> Result<()> {
+        // Taken before reading, so that writes made while this one is under
+        // way are flushed again next time.
+        let (dirty, chmod) = match self.files.lock().unwrap().get_mut(path) {
+            Some(file) => (file.dirty.take(), std::mem::take(&mut file.chmod)),
+            None => (None, false),
+        };
+        let Some(dirty) = dirty else {
+            return Ok(());
+        };
+        debug!("flushing {}", path.display());
+        let result = async {
+            let data = self.hot.read(path).await?;
+            self.cold.write(path, &data).await?;
+            if chmod {
+                let mode = self.hot.metadata(path).await?.permissions().mode();
+                self.cold
+                    .set_permissions(path, FloppyUnixPermissions::from_mode(mode))
+                    .await?;
+            }
+            // A new file's mode and owner are whatever the backing disk gave
+            // it.
+            self.take_cold_metadata(path, !chmod).await?;
+            Ok(data.len() as u64)
+        }
+        .await;
+        let mut files = self.files.lock().unwrap();
+        match (result, files.get_mut(path)) {
+            (Ok(len), Some(file)) => {
+                file.len = len;
+                Ok(())
+            }
+            (Err(e), Some(file)) => {
+                file.dirty.get_or_insert(dirty);
+                file.chmod |= chmod;
+                Err(e)
+            }
+            (result, None) => result.map(|_| ()),
+        }
+    }
+
+    /// Drop everything at or under `path` from memory, flushed or not. Call
+    /// with `moving` held.
+    async fn forget_under(&self, path: &Path) {
+        self.files
+            .lock()
+            .unwrap()
+            .retain(|key, _| !key.starts_with(path));
+        match self.hot.symlink_metadata(path).await {
+            Ok(metadata) if metadata.is_dir() => {
+                let _ = self.hot.remove_dir_all(path).await;
+            }
+            Ok(_) => {
+                let _ = self.hot.remove_file(path).await;
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn hot_metadata(&self, path: &Path, metadata: MemMetadata) -> TieredMetadata<D> {
+        let cold = self
+            .files
+            .lock()
+            .unwrap()
+            .get(path)
+            .and_then(|file| file.cold);
+        TieredMetadata::Hot(metadata, cold)
+    }
+
+    async fn metadata(&self, path: &Path) -> Result<TieredMetadata<D>> {
+        if self.touch(path) {
+            let metadata = self.hot.metadata(path).await?;
+            return Ok(self.hot_metadata(path, metadata));
+        }
+        self.cold.metadata(path).await.map(TieredMetadata::Cold)
+    }
+
+    async fn symlink_metadata(&self, path: &Path) -> Result<TieredMetadata<D>> {
+        // Files in memory may have been read through a symlink, which is
+        // only a symlink on the backing disk.
+        let cold = self.cold.symlink_metadata(path).await;
+        match cold {
+            Ok(metadata) if metadata.is_symlink() => Ok(TieredMetadata::Cold(metadata)),
+            _ if self.touch(path) => {
+                let metadata = self.hot.metadata(path).await?;
+                Ok(self.hot_metadata(path, metadata))
+            }
+            cold => cold.map(TieredMetadata::Cold),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyDisk<'a> for TieredFloppyDisk<D>
+where
+    D: for<'b> FloppyDisk<'b> + Sync + 'static,
+    for<'b> <D as FloppyDisk<'b>>::Permissions: FloppyUnixPermissions,
+    for<'b> <D as FloppyDisk<'b>>::Metadata: FloppyUnixMetadata,
+{
+    type DirBuilder = TieredDirBuilder<D>;
+    type DirEntry = TieredDirEntry<D>;
+    type File = TieredFile<D>;
+    type FileType = TieredFileType;
+    type Metadata = TieredMetadata<D>;
+    type OpenOptions = TieredOpenOptions<D>;
+    type Permissions = MemPermissions;
+    type ReadDir = TieredReadDir<D>;
+
+    async fn canonicalize<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        let path = normalise(path.as_ref());
+        match self.tiers.cold.canonicalize(&path).await {
+            // Not flushed yet, so only its directory is on the backing disk.
+            Err(e) if e.kind() == ErrorKind::NotFound && self.tiers.touch(&path) => {
+                let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+                    return Err(e);
+                };
+                Ok(self.tiers.cold.canonicalize(parent).await?.join(name))
+            }
+            result => result,
+        }
+    }
+
+    async fn copy<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<u64> {
+        let data = self.read(from).await?;
+        self.write(to, &data).await?;
+        Ok(data.len() as u64)
+    }
+
+    async fn create_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.tiers.cold.create_dir(path).await
+    }
+
+    async fn create_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        self.tiers.cold.create_dir_all(path).await
+    }
+
+    async fn hard_link<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        let src = normalise(src.as_ref());
+        let _moving = self.tiers.moving.lock().await;
+        // The backing disk's copy is about to be written through another
+        // path, so the one in memory would go stale.
+        self.tiers.flush_under(&src).await?;
+        self.tiers.forget_under(&src).await;
+        self.tiers.cold.hard_link(src.as_path(), dst.as_ref()).await
+    }
+
+    async fn metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        self.tiers.metadata(&normalise(path.as_ref())).await
+    }
+
+    async fn read<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>> {
+        let path = normalise(path.as_ref());
+        let _moving = self.tiers.moving.lock().await;
+        if !self.tiers.fault_in(&path).await? {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{} not found", path.display()),
+            ));
+        }
+        self.tiers.hot.read(&path).await
+    }
+
+    async fn read_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::ReadDir> {
+        let path = normalise(path.as_ref());
+        let mut entries = vec![];
+        let mut names = HashSet::new();
+        let mut read_dir = self.tiers.cold.read_dir(&path).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            names.insert(entry.file_name());
+            entries.push(TieredDirEntry {
+                path: path.join(entry.file_name()),
+                name: entry.file_name(),
+                ino: entry.ino(),
+                tiers: self.tiers.clone(),
+            });
+        }
+        // Files that haven't been flushed yet.
+        let hot: Vec<_> = self
+            .tiers
+            .files
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|key| key.parent() == Some(path.as_path()))
+            .filter_map(|key| key.file_name().map(|name| name.to_os_string()))
+            .filter(|name| !names.contains(name))
+            .collect();
+        for name in hot {
+            entries.push(TieredDirEntry {
+                path: path.join(&name),
+                name,
+                ino: 0,
+                tiers: self.tiers.clone(),
+            });
+        }
+        Ok(TieredReadDir {
+            entries: entries.into_iter(),
+        })
+    }
+
+    async fn read_link<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        self.tiers.cold.read_link(path).await
+    }
+
+    async fn read_to_string<P: AsRef<Path> + Send>(&self, path: P) -> Result<String> {
+        String::from_utf8(self.read(path).await?).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    async fn remove_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = normalise(path.as_ref());
+        let _moving = self.tiers.moving.lock().await;
+        // So that the backing disk refuses if there's anything in it.
+        self.tiers.flush_under(&path).await?;
+        self.tiers.cold.remove_dir(&path).await?;
+        self.tiers.forget_under(&path).await;
+        Ok(())
+    }
+
+    async fn remove_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = normalise(path.as_ref());
+        let _moving = self.tiers.moving.lock().await;
+        self.tiers.forget_under(&path).await;
+        self.tiers.cold.remove_dir_all(&path).await
+    }
+
+    async fn remove_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = normalise(path.as_ref());
+        let _moving = self.tiers.moving.lock().await;
+        let hot = self.tiers.touch(&path);
+        self.tiers.forget_under(&path).await;
+        match self.tiers.cold.remove_file(&path).await {
+            // It was never flushed.
+            Err(e) if e.kind() == ErrorKind::NotFound && hot => Ok(()),
+            result => result,
+        }
+    }
+
+    async fn rename<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<()> {
+        let (from, to) = (normalise(from.as_ref()), normalise(to.as_ref()));
+        let _moving = self.tiers.moving.lock().await;
+        self.tiers.flush_under(&from).await?;
+        self.tiers.cold.rename(&from, &to).await?;
+        self.tiers.forget_under(&from).await;
+        self.tiers.forget_under(&to).await;
+        Ok(())
+    }
+
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        perm: Self::Permissions,
+    ) -> Result<()> {
+        let path = normalise(path.as_ref());
+        if self.tiers.touch(&path) {
+            // Flushing carries the mode over.
+            self.tiers.hot.set_permissions(&path, perm).await?;
+            self.tiers.chmodded(&path);
+            return Ok(());
+        }
+        self.tiers
+            .cold
+            .set_permissions(&path, FloppyUnixPermissions::from_mode(perm.mode()))
+            .await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        self.tiers.cold.symlink(src, dst).await
+    }
+
+    async fn symlink_metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        self.tiers.symlink_metadata(&normalise(path.as_ref())).await
+    }
+
+    async fn try_exists<P: AsRef<Path> + Send>(&self, path: P) -> Result<bool> {
+        let path = normalise(path.as_ref());
+        if self.tiers.touch(&path) {
+            return Ok(true);
+        }
+        self.tiers.cold.try_exists(&path).await
+    }
+
+    async fn write<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        let path = normalise(path.as_ref());
+        let contents = contents.as_ref();
+        let _moving = self.tiers.moving.lock().await;
+        self.tiers.hot_parent(&path).await?;
+        self.tiers.hot.write(&path, contents).await?;
+        self.tiers.track(&path, contents.len() as u64, true);
+        self.tiers.evict(&path).await;
+        Ok(())
+    }
+
+    fn new_dir_builder(&'a self) -> Self::DirBuilder {
+        TieredDirBuilder {
+            tiers: self.tiers.clone(),
+            recursive: false,
+            mode: None,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<D> FloppyDiskUnixExt for TieredFloppyDisk<D>
+where
+    D: for<'a> FloppyDisk<'a> + FloppyDiskUnixExt + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+{
+    async fn chown<P: Into<PathBuf> + Send>(&self, path: P, uid: u32, gid: u32) -> Result<()> {
+        let path = normalise(&path.into());
+        let _moving = self.tiers.moving.lock().await;
+        self.tiers.flush_under(&path).await?;
+        self.tiers.forget_under(&path).await;
+        self.tiers.cold.chown(path, uid, gid).await
+    }
+}
+
+/// Creates directories on the backing disk straight away.
+#[derive(Debug)]
+pub struct TieredDirBuilder<D> {
+    tiers: Arc<Tiers<D>>,
+    recursive: bool,
+    mode: Option<u32>,
+}
+
+#[async_trait::async_trait]
+impl<D> FloppyDirBuilder for TieredDirBuilder<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+{
+    fn recursive(&mut self, recursive: bool) -> &mut Self {
+        self.recursive = recursive;
+        self
+    }
+
+    async fn create<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if self.recursive {
+            self.tiers.cold.create_dir_all(path).await?;
+        } else {
+            self.tiers.cold.create_dir(path).await?;
+        }
+        if let Some(mode) = self.mode {
+            self.tiers
+                .cold
+                .set_permissions(path, FloppyUnixPermissions::from_mode(mode))
+                .await?;
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = Some(mode);
+        self
+    }
+}
+
+#[derive(Debug)]
+pub struct TieredDirEntry<D> {
+    path: PathBuf,
+    name: OsString,
+    ino: u64,
+    tiers: Arc<Tiers<D>>,
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyDirEntry<'a, TieredFloppyDisk<D>> for TieredDirEntry<D>
+where
+    D: for<'b> FloppyDisk<'b> + Sync + 'static,
+    for<'b> <D as FloppyDisk<'b>>::Permissions: FloppyUnixPermissions,
+    for<'b> <D as FloppyDisk<'b>>::Metadata: FloppyUnixMetadata,
+{
+    fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn file_name(&self) -> OsString {
+        self.name.clone()
+    }
+
+    async fn metadata(&self) -> Result<TieredMetadata<D>> {
+        self.tiers.symlink_metadata(&self.path).await
+    }
+
+    async fn file_type(&self) -> Result<TieredFileType> {
+        Ok(FloppyMetadata::<TieredFloppyDisk<D>>::file_type(
+            &self.metadata().await?,
+        ))
+    }
+
+    /// The backing disk's inode number, or 0 for files that haven't been
+    /// flushed yet.
+    #[cfg(unix)]
+    fn ino(&self) -> u64 {
+        self.ino
+    }
+}
+
+#[derive(Debug)]
+pub struct TieredReadDir<D> {
+    entries: std::vec::IntoIter<TieredDirEntry<D>>,
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyReadDir<'a, TieredFloppyDisk<D>> for TieredReadDir<D>
+where
+    D: for<'b> FloppyDisk<'b> + Sync + 'static,
+    for<'b> <D as FloppyDisk<'b>>::Permissions: FloppyUnixPermissions,
+    for<'b> <D as FloppyDisk<'b>>::Metadata: FloppyUnixMetadata,
+{
+    async fn next_entry(&mut self) -> Result<Option<TieredDirEntry<D>>> {
+        Ok(self.entries.next())
+    }
+}
+
+/// Metadata from whichever layer the file was found in.
+pub enum TieredMetadata<D: FloppyDisk<'static>> {
+    /// A file in memory, with the device and inode numbers of its copy on
+    /// the backing disk, if it's been flushed or read through.
+    Hot(MemMetadata, Option<(u64, u64)>),
+    Cold(D::Metadata),
+}
+
+impl<D: FloppyDisk<'static>> fmt::Debug for TieredMetadata<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TieredMetadata::Hot(metadata, cold) => {
+                f.debug_tuple("Hot").field(metadata).field(cold).finish()
+            }
+            TieredMetadata::Cold(metadata) => f.debug_tuple("Cold").field(metadata).finish(),
+        }
+    }
+}
+
+macro_rules! either {
+    ( $metadata: expr, $m: ident => $e: expr ) => {
+        match $metadata {
+            TieredMetadata::Hot($m, _) => $e,
+            TieredMetadata::Cold($m) => $e,
+        }
+    };
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyMetadata<'a, TieredFloppyDisk<D>> for TieredMetadata<D>
+where
+    D: for<'b> FloppyDisk<'b> + Sync + 'static,
+    for<'b> <D as FloppyDisk<'b>>::Permissions: FloppyUnixPermissions,
+    for<'b> <D as FloppyDisk<'b>>::Metadata: FloppyUnixMetadata,
+{
+    fn file_type(&self) -> TieredFileType {
+        TieredFileType {
+            dir: self.is_dir(),
+            file: self.is_file(),
+            symlink: self.is_symlink(),
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        either!(self, m => m.is_dir())
+    }
+
+    fn is_file(&self) -> bool {
+        either!(self, m => m.is_file())
+    }
+
+    fn is_symlink(&self) -> bool {
+        either!(self, m => m.is_symlink())
+    }
+
+    fn len(&self) -> u64 {
+        either!(self, m => m.len())
+    }
+
+    fn permissions(&self) -> MemPermissions {
+        FloppyUnixPermissions::from_mode(either!(self, m => m.permissions().mode()))
+    }
+
+    fn modified(&self) -> Result<SystemTime> {
+        either!(self, m => m.modified())
+    }
+
+    fn accessed(&self) -> Result<SystemTime> {
+        either!(self, m => m.accessed())
+    }
+
+    fn created(&self) -> Result<SystemTime> {
+        either!(self, m => m.created())
+    }
+}
+
+impl<D: FloppyDisk<'static>> FloppyUnixMetadata for TieredMetadata<D>
+where
+    D::Metadata: FloppyUnixMetadata,
+{
+    fn uid(&self) -> Result<u32> {
+        either!(self, m => m.uid())
+    }
+
+    fn gid(&self) -> Result<u32> {
+        either!(self, m => m.gid())
+    }
+
+    fn dev(&self) -> Result<u64> {
+        match self {
+            TieredMetadata::Hot(_, Some((dev, _))) => Ok(*dev),
+            _ => either!(self, m => m.dev()),
+        }
+    }
+
+    fn ino(&self) -> Result<u64> {
+        match self {
+            TieredMetadata::Hot(_, Some((_, ino))) => Ok(*ino),
+            _ => either!(self, m => m.ino()),
+        }
+    }
+
+    fn blocks(&self) -> Result<u64> {
+        either!(self, m => m.blocks())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TieredFileType {
+    dir: bool,
+    file: bool,
+    symlink: bool,
+}
+
+impl FloppyFileType for TieredFileType {
+    fn is_dir(&self) -> bool {
+        self.dir
+    }
+
+    fn is_file(&self) -> bool {
+        self.file
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.symlink
+    }
+}
+
+/// Files are opened in memory, after reading them through from the backing
+/// disk if they aren't there already.
+pub struct TieredOpenOptions<D> {
+    options: MemOpenOptions,
+    write: bool,
+    create: bool,
+    _disk: PhantomData<fn() -> D>,
+}
+
+impl<D> fmt::Debug for TieredOpenOptions<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TieredOpenOptions")
+            .field("options", &self.options)
+            .field("write", &self.write)
+            .field("create", &self.create)
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyOpenOptions<'a, TieredFloppyDisk<D>> for TieredOpenOptions<D>
+where
+    D: for<'b> FloppyDisk<'b> + Sync + 'static,
+    for<'b> <D as FloppyDisk<'b>>::Permissions: FloppyUnixPermissions,
+    for<'b> <D as FloppyDisk<'b>>::Metadata: FloppyUnixMetadata,
+{
+    fn new() -> Self {
+        Self {
+            options: <MemOpenOptions as FloppyOpenOptions<MemFloppyDisk>>::new(),
+            write: false,
+            create: false,
+            _disk: PhantomData,
+        }
+    }
+
+    fn read(mut self, read: bool) -> Self {
+        self.options = self.options.read(read);
+        self
+    }
+
+    fn write(mut self, write: bool) -> Self {
+        self.options = self.options.write(write);
+        self.write |= write;
+        self
+    }
+
+    fn append(mut self, append: bool) -> Self {
+        self.options = self.options.append(append);
+        self.write |= append;
+        self
+    }
+
+    fn truncate(mut self, truncate: bool) -> Self {
+        self.options = self.options.truncate(truncate);
+        self.write |= truncate;
+        self
+    }
+
+    fn create(mut self, create: bool) -> Self {
+        self.options = self.options.create(create);
+        self.create |= create;
+        self
+    }
+
+    fn create_new(mut self, create_new: bool) -> Self {
+        self.options = self.options.create_new(create_new);
+        self.create |= create_new;
+        self
+    }
+
+    async fn open<P: AsRef<Path> + Send>(
+        &self,
+        disk: &'a TieredFloppyDisk<D>,
+        path: P,
+    ) -> Result<TieredFile<D>> {
+        let tiers = &disk.tiers;
+        let path = normalise(path.as_ref());
+        let existed = {
+            let _moving = tiers.moving.lock().await;
+            let existed = tiers.fault_in(&path).await?;
+            if !existed {
+                if !self.create {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("{} not found", path.display()),
+                    ));
+                }
+                tiers.hot_parent(&path).await?;
+                tiers.track(&path, 0, true);
+            }
+            tiers.opened(&path, true);
+            existed
+        };
+        match self.options.open(&tiers.hot, &path).await {
+            Ok(file) => {
+                if self.write {
+                    tiers.dirtied(&path);
+                }
+                Ok(TieredFile {
+                    file,
+                    path,
+                    tiers: tiers.clone(),
+                })
+            }
+            Err(e) => {
+                tiers.opened(&path, false);
+                if !existed {
+                    tiers.files.lock().unwrap().remove(&path);
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// An open file in memory. Writes to it are flushed like any other, and
+/// [`sync_all`](FloppyFile::sync_all) flushes it straight away.
+#[derive(Debug)]
+pub struct TieredFile<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+{
+    file: MemFile,
+    path: PathBuf,
+    tiers: Arc<Tiers<D>>,
+}
+
+impl<D> TieredFile<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+{
+    async fn flush_now(&self) -> Result<()> {
+        let _moving = self.tiers.moving.lock().await;
+        self.tiers.flush_file(&self.path).await
+    }
+}
+
+impl<D> Drop for TieredFile<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+{
+    fn drop(&mut self) {
+        self.tiers.opened(&self.path, false);
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyFile<'a, TieredFloppyDisk<D>> for TieredFile<D>
+where
+    D: for<'b> FloppyDisk<'b> + Sync + 'static,
+    for<'b> <D as FloppyDisk<'b>>::Permissions: FloppyUnixPermissions,
+    for<'b> <D as FloppyDisk<'b>>::Metadata: FloppyUnixMetadata,
+{
+    async fn sync_all(&mut self) -> Result<()> {
+        self.flush_now().await
+    }
+
+    async fn sync_data(&mut self) -> Result<()> {
+        self.flush_now().await
+    }
+
+    async fn set_len(&mut self, size: u64) -> Result<()> {
+        self.file.set_len(size).await?;
+        self.tiers.dirtied(&self.path);
+        Ok(())
+    }
+
+    async fn metadata(&self) -> Result<TieredMetadata<D>> {
+        let metadata = self.file.metadata().await?;
+        Ok(self.tiers.hot_metadata(&self.path, metadata))
+    }
+
+    async fn try_clone(&'a self) -> Result<Box<TieredFile<D>>> {
+        let file = *self.file.try_clone().await?;
+        self.tiers.opened(&self.path, true);
+        Ok(Box::new(TieredFile {
+            file,
+            path: self.path.clone(),
+            tiers: self.tiers.clone(),
+        }))
+    }
+
+    async fn set_permissions(&self, perm: MemPermissions) -> Result<()> {
+        self.file.set_permissions(perm).await?;
+        self.tiers.chmodded(&self.path);
+        Ok(())
+    }
+
+    async fn permissions(&self) -> Result<MemPermissions> {
+        self.file.permissions().await
+    }
+
+    async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.file.read_at(buf, offset).await
+    }
+
+    async fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        let written = self.file.write_at(buf, offset).await?;
+        self.tiers.dirtied(&self.path);
+        Ok(written)
+    }
+
+    async fn allocate(&self, offset: u64, len: u64) -> Result<()> {
+        self.file.allocate(offset, len).await?;
+        self.tiers.dirtied(&self.path);
+        Ok(())
+    }
+}
+
+impl<D> AsyncRead for TieredFile<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_read(cx, buf)
+    }
+}
+
+impl<D> AsyncWrite for TieredFile<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.file).poll_write(cx, buf);
+        if let Poll::Ready(Ok(_)) = result {
+            this.tiers.dirtied(&this.path);
+        }
+        result
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.file).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(_)) = result {
+            this.tiers.dirtied(&this.path);
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.file.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_shutdown(cx)
+    }
+}
+
+impl<D> AsyncSeek for TieredFile<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+    for<'a> <D as FloppyDisk<'a>>::Permissions: FloppyUnixPermissions,
+    for<'a> <D as FloppyDisk<'a>>::Metadata: FloppyUnixMetadata,
+{
+    fn start_seek(self: Pin<&mut Self>, position: std::io::SeekFrom) -> Result<()> {
+        Pin::new(&mut self.get_mut().file).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        Pin::new(&mut self.get_mut().file).poll_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tiered_disk() -> Result<()> {
+        let cold = MemFloppyDisk::new();
+        cold.create_dir_all("/src").await?;
+        cold.write("/src/main.rs", "fn main() {}").await?;
+        let disk = TieredFloppyDisk::new(
+            cold,
+            TieredOptions::new().with_flush_delay(Duration::from_secs(3600)),
+        );
+
+        // Read through, and kept in memory after that.
+        assert_eq!("fn main() {}", disk.read_to_string("/src/main.rs").await?);
+        disk.backing().write("/src/main.rs", "changed").await?;
+        assert_eq!("fn main() {}", disk.read_to_string("/src/main.rs").await?);
+
+        // Writes stay in memory until they're flushed.
+        disk.create_dir("/target").await?;
+        disk.write("/target/main.o", "obj").await?;
+        disk.write("/target/tmp.o", "tmp").await?;
+        assert!(!disk.backing().try_exists("/target/main.o").await?);
+        assert_eq!("obj", disk.read_to_string("/target/main.o").await?);
+        let mut names = vec![];
+        let mut read_dir = disk.read_dir("/target").await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            assert!(entry.file_type().await?.is_file());
+            names.push(entry.file_name());
+        }
+        names.sort();
+        assert_eq!(vec!["main.o", "tmp.o"], names);
+
+        // Removed before it was flushed, so it never reaches the backing disk.
+        disk.remove_file("/target/tmp.o").await?;
+        disk.flush().await?;
+        assert_eq!(
+            "obj",
+            disk.backing().read_to_string("/target/main.o").await?
+        );
+        assert!(!disk.backing().try_exists("/target/tmp.o").await?);
+
+        let mut file = TieredOpenOptions::new()
+            .write(true)
+            .append(true)
+            .open(&disk, "/target/main.o")
+            .await?;
+        file.write_all(b"ect").await?;
+        file.sync_all().await?;
+        assert_eq!(
+            "object",
+            disk.backing().read_to_string("/target/main.o").await?
+        );
+        drop(file);
+
+        disk.rename("/target", "/out").await?;
+        assert_eq!("object", disk.read_to_string("/out/main.o").await?);
+        assert!(disk.read("/target/main.o").await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tiered_metadata() -> Result<()> {
+        let cold = MemFloppyDisk::new();
+        cold.write("/a.txt", "asdf").await?;
+        cold.set_permissions("/a.txt", FloppyUnixPermissions::from_mode(0o600))
+            .await?;
+        cold.chown("/a.txt", 1234, 5678).await?;
+        cold.symlink("/a.txt", "/b.txt").await?;
+        let dev = cold.metadata("/").await?.dev()?;
+        let disk = TieredFloppyDisk::new(
+            cold,
+            TieredOptions::new().with_flush_delay(Duration::from_secs(3600)),
+        );
+
+        // Read through with its mode, owner and identity.
+        disk.read("/a.txt").await?;
+        let metadata = disk.metadata("/a.txt").await?;
+        assert!(matches!(metadata, TieredMetadata::Hot(..)));
+        assert_eq!(0o600, metadata.permissions().mode() & 0o777);
+        assert_eq!((1234, 5678), (metadata.uid()?, metadata.gid()?));
+        assert_eq!(dev, metadata.dev()?);
+        assert!(same_file(&disk, "/a.txt", "/b.txt").await?);
+
+        // Writing it back leaves a mode changed underneath it alone...
+        disk.backing()
+            .set_permissions("/a.txt", FloppyUnixPermissions::from_mode(0o640))
+            .await?;
+        disk.write("/a.txt", "jkl").await?;
+        disk.flush().await?;
+        let mode = disk
+            .backing()
+            .metadata("/a.txt")
+            .await?
+            .permissions()
+            .mode();
+        assert_eq!(0o640, mode & 0o777);
+        let mode = disk.metadata("/a.txt").await?.permissions().mode();
+        assert_eq!(0o640, mode & 0o777);
+
+        // ...unless it was changed through this disk.
+        disk.set_permissions("/a.txt", FloppyUnixPermissions::from_mode(0o644))
+            .await?;
+        disk.flush().await?;
+        let mode = disk
+            .backing()
+            .metadata("/a.txt")
+            .await?
+            .permissions()
+            .mode();
+        assert_eq!(0o644, mode & 0o777);
+
+        // New files take the backing disk's identity once they're flushed.
+        disk.write("/c.txt", "c").await?;
+        disk.flush().await?;
+        let ino = disk.backing().metadata("/c.txt").await?.ino()?;
+        assert_eq!(ino, disk.metadata("/c.txt").await?.ino()?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tiered_flush_and_evict() -> Result<()> {
+        let disk = TieredFloppyDisk::new(
+            MemFloppyDisk::new(),
+            TieredOptions::new()
+                .with_flush_delay(Duration::from_millis(10))
+                .with_max_hot_bytes(8),
+        );
+        disk.write("/a", "aaaa").await?;
+        disk.write("/b", "bbbb").await?;
+        let mut file = TieredOpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&disk, "/c")
+            .await?;
+        file.write_all(b"cccc").await?;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Flushed in the background, and only what fits is kept in memory.
+        for (path, contents) in [("/a", "aaaa"), ("/b", "bbbb"), ("/c", "cccc")] {
+            assert_eq!(contents, disk.backing().read_to_string(path).await?);
+        }
+        let hot = disk.tiers.files.lock().unwrap().len();
+        assert!(hot <= 2);
+        // The open file stays.
+        assert!(disk
+            .tiers
+            .files
+            .lock()
+            .unwrap()
+            .contains_key(Path::new("/c")));
+        drop(file);
+
+        disk.backing().write("/a", "changed").await?;
+        let mut contents = String::new();
+        TieredOpenOptions::new()
+            .read(true)
+            .open(&disk, "/a")
+            .await?
+            .read_to_string(&mut contents)
+            .await?;
+        assert_eq!("changed", contents);
+
+        Ok(())
+    }
+}
@@ -1,41 +1,243 @@
+use std::borrow::Borrow;
 use std::ffi::OsString;
 use std::fs::{FileType, Metadata, Permissions};
+use std::io::{Error, ErrorKind, IoSlice};
 use std::os::unix::prelude::PermissionsExt;
+use std::path::Component;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::SystemTime;
 
+use futures::StreamExt;
 use tokio::fs::{DirBuilder, DirEntry, File, OpenOptions, ReadDir};
 use tokio::io::ReadBuf;
 use tracing::debug;
 
+use crate::profile::Profiler;
 use crate::*;
 
+/// How many stats [`FloppyDisk::metadata_many`] runs at once.
+const METADATA_CONCURRENCY: usize = 64;
+
 #[derive(Default, Debug)]
 pub struct TokioFloppyDisk {
     scope: Option<PathBuf>,
+    audit: Option<Arc<ScopeAudit>>,
+    profiler: Option<Arc<Profiler>>,
 }
 
 impl TokioFloppyDisk {
     pub fn new(scope: Option<PathBuf>) -> Self {
-        Self { scope }
+        Self {
+            scope,
+            audit: None,
+            profiler: None,
+        }
+    }
+
+    /// Log every path translated into the scope, and every path rejected
+    /// for escaping it, to the `floppy_disk::scope` tracing target, and
+    /// count them in `audit`.
+    pub fn with_audit(mut self, audit: Arc<ScopeAudit>) -> Self {
+        self.audit = Some(audit);
+        self
     }
-}
 
-macro_rules! scoped {
-    ( $this: expr, $x:ident ) => {
-        let $x = if let Some(ref scope) = $this.scope {
-            let path: &Path = $x.as_ref();
-            if path.starts_with(scope) {
-                path.to_path_buf()
-            } else {
-                let path = path.strip_prefix("/").unwrap_or(&path).to_path_buf();
-                scope.join(path)
+    /// Sample operations on this disk into `profiler`.
+    pub fn with_profiler(mut self, profiler: Arc<Profiler>) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    /// Have the kernel read the files in `set` into the page cache, eg. at
+    /// startup for a service that knows which files it'll serve first.
+    /// Directories are warmed recursively. `progress` is called after each
+    /// file.
+    pub async fn warm(
+        &self,
+        set: &WarmSet,
+        mut progress: impl FnMut(&WarmProgress) + Send,
+    ) -> Result<WarmProgress> {
+        #[cfg(not(feature = "glob"))]
+        let roots = set.paths.clone();
+        #[cfg(feature = "glob")]
+        let roots = {
+            use crate::glob::FloppyDiskGlobExt;
+            use futures::TryStreamExt;
+
+            let mut roots = set.paths.clone();
+            for pattern in &set.globs {
+                roots.extend(self.glob(pattern)?.try_collect::<Vec<_>>().await?);
             }
-        } else {
-            let path: &Path = $x.as_ref();
+            roots
+        };
+
+        let mut files = vec![];
+        for root in roots {
+            if !self.metadata(&root).await?.is_dir() {
+                files.push(root);
+                continue;
+            }
+            let mut walk = crate::walk::FloppyWalkDir::new(self, &root);
+            while let Some(entry) = walk.next_entry().await? {
+                if entry.metadata().is_file() {
+                    files.push(entry.into_path());
+                }
+            }
+        }
+
+        let mut done = WarmProgress {
+            files: 0,
+            total_files: files.len() as u64,
+            bytes: 0,
+        };
+        for path in files {
+            let real = self.resolve(&path)?;
+            done.bytes += tokio::task::spawn_blocking(move || readahead(&real)).await??;
+            done.files += 1;
+            progress(&done);
+        }
+        debug!(files = done.files, bytes = done.bytes, "warmed files");
+        Ok(done)
+    }
+
+    /// Where `path` really is, with `..` resolved lexically so that the path
+    /// that's checked is the path that's used. Paths that `..` their way out
+    /// of the scope are rejected.
+    fn resolve(&self, path: &Path) -> Result<PathBuf> {
+        let Some(scope) = &self.scope else {
+            return Ok(path.to_path_buf());
+        };
+        let real = if path.starts_with(scope) {
             path.to_path_buf()
+        } else {
+            scope.join(path.strip_prefix("/").unwrap_or(path))
         };
+
+        let mut normalized = PathBuf::new();
+        for component in real.components() {
+            match component {
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                Component::CurDir => {}
+                component => normalized.push(component),
+            }
+        }
+        if !normalized.starts_with(scope) {
+            if let Some(audit) = &self.audit {
+                audit.escapes.fetch_add(1, Ordering::Relaxed);
+                debug!(
+                    target: "floppy_disk::scope",
+                    virtual_path = %path.display(),
+                    scope = %scope.display(),
+                    "rejected escape from scope"
+                );
+            }
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{} escapes the scope", path.display()),
+            ));
+        }
+
+        if let Some(audit) = &self.audit {
+            audit.translations.fetch_add(1, Ordering::Relaxed);
+            debug!(
+                target: "floppy_disk::scope",
+                virtual_path = %path.display(),
+                real_path = %normalized.display(),
+                "translated path"
+            );
+        }
+        Ok(normalized)
+    }
+}
+
+/// Counts of what a scoped [`TokioFloppyDisk`] was asked to access. See
+/// [`TokioFloppyDisk::with_audit`].
+#[derive(Debug, Default)]
+pub struct ScopeAudit {
+    translations: AtomicU64,
+    escapes: AtomicU64,
+}
+
+impl ScopeAudit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many paths were translated into the scope.
+    pub fn translations(&self) -> u64 {
+        self.translations.load(Ordering::Relaxed)
+    }
+
+    /// How many paths were rejected for escaping the scope.
+    pub fn escapes(&self) -> u64 {
+        self.escapes.load(Ordering::Relaxed)
+    }
+}
+
+/// Files for [`TokioFloppyDisk::warm`] to read ahead.
+#[derive(Debug, Clone, Default)]
+pub struct WarmSet {
+    paths: Vec<PathBuf>,
+    #[cfg(feature = "glob")]
+    globs: Vec<String>,
+}
+
+impl WarmSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Warm the file at `path`, or everything under it if it's a directory.
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+
+    /// Warm every path matching `pattern`, as for
+    /// [`FloppyDiskGlobExt::glob`](crate::glob::FloppyDiskGlobExt::glob).
+    #[cfg(feature = "glob")]
+    pub fn with_glob(mut self, pattern: impl Into<String>) -> Self {
+        self.globs.push(pattern.into());
+        self
+    }
+}
+
+/// How far [`TokioFloppyDisk::warm`] has got.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WarmProgress {
+    pub files: u64,
+    pub total_files: u64,
+    pub bytes: u64,
+}
+
+/// Start reading the file at `path` into the page cache, and return its
+/// length. Elsewhere than Linux, the file is read through instead.
+fn readahead(path: &Path) -> Result<u64> {
+    let file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        let result =
+            unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_WILLNEED) };
+        if result != 0 {
+            return Err(Error::from_raw_os_error(result));
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    std::io::copy(&mut &file, &mut std::io::sink())?;
+    Ok(len)
+}
+
+macro_rules! scoped {
+    ( $this: expr, $x:ident ) => {
+        let $x = $this.resolve($x.as_ref())?;
     };
 }
 
@@ -51,6 +253,7 @@ impl<'a> FloppyDisk<'a> for TokioFloppyDisk {
     type ReadDir = TokioReadDir;
 
     async fn canonicalize<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        let _sample = Profiler::sample(&self.profiler, "canonicalize");
         scoped!(self, path);
         debug!(
             "canonicalise {} (scope = {:?})",
@@ -61,6 +264,8 @@ impl<'a> FloppyDisk<'a> for TokioFloppyDisk {
     }
 
     async fn copy<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<u64> {
+        let mut sample = Profiler::sample(&self.profiler, "copy");
+        check_not_same_file(self, from.as_ref(), to.as_ref()).await?;
         scoped!(self, from);
         scoped!(self, to);
         debug!(
@@ -69,16 +274,20 @@ impl<'a> FloppyDisk<'a> for TokioFloppyDisk {
             to.display(),
             &self.scope
         );
-        tokio::fs::copy(from, to).await
+        let copied = tokio::fs::copy(from, to).await?;
+        sample.bytes(copied as usize);
+        Ok(copied)
     }
 
     async fn create_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let _sample = Profiler::sample(&self.profiler, "create_dir");
         scoped!(self, path);
         debug!("create_dir {} (scope = {:?})", path.display(), &self.scope);
         tokio::fs::create_dir(path).await
     }
 
     async fn create_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let _sample = Profiler::sample(&self.profiler, "create_dir_all");
         scoped!(self, path);
         debug!(
             "create_dir_all {} (scope = {:?})",
@@ -89,6 +298,7 @@ impl<'a> FloppyDisk<'a> for TokioFloppyDisk {
     }
 
     async fn hard_link<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
+        let _sample = Profiler::sample(&self.profiler, "hard_link");
         scoped!(self, src);
         scoped!(self, dst);
         debug!(
@@ -101,46 +311,66 @@ impl<'a> FloppyDisk<'a> for TokioFloppyDisk {
     }
 
     async fn metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        let _sample = Profiler::sample(&self.profiler, "metadata");
         scoped!(self, path);
         debug!("metadata {} (scope = {:?})", path.display(), &self.scope);
         tokio::fs::metadata(path).await.map(TokioMetadata)
     }
 
     async fn read<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>> {
+        let mut sample = Profiler::sample(&self.profiler, "read");
         scoped!(self, path);
         debug!("read {} (scope = {:?})", path.display(), &self.scope);
-        tokio::fs::read(path).await
+        let data = tokio::fs::read(path).await?;
+        sample.bytes(data.len());
+        Ok(data)
     }
 
     async fn read_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::ReadDir> {
+        let _sample = Profiler::sample(&self.profiler, "read_dir");
         scoped!(self, path);
         debug!("read_dir {} (scope = {:?})", path.display(), &self.scope);
         tokio::fs::read_dir(path).await.map(TokioReadDir)
     }
 
     async fn read_link<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        let _sample = Profiler::sample(&self.profiler, "read_link");
         scoped!(self, path);
         debug!("read_link {} (scope = {:?})", path.display(), &self.scope);
-        tokio::fs::read_link(path).await
+        let target = tokio::fs::read_link(path).await?;
+        // Targets are scoped when links are made, so they're unscoped here to
+        // be followed on this disk.
+        Ok(match &self.scope {
+            Some(scope) => match target.strip_prefix(scope) {
+                Ok(target) => Path::new("/").join(target),
+                Err(_) => target,
+            },
+            None => target,
+        })
     }
 
     async fn read_to_string<P: AsRef<Path> + Send>(&self, path: P) -> Result<String> {
+        let mut sample = Profiler::sample(&self.profiler, "read_to_string");
         scoped!(self, path);
         debug!(
             "read_to_string {} (scope = {:?})",
             path.display(),
             &self.scope
         );
-        tokio::fs::read_to_string(path).await
+        let data = tokio::fs::read_to_string(path).await?;
+        sample.bytes(data.len());
+        Ok(data)
     }
 
     async fn remove_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let _sample = Profiler::sample(&self.profiler, "remove_dir");
         scoped!(self, path);
         debug!("remove_dir {} (scope = {:?})", path.display(), &self.scope);
         tokio::fs::remove_dir(path).await
     }
 
     async fn remove_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let _sample = Profiler::sample(&self.profiler, "remove_dir_all");
         scoped!(self, path);
         debug!(
             "remove_dir_all {} (scope = {:?})",
@@ -151,12 +381,15 @@ impl<'a> FloppyDisk<'a> for TokioFloppyDisk {
     }
 
     async fn remove_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let _sample = Profiler::sample(&self.profiler, "remove_file");
         scoped!(self, path);
         debug!("remove_file {} (scope = {:?})", path.display(), &self.scope);
         tokio::fs::remove_file(path).await
     }
 
     async fn rename<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<()> {
+        let _sample = Profiler::sample(&self.profiler, "rename");
+        check_not_into_itself(self, from.as_ref(), to.as_ref()).await?;
         scoped!(self, from);
         scoped!(self, to);
         debug!(
@@ -173,6 +406,7 @@ impl<'a> FloppyDisk<'a> for TokioFloppyDisk {
         path: P,
         perm: Self::Permissions,
     ) -> Result<()> {
+        let _sample = Profiler::sample(&self.profiler, "set_permissions");
         scoped!(self, path);
         debug!(
             "set_permissions {} (scope = {:?})",
@@ -183,7 +417,16 @@ impl<'a> FloppyDisk<'a> for TokioFloppyDisk {
     }
 
     async fn symlink<P: AsRef<Path> + Send>(&self, src: P, dst: P) -> Result<()> {
-        scoped!(self, src);
+        let _sample = Profiler::sample(&self.profiler, "symlink");
+        // Relative targets are followed from the link's parent, so they're
+        // checked against the scope from there, and kept relative.
+        let src = if src.as_ref().is_relative() {
+            let parent = dst.as_ref().parent().unwrap_or(Path::new("/"));
+            self.resolve(&parent.join(src.as_ref()))?;
+            src.as_ref().to_path_buf()
+        } else {
+            self.resolve(src.as_ref())?
+        };
         scoped!(self, dst);
         debug!(
             "symlink {} -> {} (scope = {:?})",
@@ -195,6 +438,7 @@ impl<'a> FloppyDisk<'a> for TokioFloppyDisk {
     }
 
     async fn symlink_metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        let _sample = Profiler::sample(&self.profiler, "symlink_metadata");
         scoped!(self, path);
         debug!(
             "symlink_metadata {} (scope = {:?})",
@@ -205,6 +449,7 @@ impl<'a> FloppyDisk<'a> for TokioFloppyDisk {
     }
 
     async fn try_exists<P: AsRef<Path> + Send>(&self, path: P) -> Result<bool> {
+        let _sample = Profiler::sample(&self.profiler, "try_exists");
         scoped!(self, path);
         debug!("try_exists {} (scope = {:?})", path.display(), &self.scope);
         tokio::fs::try_exists(path).await
@@ -215,20 +460,51 @@ impl<'a> FloppyDisk<'a> for TokioFloppyDisk {
         path: P,
         contents: impl AsRef<[u8]> + Send,
     ) -> Result<()> {
+        let mut sample = Profiler::sample(&self.profiler, "write");
+        let contents = contents.as_ref();
         scoped!(self, path);
         debug!("write {} (scope = {:?})", path.display(), &self.scope);
-        tokio::fs::write(path, contents).await
+        tokio::fs::write(path, contents).await?;
+        sample.bytes(contents.len());
+        Ok(())
+    }
+
+    async fn truncate<P: AsRef<Path> + Send>(&'a self, path: P, len: u64) -> Result<()> {
+        let _sample = Profiler::sample(&self.profiler, "truncate");
+        scoped!(self, path);
+        debug!("truncate {} (scope = {:?})", path.display(), &self.scope);
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .await?
+            .set_len(len)
+            .await
     }
 
     fn new_dir_builder(&'a self) -> Self::DirBuilder {
         TokioDirBuilder(DirBuilder::new())
     }
+
+    /// Each stat is a blocking call on tokio's thread pool, so several are
+    /// run at once.
+    async fn metadata_many<P: AsRef<Path> + Send + Sync>(
+        &self,
+        paths: &[P],
+    ) -> Vec<Result<Self::Metadata>> {
+        let paths: Vec<PathBuf> = paths.iter().map(|path| path.as_ref().into()).collect();
+        futures::stream::iter(paths)
+            .map(|path| self.metadata(path))
+            .buffered(METADATA_CONCURRENCY)
+            .collect()
+            .await
+    }
 }
 
 #[cfg(unix)]
 #[async_trait::async_trait]
 impl FloppyDiskUnixExt for TokioFloppyDisk {
     async fn chown<P: Into<PathBuf> + Send>(&self, path: P, uid: u32, gid: u32) -> Result<()> {
+        let _sample = Profiler::sample(&self.profiler, "chown");
         let path = path.into();
         scoped!(self, path);
         debug!("chown {} (scope = {:?})", path.display(), &self.scope);
@@ -304,6 +580,21 @@ impl FloppyUnixMetadata for TokioMetadata {
         use std::os::unix::prelude::MetadataExt;
         Ok(self.0.gid())
     }
+
+    fn dev(&self) -> Result<u64> {
+        use std::os::unix::prelude::MetadataExt;
+        Ok(self.0.dev())
+    }
+
+    fn ino(&self) -> Result<u64> {
+        use std::os::unix::prelude::MetadataExt;
+        Ok(self.0.ino())
+    }
+
+    fn blocks(&self) -> Result<u64> {
+        use std::os::unix::prelude::MetadataExt;
+        Ok(self.0.blocks())
+    }
 }
 
 #[repr(transparent)]
@@ -468,61 +759,205 @@ impl<'a> FloppyOpenOptions<'a, TokioFloppyDisk> for TokioOpenOptions {
         disk: &'a TokioFloppyDisk,
         path: P,
     ) -> Result<<TokioFloppyDisk as FloppyDisk<'a>>::File> {
-        // TODO: Better way of restricting the scope?
-        let path = if let Some(ref scope) = disk.scope {
-            let path: &Path = path.as_ref();
-            let path = path.strip_prefix("/").unwrap_or(path).to_path_buf();
-            scope.join(path)
-        } else {
-            path.as_ref().to_path_buf()
-        };
+        let _sample = Profiler::sample(&disk.profiler, "open");
+        let path = disk.resolve(path.as_ref())?;
         debug!("opening {}", path.display());
-        self.0.open(path).await.map(TokioFile)
+        let file = self.0.open(path).await?;
+        Ok(TokioFile { file })
     }
 }
 
+/// Tokio writes in the background, so dropping the file without
+/// [closing](FloppyFile::close) it silently loses errors from the last write.
 #[derive(Debug)]
-#[repr(transparent)]
-pub struct TokioFile(#[doc(hidden)] File);
+pub struct TokioFile {
+    file: File,
+}
 
 #[async_trait::async_trait]
 impl<'a> FloppyFile<'a, TokioFloppyDisk> for TokioFile {
     async fn sync_all(&mut self) -> Result<()> {
-        self.0.sync_all().await
+        self.file.sync_all().await
     }
 
     async fn sync_data(&mut self) -> Result<()> {
-        self.0.sync_data().await
+        self.file.sync_data().await
     }
 
     async fn set_len(&mut self, size: u64) -> Result<()> {
-        self.0.set_len(size).await
+        self.file.set_len(size).await
     }
 
     async fn metadata(&self) -> Result<<TokioFloppyDisk as FloppyDisk<'a>>::Metadata> {
-        self.0.metadata().await.map(TokioMetadata)
+        self.file.metadata().await.map(TokioMetadata)
     }
 
     async fn try_clone(&'a self) -> Result<Box<Self>> {
-        self.0
-            .try_clone()
-            .await
-            .map(|file| Box::new(TokioFile(file)))
+        Ok(Box::new(TokioFile {
+            file: self.file.try_clone().await?,
+        }))
     }
 
     async fn set_permissions(
         &self,
         perm: <TokioFloppyDisk as FloppyDisk>::Permissions,
     ) -> Result<()> {
-        self.0.set_permissions(perm.0).await
+        self.file.set_permissions(perm.0).await
     }
 
     async fn permissions(&self) -> Result<<TokioFloppyDisk as FloppyDisk<'a>>::Permissions> {
-        self.0
+        self.file
             .metadata()
             .await
             .map(|metadata| TokioPermissions(metadata.permissions()))
     }
+
+    async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        read_at(&self.file, buf, offset).await
+    }
+
+    async fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        write_at(&self.file, buf, offset).await
+    }
+
+    async fn allocate(&self, offset: u64, len: u64) -> Result<()> {
+        allocate(&self.file, offset, len).await
+    }
+
+    async fn seek_data(&self, offset: u64) -> Result<u64> {
+        seek_extent(&self.file, offset, true).await
+    }
+
+    async fn seek_hole(&self, offset: u64) -> Result<u64> {
+        seek_extent(&self.file, offset, false).await
+    }
+}
+
+/// Tokio files have no positional reads or writes of their own, so these use
+/// `pread` and `pwrite` on a duplicate of the file descriptor, on the
+/// blocking pool. Writes through the cursor that haven't been flushed yet
+/// aren't seen.
+pub(crate) async fn read_at(file: &File, buf: &mut [u8], offset: u64) -> Result<usize> {
+    read_at_std(file.try_clone().await?.into_std().await, buf, offset).await
+}
+
+pub(crate) async fn write_at(file: &File, buf: &[u8], offset: u64) -> Result<usize> {
+    write_at_std(file.try_clone().await?.into_std().await, buf, offset).await
+}
+
+pub(crate) async fn allocate(file: &File, offset: u64, len: u64) -> Result<()> {
+    allocate_std(file.try_clone().await?.into_std().await, offset, len).await
+}
+
+pub(crate) async fn seek_extent(file: &File, offset: u64, data: bool) -> Result<u64> {
+    seek_extent_std(file.try_clone().await?.into_std().await, offset, data).await
+}
+
+pub(crate) async fn read_at_std(
+    file: impl Borrow<std::fs::File> + Send + 'static,
+    buf: &mut [u8],
+    offset: u64,
+) -> Result<usize> {
+    use std::os::unix::fs::FileExt;
+
+    let len = buf.len();
+    let (read, data) = tokio::task::spawn_blocking(move || {
+        let mut data = vec![0; len];
+        file.borrow()
+            .read_at(&mut data, offset)
+            .map(|read| (read, data))
+    })
+    .await??;
+    buf[..read].copy_from_slice(&data[..read]);
+    Ok(read)
+}
+
+pub(crate) async fn write_at_std(
+    file: impl Borrow<std::fs::File> + Send + 'static,
+    buf: &[u8],
+    offset: u64,
+) -> Result<usize> {
+    use std::os::unix::fs::FileExt;
+
+    let data = buf.to_vec();
+    tokio::task::spawn_blocking(move || file.borrow().write_at(&data, offset)).await?
+}
+
+/// `posix_fallocate` on Linux. Elsewhere, the file is only grown, without
+/// reserving anything.
+pub(crate) async fn allocate_std(
+    file: impl Borrow<std::fs::File> + Send + 'static,
+    offset: u64,
+    len: u64,
+) -> Result<()> {
+    // `posix_fallocate` fails with `EINVAL` for an empty range, where there's
+    // nothing to do.
+    if len == 0 {
+        return Ok(());
+    }
+    tokio::task::spawn_blocking(move || {
+        let file = file.borrow();
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::fd::AsRawFd;
+
+            let result = unsafe {
+                libc::posix_fallocate(file.as_raw_fd(), offset as libc::off_t, len as libc::off_t)
+            };
+            if result != 0 {
+                return Err(Error::from_raw_os_error(result));
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let end = offset.saturating_add(len);
+            if file.metadata()?.len() < end {
+                file.set_len(end)?;
+            }
+        }
+        Ok(())
+    })
+    .await?
+}
+
+/// `lseek` with `SEEK_DATA` or `SEEK_HOLE` on Linux. A duplicate of the file
+/// descriptor shares its cursor, so the cursor is put back afterwards.
+/// Elsewhere, files are taken to have no holes.
+pub(crate) async fn seek_extent_std(
+    file: impl Borrow<std::fs::File> + Send + 'static,
+    offset: u64,
+    data: bool,
+) -> Result<u64> {
+    tokio::task::spawn_blocking(move || {
+        let file = file.borrow();
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::fd::AsRawFd;
+
+            let fd = file.as_raw_fd();
+            let whence = if data {
+                libc::SEEK_DATA
+            } else {
+                libc::SEEK_HOLE
+            };
+            let position = unsafe { libc::lseek(fd, 0, libc::SEEK_CUR) };
+            if position < 0 {
+                return Err(Error::last_os_error());
+            }
+            let found = unsafe { libc::lseek(fd, offset as libc::off_t, whence) };
+            let error = Error::last_os_error();
+            if unsafe { libc::lseek(fd, position, libc::SEEK_SET) } < 0 {
+                return Err(Error::last_os_error());
+            }
+            if found < 0 {
+                return Err(error);
+            }
+            Ok(found as u64)
+        }
+        #[cfg(not(target_os = "linux"))]
+        crate::dense_extent(file.metadata()?.len(), offset, data)
+    })
+    .await?
 }
 
 impl AsyncRead for TokioFile {
@@ -531,89 +966,53 @@ impl AsyncRead for TokioFile {
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<Result<()>> {
-        Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+        Pin::new(&mut self.get_mut().file).poll_read(cx, buf)
     }
 }
 
 impl AsyncSeek for TokioFile {
     fn start_seek(self: Pin<&mut Self>, position: std::io::SeekFrom) -> std::io::Result<()> {
-        Pin::new(&mut self.get_mut().0).start_seek(position)
+        Pin::new(&mut self.get_mut().file).start_seek(position)
     }
 
     fn poll_complete(
         self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<std::io::Result<u64>> {
-        Pin::new(&mut self.get_mut().0).poll_complete(cx)
+        Pin::new(&mut self.get_mut().file).poll_complete(cx)
     }
 }
 
 impl AsyncWrite for TokioFile {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
-        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+        Pin::new(&mut self.get_mut().file).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize>> {
+        Pin::new(&mut self.get_mut().file).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.file.is_write_vectored()
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+        Pin::new(&mut self.get_mut().file).poll_flush(cx)
     }
 
     fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
-    }
-}
-
-// #[derive(Debug)]
-// pub struct TokioTempDir {
-//     path: PathBuf,
-// }
-
-// impl TokioTempDir {
-//     async fn new() -> Result<Self> {
-//         let mut path = std::env::temp_dir();
-//         path.push(format!("peckish-workdir-{}", rand::random::<u64>()));
-//         tokio::fs::create_dir_all(&path).await?;
-
-//         Ok(Self { path })
-//     }
-// }
-
-// impl FloppyTempDir for TokioTempDir {
-//     fn path(&self) -> &Path {
-//         &self.path
-//     }
-// }
-
-// impl Drop for TokioTempDir {
-//     fn drop(&mut self) {
-//         if self.path.exists() {
-//             std::fs::remove_dir_all(&self.path).unwrap();
-//         }
-//     }
-// }
-
-// impl AsRef<Path> for TokioTempDir {
-//     fn as_ref(&self) -> &Path {
-//         &self.path
-//     }
-// }
-
-// impl AsRef<PathBuf> for TokioTempDir {
-//     fn as_ref(&self) -> &PathBuf {
-//         &self.path
-//     }
-// }
-
-// impl std::ops::Deref for TokioTempDir {
-//     type Target = Path;
-
-//     fn deref(&self) -> &Self::Target {
-//         &self.path
-//     }
-// }
+        Pin::new(&mut self.get_mut().file).poll_shutdown(cx)
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::policy::{FloppyPolicy, PolicyDisk, PolicyOpenOptions};
 
     #[tokio::test]
     async fn test_scoping_works() -> std::io::Result<()> {
@@ -628,4 +1027,484 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_truncate() -> std::io::Result<()> {
+        let fs = TokioFloppyDisk::new(Some(PathBuf::from("/tmp")));
+        let path = format!("/floppy-truncate-{}", rand::random::<u64>());
+        fs.write(&path, "asdfjkl").await?;
+        fs.truncate(&path, 4).await?;
+        assert_eq!("asdf", fs.read_to_string(&path).await?);
+        fs.truncate(&path, 6).await?;
+        assert_eq!(6, fs.metadata(&path).await?.len());
+        fs.remove_file(&path).await?;
+        assert!(fs.truncate(&path, 0).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_atomic() -> std::io::Result<()> {
+        let fs = TokioFloppyDisk::new(Some(PathBuf::from("/tmp")));
+        let dir = format!("/floppy-write-atomic-{}", rand::random::<u64>());
+        fs.create_dir(&dir).await?;
+        fs.write(format!("{dir}/a.conf"), "old").await?;
+        fs.write_atomic(format!("{dir}/a.conf"), "new").await?;
+        assert_eq!("new", fs.read_to_string(format!("{dir}/a.conf")).await?);
+
+        let mut entries = fs.read_dir(&dir).await?;
+        assert!(entries.next_entry().await?.is_some());
+        assert!(entries.next_entry().await?.is_none());
+        assert!(fs
+            .write_atomic(format!("{dir}/missing/a.conf"), "new")
+            .await
+            .is_err());
+        fs.remove_dir_all(&dir).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_warm() -> std::io::Result<()> {
+        let fs = TokioFloppyDisk::new(Some(PathBuf::from("/tmp")));
+        let dir = format!("/floppy-warm-{}", rand::random::<u64>());
+        fs.create_dir_all(format!("{dir}/assets/css")).await?;
+        fs.write(format!("{dir}/assets/index.html"), "<html>")
+            .await?;
+        fs.write(format!("{dir}/assets/css/site.css"), "body {}")
+            .await?;
+        fs.write(format!("{dir}/config.toml"), "a = 1").await?;
+
+        let set = WarmSet::new()
+            .with_path(format!("{dir}/assets"))
+            .with_path(format!("{dir}/config.toml"));
+        let mut seen = vec![];
+        let done = fs
+            .warm(&set, |progress| seen.push(progress.clone()))
+            .await?;
+        assert_eq!(3, done.files);
+        assert_eq!(3, done.total_files);
+        assert_eq!(18, done.bytes);
+        assert_eq!(
+            vec![1, 2, 3],
+            seen.iter().map(|p| p.files).collect::<Vec<_>>()
+        );
+
+        let set = WarmSet::new().with_path(format!("{dir}/missing"));
+        assert!(fs.warm(&set, |_| {}).await.is_err());
+
+        #[cfg(feature = "glob")]
+        {
+            let set = WarmSet::new().with_glob(format!("{dir}/**/*.css"));
+            assert_eq!(1, fs.warm(&set, |_| {}).await?.files);
+        }
+        fs.remove_dir_all(&dir).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_metadata_many() -> std::io::Result<()> {
+        let fs = TokioFloppyDisk::new(Some(PathBuf::from("/tmp")));
+        let dir = format!("/floppy-metadata-many-{}", rand::random::<u64>());
+        fs.create_dir(&dir).await?;
+        fs.write(format!("{dir}/a"), "asdf").await?;
+        let paths = [format!("{dir}/a"), format!("{dir}/missing"), dir.clone()];
+        let metadata = fs.metadata_many(&paths).await;
+        assert_eq!(4, metadata[0].as_ref().unwrap().len());
+        assert_eq!(
+            ErrorKind::NotFound,
+            metadata[1].as_ref().unwrap_err().kind()
+        );
+        assert!(metadata[2].as_ref().unwrap().is_dir());
+
+        fs.remove_dir_all(&dir).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scope_audit() -> std::io::Result<()> {
+        let audit = Arc::new(ScopeAudit::new());
+        let fs = TokioFloppyDisk::new(Some(PathBuf::from("/tmp"))).with_audit(audit.clone());
+        let dir = format!("/floppy-audit-{}", rand::random::<u64>());
+        fs.create_dir(&dir).await?;
+        fs.write(format!("{dir}/a"), "asdf").await?;
+        fs.read_to_string(format!("{dir}/../{dir}/a")).await?;
+        assert_eq!(3, audit.translations());
+
+        let err = fs.read_to_string("/a/../../etc/passwd").await.unwrap_err();
+        assert_eq!(ErrorKind::PermissionDenied, err.kind());
+        assert_eq!(1, audit.escapes());
+        assert_eq!(3, audit.translations());
+
+        fs.remove_dir_all(&dir).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scope_escapes() -> std::io::Result<()> {
+        use tokio::io::AsyncReadExt;
+
+        let scope = PathBuf::from(format!("/tmp/floppy-scope-{}", rand::random::<u64>()));
+        tokio::fs::create_dir(&scope).await?;
+        let fs = TokioFloppyDisk::new(Some(scope.clone()));
+
+        // `..` is fine as long as it stays inside.
+        fs.create_dir("/a").await?;
+        fs.write("/a/../b", "asdf").await?;
+        assert_eq!("asdf", fs.read_to_string("/b").await?);
+
+        for path in ["/../b", "/a/../../b", "../../etc/passwd"] {
+            let err = fs.read_to_string(path).await.unwrap_err();
+            assert_eq!(ErrorKind::PermissionDenied, err.kind());
+            let err = TokioOpenOptions::new()
+                .read(true)
+                .open(&fs, path)
+                .await
+                .unwrap_err();
+            assert_eq!(ErrorKind::PermissionDenied, err.kind());
+        }
+
+        // Opening a real path inside the scope finds the same file as the
+        // other calls do, rather than nesting the scope inside itself.
+        let mut file = TokioOpenOptions::new()
+            .read(true)
+            .open(&fs, scope.join("b"))
+            .await?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await?;
+        assert_eq!("asdf", contents);
+        assert_eq!("asdf", fs.read_to_string(scope.join("b")).await?);
+
+        tokio::fs::remove_dir_all(&scope).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scope_resolves_dot_dot() -> std::io::Result<()> {
+        let scope = PathBuf::from(format!("/tmp/floppy-scope-{}", rand::random::<u64>()));
+        tokio::fs::create_dir_all(scope.join("real")).await?;
+        tokio::fs::write(scope.join("b"), "inside").await?;
+        let fs = TokioFloppyDisk::new(Some(scope.clone()));
+
+        // `/a` is a symlink out of the scope, so the host would resolve
+        // `/a/../b` through it. The checked, lexical path is used instead.
+        let outside = PathBuf::from(format!("/tmp/floppy-outside-{}", rand::random::<u64>()));
+        tokio::fs::create_dir_all(outside.join("x")).await?;
+        tokio::fs::write(outside.join("b"), "outside").await?;
+        tokio::fs::symlink(outside.join("x"), scope.join("a")).await?;
+        assert_eq!("inside", fs.read_to_string("/a/../b").await?);
+
+        // Relative symlink targets are scoped against the link's parent.
+        fs.symlink("../b", "/real/link").await?;
+        assert_eq!(PathBuf::from("../b"), fs.read_link("/real/link").await?);
+        assert_eq!("inside", fs.read_to_string("/real/link").await?);
+        let err = fs.symlink("../../b", "/real/escape").await.unwrap_err();
+        assert_eq!(ErrorKind::PermissionDenied, err.kind());
+
+        tokio::fs::remove_dir_all(&scope).await?;
+        tokio::fs::remove_dir_all(&outside).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_onto_hard_link() -> std::io::Result<()> {
+        let fs = TokioFloppyDisk::new(Some(PathBuf::from("/tmp")));
+        let a = format!("/floppy-same-file-{}", rand::random::<u64>());
+        let b = format!("{a}-link");
+        fs.write(&a, "asdf").await?;
+        fs.hard_link(&a, &b).await?;
+
+        assert!(same_file(&fs, &a, &b).await?);
+        assert!(fs.copy(&a, &b).await.is_err());
+        assert_eq!("asdf", fs.read_to_string(&a).await?);
+
+        fs.remove_file(&a).await?;
+        fs.remove_file(&b).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_and_rename_onto_itself() -> std::io::Result<()> {
+        let fs = TokioFloppyDisk::new(Some(PathBuf::from("/tmp")));
+        let dir = PathBuf::from(format!("/floppy-into-itself-{}", rand::random::<u64>()));
+        fs.create_dir_all(dir.join("a")).await?;
+        fs.write(dir.join("a/test.txt"), "asdf").await?;
+
+        assert!(fs
+            .copy(dir.join("a/test.txt"), dir.join("a/test.txt"))
+            .await
+            .is_err());
+        assert!(fs.rename(&dir, &dir.join("a/b")).await.is_err());
+        assert_eq!("asdf", fs.read_to_string(dir.join("a/test.txt")).await?);
+
+        fs.remove_dir_all(&dir).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_policy_limits() -> std::io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let fs = PolicyDisk::new(
+            TokioFloppyDisk::new(Some(PathBuf::from("/tmp"))),
+            FloppyPolicy::new().max_file_size(8).max_write_size(4),
+        );
+        let path = format!("/floppy-policy-{}", rand::random::<u64>());
+        assert!(fs.write(&path, "asdfg").await.is_err());
+        fs.write(&path, "asdf").await?;
+
+        let mut file = PolicyOpenOptions::new()
+            .append(true)
+            .open(&fs, &path)
+            .await?;
+        file.write_all(b"asdf").await?;
+        assert!(file.write_all(b"a").await.is_err());
+        drop(file);
+        assert_eq!("asdfasdf", fs.read_to_string(&path).await?);
+
+        fs.remove_file(&path).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_content_policy_links() -> std::io::Result<()> {
+        let root = format!("/floppy-content-links-{}", rand::random::<u64>());
+        let fs = PolicyDisk::new(
+            TokioFloppyDisk::new(Some(PathBuf::from("/tmp"))),
+            FloppyPolicy::new().deny_extension(format!("{root}/denied"), "exe"),
+        );
+        fs.create_dir_all(format!("{root}/denied")).await?;
+        fs.write(format!("{root}/ok.bin"), "asdf").await?;
+
+        let linked = fs
+            .hard_link(format!("{root}/ok.bin"), format!("{root}/denied/x.exe"))
+            .await;
+        assert!(linked.is_err());
+        let linked = fs
+            .symlink(format!("{root}/ok.bin"), format!("{root}/denied/y.exe"))
+            .await;
+        assert!(linked.is_err());
+        assert!(!fs.try_exists(format!("{root}/denied/x.exe")).await?);
+        fs.hard_link(format!("{root}/ok.bin"), format!("{root}/denied/x.bin"))
+            .await?;
+
+        tokio::fs::remove_dir_all(format!("/tmp{root}")).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_once_links() -> std::io::Result<()> {
+        let root = format!("/floppy-write-once-{}", rand::random::<u64>());
+        let fs = PolicyDisk::new(
+            TokioFloppyDisk::new(Some(PathBuf::from("/tmp"))),
+            FloppyPolicy::new().write_once(format!("{root}/audit")),
+        );
+        fs.create_dir_all(format!("{root}/audit/empty")).await?;
+        fs.write(format!("{root}/audit/a.log"), "asdf").await?;
+        fs.write(format!("{root}/b.log"), "hjkl").await?;
+
+        // A second name for a write-once file could be written through, and
+        // a write-once name for another file could change under it.
+        let linked = fs
+            .hard_link(format!("{root}/audit/a.log"), format!("{root}/a.log"))
+            .await;
+        assert!(linked.is_err());
+        let linked = fs
+            .hard_link(format!("{root}/b.log"), format!("{root}/audit/b.log"))
+            .await;
+        assert!(linked.is_err());
+        fs.symlink(format!("{root}/audit"), format!("{root}/link"))
+            .await?;
+        assert_eq!(
+            PathBuf::from(format!("{root}/audit")),
+            fs.read_link(format!("{root}/link")).await?
+        );
+        let denied = fs
+            .write(format!("{root}/link/a.log"), "hjkl")
+            .await
+            .unwrap_err();
+        assert_eq!(std::io::ErrorKind::PermissionDenied, denied.kind());
+        assert!(fs.remove_dir(format!("{root}/audit/empty")).await.is_err());
+        assert_eq!(
+            "asdf",
+            fs.read_to_string(format!("{root}/audit/a.log")).await?
+        );
+
+        tokio::fs::remove_dir_all(format!("/tmp{root}")).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_magic_across_opens() -> std::io::Result<()> {
+        use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+        let fs = PolicyDisk::new(
+            TokioFloppyDisk::new(Some(PathBuf::from("/tmp"))),
+            FloppyPolicy::new().deny_magic("/", b"MZ".to_vec()),
+        );
+        let path = format!("/floppy-magic-{}", rand::random::<u64>());
+        fs.write(&path, "M").await?;
+
+        let mut file = PolicyOpenOptions::new()
+            .write(true)
+            .open(&fs, &path)
+            .await?;
+        file.seek(std::io::SeekFrom::Start(1)).await?;
+        assert!(file.write_all(b"Z").await.is_err());
+        assert!(file.write_at(b"Z", 1).await.is_err());
+        drop(file);
+        assert_eq!("M", fs.read_to_string(&path).await?);
+
+        fs.remove_file(&path).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_close() -> std::io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let fs = TokioFloppyDisk::new(Some(PathBuf::from("/tmp")));
+        let path = format!("/floppy-close-{}", rand::random::<u64>());
+        let mut file = TokioOpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&fs, &path)
+            .await?;
+        file.write_all(b"asdf").await?;
+        file.close().await?;
+        assert_eq!("asdf", fs.read_to_string(&path).await?);
+
+        fs.remove_file(&path).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_at_and_write_at() -> std::io::Result<()> {
+        use tokio::io::AsyncReadExt;
+
+        let fs = TokioFloppyDisk::new(Some(PathBuf::from("/tmp")));
+        let path = format!("/floppy-pread-{}", rand::random::<u64>());
+        fs.write(&path, "asdfjkl").await?;
+        let mut file = TokioOpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&fs, &path)
+            .await?;
+        let mut buf = [0; 3];
+        assert_eq!(3, file.read_at(&mut buf, 4).await?);
+        assert_eq!(b"jkl", &buf);
+        assert_eq!(2, file.write_at(b"zz", 1).await?);
+        assert_eq!(0, file.read_at(&mut buf, 7).await?);
+
+        // The cursor hasn't moved.
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await?;
+        assert_eq!("azzfjkl", contents);
+
+        fs.remove_file(&path).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_seek_data_and_hole() -> std::io::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let fs = TokioFloppyDisk::new(Some(PathBuf::from("/tmp")));
+        let path = format!("/floppy-sparse-{}", rand::random::<u64>());
+        let mut file = TokioOpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&fs, &path)
+            .await?;
+        file.write_all(b"asdf").await?;
+        file.flush().await?;
+        file.write_at(b"jkl", 1 << 20).await?;
+        let len = (1 << 20) + 3;
+
+        assert_eq!(0, file.seek_data(0).await?);
+        let hole = file.seek_hole(0).await?;
+        assert!((4..=len).contains(&hole));
+        let data = file.seek_data(hole.min(len - 1)).await?;
+        assert!(data >= hole.min(len - 1) && data < len);
+        assert_eq!(len, file.seek_hole(1 << 20).await?);
+        let past_end = file.seek_data(len).await.unwrap_err();
+        assert_eq!(Some(libc::ENXIO), past_end.raw_os_error());
+
+        // The cursor is still after what was written.
+        file.write_all(b"!").await?;
+        file.flush().await?;
+        let mut start = [0; 5];
+        file.read_at(&mut start, 0).await?;
+        assert_eq!(b"asdf!", &start);
+        let mut rest = vec![];
+        file.read_to_end(&mut rest).await?;
+        assert_eq!(len as usize - 5, rest.len());
+        fs.remove_file(&path).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_allocate() -> std::io::Result<()> {
+        let fs = TokioFloppyDisk::new(Some(PathBuf::from("/tmp")));
+        let path = format!("/floppy-allocate-{}", rand::random::<u64>());
+        fs.write(&path, "asdf").await?;
+        let file = TokioOpenOptions::new().write(true).open(&fs, &path).await?;
+        file.allocate(0, 65536).await?;
+        assert_eq!(65536, fs.metadata(&path).await?.len());
+        file.allocate(0, 2).await?;
+        assert_eq!(65536, fs.metadata(&path).await?.len());
+        assert_eq!(b"asdf", &fs.read_range(&path, 0, 4).await?[..]);
+        fs.remove_file(&path).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_allocate_nothing() -> std::io::Result<()> {
+        use crate::mem::{MemFloppyDisk, MemOpenOptions};
+
+        let mem = MemFloppyDisk::new();
+        mem.write("/a", "asdf").await?;
+        let file = MemOpenOptions::new().write(true).open(&mem, "/a").await?;
+        file.allocate(8, 0).await?;
+        assert_eq!(4, mem.metadata("/a").await?.len());
+
+        let fs = TokioFloppyDisk::new(Some(PathBuf::from("/tmp")));
+        let path = format!("/floppy-allocate-nothing-{}", rand::random::<u64>());
+        fs.write(&path, "asdf").await?;
+        let file = TokioOpenOptions::new().write(true).open(&fs, &path).await?;
+        file.allocate(8, 0).await?;
+        assert_eq!(4, fs.metadata(&path).await?.len());
+        fs.remove_file(&path).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dev_and_ino() -> std::io::Result<()> {
+        let fs = TokioFloppyDisk::new(None);
+        let metadata = fs.metadata("/tmp").await?;
+        let std_metadata = std::fs::metadata("/tmp")?;
+
+        use std::os::unix::prelude::MetadataExt;
+        assert_eq!(std_metadata.dev(), metadata.dev()?);
+        assert_eq!(std_metadata.ino(), metadata.ino()?);
+
+        Ok(())
+    }
 }
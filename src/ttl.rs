@@ -0,0 +1,179 @@
+//! Files that expire after a time-to-live.
+//!
+//! None of the backends can store an expiry time natively, so it is kept in a
+//! sidecar file next to the file it applies to: `/a/b.txt` expires at the time
+//! stored in `/a/.b.txt.floppy-ttl`. Expired files are removed by
+//! [`expire_now`], or periodically by a [`spawn_reaper`] task.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::{FloppyDirEntry, FloppyDisk, FloppyFileType, FloppyReadDir};
+
+const SIDECAR_SUFFIX: &str = ".floppy-ttl";
+
+/// Write `contents` to `path`, and have it expire after `ttl`. Writing to the
+/// same path with a plain [`FloppyDisk::write`] keeps the existing expiry.
+pub async fn write_with_ttl<'a, D: FloppyDisk<'a>>(
+    disk: &D,
+    path: impl AsRef<Path> + Send,
+    contents: impl AsRef<[u8]> + Send,
+    ttl: Duration,
+) -> Result<()> {
+    let path = path.as_ref();
+    let expires_at = SystemTime::now() + ttl;
+    let millis = expires_at
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?
+        .as_millis();
+    disk.write(path, contents).await?;
+    disk.write(sidecar_path(path)?, millis.to_string()).await
+}
+
+/// When the file at `path` expires, if it has a time-to-live.
+pub async fn expires_at<'a, D: FloppyDisk<'a>>(
+    disk: &D,
+    path: impl AsRef<Path> + Send,
+) -> Result<Option<SystemTime>> {
+    let sidecar = sidecar_path(path.as_ref())?;
+    if !disk.try_exists(&sidecar).await? {
+        return Ok(None);
+    }
+    read_expiry(disk, &sidecar).await.map(Some)
+}
+
+/// Remove every expired file under `root`, returning the paths that were
+/// removed.
+pub async fn expire_now<'a, D: FloppyDisk<'a>>(
+    disk: &D,
+    root: impl AsRef<Path> + Send,
+) -> Result<Vec<PathBuf>> {
+    let now = SystemTime::now();
+    let mut expired = vec![];
+    let mut dirs = vec![root.as_ref().to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut read_dir = disk.read_dir(&dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let target = match target_path(&path) {
+                Some(target) => target,
+                None => continue,
+            };
+            if read_expiry(disk, &path).await? > now {
+                continue;
+            }
+            debug!("expiring {}", target.display());
+            if disk.try_exists(&target).await? {
+                disk.remove_file(&target).await?;
+                expired.push(target);
+            }
+            disk.remove_file(&path).await?;
+        }
+    }
+    Ok(expired)
+}
+
+/// Run [`expire_now`] on `root` every `interval` until the returned task is
+/// aborted. Errors are logged and the next run carries on.
+pub fn spawn_reaper<D>(disk: Arc<D>, root: PathBuf, interval: Duration) -> JoinHandle<()>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = expire_now(&*disk, &root).await {
+                debug!("failed to expire files under {}: {e}", root.display());
+            }
+        }
+    })
+}
+
+fn sidecar_path(path: &Path) -> Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} has no file name", path.display()),
+        )
+    })?;
+    let mut sidecar = std::ffi::OsString::from(".");
+    sidecar.push(name);
+    sidecar.push(SIDECAR_SUFFIX);
+    Ok(path.with_file_name(sidecar))
+}
+
+/// The file a sidecar applies to, or `None` if `path` isn't a sidecar.
+fn target_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let name = name.strip_prefix('.')?.strip_suffix(SIDECAR_SUFFIX)?;
+    if name.is_empty() {
+        return None;
+    }
+    Some(path.with_file_name(name))
+}
+
+async fn read_expiry<'a, D: FloppyDisk<'a>>(disk: &D, sidecar: &Path) -> Result<SystemTime> {
+    let millis: u64 = disk
+        .read_to_string(sidecar)
+        .await?
+        .trim()
+        .parse()
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid expiry in {}: {e}", sidecar.display()),
+            )
+        })?;
+    Ok(UNIX_EPOCH + Duration::from_millis(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_expire_now() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/cache/a").await?;
+        write_with_ttl(&fs, "/cache/a/old.txt", "asdf", Duration::ZERO).await?;
+        write_with_ttl(&fs, "/cache/new.txt", "asdf", Duration::from_secs(3600)).await?;
+        fs.write("/cache/forever.txt", "asdf").await?;
+
+        assert!(expires_at(&fs, "/cache/new.txt").await?.is_some());
+        assert!(expires_at(&fs, "/cache/forever.txt").await?.is_none());
+
+        let expired = expire_now(&fs, "/cache").await?;
+        assert_eq!(vec![PathBuf::from("/cache/a/old.txt")], expired);
+        assert!(!fs.try_exists("/cache/a/old.txt").await?);
+        assert!(!fs.try_exists("/cache/a/.old.txt.floppy-ttl").await?);
+        assert!(fs.try_exists("/cache/new.txt").await?);
+        assert!(fs.try_exists("/cache/forever.txt").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reaper() -> Result<()> {
+        let fs = Arc::new(MemFloppyDisk::new());
+        fs.create_dir("/cache").await?;
+        write_with_ttl(&*fs, "/cache/old.txt", "asdf", Duration::ZERO).await?;
+
+        let reaper = spawn_reaper(fs.clone(), "/cache".into(), Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        reaper.abort();
+        assert!(!fs.try_exists("/cache/old.txt").await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+//! How much space a tree takes up, directory by directory, like `du`.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+use crate::{FloppyDirEntry, FloppyDisk, FloppyMetadata, FloppyReadDir, FloppyUnixMetadata};
+
+/// How [`du`] measures a tree.
+#[derive(Debug, Clone, Default)]
+pub struct UsageOptions {
+    follow_symlinks: bool,
+    allocated: bool,
+}
+
+impl UsageOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count what symlinks point to, rather than the symlinks themselves.
+    /// Each directory is only walked once, so cycles are harmless.
+    pub fn with_follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Count the space allocated to files, in whole blocks, rather than their
+    /// apparent lengths. Sparse files count for less, and small files for
+    /// more.
+    pub fn with_allocated_size(mut self, allocated: bool) -> Self {
+        self.allocated = allocated;
+        self
+    }
+}
+
+/// Everything under one directory, at any depth.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirUsage {
+    pub bytes: u64,
+    pub entries: u64,
+}
+
+/// Usage of every directory in a tree, by path relative to its root.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub dirs: BTreeMap<PathBuf, DirUsage>,
+}
+
+impl Usage {
+    /// Usage of the whole tree.
+    pub fn total(&self) -> DirUsage {
+        self.dirs.get(Path::new("")).copied().unwrap_or_default()
+    }
+
+    /// The `n` directories using the most bytes, largest first.
+    pub fn largest(&self, n: usize) -> Vec<(&Path, DirUsage)> {
+        let mut dirs: Vec<_> = self
+            .dirs
+            .iter()
+            .map(|(path, usage)| (path.as_path(), *usage))
+            .collect();
+        dirs.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(b.0)));
+        dirs.truncate(n);
+        dirs
+    }
+}
+
+/// Add up the sizes of everything under `root` on `disk`, for every
+/// directory. Directories' own sizes aren't counted, since they vary between
+/// backends.
+pub async fn du<'a, D>(
+    disk: &'a D,
+    root: impl AsRef<Path> + Send,
+    options: &UsageOptions,
+) -> Result<Usage>
+where
+    D: FloppyDisk<'a>,
+    D::Metadata: FloppyUnixMetadata,
+{
+    let root = root.as_ref();
+    let mut usage = Usage::default();
+    usage.dirs.insert(PathBuf::new(), DirUsage::default());
+    let mut seen = HashSet::new();
+    if options.follow_symlinks {
+        seen.insert(disk.canonicalize(root).await?);
+    }
+
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let mut read_dir = disk.read_dir(root.join(&dir)).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let relative = dir.join(entry.file_name());
+            let path = root.join(&relative);
+            let metadata = if options.follow_symlinks {
+                disk.metadata(&path).await?
+            } else {
+                disk.symlink_metadata(&path).await?
+            };
+
+            let bytes = if metadata.is_dir() {
+                if !options.follow_symlinks || seen.insert(disk.canonicalize(&path).await?) {
+                    usage.dirs.insert(relative.clone(), DirUsage::default());
+                    dirs.push(relative.clone());
+                }
+                0
+            } else if options.allocated {
+                metadata.blocks()? * 512
+            } else {
+                metadata.len()
+            };
+
+            for ancestor in relative.ancestors().skip(1) {
+                let dir_usage = usage.dirs.get_mut(ancestor).unwrap();
+                dir_usage.bytes += bytes;
+                dir_usage.entries += 1;
+            }
+        }
+    }
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_du() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root/a/b").await?;
+        fs.create_dir_all("/root/c").await?;
+        fs.write("/root/a/b/d.txt", "asdf".repeat(1000)).await?;
+        fs.write("/root/a/e.txt", "jkl").await?;
+        fs.write("/root/c/f.txt", "qwe").await?;
+        fs.symlink("/root/a", "/root/c/g").await?;
+
+        let usage = du(&fs, "/root", &UsageOptions::new()).await?;
+        assert_eq!(
+            DirUsage {
+                bytes: 4000 + 3 + 3 + 7,
+                entries: 7
+            },
+            usage.total()
+        );
+        assert_eq!(
+            DirUsage {
+                bytes: 4003,
+                entries: 3
+            },
+            usage.dirs[Path::new("a")]
+        );
+        let largest: Vec<_> = usage.largest(3).into_iter().map(|(path, _)| path).collect();
+        assert_eq!(
+            vec![Path::new(""), Path::new("a"), Path::new("a/b")],
+            largest
+        );
+
+        let options = UsageOptions::new().with_allocated_size(true);
+        let usage = du(&fs, "/root", &options).await?;
+        assert_eq!(4096, usage.dirs[Path::new("a/b")].bytes);
+
+        // The symlinked directory is only counted once.
+        let options = UsageOptions::new().with_follow_symlinks(true);
+        let usage = du(&fs, "/root", &options).await?;
+        assert_eq!(4006, usage.total().bytes);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1058 @@
+//! Read-only mounts of a tree that's verified against a [`Manifest`], like
+//! fs-verity, over any disk. The manifest is checked against a trusted
+//! [`Manifest::merkle_root`] when it's mounted, and every read after that is
+//! checked against the manifest, so a disk that's been tampered with, or a
+//! remote one that lies, fails reads rather than returning the wrong thing.
+//!
+//! ```rust
+//! # use floppy_disk::prelude::*;
+//! # use floppy_disk::manifest;
+//! # use floppy_disk::verity::VerityFloppyDisk;
+//! # async fn example() -> std::io::Result<()> {
+//! let disk = MemFloppyDisk::new();
+//! disk.create_dir_all("/pkg/bin").await?;
+//! disk.write("/pkg/bin/tool", "#!/bin/sh").await?;
+//! let manifest = manifest::generate(&disk, "/pkg").await?;
+//! // Published somewhere the disk can't change it, eg. signed.
+//! let root_hash = manifest.merkle_root();
+//!
+//! let mount = VerityFloppyDisk::mount(disk, "/pkg", manifest, &root_hash)?;
+//! assert_eq!("#!/bin/sh", mount.read_to_string("/bin/tool").await?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Paths are relative to the mounted root, and are resolved with the
+//! manifest's symlinks rather than the disk's; absolute symlink targets are
+//! taken to be within the mount. Directory listings leave out anything the
+//! manifest doesn't have, and fail if the disk is missing something it
+//! does. Files are read and verified whole when they're opened.
+
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
+use std::fmt;
+use std::io::{Cursor, Error, ErrorKind, IoSlice, Read, Result, Seek, SeekFrom};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::diff::EntryKind;
+use crate::hash::HashAlgorithm;
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::*;
+
+/// A verified, read-only view of a tree. See the [module docs](self).
+#[derive(Debug)]
+pub struct VerityFloppyDisk<D> {
+    disk: D,
+    root: PathBuf,
+    manifest: Manifest,
+}
+
+impl<D> VerityFloppyDisk<D> {
+    /// Mount the tree at `root` on `disk`, if `manifest` hashes to
+    /// `root_hash`. The hash has to come from somewhere more trusted than the
+    /// manifest, or the disk, for any of this to mean anything.
+    pub fn mount(
+        disk: D,
+        root: impl Into<PathBuf>,
+        manifest: Manifest,
+        root_hash: &str,
+    ) -> Result<Self> {
+        if manifest.merkle_root() != root_hash {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "manifest doesn't match its root hash",
+            ));
+        }
+        Ok(Self {
+            disk,
+            root: root.into(),
+            manifest,
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// The manifest's path for `path`, with the manifest's symlinks in it
+    /// resolved. The last component is only resolved if `follow` is set.
+    fn resolve(&self, path: &Path, follow: bool) -> Result<PathBuf> {
+        fn components(path: &Path) -> impl Iterator<Item = OsString> + '_ {
+            path.components()
+                .rev()
+                .map(|component| component.as_os_str().to_os_string())
+        }
+
+        let mut pending: Vec<OsString> = components(path).collect();
+        let mut resolved = PathBuf::from("/");
+        let mut links = 0;
+        while let Some(name) = pending.pop() {
+            if name == "." {
+                continue;
+            } else if name == ".." {
+                resolved.pop();
+                continue;
+            }
+            resolved.push(&name);
+            if name == "/" {
+                continue;
+            }
+            let entry = self.entry(&resolved)?.ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("{} not found", resolved.display()),
+                )
+            })?;
+            if entry.kind == EntryKind::Symlink && (follow || !pending.is_empty()) {
+                links += 1;
+                if links > 40 {
+                    return Err(Error::from_raw_os_error(libc::ELOOP));
+                }
+                resolved.pop();
+                pending.extend(components(entry.target.as_deref().unwrap_or(Path::new(""))));
+            } else if entry.kind != EntryKind::Dir && !pending.is_empty() {
+                return Err(Error::from_raw_os_error(libc::ENOTDIR));
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// The manifest's entry for a resolved path, or `None` for the root.
+    fn entry(&self, resolved: &Path) -> Result<Option<&ManifestEntry>> {
+        let relative = resolved.strip_prefix("/").unwrap_or(resolved);
+        if relative.as_os_str().is_empty() {
+            return Ok(None);
+        }
+        self.manifest
+            .entries
+            .get(relative)
+            .map(Some)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("{} not found", resolved.display()),
+                )
+            })
+    }
+
+    fn real(&self, resolved: &Path) -> PathBuf {
+        self.root
+            .join(resolved.strip_prefix("/").unwrap_or(resolved))
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> VerityFloppyDisk<D> {
+    async fn read_verified(&self, resolved: &Path) -> Result<Vec<u8>> {
+        let Some(entry) = self
+            .entry(resolved)?
+            .filter(|entry| entry.kind == EntryKind::File)
+        else {
+            return Err(Error::from_raw_os_error(libc::EISDIR));
+        };
+        let data = self.disk.read(self.real(resolved)).await?;
+        let mut hasher = HashAlgorithm::Sha256.hasher();
+        hasher.update(&data);
+        if data.len() as u64 != entry.len
+            || entry.sha256.as_deref() != Some(hasher.finish().as_str())
+        {
+            return Err(tampered(resolved));
+        }
+        Ok(data)
+    }
+
+    async fn metadata_of(&self, resolved: &Path) -> Result<VerityMetadata<'a, D>> {
+        let metadata = self.disk.symlink_metadata(self.real(resolved)).await?;
+        VerityMetadata::check(metadata, resolved, self.entry(resolved)?)
+    }
+}
+
+fn tampered(path: &Path) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("{} doesn't match the manifest", path.display()),
+    )
+}
+
+fn read_only() -> Error {
+    Error::from_raw_os_error(libc::EROFS)
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyDisk<'a> for VerityFloppyDisk<D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirEntry: Sync,
+    D::ReadDir: Sync,
+    D::Permissions: 'static,
+{
+    type DirBuilder = VerityDirBuilder;
+    type DirEntry = VerityDirEntry<'a, D>;
+    type File = VerityFile<'a, D>;
+    type FileType = D::FileType;
+    type Metadata = VerityMetadata<'a, D>;
+    type OpenOptions = VerityOpenOptions<D>;
+    type Permissions = D::Permissions;
+    type ReadDir = VerityReadDir<'a, D>;
+
+    async fn canonicalize<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        self.resolve(path.as_ref(), true)
+    }
+
+    async fn copy<P: AsRef<Path> + Send>(&self, _from: P, _to: P) -> Result<u64> {
+        Err(read_only())
+    }
+
+    async fn create_dir<P: AsRef<Path> + Send>(&self, _path: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn create_dir_all<P: AsRef<Path> + Send>(&self, _path: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn hard_link<P: AsRef<Path> + Send>(&self, _src: P, _dst: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        let resolved = self.resolve(path.as_ref(), true)?;
+        self.metadata_of(&resolved).await
+    }
+
+    async fn read<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>> {
+        let resolved = self.resolve(path.as_ref(), true)?;
+        self.read_verified(&resolved).await
+    }
+
+    async fn read_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::ReadDir> {
+        let resolved = self.resolve(path.as_ref(), true)?;
+        if self
+            .entry(&resolved)?
+            .is_some_and(|entry| entry.kind != EntryKind::Dir)
+        {
+            return Err(Error::from_raw_os_error(libc::ENOTDIR));
+        }
+        let relative = resolved.strip_prefix("/").unwrap_or(&resolved);
+        let expected = self
+            .manifest
+            .entries
+            .iter()
+            .filter(|(path, _)| path.parent() == Some(relative))
+            .filter_map(|(path, entry)| Some((path.file_name()?.to_os_string(), entry.clone())))
+            .collect();
+        Ok(VerityReadDir {
+            read_dir: self.disk.read_dir(self.real(&resolved)).await?,
+            dir: resolved,
+            expected,
+            seen: HashSet::new(),
+        })
+    }
+
+    async fn read_link<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        let resolved = self.resolve(path.as_ref(), false)?;
+        let Some(expected) = self
+            .entry(&resolved)?
+            .and_then(|entry| entry.target.as_ref())
+        else {
+            return Err(Error::from_raw_os_error(libc::EINVAL));
+        };
+        let target = self.disk.read_link(self.real(&resolved)).await?;
+        if &target != expected {
+            return Err(tampered(&resolved));
+        }
+        Ok(target)
+    }
+
+    async fn read_to_string<P: AsRef<Path> + Send>(&self, path: P) -> Result<String> {
+        String::from_utf8(self.read(path).await?).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    async fn remove_dir<P: AsRef<Path> + Send>(&self, _path: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn remove_dir_all<P: AsRef<Path> + Send>(&self, _path: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn remove_file<P: AsRef<Path> + Send>(&self, _path: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn rename<P: AsRef<Path> + Send>(&self, _from: P, _to: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        _path: P,
+        _perm: Self::Permissions,
+    ) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn symlink<P: AsRef<Path> + Send>(&self, _src: P, _dst: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn symlink_metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        let resolved = self.resolve(path.as_ref(), false)?;
+        self.metadata_of(&resolved).await
+    }
+
+    async fn try_exists<P: AsRef<Path> + Send>(&self, path: P) -> Result<bool> {
+        match self.resolve(path.as_ref(), true) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn write<P: AsRef<Path> + Send>(
+        &self,
+        _path: P,
+        _contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn new_dir_builder(&'a self) -> Self::DirBuilder {
+        VerityDirBuilder
+    }
+}
+
+#[async_trait::async_trait]
+impl<D: Send + Sync> FloppyDiskUnixExt for VerityFloppyDisk<D> {
+    async fn chown<P: Into<PathBuf> + Send>(&self, _path: P, _uid: u32, _gid: u32) -> Result<()> {
+        Err(read_only())
+    }
+}
+
+/// Refuses to create anything, since the mount is read-only.
+#[derive(Debug)]
+pub struct VerityDirBuilder;
+
+#[async_trait::async_trait]
+impl FloppyDirBuilder for VerityDirBuilder {
+    fn recursive(&mut self, _recursive: bool) -> &mut Self {
+        self
+    }
+
+    async fn create<P: AsRef<Path> + Send>(&self, _path: P) -> Result<()> {
+        Err(read_only())
+    }
+
+    #[cfg(unix)]
+    fn mode(&mut self, _mode: u32) -> &mut Self {
+        self
+    }
+}
+
+pub struct VerityDirEntry<'a, D: FloppyDisk<'a>> {
+    entry: D::DirEntry,
+    path: PathBuf,
+    expected: ManifestEntry,
+}
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for VerityDirEntry<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerityDirEntry")
+            .field("entry", &self.entry)
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyDirEntry<'a, VerityFloppyDisk<D>> for VerityDirEntry<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirEntry: Sync,
+    D::ReadDir: Sync,
+    D::Permissions: 'static,
+{
+    fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn file_name(&self) -> OsString {
+        self.entry.file_name()
+    }
+
+    async fn metadata(&self) -> Result<VerityMetadata<'a, D>> {
+        // Taken first so that `self` isn't held across the await, which the
+        // compiler can't prove is `Send` for a disk that borrows.
+        let (path, expected) = (self.path.clone(), self.expected.clone());
+        let metadata = self.entry.metadata().await?;
+        VerityMetadata::check(metadata, &path, Some(&expected))
+    }
+
+    async fn file_type(&self) -> Result<D::FileType> {
+        Ok(self.metadata().await?.file_type())
+    }
+
+    #[cfg(unix)]
+    fn ino(&self) -> u64 {
+        self.entry.ino()
+    }
+}
+
+pub struct VerityReadDir<'a, D: FloppyDisk<'a>> {
+    read_dir: D::ReadDir,
+    dir: PathBuf,
+    /// The manifest's entries in the directory, by name.
+    expected: BTreeMap<OsString, ManifestEntry>,
+    seen: HashSet<OsString>,
+}
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for VerityReadDir<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerityReadDir")
+            .field("read_dir", &self.read_dir)
+            .field("dir", &self.dir)
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyReadDir<'a, VerityFloppyDisk<D>> for VerityReadDir<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirEntry: Sync,
+    D::ReadDir: Sync,
+    D::Permissions: 'static,
+{
+    async fn next_entry(&mut self) -> Result<Option<VerityDirEntry<'a, D>>> {
+        while let Some(entry) = self.read_dir.next_entry().await? {
+            let name = entry.file_name();
+            let Some(expected) = self.expected.get(&name) else {
+                continue;
+            };
+            self.seen.insert(name.clone());
+            return Ok(Some(VerityDirEntry {
+                entry,
+                path: self.dir.join(&name),
+                expected: expected.clone(),
+            }));
+        }
+        match self.expected.keys().find(|name| !self.seen.contains(*name)) {
+            Some(name) => Err(tampered(&self.dir.join(name))),
+            None => Ok(None),
+        }
+    }
+}
+
+pub struct VerityMetadata<'a, D: FloppyDisk<'a>>(D::Metadata);
+
+impl<'a, D: FloppyDisk<'a>> VerityMetadata<'a, D> {
+    /// The disk's metadata for `path`, if it's the kind of entry the
+    /// manifest says, and files are the right length.
+    fn check(metadata: D::Metadata, path: &Path, expected: Option<&ManifestEntry>) -> Result<Self> {
+        let matches = match expected {
+            None => metadata.is_dir(),
+            Some(entry) => match entry.kind {
+                EntryKind::Dir => metadata.is_dir(),
+                EntryKind::Symlink => metadata.is_symlink(),
+                EntryKind::File => metadata.is_file() && metadata.len() == entry.len,
+            },
+        };
+        if !matches {
+            return Err(tampered(path));
+        }
+        Ok(Self(metadata))
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> fmt::Debug for VerityMetadata<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("VerityMetadata").field(&self.0).finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyMetadata<'a, VerityFloppyDisk<D>> for VerityMetadata<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirEntry: Sync,
+    D::ReadDir: Sync,
+    D::Permissions: 'static,
+{
+    fn file_type(&self) -> D::FileType {
+        self.0.file_type()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.0.is_dir()
+    }
+
+    fn is_file(&self) -> bool {
+        self.0.is_file()
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.0.is_symlink()
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len()
+    }
+
+    fn permissions(&self) -> D::Permissions {
+        self.0.permissions()
+    }
+
+    fn modified(&self) -> Result<SystemTime> {
+        self.0.modified()
+    }
+
+    fn accessed(&self) -> Result<SystemTime> {
+        self.0.accessed()
+    }
+
+    fn created(&self) -> Result<SystemTime> {
+        self.0.created()
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> FloppyUnixMetadata for VerityMetadata<'a, D>
+where
+    D::Metadata: FloppyUnixMetadata,
+{
+    fn uid(&self) -> Result<u32> {
+        self.0.uid()
+    }
+
+    fn gid(&self) -> Result<u32> {
+        self.0.gid()
+    }
+
+    fn dev(&self) -> Result<u64> {
+        self.0.dev()
+    }
+
+    fn ino(&self) -> Result<u64> {
+        self.0.ino()
+    }
+
+    fn blocks(&self) -> Result<u64> {
+        self.0.blocks()
+    }
+}
+
+/// Options for opening files on a [`VerityFloppyDisk`]. Anything but reading
+/// fails, since the mount is read-only.
+pub struct VerityOpenOptions<D> {
+    write: bool,
+    _disk: PhantomData<fn() -> D>,
+}
+
+impl<D> fmt::Debug for VerityOpenOptions<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerityOpenOptions")
+            .field("write", &self.write)
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyOpenOptions<'a, VerityFloppyDisk<D>> for VerityOpenOptions<D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirEntry: Sync,
+    D::ReadDir: Sync,
+    D::Permissions: 'static,
+{
+    fn new() -> Self {
+        Self {
+            write: false,
+            _disk: PhantomData,
+        }
+    }
+
+    fn read(self, _read: bool) -> Self {
+        self
+    }
+
+    fn write(mut self, write: bool) -> Self {
+        self.write |= write;
+        self
+    }
+
+    fn append(mut self, append: bool) -> Self {
+        self.write |= append;
+        self
+    }
+
+    fn truncate(mut self, truncate: bool) -> Self {
+        self.write |= truncate;
+        self
+    }
+
+    fn create(mut self, create: bool) -> Self {
+        self.write |= create;
+        self
+    }
+
+    fn create_new(mut self, create_new: bool) -> Self {
+        self.write |= create_new;
+        self
+    }
+
+    async fn open<P: AsRef<Path> + Send>(
+        &self,
+        disk: &'a VerityFloppyDisk<D>,
+        path: P,
+    ) -> Result<VerityFile<'a, D>> {
+        if self.write {
+            return Err(read_only());
+        }
+        let resolved = disk.resolve(path.as_ref(), true)?;
+        let data = disk.read_verified(&resolved).await?;
+        Ok(VerityFile {
+            data: Cursor::new(data),
+            path: resolved,
+            disk,
+        })
+    }
+}
+
+/// An open file on a [`VerityFloppyDisk`], read and verified whole when it
+/// was opened.
+pub struct VerityFile<'a, D> {
+    data: Cursor<Vec<u8>>,
+    path: PathBuf,
+    disk: &'a VerityFloppyDisk<D>,
+}
+
+impl<D> fmt::Debug for VerityFile<'_, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerityFile")
+            .field("path", &self.path)
+            .field("position", &self.data.position())
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, D> FloppyFile<'a, VerityFloppyDisk<D>> for VerityFile<'a, D>
+where
+    D: FloppyDisk<'a> + Sync + 'a,
+    D::DirEntry: Sync,
+    D::ReadDir: Sync,
+    D::Permissions: 'static,
+{
+    async fn sync_all(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn sync_data(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn set_len(&mut self, _size: u64) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn metadata(&self) -> Result<VerityMetadata<'a, D>> {
+        self.disk.metadata_of(&self.path).await
+    }
+
+    async fn try_clone(&'a self) -> Result<Box<VerityFile<'a, D>>> {
+        Ok(Box::new(VerityFile {
+            data: self.data.clone(),
+            path: self.path.clone(),
+            disk: self.disk,
+        }))
+    }
+
+    async fn set_permissions(&self, _perm: D::Permissions) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn permissions(&self) -> Result<D::Permissions> {
+        Ok(self.metadata().await?.permissions())
+    }
+
+    async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let data = self.data.get_ref();
+        let start = data.len().min(offset.try_into().unwrap_or(usize::MAX));
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    async fn write_at(&self, _buf: &[u8], _offset: u64) -> Result<usize> {
+        Err(read_only())
+    }
+
+    async fn allocate(&self, _offset: u64, _len: u64) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn seek_data(&self, offset: u64) -> Result<u64> {
+        dense_extent(self.data.get_ref().len() as u64, offset, true)
+    }
+
+    async fn seek_hole(&self, offset: u64) -> Result<u64> {
+        dense_extent(self.data.get_ref().len() as u64, offset, false)
+    }
+}
+
+impl<D> AsyncRead for VerityFile<'_, D> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let data = &mut self.get_mut().data;
+        let read = data.read(buf.initialize_unfilled())?;
+        buf.advance(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<D> AsyncWrite for VerityFile<'_, D> {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, _buf: &[u8]) -> Poll<Result<usize>> {
+        Poll::Ready(Err(read_only()))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize>> {
+        Poll::Ready(Err(read_only()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<D> AsyncSeek for VerityFile<'_, D> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> Result<()> {
+        self.get_mut().data.seek(position).map(|_| ())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        Poll::Ready(Ok(self.data.position()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    use super::*;
+    use crate::manifest::generate;
+    use crate::mem::MemFloppyDisk;
+
+    async fn fixture() -> Result<(MemFloppyDisk, Manifest)> {
+        let disk = MemFloppyDisk::new();
+        disk.create_dir_all("/pkg/lib").await?;
+        disk.write("/pkg/lib/a.so", "asdf").await?;
+        disk.write("/pkg/b.txt", "jkl").await?;
+        disk.symlink("lib/a.so", "/pkg/a").await?;
+        disk.symlink("/lib", "/pkg/abs").await?;
+        let manifest = generate(&disk, "/pkg").await?;
+        Ok((disk, manifest))
+    }
+
+    #[tokio::test]
+    async fn test_verity_mount() -> Result<()> {
+        let (disk, manifest) = fixture().await?;
+        let root = manifest.merkle_root();
+        let mut forged = manifest.clone();
+        forged.entries.remove(Path::new("b.txt"));
+        assert_eq!(
+            ErrorKind::InvalidData,
+            VerityFloppyDisk::mount(MemFloppyDisk::new(), "/pkg", forged, &root)
+                .unwrap_err()
+                .kind()
+        );
+
+        let mount = VerityFloppyDisk::mount(disk, "/pkg", manifest, &root)?;
+        assert_eq!("asdf", mount.read_to_string("/lib/a.so").await?);
+        assert_eq!("asdf", mount.read_to_string("/a").await?);
+        assert_eq!("asdf", mount.read_to_string("/abs/a.so").await?);
+        assert_eq!("asdf", mount.read_to_string("lib/../a").await?);
+        assert_eq!(
+            ErrorKind::NotADirectory,
+            mount.read("/b.txt/../a").await.unwrap_err().kind()
+        );
+        assert_eq!(Path::new("lib/a.so"), mount.read_link("/a").await?);
+        assert!(mount.symlink_metadata("/a").await?.is_symlink());
+        assert_eq!(4, mount.metadata("/a").await?.len());
+        assert_eq!(PathBuf::from("/lib/a.so"), mount.canonicalize("/a").await?);
+        assert!(!mount.try_exists("/nope").await?);
+        assert_eq!(
+            ErrorKind::ReadOnlyFilesystem,
+            mount.write("/b.txt", "x").await.unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::ReadOnlyFilesystem,
+            VerityOpenOptions::new()
+                .write(true)
+                .open(&mount, "/b.txt")
+                .await
+                .unwrap_err()
+                .kind()
+        );
+
+        let mut file = VerityOpenOptions::new()
+            .read(true)
+            .open(&mount, "/lib/a.so")
+            .await?;
+        file.seek(SeekFrom::Start(1)).await?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await?;
+        assert_eq!("sdf", contents);
+        let mut buf = [0; 2];
+        assert_eq!(2, file.read_at(&mut buf, 2).await?);
+        assert_eq!(b"df", &buf);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verity_tampering() -> Result<()> {
+        let (disk, manifest) = fixture().await?;
+        let root = manifest.merkle_root();
+        disk.write("/pkg/lib/a.so", "fdsa").await?;
+        disk.write("/pkg/b.txt", "jkl!").await?;
+        disk.write("/pkg/lib/extra", "extra").await?;
+        let mount = VerityFloppyDisk::mount(disk, "/pkg", manifest, &root)?;
+
+        assert_eq!(
+            ErrorKind::InvalidData,
+            mount.read("/lib/a.so").await.unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::InvalidData,
+            mount.metadata("/b.txt").await.unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::NotFound,
+            mount.read("/lib/extra").await.unwrap_err().kind()
+        );
+        let mut read_dir = mount.read_dir("/lib").await?;
+        let mut names = vec![];
+        while let Some(entry) = read_dir.next_entry().await? {
+            names.push(entry.file_name());
+        }
+        assert_eq!(vec![OsString::from("a.so")], names);
+
+        mount.disk.remove_file("/pkg/lib/a.so").await?;
+        let mut read_dir = mount.read_dir("/lib").await?;
+        assert_eq!(
+            ErrorKind::InvalidData,
+            read_dir.next_entry().await.unwrap_err().kind()
+        );
+
+        mount.disk.remove_file("/pkg/a").await?;
+        mount.disk.symlink("b.txt", "/pkg/a").await?;
+        assert_eq!(
+            ErrorKind::InvalidData,
+            mount.read_link("/a").await.unwrap_err().kind()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verity_escaping_symlinks() -> Result<()> {
+        let disk = MemFloppyDisk::new();
+        disk.create_dir_all("/pkg/lib").await?;
+        disk.create_dir_all("/secret").await?;
+        disk.write("/secret/key", "hunter2").await?;
+        disk.write("/pkg/lib/a.so", "asdf").await?;
+        disk.symlink("../../secret/key", "/pkg/lib/up").await?;
+        disk.symlink("/secret/key", "/pkg/abs").await?;
+        disk.symlink("../..", "/pkg/lib/root").await?;
+        disk.symlink("loop-b", "/pkg/loop-a").await?;
+        disk.symlink("loop-a", "/pkg/loop-b").await?;
+        let manifest = generate(&disk, "/pkg").await?;
+        let root = manifest.merkle_root();
+        let mount = VerityFloppyDisk::mount(disk, "/pkg", manifest, &root)?;
+
+        // `..` stops at the mount's root, and absolute targets are within it,
+        // so nothing outside is ever looked at.
+        for path in ["/lib/up", "/abs", "/../secret/key", "/lib/root/secret/key"] {
+            assert_eq!(
+                ErrorKind::NotFound,
+                mount.read(path).await.unwrap_err().kind(),
+                "{path}"
+            );
+        }
+        assert_eq!(PathBuf::from("/"), mount.canonicalize("/lib/root").await?);
+        assert_eq!("asdf", mount.read_to_string("/lib/root/lib/a.so").await?);
+        assert_eq!(
+            Path::new("../../secret/key"),
+            mount.read_link("/lib/up").await?
+        );
+        let looped = mount.read("/loop-a").await.unwrap_err();
+        assert_eq!(Some(libc::ELOOP), looped.raw_os_error());
+        assert!(mount.symlink_metadata("/loop-a").await?.is_symlink());
+
+        // A directory swapped for a symlink out of the mount on the disk
+        // fails rather than serving what's outside.
+        mount.disk.create_dir_all("/elsewhere").await?;
+        mount.disk.write("/elsewhere/a.so", "fdsa").await?;
+        mount.disk.remove_dir_all("/pkg/lib").await?;
+        mount.disk.symlink("/elsewhere", "/pkg/lib").await?;
+        assert_eq!(
+            ErrorKind::InvalidData,
+            mount.read("/lib/a.so").await.unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::InvalidData,
+            mount.symlink_metadata("/lib").await.unwrap_err().kind()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_verity_concurrent_files() -> Result<()> {
+        let (disk, manifest) = fixture().await?;
+        let root = manifest.merkle_root();
+        let mount = std::sync::Arc::new(VerityFloppyDisk::mount(disk, "/pkg", manifest, &root)?);
+
+        // Open files keep their own cursors, and what they read when they
+        // were opened.
+        let mut first = VerityOpenOptions::new()
+            .read(true)
+            .open(&*mount, "/lib/a.so")
+            .await?;
+        let mut second = VerityOpenOptions::new()
+            .read(true)
+            .open(&*mount, "/a")
+            .await?;
+        first.seek(SeekFrom::Start(2)).await?;
+        let mut third = first.try_clone().await?;
+        mount.disk.write("/pkg/lib/a.so", "fdsa").await?;
+        let (mut a, mut b) = (String::new(), String::new());
+        second.read_to_string(&mut a).await?;
+        third.read_to_string(&mut b).await?;
+        assert_eq!(("asdf", "df"), (a.as_str(), b.as_str()));
+        let mut buf = [0; 4];
+        assert_eq!(4, first.read_at(&mut buf, 0).await?);
+        assert_eq!(b"asdf", &buf);
+        assert!(VerityOpenOptions::new()
+            .read(true)
+            .open(&*mount, "/lib/a.so")
+            .await
+            .is_err());
+        mount.disk.write("/pkg/lib/a.so", "asdf").await?;
+
+        let tasks: Vec<_> = (0..16)
+            .map(|i| {
+                let mount = mount.clone();
+                tokio::spawn(async move {
+                    let path = if i % 2 == 0 { "/a" } else { "/b.txt" };
+                    mount.read_to_string(path).await
+                })
+            })
+            .collect();
+        for (i, task) in tasks.into_iter().enumerate() {
+            let expected = if i % 2 == 0 { "asdf" } else { "jkl" };
+            assert_eq!(expected, task.await??);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verity_errors() -> Result<()> {
+        let (disk, manifest) = fixture().await?;
+        let root = manifest.merkle_root();
+        assert!(
+            VerityFloppyDisk::mount(MemFloppyDisk::new(), "/pkg", manifest.clone(), "").is_err()
+        );
+        let mount = VerityFloppyDisk::mount(disk, "/pkg", manifest, &root)?;
+
+        assert_eq!(
+            ErrorKind::IsADirectory,
+            mount.read("/lib").await.unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::NotADirectory,
+            mount.read_dir("/b.txt").await.unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::InvalidInput,
+            mount.read_link("/b.txt").await.unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::NotFound,
+            mount.metadata("/lib/nope").await.unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::NotFound,
+            VerityOpenOptions::new()
+                .read(true)
+                .open(&mount, "/nope")
+                .await
+                .unwrap_err()
+                .kind()
+        );
+
+        let read_only = |result: Result<()>| {
+            assert_eq!(ErrorKind::ReadOnlyFilesystem, result.unwrap_err().kind());
+        };
+        read_only(mount.copy("/b.txt", "/c").await.map(|_| ()));
+        read_only(mount.create_dir("/c").await);
+        read_only(mount.create_dir_all("/c/d").await);
+        read_only(mount.new_dir_builder().create("/c").await);
+        read_only(mount.hard_link("/b.txt", "/c").await);
+        read_only(mount.symlink("/b.txt", "/c").await);
+        read_only(mount.rename("/b.txt", "/c").await);
+        read_only(mount.remove_file("/b.txt").await);
+        read_only(mount.remove_dir("/lib").await);
+        read_only(mount.remove_dir_all("/lib").await);
+        read_only(mount.chown("/b.txt", 0, 0).await);
+        let perm = mount.metadata("/b.txt").await?.permissions();
+        read_only(mount.set_permissions("/b.txt", perm).await);
+        let mut file = VerityOpenOptions::new()
+            .read(true)
+            .open(&mount, "/b.txt")
+            .await?;
+        read_only(file.set_len(0).await);
+        read_only(file.write_at(b"x", 0).await.map(|_| ()));
+        read_only(file.allocate(0, 1).await);
+        read_only(tokio::io::AsyncWriteExt::write_all(&mut file, b"x").await);
+        for options in [
+            VerityOpenOptions::new().append(true),
+            VerityOpenOptions::new().truncate(true),
+            VerityOpenOptions::new().create(true),
+            VerityOpenOptions::new().create_new(true),
+        ] {
+            read_only(options.open(&mount, "/b.txt").await.map(|_| ()));
+        }
+
+        // In the manifest, but gone from the disk.
+        mount.disk.remove_file("/pkg/b.txt").await?;
+        assert_eq!(
+            ErrorKind::NotFound,
+            mount.read("/b.txt").await.unwrap_err().kind()
+        );
+        assert!(mount.try_exists("/b.txt").await?);
+        mount.disk.remove_dir_all("/pkg/lib").await?;
+        assert_eq!(
+            ErrorKind::NotFound,
+            mount.read_dir("/lib").await.unwrap_err().kind()
+        );
+
+        Ok(())
+    }
+}
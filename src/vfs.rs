@@ -0,0 +1,1297 @@
+//! Bridging [`FloppyDisk`]s and the `vfs` crate, in both directions.
+//!
+//! [`FloppyAsVfs`] exposes a disk to code written against `vfs`. `vfs` is
+//! synchronous, so every call blocks on the disk's runtime. Calls must come
+//! from outside that runtime, eg. from `spawn_blocking` or another thread,
+//! the same as with [`Handle::block_on`].
+//!
+//! [`VfsAsFloppy`] goes the other way, and runs any `vfs` filesystem as a
+//! disk, with every call on tokio's blocking thread pool.
+
+use std::ffi::OsString;
+use std::future::Future;
+use std::io::{Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use ::vfs::error::VfsErrorKind;
+use ::vfs::{FileSystem, SeekAndRead, SeekAndWrite, VfsError, VfsFileType, VfsMetadata, VfsResult};
+use futures::ready;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::{
+    check_not_into_itself, unsupported, FloppyDirBuilder, FloppyDirEntry, FloppyDisk,
+    FloppyDiskUnixExt, FloppyFile, FloppyFileType, FloppyMetadata, FloppyOpenOptions,
+    FloppyPermissions, FloppyReadDir, FloppyUnixMetadata, FloppyUnixPermissions,
+};
+
+/// A [`vfs::FileSystem`](FileSystem) backed by a [`FloppyDisk`]. Wrap it in a
+/// `vfs::VfsPath` to use it.
+///
+/// Files are read into memory when they're opened, and written back when
+/// they're flushed or dropped.
+#[derive(Debug)]
+pub struct FloppyAsVfs<D> {
+    disk: Arc<D>,
+    handle: Handle,
+}
+
+impl<D> FloppyAsVfs<D> {
+    /// Operations on `disk` are run on the runtime behind `handle`.
+    pub fn new(disk: Arc<D>, handle: Handle) -> Self {
+        Self { disk, handle }
+    }
+}
+
+/// `vfs` paths are relative to the filesystem's root, and empty for the root
+/// itself.
+fn disk_path(path: &str) -> PathBuf {
+    Path::new("/").join(path.trim_start_matches('/'))
+}
+
+impl<D> FileSystem for FloppyAsVfs<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    fn read_dir(&self, path: &str) -> VfsResult<Box<dyn Iterator<Item = String> + Send>> {
+        let names = self.handle.block_on(async {
+            let mut names = vec![];
+            let mut read_dir = self.disk.read_dir(disk_path(path)).await?;
+            while let Some(entry) = read_dir.next_entry().await? {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+            Result::Ok(names)
+        })?;
+        Ok(Box::new(names.into_iter()))
+    }
+
+    fn create_dir(&self, path: &str) -> VfsResult<()> {
+        let path = disk_path(path);
+        self.handle.block_on(async {
+            match self.disk.create_dir(&path).await {
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if self.disk.metadata(&path).await?.is_dir() {
+                        Err(VfsErrorKind::DirectoryExists.into())
+                    } else {
+                        Err(VfsErrorKind::FileExists.into())
+                    }
+                }
+                result => Ok(result?),
+            }
+        })
+    }
+
+    fn open_file(&self, path: &str) -> VfsResult<Box<dyn SeekAndRead + Send>> {
+        let contents = self.handle.block_on(self.disk.read(disk_path(path)))?;
+        Ok(Box::new(Cursor::new(contents)))
+    }
+
+    fn create_file(&self, path: &str) -> VfsResult<Box<dyn SeekAndWrite + Send>> {
+        let path = disk_path(path);
+        self.handle.block_on(self.disk.write(&path, []))?;
+        Ok(Box::new(VfsWriter::new(self, path, vec![])))
+    }
+
+    fn append_file(&self, path: &str) -> VfsResult<Box<dyn SeekAndWrite + Send>> {
+        let path = disk_path(path);
+        let contents = self.handle.block_on(self.disk.read(&path))?;
+        let mut writer = VfsWriter::new(self, path, contents);
+        writer.buffer.seek(SeekFrom::End(0))?;
+        Ok(Box::new(writer))
+    }
+
+    fn metadata(&self, path: &str) -> VfsResult<VfsMetadata> {
+        let metadata = self.handle.block_on(self.disk.metadata(disk_path(path)))?;
+        let (file_type, len) = if metadata.is_dir() {
+            (VfsFileType::Directory, 0)
+        } else {
+            (VfsFileType::File, metadata.len())
+        };
+        Ok(VfsMetadata {
+            file_type,
+            len,
+            created: metadata.created().ok(),
+            modified: metadata.modified().ok(),
+            accessed: metadata.accessed().ok(),
+        })
+    }
+
+    fn exists(&self, path: &str) -> VfsResult<bool> {
+        Ok(self
+            .handle
+            .block_on(self.disk.try_exists(disk_path(path)))?)
+    }
+
+    fn remove_file(&self, path: &str) -> VfsResult<()> {
+        Ok(self
+            .handle
+            .block_on(self.disk.remove_file(disk_path(path)))?)
+    }
+
+    fn remove_dir(&self, path: &str) -> VfsResult<()> {
+        Ok(self
+            .handle
+            .block_on(self.disk.remove_dir(disk_path(path)))?)
+    }
+
+    fn copy_file(&self, src: &str, dest: &str) -> VfsResult<()> {
+        self.handle
+            .block_on(self.disk.copy(disk_path(src), disk_path(dest)))?;
+        Ok(())
+    }
+
+    fn move_file(&self, src: &str, dest: &str) -> VfsResult<()> {
+        Ok(self
+            .handle
+            .block_on(self.disk.rename(disk_path(src), disk_path(dest)))?)
+    }
+
+    fn move_dir(&self, src: &str, dest: &str) -> VfsResult<()> {
+        self.move_file(src, dest)
+    }
+}
+
+/// Buffers a file's contents, and writes them to the disk when flushed or
+/// dropped.
+struct VfsWriter<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    disk: Arc<D>,
+    handle: Handle,
+    path: PathBuf,
+    buffer: Cursor<Vec<u8>>,
+    dirty: bool,
+}
+
+impl<D> VfsWriter<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    fn new(fs: &FloppyAsVfs<D>, path: PathBuf, contents: Vec<u8>) -> Self {
+        Self {
+            disk: fs.disk.clone(),
+            handle: fs.handle.clone(),
+            path,
+            buffer: Cursor::new(contents),
+            dirty: false,
+        }
+    }
+
+    fn flush_to_disk(&mut self) -> Result<()> {
+        if self.dirty {
+            self.handle
+                .block_on(self.disk.write(&self.path, self.buffer.get_ref()))?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+impl<D> Write for VfsWriter<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.dirty = true;
+        self.buffer.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.flush_to_disk()
+    }
+}
+
+impl<D> Seek for VfsWriter<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.buffer.seek(pos)
+    }
+}
+
+impl<D> Read for VfsWriter<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.buffer.read(buf)
+    }
+}
+
+impl<D> Drop for VfsWriter<D>
+where
+    D: for<'a> FloppyDisk<'a> + Sync + 'static,
+{
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_to_disk() {
+            debug!("failed to write {} on drop: {e}", self.path.display());
+        }
+    }
+}
+
+/// A [`FloppyDisk`] backed by any `vfs` filesystem, eg. a `vfs::MemoryFS`,
+/// or an `OverlayFS` layering several.
+///
+/// `vfs` has no permissions, owners, symlinks or hard links, so changing or
+/// creating any of them fails with [`ErrorKind::Unsupported`]. Directories
+/// report a mode of `0o755` and files `0o644`.
+///
+/// Files are read into memory when they're opened, and written back when
+/// they're flushed, synced or closed. As with
+/// [`StdFile`](crate::std_fs::StdFile), dropping a file without
+/// [closing](FloppyFile::close) it loses errors from writing it back.
+#[derive(Debug, Clone)]
+pub struct VfsAsFloppy {
+    fs: Arc<dyn FileSystem>,
+}
+
+impl VfsAsFloppy {
+    pub fn new(fs: impl FileSystem) -> Self {
+        Self { fs: Arc::new(fs) }
+    }
+
+    /// Run `f` against the filesystem on the blocking pool, reporting errors
+    /// against `path`.
+    async fn blocking<T, F>(&self, path: &Path, f: F) -> Result<T>
+    where
+        F: FnOnce(&dyn FileSystem) -> VfsResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let fs = self.fs.clone();
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || f(&*fs).map_err(|e| io_error(&path, e))).await?
+    }
+}
+
+/// The `vfs` path for `path`, with `.` and `..` resolved. There are no
+/// symlinks for `..` to go back through, so this is the canonical path too.
+fn vfs_path(path: &Path) -> Result<String> {
+    let mut parts = vec![];
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} is not valid UTF-8", path.display()),
+                )
+            })?),
+            Component::ParentDir => {
+                parts.pop();
+            }
+            _ => {}
+        }
+    }
+    Ok(parts.iter().map(|part| format!("/{part}")).collect())
+}
+
+fn io_error(path: &Path, e: VfsError) -> Error {
+    let kind = match e.kind() {
+        VfsErrorKind::IoError(e) => {
+            return Error::new(e.kind(), format!("{}: {e}", path.display()))
+        }
+        VfsErrorKind::FileNotFound => ErrorKind::NotFound,
+        VfsErrorKind::InvalidPath => ErrorKind::InvalidInput,
+        VfsErrorKind::NotSupported => ErrorKind::Unsupported,
+        VfsErrorKind::DirectoryExists | VfsErrorKind::FileExists => ErrorKind::AlreadyExists,
+        // `Other`, and `AsyncIoError` when `vfs`'s `async-vfs` feature is on.
+        _ => ErrorKind::Other,
+    };
+    Error::new(kind, format!("{}: {}", path.display(), e.kind()))
+}
+
+fn vfs_error(kind: ErrorKind, message: &str) -> VfsError {
+    Error::new(kind, message).into()
+}
+
+fn is_dir(fs: &dyn FileSystem, path: &str) -> VfsResult<bool> {
+    Ok(fs.metadata(path)?.file_type == VfsFileType::Directory)
+}
+
+/// `vfs` backends disagree on what happens when a parent is missing, so it's
+/// checked before creating anything.
+fn check_parent(fs: &dyn FileSystem, path: &str) -> VfsResult<()> {
+    let parent = &path[..path.rfind('/').unwrap_or(0)];
+    if !is_dir(fs, parent)? {
+        return Err(vfs_error(
+            ErrorKind::NotADirectory,
+            "parent is not a directory",
+        ));
+    }
+    Ok(())
+}
+
+fn read_file(fs: &dyn FileSystem, path: &str) -> VfsResult<Vec<u8>> {
+    if is_dir(fs, path)? {
+        return Err(vfs_error(ErrorKind::IsADirectory, "is a directory"));
+    }
+    let mut contents = vec![];
+    fs.open_file(path)?.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+fn write_file(fs: &dyn FileSystem, path: &str, contents: &[u8]) -> VfsResult<()> {
+    check_parent(fs, path)?;
+    if fs.exists(path)? && is_dir(fs, path)? {
+        return Err(vfs_error(ErrorKind::IsADirectory, "is a directory"));
+    }
+    let mut file = fs.create_file(path)?;
+    file.write_all(contents)?;
+    file.flush()?;
+    Ok(())
+}
+
+fn remove_all(fs: &dyn FileSystem, path: &str) -> VfsResult<()> {
+    if !is_dir(fs, path)? {
+        return fs.remove_file(path);
+    }
+    let names: Vec<String> = fs.read_dir(path)?.collect();
+    for name in names {
+        remove_all(fs, &format!("{path}/{name}"))?;
+    }
+    fs.remove_dir(path)
+}
+
+/// Move `from` to `to`, replacing a file or empty directory there. Backends
+/// that can't move things themselves, like `MemoryFS`, have the entry copied
+/// and removed instead.
+fn move_entry(fs: &dyn FileSystem, from: &str, to: &str) -> VfsResult<()> {
+    let exists = fs.exists(to)?;
+    if !is_dir(fs, from)? {
+        if exists && is_dir(fs, to)? {
+            return Err(vfs_error(ErrorKind::IsADirectory, "is a directory"));
+        }
+        match fs.move_file(from, to) {
+            Err(e) if matches!(e.kind(), VfsErrorKind::NotSupported) => {}
+            result => return result,
+        }
+        write_file(fs, to, &read_file(fs, from)?)?;
+        return fs.remove_file(from);
+    }
+
+    if exists {
+        if !is_dir(fs, to)? {
+            return Err(vfs_error(ErrorKind::NotADirectory, "is not a directory"));
+        }
+        if fs.read_dir(to)?.next().is_some() {
+            return Err(vfs_error(
+                ErrorKind::DirectoryNotEmpty,
+                "directory not empty",
+            ));
+        }
+        fs.remove_dir(to)?;
+    }
+    match fs.move_dir(from, to) {
+        Err(e) if matches!(e.kind(), VfsErrorKind::NotSupported) => {}
+        result => return result,
+    }
+    fs.create_dir(to)?;
+    let names: Vec<String> = fs.read_dir(from)?.collect();
+    for name in names {
+        move_entry(fs, &format!("{from}/{name}"), &format!("{to}/{name}"))?;
+    }
+    fs.remove_dir(from)
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyDisk<'a> for VfsAsFloppy {
+    type DirBuilder = VfsFloppyDirBuilder<'a>;
+    type DirEntry = VfsFloppyDirEntry;
+    type File = VfsFloppyFile;
+    type FileType = VfsFloppyFileType;
+    type Metadata = VfsFloppyMetadata;
+    type OpenOptions = VfsFloppyOpenOptions;
+    type Permissions = VfsFloppyPermissions;
+    type ReadDir = VfsFloppyReadDir;
+
+    async fn canonicalize<P: AsRef<Path> + Send>(&self, path: P) -> Result<PathBuf> {
+        let vfs = vfs_path(path.as_ref())?;
+        debug!("canonicalise {vfs}");
+        let canonical = disk_path(&vfs);
+        self.blocking(path.as_ref(), move |fs| fs.metadata(&vfs))
+            .await?;
+        Ok(canonical)
+    }
+
+    async fn copy<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<u64> {
+        let (from_vfs, to_vfs) = (vfs_path(from.as_ref())?, vfs_path(to.as_ref())?);
+        debug!("copy {from_vfs} -> {to_vfs}");
+        if from_vfs == to_vfs {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} and {} are the same file",
+                    from.as_ref().display(),
+                    to.as_ref().display()
+                ),
+            ));
+        }
+        self.blocking(from.as_ref(), move |fs| {
+            if is_dir(fs, &from_vfs)? {
+                return Err(vfs_error(ErrorKind::InvalidInput, "is a directory"));
+            }
+            match fs.copy_file(&from_vfs, &to_vfs) {
+                Err(e) if matches!(e.kind(), VfsErrorKind::NotSupported) => {
+                    let contents = read_file(fs, &from_vfs)?;
+                    write_file(fs, &to_vfs, &contents)?;
+                    Ok(contents.len() as u64)
+                }
+                result => {
+                    result?;
+                    Ok(fs.metadata(&to_vfs)?.len)
+                }
+            }
+        })
+        .await
+    }
+
+    async fn create_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let vfs = vfs_path(path.as_ref())?;
+        debug!("create_dir {vfs}");
+        self.blocking(path.as_ref(), move |fs| {
+            check_parent(fs, &vfs)?;
+            fs.create_dir(&vfs)
+        })
+        .await
+    }
+
+    async fn create_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let vfs = vfs_path(path.as_ref())?;
+        debug!("create_dir_all {vfs}");
+        self.blocking(path.as_ref(), move |fs| {
+            let mut dir = String::new();
+            for part in vfs.split('/').skip(1) {
+                dir = format!("{dir}/{part}");
+                if !fs.exists(&dir)? {
+                    fs.create_dir(&dir)?;
+                } else if !is_dir(fs, &dir)? {
+                    return Err(vfs_error(ErrorKind::NotADirectory, "is not a directory"));
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn hard_link<P: AsRef<Path> + Send>(&self, _src: P, _dst: P) -> Result<()> {
+        Err(unsupported("hard links"))
+    }
+
+    async fn metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        let vfs = vfs_path(path.as_ref())?;
+        debug!("metadata {vfs}");
+        self.blocking(path.as_ref(), move |fs| {
+            fs.metadata(&vfs).map(VfsFloppyMetadata::from)
+        })
+        .await
+    }
+
+    async fn read<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>> {
+        let vfs = vfs_path(path.as_ref())?;
+        debug!("read {vfs}");
+        self.blocking(path.as_ref(), move |fs| read_file(fs, &vfs))
+            .await
+    }
+
+    async fn read_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::ReadDir> {
+        let vfs = vfs_path(path.as_ref())?;
+        debug!("read_dir {vfs}");
+        let dir = path.as_ref().to_path_buf();
+        let entries = self
+            .blocking(path.as_ref(), move |fs| {
+                if !is_dir(fs, &vfs)? {
+                    return Err(vfs_error(ErrorKind::NotADirectory, "is not a directory"));
+                }
+                let mut entries = vec![];
+                for name in fs.read_dir(&vfs)? {
+                    // Entries removed since the listing are skipped.
+                    let metadata = match fs.metadata(&format!("{vfs}/{name}")) {
+                        Ok(metadata) => metadata,
+                        Err(e) if matches!(e.kind(), VfsErrorKind::FileNotFound) => continue,
+                        Err(e) => return Err(e),
+                    };
+                    entries.push(VfsFloppyDirEntry {
+                        path: dir.join(name),
+                        metadata: metadata.into(),
+                    });
+                }
+                Ok(entries)
+            })
+            .await?;
+        Ok(VfsFloppyReadDir(entries.into_iter()))
+    }
+
+    async fn read_link<P: AsRef<Path> + Send>(&self, _path: P) -> Result<PathBuf> {
+        Err(unsupported("symlinks"))
+    }
+
+    async fn read_to_string<P: AsRef<Path> + Send>(&self, path: P) -> Result<String> {
+        String::from_utf8(self.read(path).await?).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    async fn remove_dir<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let vfs = vfs_path(path.as_ref())?;
+        debug!("remove_dir {vfs}");
+        self.blocking(path.as_ref(), move |fs| {
+            if !is_dir(fs, &vfs)? {
+                return Err(vfs_error(ErrorKind::NotADirectory, "is not a directory"));
+            }
+            if fs.read_dir(&vfs)?.next().is_some() {
+                return Err(vfs_error(
+                    ErrorKind::DirectoryNotEmpty,
+                    "directory not empty",
+                ));
+            }
+            fs.remove_dir(&vfs)
+        })
+        .await
+    }
+
+    async fn remove_dir_all<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let vfs = vfs_path(path.as_ref())?;
+        debug!("remove_dir_all {vfs}");
+        self.blocking(path.as_ref(), move |fs| {
+            if !is_dir(fs, &vfs)? {
+                return Err(vfs_error(ErrorKind::NotADirectory, "is not a directory"));
+            }
+            remove_all(fs, &vfs)
+        })
+        .await
+    }
+
+    async fn remove_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        let vfs = vfs_path(path.as_ref())?;
+        debug!("remove_file {vfs}");
+        self.blocking(path.as_ref(), move |fs| {
+            if is_dir(fs, &vfs)? {
+                return Err(vfs_error(ErrorKind::IsADirectory, "is a directory"));
+            }
+            fs.remove_file(&vfs)
+        })
+        .await
+    }
+
+    async fn rename<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<()> {
+        check_not_into_itself(self, from.as_ref(), to.as_ref()).await?;
+        let (from_vfs, to_vfs) = (vfs_path(from.as_ref())?, vfs_path(to.as_ref())?);
+        debug!("rename {from_vfs} -> {to_vfs}");
+        self.blocking(from.as_ref(), move |fs| {
+            if from_vfs == to_vfs {
+                return fs.metadata(&from_vfs).map(|_| ());
+            }
+            check_parent(fs, &to_vfs)?;
+            move_entry(fs, &from_vfs, &to_vfs)
+        })
+        .await
+    }
+
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        _path: P,
+        _perm: Self::Permissions,
+    ) -> Result<()> {
+        Err(unsupported("permissions"))
+    }
+
+    async fn symlink<P: AsRef<Path> + Send>(&self, _src: P, _dst: P) -> Result<()> {
+        Err(unsupported("symlinks"))
+    }
+
+    async fn symlink_metadata<P: AsRef<Path> + Send>(&self, path: P) -> Result<Self::Metadata> {
+        self.metadata(path).await
+    }
+
+    async fn try_exists<P: AsRef<Path> + Send>(&self, path: P) -> Result<bool> {
+        let vfs = vfs_path(path.as_ref())?;
+        debug!("try_exists {vfs}");
+        self.blocking(path.as_ref(), move |fs| fs.exists(&vfs))
+            .await
+    }
+
+    async fn write<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        contents: impl AsRef<[u8]> + Send,
+    ) -> Result<()> {
+        let vfs = vfs_path(path.as_ref())?;
+        let contents = contents.as_ref().to_vec();
+        debug!("write {vfs}");
+        self.blocking(path.as_ref(), move |fs| write_file(fs, &vfs, &contents))
+            .await
+    }
+
+    fn new_dir_builder(&'a self) -> Self::DirBuilder {
+        VfsFloppyDirBuilder {
+            disk: self,
+            recursive: false,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl FloppyDiskUnixExt for VfsAsFloppy {
+    async fn chown<P: Into<PathBuf> + Send>(&self, _path: P, _uid: u32, _gid: u32) -> Result<()> {
+        Err(unsupported("owners"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VfsFloppyMetadata {
+    file_type: VfsFileType,
+    len: u64,
+    created: Option<SystemTime>,
+    modified: Option<SystemTime>,
+    accessed: Option<SystemTime>,
+}
+
+impl From<VfsMetadata> for VfsFloppyMetadata {
+    fn from(metadata: VfsMetadata) -> Self {
+        Self {
+            file_type: metadata.file_type,
+            len: metadata.len,
+            created: metadata.created,
+            modified: metadata.modified,
+            accessed: metadata.accessed,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyMetadata<'a, VfsAsFloppy> for VfsFloppyMetadata {
+    fn file_type(&self) -> VfsFloppyFileType {
+        VfsFloppyFileType(self.file_type)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.file_type == VfsFileType::Directory
+    }
+
+    fn is_file(&self) -> bool {
+        self.file_type == VfsFileType::File
+    }
+
+    fn is_symlink(&self) -> bool {
+        false
+    }
+
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn permissions(&self) -> VfsFloppyPermissions {
+        VfsFloppyPermissions::for_type(self.file_type)
+    }
+
+    fn modified(&self) -> Result<SystemTime> {
+        self.modified
+            .ok_or_else(|| unsupported("modification times"))
+    }
+
+    fn accessed(&self) -> Result<SystemTime> {
+        self.accessed.ok_or_else(|| unsupported("access times"))
+    }
+
+    fn created(&self) -> Result<SystemTime> {
+        self.created.ok_or_else(|| unsupported("creation times"))
+    }
+}
+
+impl FloppyUnixMetadata for VfsFloppyMetadata {
+    fn uid(&self) -> Result<u32> {
+        Err(unsupported("owners"))
+    }
+
+    fn gid(&self) -> Result<u32> {
+        Err(unsupported("owners"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VfsFloppyFileType(VfsFileType);
+
+impl FloppyFileType for VfsFloppyFileType {
+    fn is_dir(&self) -> bool {
+        self.0 == VfsFileType::Directory
+    }
+
+    fn is_file(&self) -> bool {
+        self.0 == VfsFileType::File
+    }
+
+    fn is_symlink(&self) -> bool {
+        false
+    }
+}
+
+/// Permissions can't be stored, so these are only ever the defaults for the
+/// entry's type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsFloppyPermissions {
+    mode: u32,
+}
+
+impl VfsFloppyPermissions {
+    fn for_type(file_type: VfsFileType) -> Self {
+        match file_type {
+            VfsFileType::Directory => Self { mode: 0o755 },
+            VfsFileType::File => Self { mode: 0o644 },
+        }
+    }
+}
+
+impl FloppyPermissions for VfsFloppyPermissions {
+    fn readonly(&self) -> bool {
+        self.mode & 0o222 == 0
+    }
+
+    fn set_readonly(&mut self, readonly: bool) {
+        if readonly {
+            self.mode &= !0o222;
+        } else {
+            self.mode |= 0o222;
+        }
+    }
+}
+
+impl FloppyUnixPermissions for VfsFloppyPermissions {
+    fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    fn set_mode(&mut self, mode: u32) {
+        self.mode = mode;
+    }
+
+    fn from_mode(mode: u32) -> Self {
+        Self { mode }
+    }
+}
+
+#[derive(Debug)]
+pub struct VfsFloppyDirEntry {
+    path: PathBuf,
+    metadata: VfsFloppyMetadata,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyDirEntry<'a, VfsAsFloppy> for VfsFloppyDirEntry {
+    fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn file_name(&self) -> OsString {
+        self.path.file_name().unwrap().to_os_string()
+    }
+
+    async fn metadata(&self) -> Result<VfsFloppyMetadata> {
+        Ok(self.metadata.clone())
+    }
+
+    async fn file_type(&self) -> Result<VfsFloppyFileType> {
+        Ok(VfsFloppyFileType(self.metadata.file_type))
+    }
+
+    /// `vfs` has no inode numbers, so this is always 0.
+    #[cfg(unix)]
+    fn ino(&self) -> u64 {
+        0
+    }
+}
+
+/// A directory's entries, listed with their metadata when it's read.
+#[derive(Debug)]
+pub struct VfsFloppyReadDir(std::vec::IntoIter<VfsFloppyDirEntry>);
+
+#[async_trait::async_trait]
+impl<'a> FloppyReadDir<'a, VfsAsFloppy> for VfsFloppyReadDir {
+    async fn next_entry(&mut self) -> Result<Option<VfsFloppyDirEntry>> {
+        Ok(self.0.next())
+    }
+}
+
+/// Modes can't be stored, so they're ignored.
+#[derive(Debug)]
+pub struct VfsFloppyDirBuilder<'a> {
+    disk: &'a VfsAsFloppy,
+    recursive: bool,
+}
+
+#[async_trait::async_trait]
+impl FloppyDirBuilder for VfsFloppyDirBuilder<'_> {
+    fn recursive(&mut self, recursive: bool) -> &mut Self {
+        self.recursive = recursive;
+        self
+    }
+
+    async fn create<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
+        if self.recursive {
+            self.disk.create_dir_all(path).await
+        } else {
+            self.disk.create_dir(path).await
+        }
+    }
+
+    #[cfg(unix)]
+    fn mode(&mut self, _mode: u32) -> &mut Self {
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VfsFloppyOpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyOpenOptions<'a, VfsAsFloppy> for VfsFloppyOpenOptions {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(mut self, read: bool) -> Self {
+        self.read = read;
+        self
+    }
+
+    fn write(mut self, write: bool) -> Self {
+        self.write = write;
+        self
+    }
+
+    fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    fn truncate(mut self, truncate: bool) -> Self {
+        self.truncate = truncate;
+        self
+    }
+
+    fn create(mut self, create: bool) -> Self {
+        self.create = create;
+        self
+    }
+
+    fn create_new(mut self, create_new: bool) -> Self {
+        self.create_new = create_new;
+        self
+    }
+
+    async fn open<P: AsRef<Path> + Send>(
+        &self,
+        disk: &'a VfsAsFloppy,
+        path: P,
+    ) -> Result<VfsFloppyFile> {
+        let vfs = vfs_path(path.as_ref())?;
+        debug!("opening {vfs}");
+        let options = *self;
+        let writable = options.write || options.append;
+        let contents = {
+            let vfs = vfs.clone();
+            disk.blocking(path.as_ref(), move |fs| {
+                let exists = fs.exists(&vfs)?;
+                if exists && options.create_new {
+                    return Err(VfsErrorKind::FileExists.into());
+                }
+                if !exists && !(writable && (options.create || options.create_new)) {
+                    return Err(VfsErrorKind::FileNotFound.into());
+                }
+                if exists && !(writable && options.truncate) {
+                    return read_file(fs, &vfs);
+                }
+                write_file(fs, &vfs, &[])?;
+                Ok(vec![])
+            })
+            .await?
+        };
+        let position = if options.append {
+            contents.len() as u64
+        } else {
+            0
+        };
+        Ok(VfsFloppyFile {
+            shared: Arc::new(Shared {
+                disk: disk.clone(),
+                path: path.as_ref().to_path_buf(),
+                vfs,
+                buffer: Mutex::new(Buffer {
+                    contents,
+                    dirty: false,
+                }),
+            }),
+            position,
+            writable,
+            append: options.append,
+            flushing: None,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Buffer {
+    contents: Vec<u8>,
+    /// Whether the contents have changed since they were last written back.
+    dirty: bool,
+}
+
+/// What an open file and its clones share.
+#[derive(Debug)]
+struct Shared {
+    disk: VfsAsFloppy,
+    path: PathBuf,
+    vfs: String,
+    buffer: Mutex<Buffer>,
+}
+
+impl Shared {
+    /// Start writing the contents back, if they've changed.
+    fn start_flush(self: &Arc<Self>) -> Option<JoinHandle<Result<()>>> {
+        let contents = {
+            let mut buffer = self.buffer.lock().unwrap();
+            if !std::mem::take(&mut buffer.dirty) {
+                return None;
+            }
+            buffer.contents.clone()
+        };
+        let shared = self.clone();
+        Some(tokio::task::spawn_blocking(move || {
+            shared.write_back(&contents)
+        }))
+    }
+
+    fn write_back(&self, contents: &[u8]) -> Result<()> {
+        write_file(&*self.disk.fs, &self.vfs, contents).map_err(|e| {
+            // Try again on the next flush.
+            self.buffer.lock().unwrap().dirty = true;
+            io_error(&self.path, e)
+        })
+    }
+}
+
+/// An open file, read into memory. Clones share the same contents, so
+/// writes through one are seen by the others.
+#[derive(Debug)]
+pub struct VfsFloppyFile {
+    shared: Arc<Shared>,
+    position: u64,
+    writable: bool,
+    append: bool,
+    /// Writing the contents back, if a flush is in progress.
+    flushing: Option<JoinHandle<Result<()>>>,
+}
+
+impl VfsFloppyFile {
+    fn check_writable(&self) -> Result<()> {
+        if self.writable {
+            Ok(())
+        } else {
+            Err(Error::from_raw_os_error(libc::EBADF))
+        }
+    }
+
+    fn write_at_locked(buffer: &mut Buffer, buf: &[u8], offset: u64) -> usize {
+        let offset = offset as usize;
+        if buffer.contents.len() < offset + buf.len() {
+            buffer.contents.resize(offset + buf.len(), 0);
+        }
+        buffer.contents[offset..offset + buf.len()].copy_from_slice(buf);
+        buffer.dirty = true;
+        buf.len()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a> FloppyFile<'a, VfsAsFloppy> for VfsFloppyFile {
+    async fn sync_all(&mut self) -> Result<()> {
+        tokio::io::AsyncWriteExt::flush(self).await
+    }
+
+    async fn sync_data(&mut self) -> Result<()> {
+        tokio::io::AsyncWriteExt::flush(self).await
+    }
+
+    async fn set_len(&mut self, size: u64) -> Result<()> {
+        self.check_writable()?;
+        let mut buffer = self.shared.buffer.lock().unwrap();
+        buffer.contents.resize(size as usize, 0);
+        buffer.dirty = true;
+        Ok(())
+    }
+
+    async fn metadata(&self) -> Result<VfsFloppyMetadata> {
+        let mut metadata = self.shared.disk.metadata(&self.shared.path).await?;
+        metadata.len = self.shared.buffer.lock().unwrap().contents.len() as u64;
+        Ok(metadata)
+    }
+
+    async fn try_clone(&'a self) -> Result<Box<VfsFloppyFile>> {
+        Ok(Box::new(VfsFloppyFile {
+            shared: self.shared.clone(),
+            position: self.position,
+            writable: self.writable,
+            append: self.append,
+            flushing: None,
+        }))
+    }
+
+    async fn set_permissions(&self, _perm: VfsFloppyPermissions) -> Result<()> {
+        Err(unsupported("permissions"))
+    }
+
+    async fn permissions(&self) -> Result<VfsFloppyPermissions> {
+        Ok(VfsFloppyPermissions::for_type(VfsFileType::File))
+    }
+
+    async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let buffer = self.shared.buffer.lock().unwrap();
+        let start = (offset as usize).min(buffer.contents.len());
+        let read = buf.len().min(buffer.contents.len() - start);
+        buf[..read].copy_from_slice(&buffer.contents[start..start + read]);
+        Ok(read)
+    }
+
+    async fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        self.check_writable()?;
+        let mut buffer = self.shared.buffer.lock().unwrap();
+        Ok(Self::write_at_locked(&mut buffer, buf, offset))
+    }
+}
+
+impl AsyncRead for VfsFloppyFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        let buffer = this.shared.buffer.lock().unwrap();
+        let start = (this.position as usize).min(buffer.contents.len());
+        let read = buf.remaining().min(buffer.contents.len() - start);
+        buf.put_slice(&buffer.contents[start..start + read]);
+        this.position += read as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for VfsFloppyFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> Result<()> {
+        let this = self.get_mut();
+        let (base, offset) = match position {
+            SeekFrom::Start(offset) => {
+                this.position = offset;
+                return Ok(());
+            }
+            SeekFrom::End(offset) => (
+                this.shared.buffer.lock().unwrap().contents.len() as u64,
+                offset,
+            ),
+            SeekFrom::Current(offset) => (this.position, offset),
+        };
+        this.position = base
+            .checked_add_signed(offset)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "seek to a negative position"))?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+impl AsyncWrite for VfsFloppyFile {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        this.check_writable()?;
+        let mut buffer = this.shared.buffer.lock().unwrap();
+        if this.append {
+            this.position = buffer.contents.len() as u64;
+        }
+        let written = Self::write_at_locked(&mut buffer, buf, this.position);
+        this.position += written as u64;
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(flushing) = &mut this.flushing {
+                let result = ready!(Pin::new(flushing).poll(cx));
+                this.flushing = None;
+                result??;
+            }
+            // Anything written while the last flush was running goes too.
+            match this.shared.start_flush() {
+                Some(flushing) => this.flushing = Some(flushing),
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl Drop for VfsFloppyFile {
+    fn drop(&mut self) {
+        let previous = self.flushing.take();
+        if previous.is_none() && !self.shared.buffer.lock().unwrap().dirty {
+            return;
+        }
+        let shared = self.shared.clone();
+        let Ok(handle) = Handle::try_current() else {
+            // Outside a runtime, there's nothing to stall by writing here.
+            let contents = shared.buffer.lock().unwrap().contents.clone();
+            if let Err(e) = shared.write_back(&contents) {
+                debug!("failed to write {} on drop: {e}", shared.path.display());
+            }
+            return;
+        };
+        handle.spawn(async move {
+            // Wait for the last flush, so that it can't overwrite this one.
+            if let Some(previous) = previous {
+                let _ = previous.await;
+            }
+            if let Some(flushing) = shared.start_flush() {
+                if let Ok(Err(e)) = flushing.await {
+                    debug!("failed to write {} on drop: {e}", shared.path.display());
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::vfs::{MemoryFS, VfsPath};
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[test]
+    fn test_floppy_as_vfs() -> VfsResult<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let disk = Arc::new(MemFloppyDisk::new());
+        let root = VfsPath::new(FloppyAsVfs::new(disk.clone(), runtime.handle().clone()));
+
+        let dir = root.join("a/b")?;
+        dir.create_dir_all()?;
+        let file = dir.join("c.txt")?;
+        let mut writer = file.create_file()?;
+        writer.write_all(b"asdf")?;
+        writer.flush()?;
+        drop(writer);
+        file.append_file()?.write_all(b"jkl")?;
+
+        assert_eq!("asdfjkl", file.read_to_string()?);
+        assert_eq!(7, file.metadata()?.len);
+        assert!(root.join("a")?.is_dir()?);
+        let names: Vec<String> = dir.read_dir()?.map(|path| path.filename()).collect();
+        assert_eq!(vec!["c.txt"], names);
+
+        file.move_file(&root.join("d.txt")?)?;
+        assert_eq!("asdfjkl", runtime.block_on(disk.read_to_string("/d.txt"))?);
+        root.join("d.txt")?.remove_file()?;
+        assert!(!root.join("d.txt")?.exists()?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vfs_as_floppy() -> Result<()> {
+        let fs = VfsAsFloppy::new(MemoryFS::new());
+        fs.create_dir_all("/a/b").await?;
+        fs.write("/a/b/c.txt", "asdf").await?;
+        assert_eq!("asdf", fs.read_to_string("/a/./b/../b/c.txt").await?);
+        assert_eq!(
+            PathBuf::from("/a/b/c.txt"),
+            fs.canonicalize("a/b/../b/c.txt").await?
+        );
+        let metadata = fs.metadata("/a/b/c.txt").await?;
+        assert!(metadata.is_file());
+        assert_eq!(4, metadata.len());
+        assert_eq!(0o644, metadata.permissions().mode());
+        assert!(fs.metadata("/a").await?.is_dir());
+
+        let mut read_dir = fs.read_dir("/a/b").await?;
+        let entry = read_dir.next_entry().await?.unwrap();
+        assert_eq!(PathBuf::from("/a/b/c.txt"), entry.path());
+        assert!(entry.file_type().await?.is_file());
+        assert!(read_dir.next_entry().await?.is_none());
+
+        // Files are written back when they're closed, and clones share them.
+        let mut file = VfsFloppyOpenOptions::new()
+            .append(true)
+            .open(&fs, "/a/b/c.txt")
+            .await?;
+        file.write_all(b"jkl").await?;
+        let mut clone = file.try_clone().await?;
+        clone.seek(SeekFrom::Start(0)).await?;
+        let mut contents = String::new();
+        clone.read_to_string(&mut contents).await?;
+        assert_eq!("asdfjkl", contents);
+        assert_eq!("asdf", fs.read_to_string("/a/b/c.txt").await?);
+        file.close().await?;
+        assert_eq!("asdfjkl", fs.read_to_string("/a/b/c.txt").await?);
+        assert!(VfsFloppyOpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&fs, "/a/b/c.txt")
+            .await
+            .is_err());
+        let mut read_only = fs.open_read("/a/b/c.txt").await?;
+        assert!(read_only.write_all(b"nope").await.is_err());
+
+        // `MemoryFS` can't copy or move, so both fall back to doing it by hand.
+        assert_eq!(7, fs.copy("/a/b/c.txt", "/a/d.txt").await?);
+        fs.rename("/a/b", "/e").await?;
+        assert_eq!("asdfjkl", fs.read_to_string("/e/c.txt").await?);
+        assert!(!fs.try_exists("/a/b").await?);
+        fs.rename("/a/d.txt", "/e/c.txt").await?;
+        assert!(!fs.try_exists("/a/d.txt").await?);
+        assert!(fs.rename("/e", "/e/f").await.is_err());
+
+        assert_eq!(
+            ErrorKind::DirectoryNotEmpty,
+            fs.remove_dir("/e").await.unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::NotFound,
+            fs.write("/missing/f.txt", "").await.unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::IsADirectory,
+            fs.read("/e").await.unwrap_err().kind()
+        );
+        fs.remove_dir_all("/e").await?;
+        assert!(!fs.try_exists("/e/c.txt").await?);
+        fs.remove_dir("/a").await?;
+
+        fs.write("/f.txt", "").await?;
+        for result in [
+            fs.symlink("/f.txt", "/g.txt").await,
+            fs.hard_link("/f.txt", "/g.txt").await,
+            fs.set_permissions("/f.txt", FloppyUnixPermissions::from_mode(0o600))
+                .await,
+            fs.chown("/f.txt", 1000, 1000).await,
+        ] {
+            assert_eq!(ErrorKind::Unsupported, result.unwrap_err().kind());
+        }
+
+        Ok(())
+    }
+}
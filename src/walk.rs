@@ -0,0 +1,301 @@
+//! Recursive, depth-first walks over any disk, like `walkdir`.
+
+use std::collections::HashSet;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use futures::Stream;
+
+use crate::sorted::SortedReadDir;
+use crate::{FloppyDirEntry, FloppyDisk, FloppyMetadata, FloppyReadDir};
+
+/// Walks everything under a root, depth-first, yielding each directory
+/// before its contents. The root itself isn't yielded; its children are at
+/// depth 1.
+pub struct FloppyWalkDir<'a, D: FloppyDisk<'a>> {
+    disk: &'a D,
+    root: PathBuf,
+    max_depth: Option<usize>,
+    follow_symlinks: bool,
+    sorted: bool,
+    #[allow(clippy::type_complexity)]
+    filter: Option<Box<dyn Fn(&WalkEntry<'a, D>) -> bool + Send + Sync + 'a>>,
+    started: bool,
+    /// Open directories, innermost last, with their paths and depths.
+    stack: Vec<(Listing<'a, D>, PathBuf, usize)>,
+    /// Canonical paths of directories already walked, when following
+    /// symlinks.
+    seen: HashSet<PathBuf>,
+}
+
+impl<'a, D: FloppyDisk<'a>> FloppyWalkDir<'a, D> {
+    pub fn new(disk: &'a D, root: impl AsRef<Path>) -> Self {
+        Self {
+            disk,
+            root: root.as_ref().to_path_buf(),
+            max_depth: None,
+            follow_symlinks: false,
+            sorted: false,
+            filter: None,
+            started: false,
+            stack: vec![],
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Don't yield anything deeper than `max_depth`.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Walk into symlinked directories. Each directory is only walked once,
+    /// so cycles are harmless.
+    pub fn with_follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Yield each directory's entries sorted by name, so walks go in the
+    /// same order on every backend. See [`SortedReadDir`].
+    pub fn with_sorted(mut self, sorted: bool) -> Self {
+        self.sorted = sorted;
+        self
+    }
+
+    /// Only yield entries that `filter` returns `true` for. Directories it
+    /// rejects aren't walked into.
+    pub fn with_filter(
+        mut self,
+        filter: impl Fn(&WalkEntry<'a, D>) -> bool + Send + Sync + 'a,
+    ) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    pub async fn next_entry(&mut self) -> Result<Option<WalkEntry<'a, D>>> {
+        if !self.started {
+            self.started = true;
+            if self.max_depth == Some(0) {
+                return Ok(None);
+            }
+            if self.follow_symlinks {
+                self.seen.insert(self.disk.canonicalize(&self.root).await?);
+            }
+            let read_dir = Listing::new(self.disk, &self.root, self.sorted).await?;
+            self.stack.push((read_dir, self.root.clone(), 0));
+        }
+
+        loop {
+            let Some((read_dir, dir, depth)) = self.stack.last_mut() else {
+                return Ok(None);
+            };
+            let Some(entry) = read_dir.next_entry().await? else {
+                self.stack.pop();
+                continue;
+            };
+            let (path, depth) = (dir.join(entry.file_name()), *depth + 1);
+            let metadata = if self.follow_symlinks {
+                match self.disk.metadata(&path).await {
+                    Ok(metadata) => metadata,
+                    // Broken symlinks are yielded as themselves.
+                    Err(e) if e.kind() == ErrorKind::NotFound => {
+                        self.disk.symlink_metadata(&path).await?
+                    }
+                    Err(e) => return Err(e),
+                }
+            } else {
+                self.disk.symlink_metadata(&path).await?
+            };
+            let entry = WalkEntry {
+                path,
+                depth,
+                metadata,
+            };
+            if self.filter.as_ref().is_some_and(|filter| !filter(&entry)) {
+                continue;
+            }
+
+            if entry.metadata.is_dir()
+                && self.max_depth.is_none_or(|max_depth| depth < max_depth)
+                && (!self.follow_symlinks
+                    || self.seen.insert(self.disk.canonicalize(&entry.path).await?))
+            {
+                let read_dir = Listing::new(self.disk, &entry.path, self.sorted).await?;
+                self.stack.push((read_dir, entry.path.clone(), depth));
+            }
+            return Ok(Some(entry));
+        }
+    }
+}
+
+enum Listing<'a, D: FloppyDisk<'a>> {
+    Unsorted(D::ReadDir),
+    Sorted(SortedReadDir<'a, D>),
+}
+
+impl<'a, D: FloppyDisk<'a>> Listing<'a, D> {
+    async fn new(disk: &D, path: &Path, sorted: bool) -> Result<Self> {
+        let read_dir = disk.read_dir(path).await?;
+        Ok(if sorted {
+            Listing::Sorted(SortedReadDir::new(read_dir).await?)
+        } else {
+            Listing::Unsorted(read_dir)
+        })
+    }
+
+    async fn next_entry(&mut self) -> Result<Option<D::DirEntry>> {
+        match self {
+            Listing::Unsorted(read_dir) => read_dir.next_entry().await,
+            Listing::Sorted(read_dir) => read_dir.next_entry().await,
+        }
+    }
+}
+
+pub struct WalkEntry<'a, D: FloppyDisk<'a>> {
+    path: PathBuf,
+    depth: usize,
+    metadata: D::Metadata,
+}
+
+impl<'a, D: FloppyDisk<'a>> WalkEntry<'a, D> {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn into_path(self) -> PathBuf {
+        self.path
+    }
+
+    /// How far below the root this entry is; the root's children are at 1.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The entry's metadata, or its target's if symlinks are being followed.
+    pub fn metadata(&self) -> &D::Metadata {
+        &self.metadata
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> std::fmt::Debug for WalkEntry<'a, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WalkEntry")
+            .field("path", &self.path)
+            .field("depth", &self.depth)
+            .finish_non_exhaustive()
+    }
+}
+
+pub trait FloppyDiskWalkExt<'a>: FloppyDisk<'a> + Sized {
+    /// Walk everything under `root`. See [`FloppyWalkDir`].
+    fn walk(&'a self, root: impl AsRef<Path>) -> FloppyWalkDir<'a, Self> {
+        FloppyWalkDir::new(self, root)
+    }
+
+    /// Every path under `root` with its metadata, for when the rest of
+    /// [`FloppyWalkDir`] isn't needed. Symlinked directories are walked into
+    /// if `follow_symlinks` is set, and listed as symlinks otherwise.
+    fn read_dir_recursive(
+        &'a self,
+        root: impl AsRef<Path>,
+        follow_symlinks: bool,
+    ) -> impl Stream<Item = Result<(PathBuf, Self::Metadata)>> + 'a {
+        let walk = FloppyWalkDir::new(self, root).with_follow_symlinks(follow_symlinks);
+        futures::stream::try_unfold(walk, |mut walk| async move {
+            Ok(walk
+                .next_entry()
+                .await?
+                .map(|entry| ((entry.path, entry.metadata), walk)))
+        })
+    }
+}
+
+impl<'a, D: FloppyDisk<'a>> FloppyDiskWalkExt<'a> for D {}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    async fn collect<'a, D: FloppyDisk<'a>>(mut walk: FloppyWalkDir<'a, D>) -> Result<Vec<String>> {
+        let mut paths = vec![];
+        while let Some(entry) = walk.next_entry().await? {
+            paths.push(format!("{} {}", entry.depth(), entry.path().display()));
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    #[tokio::test]
+    async fn test_walk() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        fs.create_dir_all("/root/a/b").await?;
+        fs.write("/root/a/b/c.txt", "asdf").await?;
+        fs.write("/root/d.txt", "jkl").await?;
+        fs.create_dir_all("/other").await?;
+        fs.write("/other/e.txt", "qwe").await?;
+        fs.symlink("/other", "/root/f").await?;
+        fs.symlink("/root", "/other/loop").await?;
+
+        assert_eq!(
+            vec![
+                "1 /root/a",
+                "1 /root/d.txt",
+                "1 /root/f",
+                "2 /root/a/b",
+                "3 /root/a/b/c.txt",
+            ],
+            collect(fs.walk("/root")).await?
+        );
+        assert_eq!(
+            vec!["1 /root/a", "1 /root/d.txt", "1 /root/f", "2 /root/a/b"],
+            collect(fs.walk("/root").with_max_depth(2)).await?
+        );
+        assert_eq!(
+            vec!["1 /root/d.txt", "1 /root/f"],
+            collect(
+                fs.walk("/root")
+                    .with_filter(|entry| !entry.path().ends_with("a"))
+            )
+            .await?
+        );
+
+        // The symlink back to the root isn't walked into again.
+        let followed = collect(fs.walk("/root").with_follow_symlinks(true)).await?;
+        assert!(followed.contains(&"2 /root/f/e.txt".to_string()));
+        assert!(followed.contains(&"2 /root/f/loop".to_string()));
+        assert_eq!(7, followed.len());
+
+        let listed: Vec<_> = fs.read_dir_recursive("/root", false).try_collect().await?;
+        assert_eq!(5, listed.len());
+        let (_, metadata) = listed
+            .iter()
+            .find(|(path, _)| path == Path::new("/root/f"))
+            .unwrap();
+        assert!(metadata.is_symlink());
+        let listed: Vec<_> = fs.read_dir_recursive("/root", true).try_collect().await?;
+        assert_eq!(7, listed.len());
+
+        // Sorted walks are preorder, so their order can be checked as-is.
+        let mut walk = fs.walk("/root").with_sorted(true);
+        let mut paths = vec![];
+        while let Some(entry) = walk.next_entry().await? {
+            paths.push(entry.into_path());
+        }
+        assert_eq!(
+            vec![
+                PathBuf::from("/root/a"),
+                PathBuf::from("/root/a/b"),
+                PathBuf::from("/root/a/b/c.txt"),
+                PathBuf::from("/root/d.txt"),
+                PathBuf::from("/root/f"),
+            ],
+            paths
+        );
+
+        Ok(())
+    }
+}
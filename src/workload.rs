@@ -0,0 +1,281 @@
+//! Synthetic workloads to run against any disk, for comparing backends and
+//! catching performance regressions in them. `benches/workloads.rs` runs
+//! each of these against the built-in backends.
+
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{
+    FloppyDirEntry, FloppyDisk, FloppyFile, FloppyMetadata, FloppyOpenOptions, FloppyReadDir,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Workload {
+    /// Write, read back and remove many small files in one directory, like a
+    /// build's scratch files.
+    SmallFileChurn { files: usize, size: usize },
+    /// Stream one large file in and out, `chunk` bytes at a time.
+    LargeStream { size: u64, chunk: usize },
+    /// Create a tree `depth` directories deep, with `fanout` directories and
+    /// one file in each, then list all of it.
+    DeepTree { depth: usize, fanout: usize },
+    /// Stat each of `files` files `rounds` times over, like a file watcher
+    /// or a build tool checking what's changed.
+    MetadataStorm { files: usize, rounds: usize },
+}
+
+impl Workload {
+    pub fn small_file_churn() -> Self {
+        Self::SmallFileChurn {
+            files: 1000,
+            size: 1024,
+        }
+    }
+
+    pub fn large_stream() -> Self {
+        Self::LargeStream {
+            size: 64 * 1024 * 1024,
+            chunk: 64 * 1024,
+        }
+    }
+
+    pub fn deep_tree() -> Self {
+        Self::DeepTree {
+            depth: 6,
+            fanout: 3,
+        }
+    }
+
+    pub fn metadata_storm() -> Self {
+        Self::MetadataStorm {
+            files: 100,
+            rounds: 100,
+        }
+    }
+
+    /// Each workload at its default size.
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::small_file_churn(),
+            Self::large_stream(),
+            Self::deep_tree(),
+            Self::metadata_storm(),
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SmallFileChurn { .. } => "small_file_churn",
+            Self::LargeStream { .. } => "large_stream",
+            Self::DeepTree { .. } => "deep_tree",
+            Self::MetadataStorm { .. } => "metadata_storm",
+        }
+    }
+
+    /// Run the workload in `root`, which must not exist yet. Only the
+    /// workload itself is timed, not setting it up or removing `root`
+    /// afterwards.
+    pub async fn run<'a, D: FloppyDisk<'a>>(
+        &self,
+        disk: &'a D,
+        root: impl AsRef<Path> + Send,
+    ) -> Result<WorkloadReport> {
+        let root = root.as_ref();
+        disk.create_dir_all(root).await?;
+        let mut report = WorkloadReport::default();
+        let result = match *self {
+            Self::SmallFileChurn { files, size } => {
+                small_file_churn(disk, root, files, size, &mut report).await
+            }
+            Self::LargeStream { size, chunk } => {
+                large_stream(disk, root, size, chunk, &mut report).await
+            }
+            Self::DeepTree { depth, fanout } => {
+                deep_tree(disk, root, depth, fanout, &mut report).await
+            }
+            Self::MetadataStorm { files, rounds } => {
+                metadata_storm(disk, root, files, rounds, &mut report).await
+            }
+        };
+        disk.remove_dir_all(root).await?;
+        result?;
+        Ok(report)
+    }
+}
+
+/// What running a [`Workload`] did, and how long it took.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkloadReport {
+    /// Disk operations made, counting each read or write of a file once.
+    pub ops: u64,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl WorkloadReport {
+    pub fn ops_per_sec(&self) -> f64 {
+        self.ops as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+async fn small_file_churn<'a, D: FloppyDisk<'a>>(
+    disk: &'a D,
+    root: &Path,
+    files: usize,
+    size: usize,
+    report: &mut WorkloadReport,
+) -> Result<()> {
+    let data = vec![b'x'; size];
+    let started = Instant::now();
+    for i in 0..files {
+        let path = root.join(format!("{i}.tmp"));
+        disk.write(&path, &data).await?;
+        report.bytes += disk.read(&path).await?.len() as u64 + size as u64;
+        disk.remove_file(&path).await?;
+        report.ops += 3;
+    }
+    report.elapsed = started.elapsed();
+    Ok(())
+}
+
+async fn large_stream<'a, D: FloppyDisk<'a>>(
+    disk: &'a D,
+    root: &Path,
+    size: u64,
+    chunk: usize,
+    report: &mut WorkloadReport,
+) -> Result<()> {
+    let path = root.join("large");
+    let mut buf = vec![b'x'; chunk];
+    let started = Instant::now();
+
+    let mut file = D::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .open(disk, &path)
+        .await?;
+    let mut written = 0;
+    while written < size {
+        let len = chunk.min((size - written) as usize);
+        file.write_all(&buf[..len]).await?;
+        written += len as u64;
+        report.ops += 1;
+    }
+    file.close().await?;
+
+    let mut file = D::OpenOptions::new().read(true).open(disk, &path).await?;
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        report.bytes += read as u64;
+        report.ops += 1;
+    }
+    report.elapsed = started.elapsed();
+    report.bytes += written;
+    Ok(())
+}
+
+async fn deep_tree<'a, D: FloppyDisk<'a>>(
+    disk: &'a D,
+    root: &Path,
+    depth: usize,
+    fanout: usize,
+    report: &mut WorkloadReport,
+) -> Result<()> {
+    let started = Instant::now();
+    let mut level = vec![root.to_path_buf()];
+    for _ in 0..depth {
+        let mut next = vec![];
+        for dir in &level {
+            disk.write(dir.join("file"), "").await?;
+            report.ops += 1;
+            for i in 0..fanout {
+                let child = dir.join(i.to_string());
+                disk.create_dir(&child).await?;
+                report.ops += 1;
+                next.push(child);
+            }
+        }
+        level = next;
+    }
+
+    let mut stack: Vec<PathBuf> = vec![root.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let mut read_dir = disk.read_dir(&dir).await?;
+        report.ops += 1;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = dir.join(entry.file_name());
+            if disk.symlink_metadata(&path).await?.is_dir() {
+                stack.push(path);
+            }
+            report.ops += 1;
+        }
+    }
+    report.elapsed = started.elapsed();
+    Ok(())
+}
+
+async fn metadata_storm<'a, D: FloppyDisk<'a>>(
+    disk: &'a D,
+    root: &Path,
+    files: usize,
+    rounds: usize,
+    report: &mut WorkloadReport,
+) -> Result<()> {
+    let paths: Vec<_> = (0..files).map(|i| root.join(i.to_string())).collect();
+    for path in &paths {
+        disk.write(path, "").await?;
+    }
+    let started = Instant::now();
+    for _ in 0..rounds {
+        for path in &paths {
+            disk.metadata(path).await?;
+            report.ops += 1;
+        }
+    }
+    report.elapsed = started.elapsed();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemFloppyDisk;
+
+    #[tokio::test]
+    async fn test_workloads() -> Result<()> {
+        let fs = MemFloppyDisk::new();
+        let workloads = [
+            Workload::SmallFileChurn { files: 3, size: 4 },
+            Workload::LargeStream { size: 10, chunk: 4 },
+            Workload::DeepTree {
+                depth: 2,
+                fanout: 2,
+            },
+            Workload::MetadataStorm {
+                files: 2,
+                rounds: 3,
+            },
+        ];
+        let mut ops = vec![];
+        for workload in &workloads {
+            let report = workload.run(&fs, "/bench").await?;
+            assert!(!fs.try_exists("/bench").await?);
+            ops.push((report.ops, report.bytes));
+        }
+        // 3 files written, read and removed; 3 chunks each way; 3 dirs with
+        // 6 children and a file each, then 7 listings of them; 6 stats.
+        assert_eq!(vec![(9, 24), (6, 20), (9 + 7 + 9, 0), (6, 0)], ops);
+
+        Ok(())
+    }
+}
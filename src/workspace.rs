@@ -0,0 +1,193 @@
+//! Scratch areas for work that should be cleaned up afterwards, unless
+//! something went wrong and there's debugging to do.
+
+use std::fmt::Debug;
+use std::io::Result;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use crate::debug::{render_tree, RenderOptions};
+use crate::mem::MemFloppyDisk;
+use crate::temp::TempFloppyDisk;
+use crate::tokio_fs::TokioFloppyDisk;
+use crate::walk::FloppyWalkDir;
+use crate::FloppyDisk;
+
+/// When a [`Workspace`] is kept instead of being cleaned up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeepPolicy {
+    #[default]
+    Never,
+    /// Keep it if it was [`Workspace::fail`]ed, or dropped while panicking.
+    OnFailure,
+    Always,
+}
+
+/// Where a [`Workspace`] lives.
+pub trait WorkspaceBackend: Debug {
+    type Disk;
+
+    fn disk(&self) -> &Self::Disk;
+
+    /// Make sure dropping the backend leaves its contents behind, or failing
+    /// that, leaves some record of them.
+    fn keep(&mut self);
+}
+
+impl WorkspaceBackend for TempFloppyDisk {
+    type Disk = TokioFloppyDisk;
+
+    fn disk(&self) -> &Self::Disk {
+        self
+    }
+
+    fn keep(&mut self) {
+        debug!("keeping workspace at {}", self.path().display());
+        self.disarm();
+    }
+}
+
+impl WorkspaceBackend for MemFloppyDisk {
+    type Disk = MemFloppyDisk;
+
+    fn disk(&self) -> &Self::Disk {
+        self
+    }
+
+    /// Memory can't outlive the process, so the best that can be done is
+    /// logging what was in it.
+    fn keep(&mut self) {
+        // The in-memory disk never actually waits, so this doesn't need a
+        // runtime.
+        match futures::executor::block_on(render_tree(&*self, "/", &RenderOptions::new())) {
+            Ok(tree) => debug!("in-memory workspace held:\n{tree}"),
+            Err(e) => debug!("failed to list in-memory workspace: {e}"),
+        }
+    }
+}
+
+/// A scratch disk, either a temporary directory or in memory, that's removed
+/// when it's dropped. Depending on its [`KeepPolicy`], it can be kept around
+/// instead when the work done in it fails.
+///
+/// The workspace derefs to its disk, so it can be used anywhere a
+/// `FloppyDisk` is expected with `&*workspace`.
+#[derive(Debug)]
+pub struct Workspace<B: WorkspaceBackend> {
+    backend: B,
+    keep: KeepPolicy,
+    failed: bool,
+}
+
+impl Workspace<TempFloppyDisk> {
+    /// A workspace in a fresh temporary directory.
+    pub async fn temp() -> Result<Self> {
+        Ok(Self::new(TempFloppyDisk::new().await?))
+    }
+
+    /// The temporary directory on the real filesystem.
+    pub fn path(&self) -> &Path {
+        self.backend.path()
+    }
+}
+
+impl Workspace<MemFloppyDisk> {
+    pub fn mem() -> Self {
+        Self::new(MemFloppyDisk::new())
+    }
+}
+
+impl<B: WorkspaceBackend> Workspace<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            keep: KeepPolicy::default(),
+            failed: false,
+        }
+    }
+
+    pub fn with_keep(mut self, keep: KeepPolicy) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Mark the work done in this workspace as failed, for
+    /// [`KeepPolicy::OnFailure`].
+    pub fn fail(&mut self) {
+        self.failed = true;
+    }
+
+    /// Every path created in the workspace that's still there, sorted.
+    pub async fn created<'a>(&'a self) -> Result<Vec<PathBuf>>
+    where
+        B::Disk: FloppyDisk<'a>,
+    {
+        let mut walk = FloppyWalkDir::new(self.backend.disk(), "/").with_sorted(true);
+        let mut paths = vec![];
+        while let Some(entry) = walk.next_entry().await? {
+            paths.push(entry.into_path());
+        }
+        Ok(paths)
+    }
+}
+
+impl<B: WorkspaceBackend> Deref for Workspace<B> {
+    type Target = B::Disk;
+
+    fn deref(&self) -> &Self::Target {
+        self.backend.disk()
+    }
+}
+
+impl<B: WorkspaceBackend> Drop for Workspace<B> {
+    fn drop(&mut self) {
+        let failed = self.failed || std::thread::panicking();
+        match self.keep {
+            KeepPolicy::Always => self.backend.keep(),
+            KeepPolicy::OnFailure if failed => self.backend.keep(),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_workspace() -> Result<()> {
+        let workspace = Workspace::temp().await?;
+        workspace.create_dir("/a").await?;
+        workspace.write("/a/b.txt", "asdf").await?;
+        assert_eq!(
+            vec![PathBuf::from("/a"), PathBuf::from("/a/b.txt")],
+            workspace.created().await?
+        );
+        let path = workspace.path().to_path_buf();
+        drop(workspace);
+        assert!(!tokio::fs::try_exists(&path).await?);
+
+        // Successful work is cleaned up even when failures are kept.
+        let workspace = Workspace::temp().await?.with_keep(KeepPolicy::OnFailure);
+        let path = workspace.path().to_path_buf();
+        drop(workspace);
+        assert!(!tokio::fs::try_exists(&path).await?);
+
+        let mut workspace = Workspace::temp().await?.with_keep(KeepPolicy::OnFailure);
+        workspace.write("/c.txt", "jkl").await?;
+        workspace.fail();
+        let path = workspace.path().to_path_buf();
+        drop(workspace);
+        assert_eq!("jkl", tokio::fs::read_to_string(path.join("c.txt")).await?);
+        tokio::fs::remove_dir_all(&path).await?;
+
+        let mut workspace = Workspace::mem().with_keep(KeepPolicy::OnFailure);
+        workspace.write("/d.txt", "qwe").await?;
+        assert_eq!(vec![PathBuf::from("/d.txt")], workspace.created().await?);
+        workspace.fail();
+
+        Ok(())
+    }
+}